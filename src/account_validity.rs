@@ -0,0 +1,38 @@
+use matrix_sdk::ruma::UserId;
+use matrix_sdk::HttpError;
+use serde::Serialize;
+
+const VALIDITY_PATH: &str = "_synapse/admin/v1/account_validity/validity";
+
+/// Error code returned by Synapse when the account validity period expired
+pub const EXPIRED_ACCOUNT: &str = "ORG_MATRIX_EXPIRED_ACCOUNT";
+
+#[derive(Serialize)]
+struct RenewalRequest<'a> {
+    user_id: &'a UserId,
+    enable_renewal_emails: bool,
+}
+
+/// Renew account validity through Synapse admin API, the expiration date is reset to the configured period.
+pub async fn renew(
+    http_client: &reqwest::Client,
+    homeserver_url: &str,
+    admin_token: &str,
+    user_id: &UserId,
+) -> Result<(), HttpError> {
+    let url = format!("{}/{VALIDITY_PATH}", homeserver_url.trim_end_matches('/'));
+    let request = RenewalRequest {
+        user_id,
+        enable_renewal_emails: false,
+    };
+
+    http_client
+        .post(url)
+        .bearer_auth(admin_token)
+        .json(&request)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use crate::client::Client;
+use crate::configuration::Config;
+use crate::simulation::Context;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+///
+/// Behavior performed by users when they socialize, added without changing the built-in actions,
+/// ex: product-specific endpoints. Registered actions are picked before the built-in ones with their own chance.
+///
+#[async_trait]
+pub trait Action: Send + Sync {
+    /// Name of the action in logs, reports and recordings
+    fn name(&self) -> &str;
+
+    /// Probability (0 to 100) of the action being picked when a user socializes
+    fn chance(&self, config: &Config) -> f64;
+
+    async fn execute(&self, client: &Client, context: &Context);
+}
+
+/// Actions registered in the simulation, in the order they were registered
+#[derive(Default, Clone)]
+pub struct ActionRegistry {
+    actions: Vec<Arc<dyn Action>>,
+}
+
+impl ActionRegistry {
+    pub fn register(&mut self, action: impl Action + 'static) {
+        self.actions.push(Arc::new(action));
+    }
+
+    pub(crate) fn get(&self, index: usize) -> &dyn Action {
+        self.actions[index].as_ref()
+    }
+
+    /// Position and chance of the enabled actions
+    pub(crate) fn chances<'a>(
+        &'a self,
+        config: &'a Config,
+    ) -> impl Iterator<Item = (usize, f64)> + 'a {
+        self.actions
+            .iter()
+            .enumerate()
+            .map(|(index, action)| (index, action.chance(config)))
+            .filter(|(_, chance)| *chance > 0.)
+    }
+
+    pub(crate) fn position(&self, name: &str) -> Option<usize> {
+        self.actions.iter().position(|action| action.name() == name)
+    }
+}
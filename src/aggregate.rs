@@ -0,0 +1,21 @@
+use crate::configuration::Config;
+use crate::report::Report;
+use crate::time::execution_id;
+
+///
+/// Merge reports produced by independent instances of the tool into a combined report.
+///
+/// # Panics
+///
+/// If any of the report files cannot be read or parsed.
+///
+pub fn aggregate(config: &Config, report_files: &[String]) {
+    let reports = report_files
+        .iter()
+        .map(|path| Report::load(path))
+        .collect::<Vec<_>>();
+
+    let output_dir = format!("{}/aggregated", config.simulation.output);
+    let path = Report::merge(&reports).generate(&output_dir, &execution_id(), None);
+    println!("{} reports aggregated into {}", reports.len(), path);
+}
@@ -0,0 +1,392 @@
+use crate::client::{Client, LoginResult, RegisterResult, SyncResult};
+use crate::configuration::Config;
+use crate::events::{Event, UserNotifications, UserNotificationsSender};
+use crate::http_server::{self, Response};
+use crate::room::RoomType;
+use async_channel::Sender;
+use futures::future::join_all;
+use futures::Future;
+use matrix_sdk::ruma::{EventId, OwnedEventId, OwnedRoomId};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use strum::Display;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+const METRIC_PREFIX: &str = "matrix_reloaded_canary";
+const STEPS: [Step; 4] = [Step::Login, Step::Send, Step::Receive, Step::Receipt];
+
+/// Steps of the script, run in this order on every iteration, a failed step skips the next ones
+#[derive(Debug, Clone, Copy, Display, PartialEq, Eq, PartialOrd, Ord)]
+#[strum(serialize_all = "snake_case")]
+enum Step {
+    /// new session of every user, with its initial sync
+    Login,
+    /// sender joins (or creates) the canary room and sends a message to it, once receivers synced
+    Send,
+    /// time until every receiver got the message in its sync
+    Receive,
+    /// every receiver sends a read receipt of the message
+    Receipt,
+}
+
+#[derive(Default)]
+struct StepMetrics {
+    successes: u64,
+    failures: u64,
+    last_success: bool,
+    last_duration: Duration,
+}
+
+/// Results of the script since the canary started, exposed to Prometheus
+#[derive(Default)]
+struct Metrics {
+    runs: u64,
+    last_success: bool,
+    steps: BTreeMap<Step, StepMetrics>,
+}
+
+impl Metrics {
+    fn record(&mut self, step: Step, duration: Option<Duration>) {
+        let metrics = self.steps.entry(step).or_default();
+        metrics.last_success = duration.is_some();
+        match duration {
+            Some(duration) => {
+                metrics.successes += 1;
+                metrics.last_duration = duration;
+            }
+            None => metrics.failures += 1,
+        }
+    }
+
+    fn prometheus(&self) -> String {
+        let mut lines = vec![
+            format!("{METRIC_PREFIX}_runs_total {}", self.runs),
+            format!("{METRIC_PREFIX}_success {}", u8::from(self.last_success)),
+        ];
+        for (step, metrics) in &self.steps {
+            lines.extend([
+                format!(
+                    "{METRIC_PREFIX}_step_success{{step=\"{step}\"}} {}",
+                    u8::from(metrics.last_success)
+                ),
+                format!(
+                    "{METRIC_PREFIX}_step_duration_ms{{step=\"{step}\"}} {}",
+                    metrics.last_duration.as_millis()
+                ),
+                format!(
+                    "{METRIC_PREFIX}_step_successes_total{{step=\"{step}\"}} {}",
+                    metrics.successes
+                ),
+                format!(
+                    "{METRIC_PREFIX}_step_failures_total{{step=\"{step}\"}} {}",
+                    metrics.failures
+                ),
+            ]);
+        }
+        lines.into_iter().map(|line| line + "\n").collect()
+    }
+}
+
+/// Canary user, with a new session on every run of the script
+struct CanaryUser {
+    client: Client,
+    localpart: String,
+    /// events notified by the client, forwarded unbounded so its requests never wait for the script
+    events: mpsc::UnboundedReceiver<Event>,
+    cancel_sync: Option<Sender<bool>>,
+}
+
+impl CanaryUser {
+    /// New session that keeps syncing until it's logged out
+    async fn login(&mut self, user_notifier: &UserNotificationsSender) -> Result<(), String> {
+        match self.client.login(&self.localpart).await {
+            LoginResult::Ok => {}
+            LoginResult::NotRegistered => return Err(format!("{} not registered", self.localpart)),
+            LoginResult::Failed => return Err(self.failure("login")),
+        }
+        match self.client.sync(user_notifier, false).await {
+            SyncResult::Ok { cancel_sync, .. } => {
+                self.cancel_sync = Some(cancel_sync);
+                Ok(())
+            }
+            SyncResult::Failed => Err(self.failure("sync")),
+        }
+    }
+
+    /// Sessions are logged out after every run, so devices don't pile up on the homeserver
+    async fn logout(&mut self, config: &Config) {
+        if let Some(cancel_sync) = self.cancel_sync.take() {
+            // the sync loop may have stopped already
+            let _ = cancel_sync.send(true).await;
+        }
+        if self.client.user_id().is_some() && !self.client.logout().await {
+            log::debug!("canary {} couldn't logout", self.localpart);
+        }
+        self.client.reset(config).await;
+        self.client.read_sync_events().await;
+        while self.events.try_recv().is_ok() {}
+    }
+
+    /// Wait until the sync of the user notifies the message or the deadline is reached
+    async fn receive(&mut self, event_id: &str, deadline: Instant) -> Result<(), String> {
+        let received = tokio::time::timeout_at(deadline.into(), async {
+            while let Some(event) = self.events.recv().await {
+                if matches!(event, Event::MessageReceived(received) if received == event_id) {
+                    return true;
+                }
+            }
+            false
+        })
+        .await;
+        match received {
+            Ok(true) => Ok(()),
+            _ => Err(format!("{} didn't receive {event_id}", self.localpart)),
+        }
+    }
+
+    /// Event id of the message sent, notified by the client
+    fn sent_message(&mut self) -> Result<OwnedEventId, String> {
+        let mut failure = format!("{} couldn't send the message", self.localpart);
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Event::MessageSent(event_id) => {
+                    return EventId::parse(event_id).map_err(|e| e.to_string())
+                }
+                Event::Error((request, e)) => failure = format!("{failure}, {request:?}: {e}"),
+                _ => {}
+            }
+        }
+        Err(failure)
+    }
+
+    /// Last error notified by the client for the action
+    fn failure(&mut self, action: &str) -> String {
+        let mut failure = format!("{} couldn't {action}", self.localpart);
+        while let Ok(event) = self.events.try_recv() {
+            if let Event::Error((request, e)) = event {
+                failure = format!("{} couldn't {action}, {request:?}: {e}", self.localpart);
+            }
+        }
+        failure
+    }
+}
+
+/// Canary users, the first one sends the message and the others receive it
+struct Canary {
+    sender: CanaryUser,
+    receivers: Vec<CanaryUser>,
+    /// channels found by the users syncs, the canary doesn't track them
+    user_notifier: UserNotificationsSender,
+    alias_name: String,
+    room_id: Option<OwnedRoomId>,
+    /// message sent in the current run
+    pending: Option<(OwnedEventId, Instant)>,
+    timeout: Duration,
+}
+
+impl Canary {
+    /// Run the script once, returns the duration of every step until the first failure
+    async fn run(&mut self, config: &Config) -> Vec<(Step, Result<Duration, String>)> {
+        let mut results = vec![];
+        for step in STEPS {
+            let started = Instant::now();
+            let result = match step {
+                Step::Login => self.login().await,
+                Step::Send => self.send().await,
+                Step::Receive => self.receive().await,
+                Step::Receipt => self.receipt().await,
+            };
+            let failed = result.is_err();
+            // receive measures since the message was sent
+            let elapsed = started.elapsed();
+            results.push((step, result.map(|duration| duration.unwrap_or(elapsed))));
+            if failed {
+                break;
+            }
+        }
+        self.sender.logout(config).await;
+        join_all(
+            self.receivers
+                .iter_mut()
+                .map(|receiver| receiver.logout(config)),
+        )
+        .await;
+        results
+    }
+
+    async fn login(&mut self) -> Result<Option<Duration>, String> {
+        self.sender.login(&self.user_notifier).await?;
+        for receiver in &mut self.receivers {
+            receiver.login(&self.user_notifier).await?;
+        }
+        Ok(None)
+    }
+
+    async fn send(&mut self) -> Result<Option<Duration>, String> {
+        self.pending = None;
+        let room_id = match &self.room_id {
+            Some(room_id) => room_id.clone(),
+            None => {
+                let room_id = self
+                    .sender
+                    .client
+                    .join_or_create_room(&self.alias_name, None)
+                    .await
+                    .ok_or_else(|| self.sender.failure("join the canary room"))?;
+                for receiver in &mut self.receivers {
+                    if !receiver
+                        .client
+                        .join_room(&room_id, RoomType::Channel, false)
+                        .await
+                    {
+                        return Err(receiver.failure("join the canary room"));
+                    }
+                }
+                self.room_id = Some(room_id.clone());
+                room_id
+            }
+        };
+        // the message is sent to the room once the sync of the sender has it
+        let deadline = Instant::now() + self.timeout;
+        while !self.sender.client.is_joined(&room_id) {
+            if Instant::now() > deadline {
+                return Err(format!("canary room not synced in {:?}", self.timeout));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        // events notified before the message are not about it
+        for user in std::iter::once(&mut self.sender).chain(&mut self.receivers) {
+            while user.events.try_recv().is_ok() {}
+        }
+        self.sender
+            .client
+            .send_message(&room_id, "canary".to_string())
+            .await;
+        let event_id = self.sender.sent_message()?;
+        self.pending = Some((event_id, Instant::now()));
+        Ok(None)
+    }
+
+    async fn receive(&mut self) -> Result<Option<Duration>, String> {
+        let (event_id, sent_at) = self.pending.as_ref().ok_or("no message was sent")?;
+        let deadline = *sent_at + self.timeout;
+        join_all(
+            self.receivers
+                .iter_mut()
+                .map(|receiver| receiver.receive(event_id.as_str(), deadline)),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(sent_at.elapsed()))
+    }
+
+    async fn receipt(&mut self) -> Result<Option<Duration>, String> {
+        let (room_id, (event_id, _)) = match (&self.room_id, &self.pending) {
+            (Some(room_id), Some(pending)) => (room_id, pending),
+            _ => return Err("no message was sent".to_string()),
+        };
+        for receiver in &mut self.receivers {
+            if !receiver.client.send_read_receipt(room_id, event_id).await {
+                return Err(receiver.failure("send the read receipt"));
+            }
+        }
+        Ok(None)
+    }
+}
+
+///
+/// Run the canary script every `canary.every_in_secs` until shutdown is requested, serving the results
+/// of the last run and the counters since it started at `/metrics` for Prometheus.
+///
+/// # Panics
+///
+/// If the metrics endpoint cannot listen on the address.
+///
+pub async fn run(config: &Config, shutdown: impl Future<Output = ()>) {
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    let server = serve(&config.canary.metrics_address, metrics.clone()).await;
+    let mut canary = setup(config).await;
+
+    let script = async {
+        let mut interval = tokio::time::interval(config.canary.every);
+        for run in 1.. {
+            interval.tick().await;
+            let results = canary.run(config).await;
+            let succeeded =
+                results.len() == STEPS.len() && results.iter().all(|(_, result)| result.is_ok());
+            let mut metrics = metrics.lock().expect("lock not poisoned");
+            metrics.runs = run;
+            metrics.last_success = succeeded;
+            for (step, result) in results {
+                match &result {
+                    Ok(duration) => log::debug!("canary run {} {}: {:?}", run, step, duration),
+                    Err(e) => println!("canary run {run} failed to {step}: {e}"),
+                }
+                metrics.record(step, result.ok());
+            }
+        }
+    };
+    tokio::select! {
+        _ = script => {}
+        _ = shutdown => {}
+    }
+    server.abort();
+}
+
+/// Canary users are registered once, registration fails when they already exist from a previous canary
+async fn setup(config: &Config) -> Canary {
+    let execution_id = &config.simulation.execution_id;
+    let mut users = vec![];
+    for id_number in 0..config.canary.users.max(2) {
+        let id_number = config.simulation.first_user_id + id_number;
+        let (tx, mut rx) = mpsc::channel::<Event>(100);
+        let (forward, events) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                if forward.send(event).is_err() {
+                    return;
+                }
+            }
+        });
+        let client = Client::new(tx, None, None, id_number, config).await;
+        let localpart = config.accounts.localpart(id_number, execution_id);
+        if let RegisterResult::Failed = client.register(&localpart).await {
+            log::debug!("canary {} not registered, it may already exist", localpart);
+        }
+        let mut user = CanaryUser {
+            client,
+            localpart,
+            events,
+            cancel_sync: None,
+        };
+        // the first run starts without the registration events, or its session if it logged in
+        user.logout(config).await;
+        users.push(user);
+    }
+    let (user_notifier, mut user_notifications) = mpsc::channel::<UserNotifications>(100);
+    tokio::spawn(async move { while user_notifications.recv().await.is_some() {} });
+    let sender = users.remove(0);
+    Canary {
+        sender,
+        receivers: users,
+        user_notifier,
+        alias_name: format!("canary_{execution_id}"),
+        room_id: None,
+        pending: None,
+        timeout: config.canary.timeout,
+    }
+}
+
+/// Every request is answered with the metrics in the Prometheus text format
+async fn serve(address: &str, metrics: Arc<Mutex<Metrics>>) -> JoinHandle<()> {
+    let server = http_server::serve(address, "canary metrics", move |_| {
+        let body = metrics.lock().expect("lock not poisoned").prometheus();
+        async move { Some(Response::new("text/plain; version=0.0.4", body)) }
+    })
+    .await;
+    println!("canary metrics served on http://{address}/metrics");
+    server
+}
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::{create_dir_all, File};
+
+///
+/// Simulation state persisted periodically so an interrupted run can be resumed with `--resume <execution_id>`.
+/// Rooms and friendships are not stored, they live in the server and users get them back in the initial sync.
+///
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Checkpoint {
+    pub execution_id: String,
+    pub ticks_completed: usize,
+    /// id numbers of the users already registered, they only need to log in
+    pub registered_users: BTreeSet<usize>,
+}
+
+impl Checkpoint {
+    pub fn path(output: &str, execution_id: &str) -> String {
+        format!("{output}/checkpoints/{execution_id}.json")
+    }
+
+    ///
+    /// Load checkpoint of a previous run.
+    ///
+    /// # Panics
+    ///
+    /// If the file cannot be read or is not a valid checkpoint.
+    ///
+    pub fn load(output: &str, execution_id: &str) -> Self {
+        let path = Self::path(output, execution_id);
+        let file = File::open(&path).unwrap_or_else(|_| panic!("could not open checkpoint {path}"));
+        serde_json::from_reader(file)
+            .unwrap_or_else(|e| panic!("could not parse checkpoint {path}: {e}"))
+    }
+
+    ///
+    /// Save checkpoint replacing the previous one.
+    ///
+    /// # Panics
+    ///
+    /// If the checkpoint file cannot be written.
+    ///
+    pub fn save(&self, output: &str) {
+        let directory = format!("{output}/checkpoints");
+        create_dir_all(&directory)
+            .unwrap_or_else(|_| panic!("could not create checkpoints directory {directory}"));
+
+        let path = Self::path(output, &self.execution_id);
+        let file =
+            File::create(&path).unwrap_or_else(|_| panic!("could not create checkpoint {path}"));
+        serde_json::to_writer(file, self).expect("couldn't write checkpoint to file");
+    }
+}
@@ -1,24 +1,78 @@
 use crate::{
-    configuration::{get_homeserver_url, Config},
+    account_validity::{self, EXPIRED_ACCOUNT},
+    configuration::{
+        AccountValidity, Appservice, Chaos, Config, CustomEvent, CustomEventKind, Fuzzing,
+        Lifecycle, Login, LoginMethod, Network, ProbeEndpoint, RateLimitProbe, Registration, Retry,
+        RetryPolicy, StuckUserRecovery, Timeouts,
+    },
+    credentials::Credentials,
     events::{
-        Event, SyncEvent, SyncEventsSender, UserNotifications, UserNotificationsSender, UserRequest,
+        Event, ProbeBurst, SyncEvent, SyncEventsSender, SyncStats, UserNotifications,
+        UserNotificationsSender, UserRequest, CLIENT_TIMEOUT,
     },
-    room::RoomType,
-    text::get_random_string,
+    fuzzing, jwt, pusher,
+    request_log::RequestRecord,
+    resources,
+    room::{CreationOptions, Permutation, RoomType},
+    shared_secret::{self, SharedSecretRegistration},
+    telemetry::Traced,
+    text::{get_random_string, render_template, render_text},
+    time::time_now,
 };
 use async_channel::Sender;
 use futures::Future;
 use matrix_sdk::ruma::{
     api::{
         client::{
-            account::register::v3::Request as RegistrationRequest,
+            account::deactivate::v3::Request as DeactivateRequest,
+            account::register::{v3::Request as RegistrationRequest, LoginType},
+            account::whoami::v3::Request as WhoamiRequest,
+            config::set_room_account_data::v3::Request as RoomAccountDataRequest,
+            context::get_context::v3::Request as ContextRequest,
+            directory::get_public_rooms::v3::Request as PublicRoomsRequest,
+            directory::set_room_visibility::v3::Request as RoomVisibilityRequest,
+            discovery::get_capabilities::{v3::Request as CapabilitiesRequest, Capabilities},
             error::ErrorKind,
+            filter::RoomEventFilter,
+            keys::{
+                claim_keys::v3::Request as ClaimKeysRequest,
+                get_keys::v3::Request as QueryKeysRequest,
+                upload_keys::v3::Request as UploadKeysRequest,
+                upload_signing_keys::v3::Request as UploadSigningKeysRequest,
+            },
+            knock::knock_room::v3::Request as KnockRequest,
+            media::get_media_preview::v3::Request as PreviewUrlRequest,
+            membership::ban_user::v3::Request as BanUserRequest,
+            membership::invite_user::v3::{InvitationRecipient, Request as InviteUserRequest},
             membership::join_room_by_id::v3::Request as JoinRoomRequest,
+            membership::join_room_by_id_or_alias::v3::Request as JoinRoomByAliasRequest,
+            membership::kick_user::v3::Request as KickUserRequest,
             membership::leave_room::v3::Request as LeaveRoomRequest,
             message::get_message_events::v3::Request as MessagesRequest,
+            message::send_message_event::v3::Request as MessageEventRequest,
             presence::set_presence::v3::Request as UpdatePresenceRequest,
-            room::create_room::v3::{Request as CreateRoomRequest, RoomPreset},
-            uiaa::{AuthData, Dummy, UiaaResponse},
+            profile::set_display_name::v3::Request as DisplayNameRequest,
+            room::{
+                create_room::v3::{CreationContent, Request as CreateRoomRequest, RoomPreset},
+                get_room_event::v3::Request as RoomEventRequest,
+                upgrade_room::v3::Request as UpgradeRoomRequest,
+                Visibility,
+            },
+            search::search_events::v3::{Categories, Criteria, Request as SearchRequest},
+            session::logout::v3::Request as LogoutRequest,
+            space::get_hierarchy::v1::Request as SpaceHierarchyRequest,
+            state::get_state_events_for_key::v3::Request as StateEventForKeyRequest,
+            state::send_state_event::v3::Request as StateEventRequest,
+            tag::{
+                create_tag::v3::Request as CreateTagRequest,
+                delete_tag::v3::Request as DeleteTagRequest,
+            },
+            to_device::send_event_to_device::v3::Request as ToDeviceRequest,
+            uiaa::{
+                AuthData, AuthType, Dummy, Password, ReCaptcha, RegistrationToken, UiaaInfo,
+                UiaaResponse, UserIdentifier,
+            },
+            user_directory::search_users::v3::Request as SearchUsersRequest,
             Error,
         },
         error::FromHttpResponseError::{self, Server},
@@ -27,29 +81,164 @@ use matrix_sdk::ruma::{
     },
     assign,
     events::{
+        call::invite::OriginalSyncCallInviteEvent,
+        direct::DirectEventContent,
+        ignored_user_list::IgnoredUserListEventContent,
         room::{
-            join_rules::OriginalSyncRoomJoinRulesEvent,
-            member::StrippedRoomMemberEvent,
+            join_rules::{JoinRule, OriginalSyncRoomJoinRulesEvent},
+            member::{MembershipState, OriginalSyncRoomMemberEvent, StrippedRoomMemberEvent},
             message::{
                 MessageType as MatrixMessageType, OriginalSyncRoomMessageEvent,
                 RoomMessageEventContent,
             },
+            power_levels::RoomPowerLevelsEventContent,
+            tombstone::OriginalSyncRoomTombstoneEvent,
         },
-        AnyMessageLikeEventContent,
+        room_key_request::ToDeviceRoomKeyRequestEvent,
+        space::child::SpaceChildEventContent,
+        sticker::OriginalSyncStickerEvent,
+        tag::TagInfo,
+        AnyMessageLikeEventContent, GlobalAccountDataEventType, MessageLikeEventType,
+        RoomAccountDataEventType, StateEventType, ToDeviceEventType,
     },
+    int,
     presence::PresenceState,
-    OwnedRoomId, OwnedUserId, RoomId, UserId,
+    room::RoomType as MatrixRoomType,
+    serde::Raw,
+    to_device::DeviceIdOrAllDevices,
+    uint, DeviceKeyAlgorithm, EventId, OwnedDeviceKeyId, OwnedEventId, OwnedRoomAliasId,
+    OwnedRoomId, OwnedUserId, RoomAliasId, RoomId, RoomOrAliasId, RoomVersionId, TransactionId,
+    UserId,
 };
 use matrix_sdk::{
     config::{RequestConfig, SyncSettings},
     room::Room,
+    sync::SyncResponse,
     ClientBuildError,
     Error::Http,
     HttpError::{self, Api, UiaaError},
-    LoopCtrl, RumaApiError,
+    RefreshTokenError, RumaApiError, Session,
 };
-use std::fmt::Debug;
+use rand::distributions::Alphanumeric;
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, Proxy, StatusCode};
+use serde_json::{json, value::to_raw_value, Value};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{Debug, Display};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, Span};
+
+/// Outcome of an instrumented request, logged with the request latency and used to decide if it's retried
+trait RequestOutcome: Sized {
+    fn error(&self) -> Option<String>;
+    fn status_code(&self) -> Option<u16>;
+    fn is_retryable(&self, status_codes: &[u16]) -> bool;
+    /// Outcome when the request takes longer than its timeout, None if it cannot be cancelled
+    fn timed_out() -> Option<Self>;
+}
+
+impl<T, E: RequestError> RequestOutcome for Result<T, E> {
+    fn error(&self) -> Option<String> {
+        self.as_ref().err().map(ToString::to_string)
+    }
+
+    fn status_code(&self) -> Option<u16> {
+        self.as_ref().err().and_then(RequestError::status_code)
+    }
+
+    fn is_retryable(&self, status_codes: &[u16]) -> bool {
+        self.as_ref()
+            .err()
+            .map_or(false, |e| e.is_retryable(status_codes))
+    }
+
+    fn timed_out() -> Option<Self> {
+        E::timed_out().map(Err)
+    }
+}
+
+impl RequestOutcome for () {
+    fn error(&self) -> Option<String> {
+        None
+    }
+
+    fn status_code(&self) -> Option<u16> {
+        None
+    }
+
+    fn is_retryable(&self, _status_codes: &[u16]) -> bool {
+        false
+    }
+
+    fn timed_out() -> Option<Self> {
+        Some(())
+    }
+}
+
+trait RequestError: Display + Sized {
+    fn is_retryable(&self, status_codes: &[u16]) -> bool;
+    fn status_code(&self) -> Option<u16>;
+    fn timed_out() -> Option<Self>;
+}
+
+impl RequestError for HttpError {
+    fn is_retryable(&self, status_codes: &[u16]) -> bool {
+        match self {
+            HttpError::Reqwest(e) => e.is_connect(),
+            _ => get_status_code(self).map_or(false, |code| status_codes.contains(&code)),
+        }
+    }
+
+    fn status_code(&self) -> Option<u16> {
+        get_status_code(self)
+    }
+
+    /// Reported as timeout, with a status the server never answers
+    fn timed_out() -> Option<Self> {
+        let status_code = StatusCode::from_u16(CLIENT_TIMEOUT).expect("valid status code");
+        Some(HttpError::Server(status_code))
+    }
+}
+
+impl RequestError for matrix_sdk::Error {
+    fn is_retryable(&self, status_codes: &[u16]) -> bool {
+        match self {
+            Http(e) => e.is_retryable(status_codes),
+            _ => false,
+        }
+    }
+
+    fn status_code(&self) -> Option<u16> {
+        match self {
+            Http(e) => get_status_code(e),
+            _ => None,
+        }
+    }
+
+    fn timed_out() -> Option<Self> {
+        HttpError::timed_out().map(Http)
+    }
+}
+
+impl RequestError for RefreshTokenError {
+    fn is_retryable(&self, _status_codes: &[u16]) -> bool {
+        false
+    }
+
+    fn status_code(&self) -> Option<u16> {
+        None
+    }
+
+    fn timed_out() -> Option<Self> {
+        None
+    }
+}
 
 // unbounded channel used to queue sync events like room messages or invites
 type SyncChannel = (
@@ -57,11 +246,134 @@ type SyncChannel = (
     async_channel::Receiver<SyncEvent>,
 );
 
+/// How the user reaches the homeserver, users with headers, a proxy or TLS options need their own HTTP client
+#[derive(Clone, Debug, Default)]
+struct Connection {
+    headers: HeaderMap,
+    proxy: Option<Proxy>,
+    ca_certificates: Vec<Certificate>,
+    accept_invalid_certs: bool,
+}
+
+impl Connection {
+    ///
+    /// # Panics
+    ///
+    /// If a header, the proxy URL or the CA bundle is invalid.
+    ///
+    fn new(config: &Config, id_number: usize) -> Self {
+        let mut connection = Self::default();
+        if let Some(tls) = &config.tls {
+            connection.accept_invalid_certs = tls.accept_invalid_certs;
+            if let Some(ca_bundle) = &tls.ca_bundle {
+                connection.ca_certificates = ca_certificates(ca_bundle);
+            }
+        }
+        let http = match &config.http {
+            Some(http) => http,
+            None => return connection,
+        };
+        connection.headers = http
+            .headers(id_number)
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_bytes(name.as_bytes())
+                        .unwrap_or_else(|_| panic!("invalid header name {name}")),
+                    HeaderValue::from_str(&value)
+                        .unwrap_or_else(|_| panic!("invalid value of header {name}")),
+                )
+            })
+            .collect();
+        connection.proxy = http
+            .proxy(id_number)
+            .map(|proxy| Proxy::all(proxy).unwrap_or_else(|_| panic!("invalid proxy {proxy}")));
+        connection
+    }
+
+    fn is_default(&self) -> bool {
+        self.headers.is_empty()
+            && self.proxy.is_none()
+            && self.ca_certificates.is_empty()
+            && !self.accept_invalid_certs
+    }
+
+    fn http_client_builder(&self) -> reqwest::ClientBuilder {
+        let builder = reqwest::Client::builder()
+            .default_headers(self.headers.clone())
+            .danger_accept_invalid_certs(self.accept_invalid_certs);
+        let builder = self
+            .ca_certificates
+            .iter()
+            .fold(builder, |builder, certificate| {
+                builder.add_root_certificate(certificate.clone())
+            });
+        match &self.proxy {
+            Some(proxy) => builder.proxy(proxy.clone()),
+            None => builder,
+        }
+    }
+}
+
+///
+/// Certificates of a PEM file with one or more of them.
+///
+/// # Panics
+///
+/// If the file cannot be read or a certificate is invalid.
+///
+fn ca_certificates(path: &str) -> Vec<Certificate> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let bundle =
+        std::fs::read_to_string(path).unwrap_or_else(|_| panic!("could not read CA bundle {path}"));
+    bundle
+        .split_inclusive(END)
+        .filter(|pem| pem.contains(END))
+        .map(|pem| {
+            Certificate::from_pem(pem.as_bytes())
+                .unwrap_or_else(|e| panic!("invalid certificate in CA bundle {path}: {e}"))
+        })
+        .collect()
+}
+
 #[derive(Clone, Debug)]
 pub struct Client {
     inner: matrix_sdk::Client,
+    homeserver_url: String, // base URL assigned to the user
+    connection: Connection,
+    direct_http_client: reqwest::Client, // requests sent without the SDK, ex: admin APIs
+    network: Option<Network>,
+    lifecycle: Option<Lifecycle>, // app lifecycle of mobile users
+    base_url_metrics: bool,       // requests are also reported by base URL when there are several
     event_notifier: SyncEventsSender,
     sync_channel: SyncChannel,
+    login: Login,
+    password: String, // password of the accounts registered by the tool
+    registration: Registration,
+    appservice: Option<Appservice>,
+    account_validity: Option<AccountValidity>,
+    refresh_token_enabled: bool,
+    directory_enabled: bool, // channels are published to the public rooms directory
+    direct_room_options: Arc<CreationOptions>,
+    channel_options: Arc<CreationOptions>,
+    retry: Retry,
+    timeouts: Timeouts,
+    request_limiter: Option<Arc<Semaphore>>, // limits requests in flight across all clients
+    fast_forward: bool,                      // mocked client, requests are not sent to the server
+    session_span: Span, // parent of the requests spans, their trace context is sent to the homeserver
+    rate_limited_until: Arc<Mutex<Option<Instant>>>, // requests wait until the last Retry-After is over
+    rate_limited: Arc<AtomicBool>, // rate limited since last check, used to adapt pace
+    direct_rooms: Arc<Mutex<DirectEventContent>>, // `m.direct` account data, direct message rooms per friend
+    seen_events: Arc<Mutex<VecDeque<(OwnedRoomId, OwnedEventId)>>>, // last messages received, fetched as permalinks
+    http_client: Option<reqwest::Client>, // connection pool shared by all users clients
+    request_log_enabled: bool,            // every request attempt is sent as a record
+    fuzzing: Option<Fuzzing>,             // messages replaced by malformed payloads
+    chaos: Option<Chaos>,                 // sync loops restarted and requests dropped at random
+    sync_task: Arc<Mutex<Option<JoinHandle<()>>>>, // running sync loop, aborted by chaos restarts
+    upgrade_version: RoomVersionId, // version of the replacement rooms when channels are upgraded
+    permutations: bool, // room requests are reported by the visibility and join rule of the channel
+    ignored_users: Arc<Mutex<HashMap<OwnedUserId, u128>>>, // `m.ignored_user_list` of the session, with when each user was ignored
+    room_tags: Arc<Mutex<HashMap<OwnedRoomId, &'static str>>>, // rooms tagged by the session
 }
 
 pub enum LoginResult {
@@ -84,46 +396,222 @@ pub enum SyncResult {
     Failed,
 }
 
-const PASSWORD: &str = "asdfasdf";
+// server name of the fake users and rooms used in fast forward mode
+const FAST_FORWARD_SERVER_NAME: &str = "fast.forward";
+// wait used when the server rate limits a request without telling for how long
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+// device used when restoring a session from an access token without device (or for appservice virtual users)
+const DEFAULT_DEVICE_ID: &str = "MATRIX_RELOADED";
+// VoIP signaling events, calls are never established so the session descriptions are fake
+const CALL_VERSION: &str = "1";
+const CALL_LIFETIME_MS: u64 = 60000;
+const CALL_SDP: &str =
+    "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\nm=audio 9 UDP/TLS/RTP/SAVPF 111\r\n";
+const CALL_CANDIDATE: &str = "candidate:0 1 UDP 2122252543 127.0.0.1 9 typ host";
+// one-time keys uploaded on login, clients keep around half of the maximum the server holds
+const ONE_TIME_KEYS: usize = 50;
+// key description, default key and the three cross-signing secrets
+const SECRET_STORAGE_EVENTS: usize = 5;
+// account data written by users, breadcrumbs are the recent rooms list written by Element
+const BREADCRUMBS_EVENT_TYPE: &str = "im.vector.setting.breadcrumbs";
+const SETTINGS_EVENT_TYPE: &str = "org.decentraland.settings";
+const ROOM_PREFERENCES_EVENT_TYPE: &str = "org.decentraland.room_preferences";
+// events notifying room members with the default push rules
+// media of the stickers, never downloaded by the users
+const STICKER_URL: &str = "mxc://matrix.org/sticker";
+// media of the room avatars, a number is appended so every update changes the avatar
+const ROOM_AVATAR_URL: &str = "mxc://matrix.org/room_avatar";
+
+const FORK_EVENT_TYPE: &str = "org.decentraland.fork";
+
+// messages received kept to be fetched again
+const SEEN_EVENTS: usize = 20;
+
+// tags set by clients when users mark rooms as favourites or low priority
+const ROOM_TAGS: [&str; 2] = ["m.favourite", "m.lowpriority"];
+
+// sync responses in flight when the ignore list was updated may still carry messages of the ignored user
+const IGNORE_GRACE_PERIOD_MS: u128 = 5000;
+
+// time the server holds an incremental sync waiting for new events, same as the SDK sync loop
+const SYNC_LONG_POLL: Duration = Duration::from_secs(30);
+
+const NOTIFYING_EVENT_TYPES: [&str; 3] = ["m.room.message", "m.room.encrypted", "m.call.invite"];
 
 impl Client {
-    pub async fn new(notifier: SyncEventsSender, config: &Config) -> Self {
-        let inner = Self::create(
-            &config.server.homeserver,
-            config.requests.retry_enabled,
-            config.server.wk_login,
-        )
-        .await
-        .expect("Couldn't create client");
+    pub async fn new(
+        notifier: SyncEventsSender,
+        request_limiter: Option<Arc<Semaphore>>,
+        http_client: Option<reqwest::Client>,
+        id_number: usize,
+        config: &Config,
+    ) -> Self {
+        let session_span = tracing::info_span!(parent: None, "session");
+        let homeserver_url = config.base_url(id_number);
+        let connection = Connection::new(config, id_number);
+        let inner = Self::create(config, &homeserver_url, &connection, http_client.as_ref())
+            .await
+            .expect("Couldn't create client");
+        let direct_http_client = http_client.clone().unwrap_or_else(|| {
+            connection
+                .http_client_builder()
+                .build()
+                .expect("Couldn't create http client")
+        });
         let channel = async_channel::unbounded::<SyncEvent>();
         Self {
             inner,
+            base_url_metrics: config.server.base_urls.len() > 1,
+            homeserver_url,
+            connection,
+            direct_http_client,
+            // decided once per user, mobile users keep their lifecycle across sessions
+            lifecycle: config.lifecycle.clone().filter(|lifecycle| {
+                rand::thread_rng().gen_bool((lifecycle.probability / 100.).clamp(0., 1.))
+            }),
+            network: config
+                .http
+                .as_ref()
+                .and_then(|http| http.network(id_number))
+                .cloned(),
             event_notifier: notifier,
             sync_channel: channel,
+            login: config.login.clone(),
+            password: config
+                .accounts
+                .password(id_number, &config.simulation.execution_id),
+            registration: Registration {
+                // the registration admin API is specific to Synapse and Dendrite
+                shared_secret: config
+                    .registration
+                    .shared_secret
+                    .clone()
+                    .filter(|_| config.server.flavor.has_shared_secret_registration()),
+                ..config.registration.clone()
+            },
+            appservice: config.appservice.clone(),
+            account_validity: config
+                .account_validity
+                .clone()
+                .filter(|_| config.server.flavor.has_account_validity()),
+            refresh_token_enabled: config.requests.refresh_token_enabled,
+            directory_enabled: config.feature_flags.directory_enabled,
+            direct_room_options: Arc::new(CreationOptions::from(&config.rooms.direct)),
+            channel_options: Arc::new(CreationOptions::from(&config.rooms.channel)),
+            retry: config.retry.clone(),
+            timeouts: config.timeouts.clone(),
+            request_limiter,
+            fast_forward: config.simulation.fast_forward,
+            session_span,
+            rate_limited_until: Arc::new(Mutex::new(None)),
+            rate_limited: Arc::new(AtomicBool::new(false)),
+            direct_rooms: Arc::new(Mutex::new(DirectEventContent::default())),
+            seen_events: Arc::new(Mutex::new(VecDeque::with_capacity(SEEN_EVENTS))),
+            http_client,
+            request_log_enabled: config.request_log.is_some(),
+            fuzzing: config.fuzzing.clone(),
+            chaos: config.chaos.clone(),
+            sync_task: Arc::new(Mutex::new(None)),
+            upgrade_version: RoomVersionId::try_from(config.rooms.upgrade_version.as_str())
+                .unwrap_or_else(|_| {
+                    panic!("invalid room version {}", config.rooms.upgrade_version)
+                }),
+            permutations: config.rooms.permutations,
+            ignored_users: Arc::new(Mutex::new(HashMap::new())),
+            room_tags: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// HTTP client of the requests sent without the SDK, with the user connection options
+    pub fn direct_http_client(&self) -> &reqwest::Client {
+        &self.direct_http_client
+    }
+
+    ///
+    /// HTTP client with the connection options of the first user, for requests sent before there are users.
+    ///
+    /// # Panics
+    ///
+    /// If the HTTP client cannot be created.
+    ///
+    pub fn first_user_http_client(config: &Config) -> reqwest::Client {
+        Connection::new(config, 0)
+            .http_client_builder()
+            .build()
+            .expect("Couldn't create http client")
+    }
+
+    ///
+    /// HTTP client whose connection pool is shared by all the users clients when it's configured.
+    ///
+    /// # Panics
+    ///
+    /// If the HTTP client cannot be created.
+    ///
+    pub fn shared_http_client(config: &Config) -> Option<reqwest::Client> {
+        let pool = config.connection_pool.as_ref()?;
+        if config
+            .http
+            .as_ref()
+            .map_or(false, |http| http.is_per_user())
+        {
+            println!("connection pool is not shared, users send their own headers or use their own proxy");
+            return None;
         }
+        let builder = Connection::new(config, 0)
+            .http_client_builder()
+            .pool_max_idle_per_host(pool.max_idle_connections);
+        let builder = if pool.http2_prior_knowledge {
+            builder.http2_prior_knowledge()
+        } else {
+            builder
+        };
+        Some(builder.build().expect("Couldn't create http client"))
     }
 
+    ///
+    /// # Panics
+    ///
+    /// If the HTTP client with the user connection cannot be created.
+    ///
     async fn create(
+        config: &Config,
         homeserver_url: &str,
-        retry_enabled: bool,
-        respect_login_well_known: bool,
+        connection: &Connection,
+        http_client: Option<&reqwest::Client>,
     ) -> Result<matrix_sdk::Client, ClientBuildError> {
-        let homeserver = get_homeserver_url(homeserver_url, None);
+        let timeout = config.timeouts.max();
 
-        let timeout = Duration::from_secs(30);
+        // requests are retried by `instrument` following the retry policy
+        let request_config = RequestConfig::new().disable_retry().timeout(timeout);
 
-        let request_config = if retry_enabled {
-            RequestConfig::short_retry().retry_timeout(timeout)
+        // in appservice mode every request is sent on behalf of the virtual user (`user_id` query param)
+        let request_config = if config.appservice.is_some() {
+            request_config.assert_identity()
         } else {
-            RequestConfig::new().disable_retry().timeout(timeout)
+            request_config
         };
 
-        matrix_sdk::Client::builder()
+        let builder = matrix_sdk::Client::builder()
             .request_config(request_config)
-            .homeserver_url(homeserver)
-            .respect_login_well_known(respect_login_well_known)
-            .build()
-            .await
+            .homeserver_url(homeserver_url)
+            // the well-known base URL would replace the one assigned to the user
+            .respect_login_well_known(config.server.wk_login && config.server.base_urls.is_empty());
+
+        // every request carries the user headers, the trace context is added per request by `Traced`
+        let builder = match http_client {
+            Some(http_client) => builder.http_client(http_client.clone()),
+            None if !connection.is_default() => {
+                let http_client = connection
+                    .http_client_builder()
+                    .build()
+                    .expect("Couldn't create http client");
+                builder.http_client(http_client)
+            }
+            None => builder,
+        };
+
+        builder.build().await
     }
 
     pub async fn read_sync_events(&self) -> Vec<SyncEvent> {
@@ -137,23 +625,199 @@ impl Client {
     }
 
     pub async fn reset(&mut self, config: &Config) {
+        self.session_span = tracing::info_span!(parent: None, "session");
         let client = Self::create(
-            &config.server.homeserver,
-            config.requests.retry_enabled,
-            config.server.wk_login,
+            config,
+            &self.homeserver_url,
+            &self.connection,
+            self.http_client.as_ref(),
         )
         .await
         .expect("Couldn't create client");
         self.inner = client;
+        self.ignored_users
+            .lock()
+            .expect("lock not poisoned")
+            .clear();
+        self.room_tags.lock().expect("lock not poisoned").clear();
     }
 
     pub async fn login(&self, localpart: &str) -> LoginResult {
-        let login_builder = self.inner.login_username(localpart, PASSWORD);
+        if self.fast_forward {
+            return self.fast_forward_login(localpart).await;
+        }
+        match &self.appservice {
+            Some(appservice) => self.appservice_login(appservice, localpart).await,
+            None => match self.login.method {
+                LoginMethod::Password => self.password_login(localpart, &self.password).await,
+                LoginMethod::Jwt => self.jwt_login(localpart).await,
+                LoginMethod::Token => self.token_login(localpart).await,
+            },
+        }
+    }
+
+    async fn jwt_login(&self, localpart: &str) -> LoginResult {
+        let token = match (self.login.token(localpart), &self.login.jwt_secret) {
+            (Some(token), _) => token,
+            (None, Some(secret)) => jwt::encode(secret, localpart),
+            (None, None) => {
+                log::debug!("jwt login needs a secret or a token template");
+                return LoginResult::Failed;
+            }
+        };
+
+        let response = self
+            .instrument(UserRequest::Login, || {
+                jwt::login(
+                    &self.direct_http_client,
+                    &self.homeserver_url,
+                    &token,
+                    self.refresh_token_enabled,
+                )
+            })
+            .await;
+
+        match response {
+            Ok(session) => match self.inner.restore_login(session).await {
+                Ok(_) => LoginResult::Ok,
+                Err(e) => {
+                    log::debug!("couldn't restore session for {}: {}", localpart, e);
+                    LoginResult::Failed
+                }
+            },
+            Err(e) => {
+                self.notify_error(UserRequest::Login, e).await;
+                LoginResult::Failed
+            }
+        }
+    }
+
+    async fn token_login(&self, localpart: &str) -> LoginResult {
+        let token = match self.login.token(localpart) {
+            Some(token) => token,
+            None => {
+                log::debug!("token login needs a token template");
+                return LoginResult::Failed;
+            }
+        };
+        let response = self
+            .instrument(UserRequest::Login, || async {
+                let login_builder = self.inner.login_token(&token);
+                if self.refresh_token_enabled {
+                    login_builder.request_refresh_token().send().await
+                } else {
+                    login_builder.send().await
+                }
+            })
+            .await;
+
+        self.login_result(response).await
+    }
+
+    /// Virtual users don't need to login, requests are sent with the AS token asserting the user identity.
+    async fn appservice_login(&self, appservice: &Appservice, localpart: &str) -> LoginResult {
+        if self.user_id().is_some() {
+            return LoginResult::Ok;
+        }
+        match self.restore_appservice_session(appservice, localpart).await {
+            Ok(_) => LoginResult::Ok,
+            Err(e) => {
+                log::debug!("couldn't act as virtual user {}: {}", localpart, e);
+                LoginResult::Failed
+            }
+        }
+    }
+
+    async fn restore_appservice_session(
+        &self,
+        appservice: &Appservice,
+        localpart: &str,
+    ) -> Result<(), matrix_sdk::Error> {
+        let user_id = UserId::parse(format!("@{}:{}", localpart, appservice.server_name))
+            .map_err(matrix_sdk::Error::from)?;
+        let session = Session {
+            access_token: appservice.as_token.clone(),
+            refresh_token: None,
+            user_id,
+            device_id: DEFAULT_DEVICE_ID.into(),
+        };
+        self.inner.restore_login(session).await
+    }
+
+    /// Login with pre-provisioned credentials, access token is preferred over password when both are present.
+    pub async fn login_with_credentials(&self, credentials: &Credentials) -> LoginResult {
+        if self.fast_forward {
+            return self.fast_forward_login(credentials.localpart()).await;
+        }
+        match (&credentials.access_token, &credentials.password) {
+            (Some(access_token), _) => self.restore_login(credentials, access_token).await,
+            (None, Some(password)) => self.password_login(&credentials.user_id, password).await,
+            (None, None) => {
+                log::debug!("credentials for {} have no secret", credentials.user_id);
+                LoginResult::Failed
+            }
+        }
+    }
+
+    /// Restore session from the access token and validate it against the server.
+    async fn restore_login(&self, credentials: &Credentials, access_token: &str) -> LoginResult {
+        let user_id = match UserId::parse(credentials.user_id.as_str()) {
+            Ok(user_id) => user_id,
+            Err(e) => {
+                log::debug!("invalid user id {}: {}", credentials.user_id, e);
+                return LoginResult::Failed;
+            }
+        };
+        let session = Session {
+            access_token: access_token.to_string(),
+            refresh_token: None,
+            user_id,
+            device_id: credentials
+                .device_id
+                .as_deref()
+                .unwrap_or(DEFAULT_DEVICE_ID)
+                .into(),
+        };
+        if let Err(e) = self.inner.restore_login(session).await {
+            log::debug!(
+                "couldn't restore session for {}: {}",
+                credentials.user_id,
+                e
+            );
+            return LoginResult::Failed;
+        }
+
+        let response = self
+            .instrument(UserRequest::Login, || async {
+                self.inner.send(Traced(WhoamiRequest::new()), None).await
+            })
+            .await;
+
+        match response {
+            Ok(_) => LoginResult::Ok,
+            Err(e) => {
+                self.notify_error(UserRequest::Login, e).await;
+                LoginResult::Failed
+            }
+        }
+    }
 
+    async fn password_login(&self, user: &str, password: &str) -> LoginResult {
         let response = self
-            .instrument(UserRequest::Login, || async { login_builder.send().await })
+            .instrument(UserRequest::Login, || async {
+                let login_builder = self.inner.login_username(user, password);
+                if self.refresh_token_enabled {
+                    login_builder.request_refresh_token().send().await
+                } else {
+                    login_builder.send().await
+                }
+            })
             .await;
 
+        self.login_result(response).await
+    }
+
+    async fn login_result<Response>(&self, response: matrix_sdk::Result<Response>) -> LoginResult {
         match response {
             Ok(_) => LoginResult::Ok,
             Err(Http(Api(Server(Known(RumaApiError::ClientApi(Error {
@@ -169,21 +833,95 @@ impl Client {
         }
     }
 
+    /// Register user completing the User-Interactive Auth stages required by the homeserver.
+    /// First attempt is done with dummy auth, if the server asks for more stages we follow the
+    /// first flow we are able to fulfill with the registration config.
     pub async fn register(&self, localpart: &str) -> RegisterResult {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::Register).await;
+            return RegisterResult::Ok;
+        }
+        if let Some(appservice) = &self.appservice {
+            return self.appservice_register(appservice, localpart).await;
+        }
+        if let Some(shared_secret) = &self.registration.shared_secret {
+            return self.shared_secret_register(shared_secret, localpart).await;
+        }
+
+        let mut stage = AuthType::Dummy;
+        let mut session: Option<String> = None;
+        let mut completed = vec![];
+
+        loop {
+            let auth = self.registration_auth_data(&stage, session.as_deref());
+            let req = assign!(RegistrationRequest::new(), {
+                username: Some(localpart),
+                password: Some(self.password.as_str()),
+                auth
+            });
+
+            let response = self
+                .instrument(UserRequest::Register, || async {
+                    self.inner.register(req.clone()).await
+                })
+                .await;
+
+            match response {
+                Err(UiaaError(Server(Known(UiaaResponse::MatrixError(Error {
+                    kind: ErrorKind::UserInUse,
+                    ..
+                }))))) => return RegisterResult::Ok,
+                Err(UiaaError(Server(Known(UiaaResponse::AuthResponse(info))))) => {
+                    completed.push(stage);
+                    match self.next_registration_stage(&info) {
+                        // avoid retrying forever a stage that server keeps rejecting
+                        Some(next_stage) if !completed.contains(&next_stage) => {
+                            log::debug!("register {} needs stage {:?}", localpart, next_stage);
+                            stage = next_stage;
+                            session = info.session;
+                        }
+                        _ => {
+                            log::debug!("register {} has no more stages to try", localpart);
+                            return RegisterResult::Failed;
+                        }
+                    }
+                }
+                Err(e) => {
+                    self.notify_error(UserRequest::Register, e).await;
+                    return RegisterResult::Failed;
+                }
+                Ok(_) => return RegisterResult::Ok,
+            }
+        }
+    }
+
+    /// Register virtual user in the appservice namespace, no User-Interactive Auth is needed.
+    async fn appservice_register(
+        &self,
+        appservice: &Appservice,
+        localpart: &str,
+    ) -> RegisterResult {
+        if let Err(e) = self.restore_appservice_session(appservice, localpart).await {
+            log::debug!("couldn't act as virtual user {}: {}", localpart, e);
+            return RegisterResult::Failed;
+        }
+
+        let login_type = LoginType::ApplicationService;
         let req = assign!(RegistrationRequest::new(), {
             username: Some(localpart),
-            password: Some(PASSWORD),
-            auth: Some(AuthData::Dummy(Dummy::new()))
+            login_type: Some(&login_type),
+            inhibit_login: true
         });
 
         let response = self
             .instrument(UserRequest::Register, || async {
-                self.inner.register(req).await
+                self.inner.register(req.clone()).await
             })
             .await;
 
         match response {
-            Err(UiaaError(Server(Known(UiaaResponse::MatrixError(Error {
+            Ok(_)
+            | Err(UiaaError(Server(Known(UiaaResponse::MatrixError(Error {
                 kind: ErrorKind::UserInUse,
                 ..
             }))))) => RegisterResult::Ok,
@@ -191,7 +929,76 @@ impl Client {
                 self.notify_error(UserRequest::Register, e).await;
                 RegisterResult::Failed
             }
-            Ok(_) => RegisterResult::Ok,
+        }
+    }
+
+    async fn shared_secret_register(&self, shared_secret: &str, localpart: &str) -> RegisterResult {
+        let response = self
+            .instrument(UserRequest::Register, || {
+                shared_secret::register(
+                    &self.direct_http_client,
+                    &self.homeserver_url,
+                    shared_secret,
+                    localpart,
+                    &self.password,
+                )
+            })
+            .await;
+
+        match response {
+            Ok(SharedSecretRegistration::Registered | SharedSecretRegistration::UserInUse) => {
+                RegisterResult::Ok
+            }
+            Err(e) => {
+                self.notify_error(UserRequest::Register, e).await;
+                RegisterResult::Failed
+            }
+        }
+    }
+
+    /// Pick the next stage to complete from the first flow that can be fulfilled with the current config.
+    fn next_registration_stage(&self, info: &UiaaInfo) -> Option<AuthType> {
+        info.flows
+            .iter()
+            .find(|flow| {
+                flow.stages
+                    .iter()
+                    .all(|stage| self.registration_auth_data(stage, None).is_some())
+            })
+            .and_then(|flow| {
+                flow.stages
+                    .iter()
+                    .find(|stage| !info.completed.contains(stage))
+            })
+            .cloned()
+    }
+
+    fn registration_auth_data<'a>(
+        &'a self,
+        stage: &AuthType,
+        session: Option<&'a str>,
+    ) -> Option<AuthData<'a>> {
+        match stage {
+            AuthType::Dummy => Some(AuthData::Dummy(assign!(Dummy::new(), { session }))),
+            AuthType::RegistrationToken => {
+                self.registration
+                    .registration_token
+                    .as_deref()
+                    .map(|token| {
+                        AuthData::RegistrationToken(assign!(RegistrationToken::new(token), {
+                            session
+                        }))
+                    })
+            }
+            AuthType::ReCaptcha => {
+                self.registration
+                    .recaptcha_response
+                    .as_deref()
+                    .map(|response| {
+                        AuthData::ReCaptcha(assign!(ReCaptcha::new(response), { session }))
+                    })
+            }
+            _ => None,
         }
     }
 
@@ -199,12 +1006,51 @@ impl Client {
         self.inner.user_id()
     }
 
+    /// Start syncing until cancelled, returns the sender to cancel it
+    async fn spawn_sync_loop(&self, user_id: &UserId) -> Sender<bool> {
+        let (cancel_sync, check_cancel) = async_channel::bounded::<bool>(1);
+        let handle = resources::spawn(
+            sync_until_cancel(
+                &self.inner,
+                check_cancel,
+                user_id,
+                &self.event_notifier,
+                self.lifecycle.clone(),
+                self.timeout(&UserRequest::Sync),
+                self.network.clone(),
+            )
+            .await,
+        );
+        *self.sync_task.lock().expect("lock not poisoned") = Some(handle);
+        cancel_sync
+    }
+
+    ///
+    /// Kill the running sync loop in the middle of whatever it is doing and start a new one,
+    /// returns the sender to cancel the new loop or none if the previous one wasn't started by this client.
+    ///
+    pub async fn restart_sync_loop(&self) -> Option<Sender<bool>> {
+        if self.fast_forward {
+            return None;
+        }
+        let user_id = self.user_id()?;
+        let previous = self.sync_task.lock().expect("lock not poisoned").take()?;
+        previous.abort();
+        log::debug!("chaos: sync loop of user '{}' killed", user_id);
+        let cancel_sync = self.spawn_sync_loop(user_id).await;
+        self.notify_event(Event::ChaosSyncRestarted).await;
+        Some(cancel_sync)
+    }
+
     /// Do initial sync and return rooms and new invites. Then register event handler for future syncs and notify events.
     pub async fn sync(
         &self,
         user_notifier: &UserNotificationsSender,
         presence_enabled: bool,
     ) -> SyncResult {
+        if self.fast_forward {
+            return self.fast_forward_sync().await;
+        }
         let client = &self.inner;
         let user_id = self.user_id().expect("user_id to be present");
         let user_presence = if presence_enabled {
@@ -212,13 +1058,18 @@ impl Client {
         } else {
             PresenceState::Offline
         };
+        let started = Instant::now();
         let response = self
             .instrument(UserRequest::InitialSync, || async {
                 client
-                    .sync_once(SyncSettings::default().set_presence(user_presence))
+                    .sync_once(SyncSettings::default().set_presence(user_presence.clone()))
                     .await
             })
             .await;
+        if let Ok(response) = &response {
+            let stats = SyncStats::new(response, true, started.elapsed());
+            self.notify_event(Event::SyncCompleted(stats)).await;
+        }
         match response {
             Err(_) => {
                 if let Some(Http(e)) = response.err() {
@@ -227,15 +1078,22 @@ impl Client {
                 SyncResult::Failed
             }
             Ok(_) => {
+                self.load_direct_rooms().await;
+                self.load_ignored_users().await;
                 let (tx, _) = &self.sync_channel;
 
-                add_invite_event_handler(client, tx, user_id).await;
+                add_invite_event_handler(client, tx, user_id, &self.event_notifier).await;
+                add_join_event_handler(client, user_id, &self.event_notifier).await;
+                add_knock_event_handler(client, tx).await;
+                add_call_invite_event_handler(client, tx, user_id).await;
+                add_to_device_event_handler(client, &self.event_notifier).await;
                 add_room_message_event_handler(client, tx, user_id, &self.event_notifier).await;
+                add_seen_events_handler(client, &self.seen_events, user_id).await;
                 add_room_join_rules_event_handler(client, user_notifier, tx).await;
+                add_tombstone_event_handler(client, tx, user_id).await;
+                add_ignored_users_handler(client, &self.ignored_users, &self.event_notifier).await;
 
-                let (cancel_sync, check_cancel) = async_channel::bounded::<bool>(1);
-
-                tokio::spawn(sync_until_cancel(client, check_cancel).await);
+                let cancel_sync = self.spawn_sync_loop(user_id).await;
 
                 let res = response.expect("already checked it is not an error");
                 let invited_rooms = res.rooms.invite.keys().cloned().collect::<Vec<_>>();
@@ -269,6 +1127,17 @@ impl Client {
     /// If room_id is not one of the joined rooms or couldn't retrieve it.
     ///
     pub async fn send_message(&self, room_id: &RoomId, message: String) {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::SendMessage).await;
+            let event_id = format!("${}", fast_forward_id());
+            self.notify_event(Event::MessageSent(event_id)).await;
+            return;
+        }
+        if let Some(fuzzing) = &self.fuzzing {
+            if rand::thread_rng().gen_bool((fuzzing.probability / 100.).clamp(0., 1.)) {
+                return self.send_fuzzed_message(room_id).await;
+            }
+        }
         let client = &self.inner;
 
         let content =
@@ -278,10 +1147,16 @@ impl Client {
             .get_joined_room(room_id)
             .unwrap_or_else(|| panic!("cannot get joined room {}", room_id));
 
+        // the same transaction id on every attempt, so a retried message that reached the server is not duplicated
+        let txn_id = TransactionId::new();
         let response = self
-            .instrument(UserRequest::SendMessage, || async {
-                room.send(content, None).await
-            })
+            .timed_room_request(
+                room_id,
+                UserRequest::SendMessage,
+                self.instrument(UserRequest::SendMessage, || async {
+                    room.send(content.clone(), Some(&txn_id)).await
+                }),
+            )
             .await;
 
         match response {
@@ -296,149 +1171,2069 @@ impl Client {
         }
     }
 
-    pub async fn add_friend(&self, friend_id: &UserId) {
-        let client = &self.inner;
-        // try to create room (maybe it already exists, in that case we ignore that)
-        let user_id = client.user_id().expect("user id should be present");
-        let alias = get_room_alias(user_id, friend_id);
-        let invites = [friend_id.to_owned()];
-        let request = assign!(CreateRoomRequest::new(), { room_alias_name: Some(&alias), invite: &invites, is_direct: true, preset: Some(RoomPreset::TrustedPrivateChat) });
+    /// Send a read receipt of the event, returns true when it was sent.
+    pub async fn send_read_receipt(&self, room_id: &RoomId, event_id: &EventId) -> bool {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::SendReadReceipt)
+                .await;
+            return true;
+        }
+        let room = match self.inner.get_joined_room(room_id) {
+            Some(room) => room,
+            None => {
+                log::debug!("cannot send read receipt to not joined room {}", room_id);
+                return false;
+            }
+        };
         let response = self
-            .instrument(UserRequest::CreateRoom, || async {
-                client.create_room(request).await
+            .instrument(UserRequest::SendReadReceipt, || async {
+                room.read_receipt(event_id).await
             })
             .await;
-        log::debug!("Create room with alias {} response: {:#?}", alias, response);
-
         match response {
-            Err(Api(Server(Known(RumaApiError::ClientApi(Error {
-                kind: ErrorKind::RoomInUse,
-                ..
-            }))))) => log::debug!("CreateRoom failed but it was already created"),
-            Err(e) => {
+            Ok(_) => true,
+            Err(Http(e)) => {
+                self.notify_error(UserRequest::SendReadReceipt, e).await;
+                false
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Send a malformed payload instead of the message, the server response is reported by payload and status
+    async fn send_fuzzed_message(&self, room_id: &RoomId) {
+        let access_token = match self.inner.access_token() {
+            Some(access_token) => access_token,
+            None => {
+                log::debug!("cannot send fuzzed message without access token");
+                return;
+            }
+        };
+        let payload = fuzzing::Payload::random();
+        let _permit = self.request_slot().await;
+        let status = fuzzing::send(
+            &self.direct_http_client,
+            &self.homeserver_url,
+            &access_token,
+            room_id.as_str(),
+            payload,
+        )
+        .await;
+        log::debug!("fuzzed {} message responded with {}", payload, status);
+        self.notify_event(Event::FuzzResponse(format!("{payload}: {status}")))
+            .await;
+    }
+
+    pub async fn send_sticker(&self, room_id: &RoomId) {
+        let content = json!({
+            "body": "sticker",
+            "url": STICKER_URL,
+            "info": { "mimetype": "image/png", "w": 256, "h": 256, "size": 16384 },
+        });
+        let event_id = self
+            .send_raw_event(room_id, UserRequest::SendSticker, "m.sticker", content)
+            .await;
+        if let Some(event_id) = event_id {
+            self.notify_event(Event::MessageSent(event_id)).await;
+        }
+    }
+
+    /// Send a `m.location` message with random coordinates, in stable and extensible events (MSC3488) formats.
+    pub async fn send_location(&self, room_id: &RoomId) {
+        let mut rng = rand::thread_rng();
+        let geo_uri = format!(
+            "geo:{:.6},{:.6}",
+            rng.gen_range(-90.0..90.0),
+            rng.gen_range(-180.0..180.0)
+        );
+        let content = json!({
+            "msgtype": "m.location",
+            "body": format!("Location at {geo_uri}"),
+            "geo_uri": geo_uri,
+            "org.matrix.msc3488.location": { "uri": geo_uri },
+            "org.matrix.msc3488.asset": { "type": "m.self" },
+        });
+        let event_id = self
+            .send_raw_event(
+                room_id,
+                UserRequest::SendLocation,
+                "m.room.message",
+                content,
+            )
+            .await;
+        if let Some(event_id) = event_id {
+            self.notify_event(Event::MessageSent(event_id)).await;
+        }
+    }
+
+    /// Send a configured custom event to the room, as a message or a state event depending on its kind.
+    pub async fn send_custom_event(&self, room_id: &RoomId, event: &CustomEvent, localpart: &str) {
+        let user_request = match event.kind {
+            CustomEventKind::Message => UserRequest::SendCustomEvent,
+            CustomEventKind::State => UserRequest::SendCustomStateEvent,
+        };
+        if self.fast_forward {
+            return self.fast_forward_request(user_request).await;
+        }
+        let room = match self.inner.get_joined_room(room_id) {
+            Some(room) => room,
+            None => {
+                log::debug!("cannot send custom event to not joined room {}", room_id);
+                return;
+            }
+        };
+
+        let content = render_template(&event.content, localpart);
+        let txn_id = TransactionId::new();
+        let response = match event.kind {
+            CustomEventKind::Message => {
+                self.instrument(user_request.clone(), || async {
+                    room.send_raw(content.clone(), &event.event_type, Some(&txn_id))
+                        .await
+                        .map(|response| response.event_id)
+                })
+                .await
+            }
+            CustomEventKind::State => {
+                let state_key = render_text(&event.state_key, localpart);
+                self.instrument(user_request.clone(), || async {
+                    room.send_state_event_raw(content.clone(), &event.event_type, &state_key)
+                        .await
+                        .map(|response| response.event_id)
+                })
+                .await
+            }
+        };
+
+        match response {
+            Ok(event_id) => log::debug!("custom event {} sent: {}", event.event_type, event_id),
+            Err(Http(e)) => self.notify_error(user_request, e).await,
+            _ => {}
+        }
+    }
+
+    /// Call the friend in the direct message room, returns the call id when the invite was sent.
+    pub async fn start_call(&self, room_id: &RoomId) -> Option<String> {
+        let call_id = fast_forward_id();
+        let content = json!({
+            "call_id": call_id,
+            "party_id": self.device_id(),
+            "version": CALL_VERSION,
+            "lifetime": CALL_LIFETIME_MS,
+            "offer": { "type": "offer", "sdp": CALL_SDP },
+        });
+        self.send_raw_event(room_id, UserRequest::CallInvite, "m.call.invite", content)
+            .await
+            .map(|_| call_id)
+    }
+
+    /// Answer the call and send the ICE candidates right after, like clients do when picking up.
+    pub async fn answer_call(&self, room_id: &RoomId, call_id: &str) {
+        let answer = json!({
+            "call_id": call_id,
+            "party_id": self.device_id(),
+            "version": CALL_VERSION,
+            "answer": { "type": "answer", "sdp": CALL_SDP },
+        });
+        if self
+            .send_raw_event(room_id, UserRequest::CallAnswer, "m.call.answer", answer)
+            .await
+            .is_none()
+        {
+            return;
+        }
+        let candidates = json!({
+            "call_id": call_id,
+            "party_id": self.device_id(),
+            "version": CALL_VERSION,
+            "candidates": [
+                { "candidate": CALL_CANDIDATE, "sdpMid": "0", "sdpMLineIndex": 0 },
+            ],
+        });
+        self.send_raw_event(
+            room_id,
+            UserRequest::CallCandidates,
+            "m.call.candidates",
+            candidates,
+        )
+        .await;
+    }
+
+    pub async fn hang_up(&self, room_id: &RoomId, call_id: &str) {
+        let content = json!({
+            "call_id": call_id,
+            "party_id": self.device_id(),
+            "version": CALL_VERSION,
+            "reason": "user_hangup",
+        });
+        self.send_raw_event(room_id, UserRequest::CallHangup, "m.call.hangup", content)
+            .await;
+    }
+
+    /// Send an `m.room_key_request` to all the devices of a random friend, as clients do when they cannot
+    /// decrypt a message. The request id is used to measure the delivery time through the friend sync loop.
+    pub async fn send_to_device(&self) {
+        if self.fast_forward {
+            return self.fast_forward_request(UserRequest::SendToDevice).await;
+        }
+        let friend = self
+            .direct_rooms
+            .lock()
+            .expect("lock not poisoned")
+            .iter()
+            .filter_map(|(friend_id, rooms)| {
+                rooms
+                    .first()
+                    .map(|room_id| (friend_id.clone(), room_id.clone()))
+            })
+            .choose(&mut rand::thread_rng());
+        let (friend_id, room_id) = match friend {
+            Some(friend) => friend,
+            None => {
+                log::debug!("user has no friends to send to-device messages");
+                return;
+            }
+        };
+
+        let request_id = fast_forward_id();
+        let content = json!({
+            "action": "request",
+            "requesting_device_id": self.device_id(),
+            "request_id": request_id,
+            "body": {
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "room_id": room_id,
+                "sender_key": get_random_string(),
+                "session_id": fast_forward_id(),
+            },
+        });
+        let messages = BTreeMap::from([(
+            friend_id,
+            BTreeMap::from([(DeviceIdOrAllDevices::AllDevices, raw_json(&content))]),
+        )]);
+        let event_type = ToDeviceEventType::RoomKeyRequest;
+        let txn_id = TransactionId::new();
+        let request = ToDeviceRequest::new_raw(&event_type, &txn_id, messages);
+
+        let response = self
+            .instrument(UserRequest::SendToDevice, || async {
+                self.inner.send(Traced(request.clone()), None).await
+            })
+            .await;
+        match response {
+            Ok(_) => self.notify_event(Event::ToDeviceSent(request_id)).await,
+            Err(e) => self.notify_error(UserRequest::SendToDevice, e).await,
+        }
+    }
+
+    /// Upload device keys and a batch of one-time keys, as E2EE clients do after login.
+    /// Keys are random, they cannot be used to encrypt but they load the key server the same way.
+    pub async fn upload_keys(&self) {
+        if self.fast_forward {
+            return self.fast_forward_request(UserRequest::UploadKeys).await;
+        }
+        let user_id = self.user_id().expect("user id should be present");
+        let device_id = self.device_id();
+        let signing_key_id = format!("ed25519:{device_id}");
+        let signatures = json!({ user_id.as_str(): { signing_key_id.as_str(): random_key(64) } });
+
+        let device_keys = json!({
+            "user_id": user_id,
+            "device_id": device_id,
+            "algorithms": ["m.olm.v1.curve25519-aes-sha2", "m.megolm.v1.aes-sha2"],
+            "keys": {
+                format!("curve25519:{device_id}"): random_key(32),
+                signing_key_id.as_str(): random_key(32),
+            },
+            "signatures": signatures,
+        });
+        let one_time_keys = (0..ONE_TIME_KEYS)
+            .map(|_| {
+                let key_id =
+                    OwnedDeviceKeyId::try_from(format!("signed_curve25519:{}", fast_forward_id()))
+                        .expect("one-time key id to be valid");
+                let key = json!({ "key": random_key(32), "signatures": signatures });
+                (key_id, raw_json(&key))
+            })
+            .collect();
+        let request = assign!(UploadKeysRequest::new(), {
+            device_keys: Some(raw_json(&device_keys)),
+            one_time_keys,
+        });
+        self.send_and_notify(request, UserRequest::UploadKeys).await;
+    }
+
+    /// Query the device keys of the friends and claim a one-time key of one of their devices,
+    /// as clients do before starting an encrypted session.
+    pub async fn query_keys(&self) {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::QueryKeys).await;
+            return self.fast_forward_request(UserRequest::ClaimKeys).await;
+        }
+        let device_keys = self
+            .direct_rooms
+            .lock()
+            .expect("lock not poisoned")
+            .keys()
+            .map(|friend_id| (friend_id.clone(), vec![]))
+            .collect::<BTreeMap<_, _>>();
+        if device_keys.is_empty() {
+            log::debug!("user has no friends to query keys");
+            return;
+        }
+        let request = assign!(QueryKeysRequest::new(), { device_keys });
+        let response = self
+            .instrument(UserRequest::QueryKeys, || async {
+                self.inner.send(Traced(request.clone()), None).await
+            })
+            .await;
+        let devices = match response {
+            Ok(response) => response
+                .device_keys
+                .into_iter()
+                .flat_map(|(friend_id, devices)| {
+                    devices
+                        .into_keys()
+                        .map(move |device_id| (friend_id.clone(), device_id))
+                })
+                .collect::<Vec<_>>(),
+            Err(e) => return self.notify_error(UserRequest::QueryKeys, e).await,
+        };
+
+        let (friend_id, device_id) = match devices.choose(&mut rand::thread_rng()) {
+            Some(device) => device.clone(),
+            None => {
+                log::debug!("friends have no devices with keys");
+                return;
+            }
+        };
+        let one_time_keys = BTreeMap::from([(
+            friend_id,
+            BTreeMap::from([(device_id, DeviceKeyAlgorithm::SignedCurve25519)]),
+        )]);
+        self.send_and_notify(ClaimKeysRequest::new(one_time_keys), UserRequest::ClaimKeys)
+            .await;
+    }
+
+    /// Upload master, self-signing and user-signing keys and store them encrypted in the secret storage
+    /// account data, as clients do when a new account sets up E2EE. The password auth is only sent
+    /// when the server asks for it.
+    pub async fn bootstrap_cross_signing(&self) {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::UploadCrossSigningKeys)
+                .await;
+            for _ in 0..SECRET_STORAGE_EVENTS {
+                self.fast_forward_request(UserRequest::UpdateSecretStorage)
+                    .await;
+            }
+            return;
+        }
+        let user_id = self.user_id().expect("user id should be present");
+        let cross_signing_key = |usage: &str| {
+            let key = random_key(32);
+            raw_json(&json!({
+                "user_id": user_id,
+                "usage": [usage],
+                "keys": { format!("ed25519:{key}"): key },
+                "signatures": { user_id.as_str(): { format!("ed25519:{key}"): random_key(64) } },
+            }))
+        };
+        let master_key = cross_signing_key("master");
+        let self_signing_key = cross_signing_key("self_signing");
+        let user_signing_key = cross_signing_key("user_signing");
+
+        let mut authenticate = false;
+        let mut session = None;
+        loop {
+            let auth = authenticate.then(|| {
+                let identifier = UserIdentifier::UserIdOrLocalpart(user_id.as_str());
+                AuthData::Password(assign!(Password::new(identifier, &self.password), {
+                    session: session.as_deref()
+                }))
+            });
+            let request = assign!(UploadSigningKeysRequest::new(), {
+                auth,
+                master_key: Some(master_key.clone()),
+                self_signing_key: Some(self_signing_key.clone()),
+                user_signing_key: Some(user_signing_key.clone()),
+            });
+            let response = self
+                .instrument(UserRequest::UploadCrossSigningKeys, || async {
+                    self.inner.send(Traced(request.clone()), None).await
+                })
+                .await;
+            match response {
+                Ok(_) => break,
+                Err(UiaaError(Server(Known(UiaaResponse::AuthResponse(info)))))
+                    if !authenticate =>
+                {
+                    authenticate = true;
+                    session = info.session;
+                }
+                Err(e) => {
+                    return self
+                        .notify_error(UserRequest::UploadCrossSigningKeys, e)
+                        .await
+                }
+            }
+        }
+
+        let key_id = fast_forward_id();
+        let encrypted = || json!({ "encrypted": { key_id.as_str(): encrypted_secret() } });
+        let events = [
+            (
+                format!("m.secret_storage.key.{key_id}"),
+                json!({
+                    "algorithm": "m.secret_storage.v1.aes-hmac-sha2",
+                    "iv": random_key(16),
+                    "mac": random_key(32),
+                }),
+            ),
+            (
+                "m.secret_storage.default_key".to_string(),
+                json!({ "key": key_id }),
+            ),
+            ("m.cross_signing.master".to_string(), encrypted()),
+            ("m.cross_signing.self_signing".to_string(), encrypted()),
+            ("m.cross_signing.user_signing".to_string(), encrypted()),
+        ];
+        for (event_type, content) in events {
+            let event_type = GlobalAccountDataEventType::from(event_type.as_str());
+            let response = self
+                .instrument(UserRequest::UpdateSecretStorage, || async {
+                    self.inner
+                        .account()
+                        .set_account_data_raw(event_type.clone(), raw_json(&content))
+                        .await
+                })
+                .await;
+            if let Err(Http(e)) = response {
+                return self.notify_error(UserRequest::UpdateSecretStorage, e).await;
+            }
+        }
+    }
+
+    /// Register an HTTP pusher for the current device pointing at the push gateway.
+    pub async fn set_pusher(&self, gateway_url: &str) {
+        if self.fast_forward {
+            return self.fast_forward_request(UserRequest::SetPusher).await;
+        }
+        let access_token = match self.inner.access_token() {
+            Some(access_token) => access_token,
+            None => {
+                log::debug!("cannot set pusher without access token");
+                return;
+            }
+        };
+        let device_id = self.device_id();
+
+        let response = self
+            .instrument(UserRequest::SetPusher, || {
+                pusher::set(
+                    &self.direct_http_client,
+                    &self.homeserver_url,
+                    &access_token,
+                    &device_id,
+                    gateway_url,
+                )
+            })
+            .await;
+
+        if let Err(e) = response {
+            self.notify_error(UserRequest::SetPusher, e).await;
+        }
+    }
+
+    /// Write a settings blob or the recent rooms (breadcrumbs) to the global account data.
+    pub async fn update_account_data(&self, recent_rooms: &[OwnedRoomId], blob_size: usize) {
+        if self.fast_forward {
+            return self
+                .fast_forward_request(UserRequest::UpdateAccountData)
+                .await;
+        }
+        let (event_type, content) = if !recent_rooms.is_empty() && rand::thread_rng().gen() {
+            (
+                BREADCRUMBS_EVENT_TYPE,
+                json!({ "recent_rooms": recent_rooms }),
+            )
+        } else {
+            (
+                SETTINGS_EVENT_TYPE,
+                json!({ "settings": random_blob(blob_size) }),
+            )
+        };
+        let event_type = GlobalAccountDataEventType::from(event_type);
+
+        let response = self
+            .instrument(UserRequest::UpdateAccountData, || async {
+                self.inner
+                    .account()
+                    .set_account_data_raw(event_type.clone(), raw_json(&content))
+                    .await
+            })
+            .await;
+        if let Err(Http(e)) = response {
+            self.notify_error(UserRequest::UpdateAccountData, e).await;
+        }
+    }
+
+    /// Add the user to the `m.ignored_user_list` account data, or remove it when it's already ignored
+    pub async fn toggle_ignored_user(&self, user_id: &UserId) {
+        if self.fast_forward {
+            return self
+                .fast_forward_request(UserRequest::UpdateIgnoredUsers)
+                .await;
+        }
+        let (ignoring, ignored_users) = {
+            let ignored_users = self.ignored_users.lock().expect("lock not poisoned");
+            let ignoring = !ignored_users.contains_key(user_id);
+            let mut content = ignored_users
+                .keys()
+                .filter(|ignored| *ignored != user_id)
+                .map(|ignored| (ignored.to_string(), json!({})))
+                .collect::<serde_json::Map<_, _>>();
+            if ignoring {
+                content.insert(user_id.to_string(), json!({}));
+            }
+            (ignoring, content)
+        };
+        let content = json!({ "ignored_users": ignored_users });
+        let response = self
+            .instrument(UserRequest::UpdateIgnoredUsers, || async {
+                self.inner
+                    .account()
+                    .set_account_data_raw(
+                        GlobalAccountDataEventType::IgnoredUserList,
+                        raw_json(&content),
+                    )
+                    .await
+            })
+            .await;
+        match response {
+            Ok(_) => {
+                {
+                    let mut ignored_users = self.ignored_users.lock().expect("lock not poisoned");
+                    if ignoring {
+                        ignored_users.insert(user_id.to_owned(), time_now());
+                    } else {
+                        ignored_users.remove(user_id);
+                    }
+                }
+                self.notify_event(Event::IgnoredUsersUpdated(ignoring))
+                    .await;
+            }
+            Err(Http(e)) => self.notify_error(UserRequest::UpdateIgnoredUsers, e).await,
+            Err(_) => {}
+        }
+    }
+
+    /// Tag the room as favourite or low priority, or remove its tag when it's already tagged
+    pub async fn toggle_room_tag(&self, room_id: &RoomId) {
+        let tagged = self
+            .room_tags
+            .lock()
+            .expect("lock not poisoned")
+            .get(room_id)
+            .copied();
+        if self.fast_forward {
+            let user_request = match tagged {
+                Some(_) => UserRequest::UntagRoom,
+                None => UserRequest::TagRoom,
+            };
+            return self.fast_forward_request(user_request).await;
+        }
+        let user_id = self.user_id().expect("user id should be present");
+        match tagged {
+            Some(tag) => {
+                let request = DeleteTagRequest::new(user_id, room_id, tag);
+                if self.send_and_notify(request, UserRequest::UntagRoom).await {
+                    self.room_tags
+                        .lock()
+                        .expect("lock not poisoned")
+                        .remove(room_id);
+                }
+            }
+            None => {
+                let tag = ROOM_TAGS
+                    .choose(&mut rand::thread_rng())
+                    .expect("tags not empty");
+                let tag_info = assign!(TagInfo::new(), { order: Some(rand::thread_rng().gen()) });
+                let request = CreateTagRequest::new(user_id, room_id, tag, tag_info);
+                if self.send_and_notify(request, UserRequest::TagRoom).await {
+                    self.room_tags
+                        .lock()
+                        .expect("lock not poisoned")
+                        .insert(room_id.to_owned(), tag);
+                }
+            }
+        }
+    }
+
+    /// Write a preferences blob to the account data of the room.
+    pub async fn update_room_account_data(&self, room_id: &RoomId, blob_size: usize) {
+        if self.fast_forward {
+            return self
+                .fast_forward_request(UserRequest::UpdateRoomAccountData)
+                .await;
+        }
+        let user_id = self.user_id().expect("user id should be present");
+        let content = json!({ "preferences": random_blob(blob_size) });
+        let request = RoomAccountDataRequest::new_raw(
+            raw_json(&content),
+            RoomAccountDataEventType::from(ROOM_PREFERENCES_EVENT_TYPE),
+            room_id,
+            user_id,
+        );
+        self.send_and_notify(request, UserRequest::UpdateRoomAccountData)
+            .await;
+    }
+
+    pub async fn search_user(&self, search_term: &str) {
+        if self.fast_forward {
+            return self.fast_forward_request(UserRequest::SearchUser).await;
+        }
+        let request = assign!(SearchUsersRequest::new(search_term), { limit: uint!(10) });
+        self.send_and_notify(request, UserRequest::SearchUser).await;
+    }
+
+    /// Full-text search of the term in the messages of the rooms.
+    pub async fn search_messages(&self, rooms: &[OwnedRoomId], search_term: &str) {
+        if self.fast_forward {
+            return self.fast_forward_request(UserRequest::SearchMessages).await;
+        }
+        let filter = assign!(RoomEventFilter::default(), { rooms: Some(rooms) });
+        let criteria = assign!(Criteria::new(search_term), { filter });
+        let categories = assign!(Categories::new(), { room_events: Some(criteria) });
+        self.send_and_notify(SearchRequest::new(categories), UserRequest::SearchMessages)
+            .await;
+    }
+
+    /// Fetch one of the last messages received as a permalink click would do, either alone (`/event`)
+    /// or with the events around it (`/context`).
+    pub async fn fetch_event(&self) {
+        let with_context = rand::thread_rng().gen_bool(0.5);
+        let user_request = if with_context {
+            UserRequest::GetEventContext
+        } else {
+            UserRequest::GetEvent
+        };
+        if self.fast_forward {
+            return self.fast_forward_request(user_request).await;
+        }
+        let seen_event = self
+            .seen_events
+            .lock()
+            .expect("lock not poisoned")
+            .iter()
+            .choose(&mut rand::thread_rng())
+            .cloned();
+        let (room_id, event_id) = match seen_event {
+            Some(seen_event) => seen_event,
+            None => {
+                log::debug!("user has not received messages to fetch");
+                return;
+            }
+        };
+        if with_context {
+            let request = assign!(ContextRequest::new(&room_id, &event_id), { limit: uint!(10) });
+            self.send_and_notify(request, user_request).await;
+        } else {
+            let request = RoomEventRequest::new(&room_id, &event_id);
+            self.send_and_notify(request, user_request).await;
+        }
+    }
+
+    pub async fn preview_url(&self, url: &str) {
+        if self.fast_forward {
+            return self.fast_forward_request(UserRequest::PreviewUrl).await;
+        }
+        self.send_and_notify(PreviewUrlRequest::new(url), UserRequest::PreviewUrl)
+            .await;
+    }
+
+    fn device_id(&self) -> String {
+        self.inner
+            .device_id()
+            .map_or_else(fast_forward_id, ToString::to_string)
+    }
+
+    /// Send a message-like event with a raw content, returns true when the event was sent.
+    async fn send_raw_event(
+        &self,
+        room_id: &RoomId,
+        user_request: UserRequest,
+        event_type: &str,
+        content: Value,
+    ) -> Option<String> {
+        if self.fast_forward {
+            self.fast_forward_request(user_request).await;
+            return Some(format!("${}", fast_forward_id()));
+        }
+        let room = match self.inner.get_joined_room(room_id) {
+            Some(room) => room,
+            None => {
+                log::debug!("cannot send {} to not joined room {}", event_type, room_id);
+                return None;
+            }
+        };
+        let txn_id = TransactionId::new();
+        let response = self
+            .instrument(user_request.clone(), || async {
+                room.send_raw(content.clone(), event_type, Some(&txn_id))
+                    .await
+            })
+            .await;
+
+        match response {
+            Ok(response) => Some(response.event_id.to_string()),
+            Err(Http(e)) => {
+                self.notify_error(user_request, e).await;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Create a direct message room inviting the friend, returns true when the invite was sent.
+    /// No room is created when there is already a direct message room with the friend.
+    pub async fn add_friend(&self, friend_id: &UserId) -> bool {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::CreateRoom).await;
+            self.notify_sync(SyncEvent::RoomCreated(fast_forward_room_id()))
+                .await;
+            return true;
+        }
+        if let Some(room_id) = self.direct_room_with(friend_id) {
+            log::debug!("already friend of {} in room {}", friend_id, room_id);
+            return false;
+        }
+        let client = &self.inner;
+        // try to create room (maybe it already exists, in that case we ignore that)
+        let user_id = client.user_id().expect("user id should be present");
+        let alias = get_room_alias(user_id, friend_id);
+        let invites = [friend_id.to_owned()];
+        let options = &self.direct_room_options;
+        let initial_state = options.initial_state(None);
+        let request = assign!(CreateRoomRequest::new(), {
+            room_alias_name: Some(&alias),
+            invite: &invites,
+            is_direct: true,
+            preset: Some(options.preset.clone()),
+            room_version: options.room_version.as_ref(),
+            initial_state: &initial_state
+        });
+        let response = self
+            .instrument(UserRequest::CreateRoom, || async {
+                client.create_room(request.clone()).await
+            })
+            .await;
+        log::debug!("Create room with alias {} response: {:#?}", alias, response);
+
+        match response {
+            Err(Api(Server(Known(RumaApiError::ClientApi(Error {
+                kind: ErrorKind::RoomInUse,
+                ..
+            }))))) => {
+                log::debug!("CreateRoom failed but it was already created");
+                false
+            }
+            Err(e) => {
                 log::debug!("CreateRoom failed! {}", e);
                 self.notify_error(UserRequest::CreateRoom, e).await;
+                false
+            }
+            Ok(response) => {
+                log::debug!("room created and invite sent to {}!", friend_id);
+                self.notify_event(Event::InviteSent(membership_key(
+                    &response.room_id,
+                    friend_id,
+                )))
+                .await;
+                self.add_direct_room(friend_id, &response.room_id).await;
+                self.notify_sync(SyncEvent::RoomCreated(response.room_id))
+                    .await;
+                true
+            }
+        }
+    }
+
+    ///
+    /// Create public channel, restricted channels can be joined by the members of `space_id`.
+    /// With a permutation, it's used instead of the configured history visibility and join rule.
+    /// Returns the channel id when it was created.
+    ///
+    pub async fn create_channel(
+        &self,
+        channel_name: String,
+        space_id: Option<&RoomId>,
+        permutation: Option<&Permutation>,
+    ) -> Option<OwnedRoomId> {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::CreateChannel).await;
+            let room_id = fast_forward_room_id();
+            self.notify_sync(SyncEvent::ChannelCreated(room_id.clone()))
+                .await;
+            return Some(room_id);
+        }
+        let options = &self.channel_options;
+        let initial_state = match permutation {
+            Some(permutation) => options.permuted_initial_state(permutation, space_id),
+            None => options.initial_state(space_id),
+        };
+        let request = assign!(CreateRoomRequest::new(), {
+            room_alias_name: Some(&channel_name),
+            preset: Some(options.preset.clone()),
+            room_version: options.room_version.as_ref(),
+            initial_state: &initial_state
+        });
+        let response = self
+            .instrument(UserRequest::CreateChannel, || async {
+                self.inner.create_room(request.clone()).await
+            })
+            .await;
+
+        match response {
+            Err(Api(Server(Known(RumaApiError::ClientApi(Error {
+                kind: ErrorKind::RoomInUse,
+                ..
+            }))))) => {
+                log::debug!("CreateChannel failed but it was already created");
+                None
+            }
+            Err(e) => {
+                log::debug!("CreateChannel failed! {}", e);
+                self.notify_error(UserRequest::CreateChannel, e).await;
+                None
+            }
+            Ok(response) => {
+                log::debug!("channel created succesfully, {}", response.room_id);
+                if self.directory_enabled {
+                    let request = RoomVisibilityRequest::new(&response.room_id, Visibility::Public);
+                    self.send_and_notify(request, UserRequest::PublishRoom)
+                        .await;
+                }
+                if let Some(permutation) = permutation {
+                    self.notify_event(Event::ChannelPermuted((
+                        response.room_id.clone(),
+                        permutation.name(),
+                    )))
+                    .await;
+                }
+                Some(response.room_id)
+            }
+        }
+    }
+
+    /// First page of the public rooms directory, with the rooms alias when they have one.
+    pub async fn public_rooms(&self) -> Vec<(OwnedRoomId, Option<OwnedRoomAliasId>)> {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::PublicRooms).await;
+            return vec![];
+        }
+        let request = assign!(PublicRoomsRequest::new(), { limit: Some(uint!(20)) });
+        let response = self
+            .instrument(UserRequest::PublicRooms, || async {
+                self.inner.send(Traced(request.clone()), None).await
+            })
+            .await;
+
+        match response {
+            Ok(response) => response
+                .chunk
+                .into_iter()
+                .map(|room| (room.room_id, room.canonical_alias))
+                .collect(),
+            Err(e) => {
+                self.notify_error(UserRequest::PublicRooms, e).await;
+                vec![]
+            }
+        }
+    }
+
+    /// Create a public space with the channels as children, returns the space id when it was created.
+    pub async fn create_space(&self, channels: &[OwnedRoomId]) -> Option<OwnedRoomId> {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::CreateSpace).await;
+            for _ in channels {
+                self.fast_forward_request(UserRequest::AddSpaceChild).await;
+            }
+            return Some(fast_forward_room_id());
+        }
+        let user_id = self.user_id().expect("user id should be present");
+        let creation_content =
+            assign!(CreationContent::new(), { room_type: Some(MatrixRoomType::Space) });
+        let creation_content =
+            Raw::new(&creation_content).expect("creation content to be serializable");
+        let request = assign!(CreateRoomRequest::new(), { creation_content: Some(creation_content), preset: Some(RoomPreset::PublicChat) });
+        let response = self
+            .instrument(UserRequest::CreateSpace, || async {
+                self.inner.create_room(request.clone()).await
+            })
+            .await;
+
+        let space_id = match response {
+            Ok(response) => response.room_id,
+            Err(e) => {
+                self.notify_error(UserRequest::CreateSpace, e).await;
+                return None;
+            }
+        };
+        log::debug!(
+            "space created {} with {} children",
+            space_id,
+            channels.len()
+        );
+
+        let child = assign!(SpaceChildEventContent::new(), { via: Some(vec![user_id.server_name().to_owned()]) });
+        for channel_id in channels {
+            let request = StateEventRequest::new(&space_id, channel_id.as_str(), &child)
+                .expect("space child to be serializable");
+            self.send_and_notify(request, UserRequest::AddSpaceChild)
+                .await;
+        }
+        Some(space_id)
+    }
+
+    /// Rooms in the space hierarchy, excluding the space itself.
+    pub async fn space_hierarchy(&self, space_id: &RoomId) -> Vec<OwnedRoomId> {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::SpaceHierarchy).await;
+            return vec![];
+        }
+        let request = SpaceHierarchyRequest::new(space_id);
+        let response = self
+            .instrument(UserRequest::SpaceHierarchy, || async {
+                self.inner.send(Traced(request.clone()), None).await
+            })
+            .await;
+
+        match response {
+            Ok(response) => response
+                .rooms
+                .into_iter()
+                .map(|room| room.room_id)
+                .filter(|room_id| room_id != space_id)
+                .collect(),
+            Err(e) => {
+                self.notify_error(UserRequest::SpaceHierarchy, e).await;
+                vec![]
+            }
+        }
+    }
+
+    /// Kick, ban or promote to moderator a random member of one of the channels where the user is admin.
+    pub async fn moderate(&self, channels: &[OwnedRoomId]) {
+        if self.fast_forward {
+            let user_request = [
+                UserRequest::KickUser,
+                UserRequest::BanUser,
+                UserRequest::UpdatePowerLevels,
+            ]
+            .choose(&mut rand::thread_rng())
+            .cloned()
+            .expect("moderation requests not to be empty");
+            return self.fast_forward_request(user_request).await;
+        }
+        let user_id = self.user_id().expect("user id should be present");
+
+        let mut moderated = vec![];
+        for channel_id in channels {
+            let room = match self.inner.get_joined_room(channel_id) {
+                Some(room) if self.is_admin(channel_id).await => room,
+                _ => continue,
+            };
+            let members = room.joined_members_no_sync().await.unwrap_or_default();
+            moderated.extend(
+                members
+                    .into_iter()
+                    .filter(|member| member.user_id() != user_id)
+                    .map(|member| (channel_id.clone(), member.user_id().to_owned())),
+            );
+        }
+
+        let (room_id, member_id) = match moderated.choose(&mut rand::thread_rng()) {
+            Some(moderated) => moderated,
+            None => {
+                log::debug!("user {} has no channel members to moderate", user_id);
+                return;
+            }
+        };
+        match rand::thread_rng().gen_range(0..3) {
+            0 => {
+                let request = KickUserRequest::new(room_id, member_id);
+                self.send_and_notify(request, UserRequest::KickUser).await;
+            }
+            1 => {
+                let request = BanUserRequest::new(room_id, member_id);
+                self.send_and_notify(request, UserRequest::BanUser).await;
+            }
+            _ => {
+                // start from the current power levels so the rest of the users and the event levels are kept
+                let request =
+                    StateEventForKeyRequest::new(room_id, StateEventType::RoomPowerLevels, "");
+                let response = self
+                    .instrument(UserRequest::GetPowerLevels, || async {
+                        self.inner.send(Traced(request.clone()), None).await
+                    })
+                    .await;
+                let mut power_levels = match response {
+                    Ok(response) => {
+                        match response
+                            .content
+                            .deserialize_as::<RoomPowerLevelsEventContent>()
+                        {
+                            Ok(power_levels) => power_levels,
+                            Err(e) => {
+                                log::debug!("invalid power levels in room {} - {}", room_id, e);
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        self.notify_error(UserRequest::GetPowerLevels, e).await;
+                        return;
+                    }
+                };
+                power_levels.users.insert(member_id.clone(), int!(50));
+                let request = StateEventRequest::new(room_id, "", &power_levels)
+                    .expect("power levels to be serializable");
+                self.send_and_notify(request, UserRequest::UpdatePowerLevels)
+                    .await;
+            }
+        }
+    }
+
+    /// Change the name, topic or avatar of the room, a state event received by all its members.
+    /// Only admins of the room are allowed to change them.
+    pub async fn update_room_details(&self, room_id: &RoomId) {
+        if self.fast_forward {
+            return self
+                .fast_forward_request(UserRequest::UpdateRoomDetails)
+                .await;
+        }
+        if !self.is_admin(room_id).await {
+            log::debug!("cannot update details of room {}, not admin", room_id);
+            return;
+        }
+        let (event_type, content) = match rand::thread_rng().gen_range(0..3) {
+            0 => ("m.room.name", json!({ "name": get_random_string() })),
+            1 => ("m.room.topic", json!({ "topic": get_random_string() })),
+            _ => {
+                let avatar = rand::thread_rng().gen_range(0..100);
+                (
+                    "m.room.avatar",
+                    json!({ "url": format!("{ROOM_AVATAR_URL}_{avatar}") }),
+                )
+            }
+        };
+        let request = StateEventRequest::new_raw(
+            room_id,
+            StateEventType::from(event_type),
+            "",
+            raw_json(&content),
+        );
+        self.send_and_notify(request, UserRequest::UpdateRoomDetails)
+            .await;
+    }
+
+    /// Upgrade a channel the user administers, returns the replacement room when it was upgraded.
+    pub async fn upgrade_room(&self, room_id: &RoomId) -> Option<OwnedRoomId> {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::UpgradeRoom).await;
+            self.notify_event(Event::RoomUpgraded).await;
+            return Some(fast_forward_room_id());
+        }
+        if !self.is_admin(room_id).await {
+            log::debug!("cannot upgrade room {}, not admin", room_id);
+            return None;
+        }
+        let request = UpgradeRoomRequest::new(room_id, self.upgrade_version.clone());
+        let response = self
+            .instrument(UserRequest::UpgradeRoom, || async {
+                self.inner.send(Traced(request.clone()), None).await
+            })
+            .await;
+        match response {
+            Ok(response) => {
+                log::debug!("room {} upgraded to {}", room_id, response.replacement_room);
+                self.notify_event(Event::RoomUpgraded).await;
+                Some(response.replacement_room)
+            }
+            Err(e) => {
+                self.notify_error(UserRequest::UpgradeRoom, e).await;
+                None
+            }
+        }
+    }
+
+    ///
+    /// Join the room replacing a tombstoned one, reporting the time since the tombstone was sent, and check the
+    /// replacement links back to it so the history is continuous. Returns true when the user joined it.
+    ///
+    pub async fn follow_tombstone(
+        &self,
+        room_id: &RoomId,
+        replacement_room: &RoomId,
+        tombstoned_at: u128,
+    ) -> bool {
+        let request = JoinRoomRequest::new(replacement_room);
+        if !self
+            .send_and_notify(request, UserRequest::FollowTombstone)
+            .await
+        {
+            return false;
+        }
+        if self.fast_forward {
+            return true;
+        }
+        let latency = Duration::from_millis(time_now().saturating_sub(tombstoned_at) as u64);
+        self.notify_event(Event::TombstoneFollowed(latency)).await;
+
+        let request =
+            StateEventForKeyRequest::new(replacement_room, StateEventType::RoomCreate, "");
+        let response = self
+            .instrument(UserRequest::GetRoomPredecessor, || async {
+                self.inner.send(Traced(request.clone()), None).await
+            })
+            .await;
+        match response {
+            Ok(response) => {
+                let predecessor =
+                    response
+                        .content
+                        .deserialize_as::<Value>()
+                        .ok()
+                        .and_then(|content| {
+                            content["predecessor"]["room_id"]
+                                .as_str()
+                                .map(ToString::to_string)
+                        });
+                let continuous = predecessor.as_deref() == Some(room_id.as_str());
+                if !continuous {
+                    log::debug!(
+                        "room {} doesn't link back to {}, predecessor: {:?}",
+                        replacement_room,
+                        room_id,
+                        predecessor
+                    );
+                }
+                self.notify_event(Event::PredecessorChecked(continuous))
+                    .await;
+            }
+            Err(e) => self.notify_error(UserRequest::GetRoomPredecessor, e).await,
+        }
+        true
+    }
+
+    pub fn is_joined(&self, room_id: &RoomId) -> bool {
+        self.inner.get_joined_room(room_id).is_some()
+    }
+
+    /// Ask to join a room with `knock` join rule, its admins invite the user.
+    pub async fn knock(&self, room_id: &RoomId) {
+        let request = KnockRequest::new(<&RoomOrAliasId>::from(room_id));
+        self.send_and_notify(request, UserRequest::Knock).await;
+    }
+
+    /// Invite the user who knocked, only when the current user is admin of the room.
+    pub async fn accept_knock(&self, room_id: &RoomId, knocker_id: &UserId) {
+        if !self.fast_forward && !self.is_admin(room_id).await {
+            return;
+        }
+        let request = InviteUserRequest::new(
+            room_id,
+            InvitationRecipient::UserId {
+                user_id: knocker_id,
+            },
+        );
+        if self
+            .send_and_notify(request, UserRequest::AcceptKnock)
+            .await
+        {
+            self.notify_event(Event::InviteSent(membership_key(room_id, knocker_id)))
+                .await;
+        }
+    }
+
+    /// Create a private room and invite the victims one by one, as spammers do.
+    pub async fn spam_invites(&self, victims: &[OwnedUserId]) {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::CreateRoom).await;
+            for _ in victims {
+                self.fast_forward_request(UserRequest::SpamInvite).await;
+            }
+            return;
+        }
+        let request = assign!(CreateRoomRequest::new(), {
+            preset: Some(RoomPreset::PrivateChat),
+        });
+        let response = self
+            .instrument(UserRequest::CreateRoom, || async {
+                self.inner.create_room(request.clone()).await
+            })
+            .await;
+        let room_id = match response {
+            Ok(response) => response.room_id,
+            Err(e) => {
+                self.notify_error(UserRequest::CreateRoom, e).await;
+                return;
             }
-            Ok(response) => {
-                log::debug!("room created and invite sent to {}!", friend_id);
-                self.notify_sync(SyncEvent::RoomCreated(response.room_id))
+        };
+        for victim in victims {
+            let request =
+                InviteUserRequest::new(&room_id, InvitationRecipient::UserId { user_id: victim });
+            if self.send_and_notify(request, UserRequest::SpamInvite).await {
+                self.notify_event(Event::InviteSent(membership_key(&room_id, victim)))
                     .await;
             }
         }
     }
 
-    pub async fn create_channel(&self, channel_name: String) {
-        let request = assign!(CreateRoomRequest::new(), { room_alias_name: Some(&channel_name), preset: Some(RoomPreset::PublicChat) });
+    ///
+    /// Join the room forked by the forward extremities scenario, the first forker creates it allowing every member
+    /// to send state events. Returns none while it cannot be joined, ex: another forker is creating it.
+    ///
+    /// # Panics
+    ///
+    /// If the alias name is not a valid alias localpart.
+    ///
+    pub async fn join_forked_room(&self, alias_name: &str) -> Option<OwnedRoomId> {
+        self.join_or_create_room(alias_name, Some(json!({ "state_default": 0 })))
+            .await
+    }
+
+    /// Join the public room of the alias, creating it with the power levels when it's not found.
+    /// Returns none while it cannot be joined, ex: another user is creating it.
+    ///
+    /// # Panics
+    ///
+    /// If the alias name is not a valid alias localpart.
+    ///
+    pub async fn join_or_create_room(
+        &self,
+        alias_name: &str,
+        power_levels: Option<Value>,
+    ) -> Option<OwnedRoomId> {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::JoinRoomByAlias)
+                .await;
+            return Some(fast_forward_room_id());
+        }
+        let user_id = self.user_id()?;
+        let alias = RoomAliasId::parse(format!("#{alias_name}:{}", user_id.server_name()))
+            .expect("room alias to be valid");
+        let request = JoinRoomByAliasRequest::new(<&RoomOrAliasId>::from(alias.as_ref()));
         let response = self
-            .instrument(UserRequest::CreateChannel, || async {
-                self.inner.create_room(request).await
+            .instrument(UserRequest::JoinRoomByAlias, || async {
+                self.inner.send(Traced(request.clone()), None).await
             })
             .await;
+        match response {
+            Ok(response) => return Some(response.room_id),
+            Err(Api(Server(Known(RumaApiError::ClientApi(Error {
+                kind: ErrorKind::NotFound,
+                ..
+            }))))) => log::debug!("room {} not found, creating it", alias),
+            Err(e) => {
+                self.notify_error(UserRequest::JoinRoomByAlias, e).await;
+                return None;
+            }
+        }
 
+        let request = assign!(CreateRoomRequest::new(), {
+            room_alias_name: Some(alias_name),
+            preset: Some(RoomPreset::PublicChat),
+            power_level_content_override: power_levels.as_ref().map(raw_json),
+        });
+        let response = self
+            .instrument(UserRequest::CreateRoom, || async {
+                self.inner.create_room(request.clone()).await
+            })
+            .await;
         match response {
+            Ok(response) => Some(response.room_id),
             Err(Api(Server(Known(RumaApiError::ClientApi(Error {
                 kind: ErrorKind::RoomInUse,
                 ..
-            }))))) => log::debug!("CreateChannel failed but it was already created"),
-            Err(e) => {
-                log::debug!("CreateChannel failed! {}", e);
-                self.notify_error(UserRequest::CreateChannel, e).await;
+            }))))) => {
+                log::debug!("room {} created by another user", alias);
+                None
             }
-            Ok(response) => {
-                log::debug!("channel created succesfully, {}", response.room_id);
+            Err(e) => {
+                self.notify_error(UserRequest::CreateRoom, e).await;
+                None
             }
         }
     }
 
+    /// Send state events to the room at the same time, half of them to the same state key so they conflict.
+    pub async fn fork_room_state(&self, room_id: &RoomId, state_events: usize) {
+        let own_state_key = self.user_id().map(ToString::to_string).unwrap_or_default();
+        let requests = (0..state_events)
+            .map(|i| {
+                let state_key = if i % 2 == 0 {
+                    ""
+                } else {
+                    own_state_key.as_str()
+                };
+                StateEventRequest::new_raw(
+                    room_id,
+                    StateEventType::from(FORK_EVENT_TYPE),
+                    state_key,
+                    raw_json(&json!({ "fork": get_random_string() })),
+                )
+            })
+            .collect::<Vec<_>>();
+        futures::future::join_all(
+            requests
+                .into_iter()
+                .map(|request| self.send_and_notify(request, UserRequest::ForkRoomState)),
+        )
+        .await;
+    }
+
+    /// Leave the room and join it again, every membership change is another state event.
+    pub async fn flap_membership(&self, room_id: &RoomId) {
+        let request = LeaveRoomRequest::new(room_id);
+        if self.send_and_notify(request, UserRequest::LeaveRoom).await {
+            let request = JoinRoomRequest::new(room_id);
+            self.send_and_notify(request, UserRequest::JoinRoom).await;
+        }
+    }
+
+    /// Message to the forked room, its latency is reported per tick to show how it degrades.
+    pub async fn send_forked_room_message(&self, room_id: &RoomId) {
+        let txn_id = TransactionId::new();
+        let content = json!({ "msgtype": "m.text", "body": get_random_string() });
+        let request = MessageEventRequest::new_raw(
+            room_id,
+            &txn_id,
+            MessageLikeEventType::RoomMessage,
+            raw_json(&content),
+        );
+        self.send_and_notify(request, UserRequest::SendForkedRoomMessage)
+            .await;
+    }
+
+    async fn is_admin(&self, room_id: &RoomId) -> bool {
+        let (room, user_id) = match (self.inner.get_joined_room(room_id), self.user_id()) {
+            (Some(room), Some(user_id)) => (room, user_id),
+            _ => return false,
+        };
+        matches!(room.get_member_no_sync(user_id).await, Ok(Some(member)) if member.power_level() >= 100)
+    }
+
+    pub async fn join_room_by_alias(&self, alias: &RoomAliasId) {
+        let request = JoinRoomByAliasRequest::new(<&RoomOrAliasId>::from(alias));
+        self.send_and_notify(request, UserRequest::JoinRoomByAlias)
+            .await;
+    }
+
+    /// Join room, returns true when the user joined it.
     pub async fn join_room(
         &self,
         room_id: &RoomId,
         room_type: RoomType,
         allow_get_channel_members: bool,
-    ) {
+    ) -> bool {
         let request = JoinRoomRequest::new(room_id);
-        self.send_and_notify(request, UserRequest::JoinRoom).await;
+        let joined = self
+            .timed_room_request(
+                room_id,
+                UserRequest::JoinRoom,
+                self.send_and_notify(request, UserRequest::JoinRoom),
+            )
+            .await;
+        if let (true, Some(user_id)) = (joined, self.user_id()) {
+            self.notify_event(Event::JoinSent(membership_key(room_id, user_id)))
+                .await;
+        }
         if allow_get_channel_members {
             if let RoomType::Channel = room_type {
                 self.notify_sync(SyncEvent::GetChannelMembers(room_id.to_owned()))
                     .await;
             }
         }
+        joined
+    }
+
+    /// Random friend the user has a direct message room with, the ones whose messages it receives
+    pub fn pick_direct_friend(&self) -> Option<OwnedUserId> {
+        self.direct_rooms
+            .lock()
+            .expect("lock not poisoned")
+            .keys()
+            .choose(&mut rand::thread_rng())
+            .cloned()
+    }
+
+    fn direct_room_with(&self, friend_id: &UserId) -> Option<OwnedRoomId> {
+        self.direct_rooms
+            .lock()
+            .expect("lock not poisoned")
+            .get(friend_id)
+            .and_then(|rooms| rooms.first().cloned())
+    }
+
+    /// Direct message rooms are read from the account data received in the initial sync.
+    async fn load_direct_rooms(&self) {
+        let direct_rooms = match self
+            .inner
+            .account()
+            .account_data::<DirectEventContent>()
+            .await
+        {
+            Ok(Some(raw)) => raw.deserialize(),
+            Ok(None) => return,
+            Err(e) => {
+                log::debug!("couldn't read m.direct account data: {}", e);
+                return;
+            }
+        };
+        match direct_rooms {
+            Ok(direct_rooms) => {
+                *self.direct_rooms.lock().expect("lock not poisoned") = direct_rooms
+            }
+            Err(e) => log::debug!("invalid m.direct account data: {}", e),
+        }
+    }
+
+    /// Users ignored in previous sessions, so toggling one doesn't drop the others from `m.ignored_user_list`.
+    /// They were ignored before the grace period, their messages count as soon as they arrive.
+    async fn load_ignored_users(&self) {
+        let ignored_users = match self
+            .inner
+            .account()
+            .account_data::<IgnoredUserListEventContent>()
+            .await
+        {
+            Ok(Some(raw)) => raw.deserialize_as::<Value>(),
+            Ok(None) => return,
+            Err(e) => {
+                log::debug!("couldn't read m.ignored_user_list account data: {}", e);
+                return;
+            }
+        };
+        match ignored_users {
+            Ok(content) => {
+                let loaded = content["ignored_users"]
+                    .as_object()
+                    .into_iter()
+                    .flat_map(|ignored_users| ignored_users.keys())
+                    .filter_map(|user_id| UserId::parse(user_id).ok())
+                    .map(|user_id| (user_id, 0));
+                self.ignored_users
+                    .lock()
+                    .expect("lock not poisoned")
+                    .extend(loaded);
+            }
+            Err(e) => log::debug!("invalid m.ignored_user_list account data: {}", e),
+        }
+    }
+
+    /// Add room to the `m.direct` account data as real clients do when a direct message room is created or joined.
+    pub async fn add_direct_room(&self, friend_id: &UserId, room_id: &RoomId) {
+        let mut direct_rooms = self.direct_rooms.lock().expect("lock not poisoned").clone();
+        let rooms = direct_rooms.entry(friend_id.to_owned()).or_default();
+        if rooms.iter().any(|room| room == room_id) {
+            return;
+        }
+        rooms.push(room_id.to_owned());
+        self.save_direct_rooms(direct_rooms).await;
+    }
+
+    /// Write the `m.direct` account data, the local copy is only updated once the server stored it
+    async fn save_direct_rooms(&self, direct_rooms: DirectEventContent) {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::UpdateDirectRooms)
+                .await;
+            *self.direct_rooms.lock().expect("lock not poisoned") = direct_rooms;
+            return;
+        }
+
+        let response = self
+            .instrument(UserRequest::UpdateDirectRooms, || async {
+                self.inner
+                    .account()
+                    .set_account_data(direct_rooms.clone())
+                    .await
+            })
+            .await;
+        match response {
+            Ok(_) => *self.direct_rooms.lock().expect("lock not poisoned") = direct_rooms,
+            Err(Http(e)) => self.notify_error(UserRequest::UpdateDirectRooms, e).await,
+            Err(e) => log::debug!("update direct rooms failed! {}", e),
+        }
+    }
+
+    pub async fn get_channel_members(&self, room_id: &RoomId) {
+        if self.fast_forward {
+            return self
+                .fast_forward_request(UserRequest::GetChannelMembers)
+                .await;
+        }
+        self.instrument(UserRequest::GetChannelMembers, || async {
+            match self.inner.get_room(room_id) {
+                None => log::debug!("get_channel_members: room {} not found", room_id),
+                Some(room) => {
+                    if let Err(Http(e)) = room.members().await {
+                        log::debug!("get channel members failed! {}", e);
+                        self.notify_error(UserRequest::GetChannelMembers, e).await;
+                    }
+                }
+            }
+        })
+        .await;
+    }
+
+    /// Capabilities are only read by the pre-flight check, the request is not instrumented.
+    pub async fn capabilities(&self) -> Result<Capabilities, HttpError> {
+        let response = self.inner.send(CapabilitiesRequest::new(), None).await?;
+        Ok(response.capabilities)
+    }
+
+    /// Leave room, returns true when the user left it.
+    pub async fn leave_room(&self, room_id: OwnedRoomId) -> bool {
+        let req = LeaveRoomRequest::new(&room_id);
+        self.send_and_notify(req, UserRequest::LeaveChannel).await
+    }
+
+    /// Leave direct message room and remove it from the `m.direct` account data, returns true when the user left it.
+    pub async fn leave_direct_room(&self, room_id: &RoomId) -> bool {
+        let req = LeaveRoomRequest::new(room_id);
+        if !self.send_and_notify(req, UserRequest::LeaveRoom).await {
+            return false;
+        }
+        let mut direct_rooms = self.direct_rooms.lock().expect("lock not poisoned").clone();
+        for rooms in direct_rooms.values_mut() {
+            rooms.retain(|room| room != room_id);
+        }
+        direct_rooms.retain(|_, rooms| !rooms.is_empty());
+        self.save_direct_rooms(direct_rooms).await;
+        true
+    }
+
+    /// Log out the current session, its access token is invalidated
+    pub async fn logout(&self) -> bool {
+        self.send_and_notify(LogoutRequest::new(), UserRequest::Logout)
+            .await
+    }
+
+    /// Deactivate the account authenticating with the user password, the homeserver logs out all its sessions
+    pub async fn deactivate(&self) -> bool {
+        if self.fast_forward {
+            self.fast_forward_request(UserRequest::DeactivateAccount)
+                .await;
+            return true;
+        }
+        let user_id = self.user_id().expect("user id should be present");
+        let mut authenticate = false;
+        let mut session = None;
+        loop {
+            let auth = authenticate.then(|| {
+                let identifier = UserIdentifier::UserIdOrLocalpart(user_id.as_str());
+                AuthData::Password(assign!(Password::new(identifier, &self.password), {
+                    session: session.as_deref()
+                }))
+            });
+            let request = assign!(DeactivateRequest::new(), { auth });
+            let response = self
+                .instrument(UserRequest::DeactivateAccount, || async {
+                    self.inner.send(Traced(request.clone()), None).await
+                })
+                .await;
+            match response {
+                Ok(_) => return true,
+                Err(UiaaError(Server(Known(UiaaResponse::AuthResponse(info)))))
+                    if !authenticate =>
+                {
+                    authenticate = true;
+                    session = info.session;
+                }
+                Err(e) => {
+                    self.notify_error(UserRequest::DeactivateAccount, e).await;
+                    return false;
+                }
+            }
+        }
+    }
+
+    pub async fn set_display_name(&self, display_name: &str) {
+        let user_id = self.user_id().expect("user_id to be present");
+        let request = DisplayNameRequest::new(user_id, Some(display_name));
+        self.send_and_notify(request, UserRequest::SetDisplayName)
+            .await;
+    }
+
+    /// Send a burst of requests one after the other, ignoring the Retry-After of the server and the client limits.
+    /// The requests are reported with the burst, not with the ones of the other users.
+    pub async fn probe_rate_limit(&self, probe: &RateLimitProbe, room_id: Option<&RoomId>) {
+        let mut burst = ProbeBurst::default();
+        if self.fast_forward {
+            burst.requests = probe.requests_per_burst;
+            return self.notify_event(Event::RateLimitProbed(burst)).await;
+        }
+        let user_id = self.user_id().expect("user_id to be present");
+        let room = room_id.and_then(|room_id| self.inner.get_joined_room(room_id));
+        let started_at = Instant::now();
+        for _ in 0..probe.requests_per_burst {
+            let error = match (probe.endpoint, &room) {
+                (ProbeEndpoint::SendMessage, Some(room)) => {
+                    let content = json!({ "msgtype": "m.text", "body": get_random_string() });
+                    match room.send_raw(content, "m.room.message", None).await {
+                        Err(Http(e)) => Some(e),
+                        _ => None,
+                    }
+                }
+                _ => {
+                    let request = UpdatePresenceRequest::new(user_id, PresenceState::Online);
+                    self.inner.send(Traced(request), None).await.err()
+                }
+            };
+            burst.requests += 1;
+            if error.as_ref().and_then(get_retry_after).is_some() {
+                burst.rate_limited += 1;
+                burst
+                    .first_limited
+                    .get_or_insert((burst.requests, started_at.elapsed()));
+            }
+        }
+        self.notify_event(Event::RateLimitProbed(burst)).await;
+    }
+
+    pub async fn update_status(&self) {
+        let user_id = self.user_id().expect("user_id to be present");
+        let random_status_msg = get_random_string();
+        let update_presence = assign!(UpdatePresenceRequest::new(user_id, PresenceState::Online), { status_msg: Some(random_status_msg.as_str())});
+        self.send_and_notify(update_presence, UserRequest::UpdateStatus)
+            .await;
+    }
+
+    /// Send a request of a registered action (any endpoint), it's retried and reported as a custom action.
+    /// Returns true when the request succeeded.
+    pub async fn send_custom_request<Request>(&self, request: Request) -> bool
+    where
+        Request: OutgoingRequest + Debug + Clone,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        self.send_and_notify(request, UserRequest::CustomAction)
+            .await
+    }
+
+    pub async fn read_messages(&self, room_id: OwnedRoomId) {
+        let messages_request = MessagesRequest::forward(&room_id);
+        self.timed_room_request(
+            &room_id,
+            UserRequest::Messages,
+            self.send_and_notify(messages_request, UserRequest::Messages),
+        )
+        .await;
+    }
+
+    /// Time a request to a room including its retries, in permutations mode it's reported by the room
+    async fn timed_room_request<Response>(
+        &self,
+        room_id: &RoomId,
+        user_request: UserRequest,
+        request: impl Future<Output = Response>,
+    ) -> Response {
+        let started = Instant::now();
+        let response = request.await;
+        if self.permutations {
+            self.notify_event(Event::RoomRequestDuration((
+                room_id.to_owned(),
+                user_request,
+                started.elapsed(),
+            )))
+            .await;
+        }
+        response
+    }
+
+    /// Send request and notify its error, returns true when it succeeded.
+    async fn send_and_notify<Request>(&self, request: Request, user_request: UserRequest) -> bool
+    where
+        Request: OutgoingRequest + Debug + Clone,
+        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
+    {
+        if self.fast_forward {
+            self.fast_forward_request(user_request).await;
+            return true;
+        }
+        let response = self
+            .instrument(user_request.clone(), || async {
+                self.inner.send(Traced(request.clone()), None).await
+            })
+            .await;
+
+        match response {
+            Ok(_) => true,
+            Err(e) => {
+                self.notify_error(user_request, e).await;
+                false
+            }
+        }
+    }
+    ///
+    /// Send request retrying it as configured in the retry policy of its endpoint class.
+    /// Every attempt is recorded as a request, errors are notified by the caller with the last result.
+    ///
+    async fn instrument<F, Fut, Result>(
+        &self,
+        user_request: UserRequest,
+        mut send_request: F,
+    ) -> Result
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result>,
+        Result: RequestOutcome,
+    {
+        let policy = self.retry_policy(&user_request);
+        let timeout = self.timeout(&user_request);
+        let mut attempt = 1;
+        loop {
+            let span = tracing::info_span!(
+                parent: &self.session_span,
+                "request",
+                action = %user_request,
+                endpoint = user_request.endpoint(),
+                attempt
+            );
+            self.wait_rate_limit().await;
+            // time waiting for a free slot is not part of the request latency
+            let permit = self.request_slot().await;
+            let now = Instant::now();
+            let request = send_request().instrument(span);
+            // the simulated network delay and drops are part of the request latency and count against its timeout
+            let request = async {
+                if let Some(network) = &self.network {
+                    tokio::time::sleep(network.delay()).await;
+                    if network.drops_request() && Result::timed_out().is_some() {
+                        std::future::pending::<()>().await;
+                    }
+                }
+                request.await
+            };
+            // chaos drops abandon the request before its timeout, as a client disappearing would
+            let drop_after = match (&self.chaos, Result::timed_out()) {
+                (Some(chaos), Some(_)) => chaos.drop_after().filter(|after| *after < timeout),
+                _ => None,
+            };
+            let mut dropped = false;
+            let result = match Result::timed_out() {
+                Some(timed_out) => {
+                    match tokio::time::timeout(drop_after.unwrap_or(timeout), request).await {
+                        Ok(result) => result,
+                        Err(_) => {
+                            dropped = drop_after.is_some();
+                            timed_out
+                        }
+                    }
+                }
+                None => request.await,
+            };
+            let latency = now.elapsed();
+            drop(permit);
+            let error = result.error();
+            tracing::info!(
+                action = %user_request,
+                endpoint = user_request.endpoint(),
+                latency_ms = latency.as_millis() as u64,
+                status = if error.is_some() { "error" } else { "ok" },
+                error = error.as_deref(),
+                attempt,
+                "request finished"
+            );
+            self.notify_event(Event::RequestDuration((user_request.clone(), latency)))
+                .await;
+            if dropped {
+                log::debug!(
+                    "chaos: {} request dropped after {:?}",
+                    user_request,
+                    latency
+                );
+                self.notify_event(Event::ChaosRequestDropped).await;
+            }
+            if self.base_url_metrics {
+                self.notify_event(Event::BaseUrlRequestDuration((
+                    self.homeserver_url.clone(),
+                    latency,
+                )))
+                .await;
+            }
+            if self.request_log_enabled {
+                let status = match (&error, result.status_code()) {
+                    (None, _) => "ok".to_string(),
+                    (Some(_), Some(status_code)) => status_code.to_string(),
+                    (Some(_), None) => "error".to_string(),
+                };
+                let record = RequestRecord {
+                    timestamp: time_now(),
+                    user: self.inner.user_id().map(ToString::to_string),
+                    request: user_request.to_string(),
+                    endpoint: user_request.endpoint(),
+                    duration_ms: latency.as_millis(),
+                    status,
+                    attempt,
+                };
+                self.notify_event(Event::RequestRecord(record)).await;
+            }
+
+            if attempt >= policy.max_attempts
+                || !result.is_retryable(&policy.retryable_status_codes)
+            {
+                return result;
+            }
+            let delay = policy.delay(attempt);
+            log::debug!(
+                "retrying {} in {:?} (attempt {})",
+                user_request,
+                delay,
+                attempt
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    fn timeout(&self, user_request: &UserRequest) -> Duration {
+        match user_request {
+            UserRequest::InitialSync => self.timeouts.sync,
+            // incremental syncs wait for events on the server before answering
+            UserRequest::Sync => SYNC_LONG_POLL + self.timeouts.sync,
+            UserRequest::SendMessage
+            | UserRequest::SendCustomEvent
+            | UserRequest::SendCustomStateEvent
+            | UserRequest::ForkRoomState
+            | UserRequest::SendForkedRoomMessage
+            | UserRequest::SendSticker
+            | UserRequest::SendLocation
+            | UserRequest::CallInvite
+            | UserRequest::CallAnswer
+            | UserRequest::CallCandidates
+            | UserRequest::CallHangup => self.timeouts.send,
+            UserRequest::Register => self.timeouts.register,
+            UserRequest::Login => self.timeouts.login,
+            UserRequest::CreateRoom | UserRequest::CreateChannel | UserRequest::CreateSpace => {
+                self.timeouts.create_room
+            }
+            _ => self.timeouts.default,
+        }
+    }
+
+    fn retry_policy(&self, user_request: &UserRequest) -> &RetryPolicy {
+        let policy = match user_request {
+            UserRequest::Register
+            | UserRequest::Login
+            | UserRequest::Logout
+            | UserRequest::DeactivateAccount
+            | UserRequest::RenewAccount
+            | UserRequest::RefreshToken => &self.retry.auth,
+            UserRequest::InitialSync | UserRequest::Sync => &self.retry.sync,
+            UserRequest::SendMessage
+            | UserRequest::Messages
+            | UserRequest::SendCustomEvent
+            | UserRequest::SendCustomStateEvent
+            | UserRequest::ForkRoomState
+            | UserRequest::SendForkedRoomMessage
+            | UserRequest::SendSticker
+            | UserRequest::SendLocation
+            | UserRequest::CallInvite
+            | UserRequest::CallAnswer
+            | UserRequest::CallCandidates
+            | UserRequest::CallHangup => &self.retry.messages,
+            UserRequest::CreateRoom
+            | UserRequest::JoinRoom
+            | UserRequest::CreateChannel
+            | UserRequest::GetChannelMembers
+            | UserRequest::LeaveChannel
+            | UserRequest::LeaveRoom
+            | UserRequest::PublishRoom
+            | UserRequest::PublicRooms
+            | UserRequest::JoinRoomByAlias
+            | UserRequest::CreateSpace
+            | UserRequest::AddSpaceChild
+            | UserRequest::SpaceHierarchy
+            | UserRequest::KickUser
+            | UserRequest::BanUser
+            | UserRequest::GetPowerLevels
+            | UserRequest::UpdatePowerLevels
+            | UserRequest::UpdateRoomDetails
+            | UserRequest::UpgradeRoom
+            | UserRequest::FollowTombstone
+            | UserRequest::GetRoomPredecessor
+            | UserRequest::Knock
+            | UserRequest::AcceptKnock
+            | UserRequest::SpamInvite => &self.retry.rooms,
+            UserRequest::UpdateStatus
+            | UserRequest::UpdateDirectRooms
+            | UserRequest::SendToDevice
+            | UserRequest::UploadKeys
+            | UserRequest::QueryKeys
+            | UserRequest::ClaimKeys
+            | UserRequest::UploadCrossSigningKeys
+            | UserRequest::UpdateSecretStorage
+            | UserRequest::SetPusher
+            | UserRequest::SetDisplayName
+            | UserRequest::UpdateAccountData
+            | UserRequest::UpdateRoomAccountData
+            | UserRequest::UpdateIgnoredUsers
+            | UserRequest::TagRoom
+            | UserRequest::UntagRoom
+            | UserRequest::SearchUser
+            | UserRequest::SearchMessages
+            | UserRequest::GetEvent
+            | UserRequest::GetEventContext
+            | UserRequest::PreviewUrl
+            | UserRequest::SendReadReceipt
+            | UserRequest::CustomAction => return &self.retry.default,
+        };
+        policy.as_ref().unwrap_or(&self.retry.default)
+    }
+
+    /// Fast forward mode: count request as done instantly, nothing is sent to the server.
+    async fn fast_forward_request(&self, user_request: UserRequest) {
+        self.notify_event(Event::RequestDuration((user_request, Duration::ZERO)))
+            .await;
+    }
+
+    /// Fast forward mode: restore a fake session so the user has an id without calling the server.
+    async fn fast_forward_login(&self, localpart: &str) -> LoginResult {
+        self.fast_forward_request(UserRequest::Login).await;
+        let user_id = UserId::parse(format!("@{localpart}:{FAST_FORWARD_SERVER_NAME}"));
+        let session = user_id.map(|user_id| Session {
+            access_token: fast_forward_id(),
+            refresh_token: None,
+            user_id,
+            device_id: DEFAULT_DEVICE_ID.into(),
+        });
+        match session {
+            Ok(session) if self.inner.restore_login(session).await.is_ok() => LoginResult::Ok,
+            _ => LoginResult::Failed,
+        }
+    }
+
+    /// Fast forward mode: user starts without rooms and the sync loop only waits to be cancelled.
+    async fn fast_forward_sync(&self) -> SyncResult {
+        self.fast_forward_request(UserRequest::InitialSync).await;
+        let (cancel_sync, check_cancel) = async_channel::bounded::<bool>(1);
+        resources::spawn(async move { check_cancel.recv().await });
+        SyncResult::Ok {
+            rooms: vec![],
+            invited_rooms: vec![],
+            cancel_sync,
+        }
+    }
+
+    pub async fn notify_dropped_sync_events(&self, dropped: usize) {
+        if dropped > 0 {
+            self.notify_event(Event::SyncEventsDropped(dropped)).await;
+        }
+    }
+
+    pub async fn notify_stuck_user(&self, recovery: StuckUserRecovery) {
+        self.notify_event(Event::UserStuck(recovery)).await;
+    }
+
+    async fn notify_event(&self, event: Event) {
+        self.event_notifier
+            .send(event)
+            .await
+            .expect("channel should not be closed");
+    }
+
+    /// Wait for a free slot of the requests in flight, none when they are unlimited
+    async fn request_slot(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.request_limiter {
+            Some(limiter) => Some(limiter.acquire().await.expect("semaphore not closed")),
+            None => None,
+        }
     }
 
-    pub async fn get_channel_members(&self, room_id: &RoomId) {
-        self.instrument(UserRequest::GetChannelMembers, || async {
-            match self.inner.get_room(room_id) {
-                None => log::debug!("get_channel_members: room {} not found", room_id),
-                Some(room) => {
-                    if let Err(Http(e)) = room.members().await {
-                        log::debug!("get channel members failed! {}", e);
-                        self.notify_error(UserRequest::GetChannelMembers, e).await;
-                    }
-                }
+    /// Wait until the Retry-After of the last rate limited request is over, so we don't keep hitting the limit
+    async fn wait_rate_limit(&self) {
+        let rate_limited_until = *self.rate_limited_until.lock().expect("lock not poisoned");
+        if let Some(until) = rate_limited_until {
+            let now = Instant::now();
+            if until > now {
+                tokio::time::sleep(until - now).await;
             }
-        })
-        .await;
+        }
     }
 
-    pub async fn leave_room(&self, room_id: OwnedRoomId) {
-        let req = LeaveRoomRequest::new(&room_id);
-        self.send_and_notify(req, UserRequest::LeaveChannel).await;
+    /// Returns true if a request was rate limited since the last call
+    pub fn take_rate_limited(&self) -> bool {
+        self.rate_limited.swap(false, Ordering::Relaxed)
     }
 
-    pub async fn update_status(&self) {
-        let user_id = self.user_id().expect("user_id to be present");
-        let random_status_msg = get_random_string();
-        let update_presence = assign!(UpdatePresenceRequest::new(user_id, PresenceState::Online), { status_msg: Some(random_status_msg.as_str())});
-        self.send_and_notify(update_presence, UserRequest::UpdateStatus)
-            .await;
+    async fn notify_error(&self, user_request: UserRequest, error: HttpError) {
+        if let Some(retry_after) = get_retry_after(&error) {
+            log::debug!(
+                "{} rate limited, retry after {:?}",
+                user_request,
+                retry_after
+            );
+            *self.rate_limited_until.lock().expect("lock not poisoned") =
+                Some(Instant::now() + retry_after);
+            self.rate_limited.store(true, Ordering::Relaxed);
+            self.notify_event(Event::RateLimited(user_request)).await;
+            return;
+        }
+        let expired = is_expired_account(&error);
+        let soft_logout = is_soft_logout(&error);
+        self.notify_event(Event::Error((user_request, error))).await;
+        if expired {
+            self.renew_account().await;
+        }
+        if soft_logout {
+            self.refresh_access_token().await;
+        }
     }
 
-    pub async fn read_messages(&self, room_id: OwnedRoomId) {
-        let messages_request = MessagesRequest::forward(&room_id);
-        self.send_and_notify(messages_request, UserRequest::Messages)
-            .await;
-    }
+    /// On soft logout the session is kept and only the access token is refreshed, no need to login again.
+    async fn refresh_access_token(&self) {
+        if !self.refresh_token_enabled {
+            log::debug!("soft logout but refresh tokens are disabled");
+            return;
+        }
 
-    async fn send_and_notify<Request>(&self, request: Request, user_request: UserRequest)
-    where
-        Request: OutgoingRequest + Debug,
-        HttpError: From<FromHttpResponseError<Request::EndpointError>>,
-    {
         let response = self
-            .instrument(user_request.clone(), || async {
-                self.inner.send(request, None).await
+            .instrument(UserRequest::RefreshToken, || async {
+                self.inner.refresh_access_token().await
             })
             .await;
 
         if let Err(e) = response {
-            self.notify_error(user_request, e).await;
+            log::debug!("couldn't refresh access token: {:?}", e);
         }
     }
-    async fn instrument<F, Fut, Result>(&self, user_request: UserRequest, send_request: F) -> Result
-    where
-        F: FnOnce() -> Fut,
-        Fut: Future<Output = Result>,
-    {
-        let now = Instant::now();
-        let result = send_request().await;
-        self.notify_event(Event::RequestDuration((
-            user_request.clone(),
-            now.elapsed(),
-        )))
-        .await;
-        result
-    }
 
-    async fn notify_event(&self, event: Event) {
-        self.event_notifier
-            .send(event)
-            .await
-            .expect("channel should not be closed");
-    }
+    /// Renew account so the user can keep acting after the account validity period expired.
+    async fn renew_account(&self) {
+        let (account_validity, user_id) = match (&self.account_validity, self.user_id()) {
+            (Some(account_validity), Some(user_id)) => (account_validity, user_id),
+            _ => {
+                log::debug!("account expired but renewal is not configured");
+                return;
+            }
+        };
+        log::debug!("renewing expired account {}", user_id);
 
-    async fn notify_error(&self, user_request: UserRequest, error: HttpError) {
-        self.notify_event(Event::Error((user_request, error))).await
+        let response = self
+            .instrument(UserRequest::RenewAccount, || {
+                account_validity::renew(
+                    &self.direct_http_client,
+                    &self.homeserver_url,
+                    &account_validity.admin_token,
+                    user_id,
+                )
+            })
+            .await;
+
+        if let Err(e) = response {
+            self.notify_event(Event::Error((UserRequest::RenewAccount, e)))
+                .await;
+        }
     }
 
     async fn notify_sync(&self, msg: SyncEvent) {
@@ -450,33 +3245,234 @@ impl Client {
     }
 }
 
+///
+/// Sync loop of the user, incremental syncs continue from the last response until cancelled.
+/// Syncs taking longer than `timeout` are abandoned and reported as timeouts, the loop goes on.
+/// The simulated network delays the syncs and its drops leave them pending until they time out.
+///
 async fn sync_until_cancel(
     client: &matrix_sdk::Client,
     check_cancel: async_channel::Receiver<bool>,
+    user_id: &UserId,
+    notifier: &SyncEventsSender,
+    lifecycle: Option<Lifecycle>,
+    timeout: Duration,
+    network: Option<Network>,
 ) -> impl Future<Output = ()> {
     // client state is held in an `Arc` so the `Client` can be cloned freely.
     let client = client.clone();
+    let user_id = user_id.to_owned();
+    let notifier = notifier.clone();
+    let mut notification_counts = HashMap::new();
+    let mut background_at = lifecycle
+        .as_ref()
+        .map(|lifecycle| Instant::now() + lifecycle.foreground_period());
     async move {
-        match client
-            .sync_with_callback(SyncSettings::default(), {
-                let check_cancel = check_cancel.clone();
-                move |_| {
-                    let check_cancel = check_cancel.clone();
-                    async move {
-                        if check_cancel.try_recv().is_ok() {
-                            LoopCtrl::Break
-                        } else {
-                            LoopCtrl::Continue
-                        }
+        loop {
+            // the SDK keeps the token of the last response processed
+            let settings = SyncSettings::default().timeout(SYNC_LONG_POLL);
+            let settings = match client.sync_token().await {
+                Some(token) => settings.token(token),
+                None => settings,
+            };
+            let sync_started = Instant::now();
+            let sync = async {
+                if let Some(network) = &network {
+                    tokio::time::sleep(network.delay()).await;
+                    if network.drops_request() {
+                        std::future::pending::<()>().await;
+                    }
+                }
+                client.sync_once(settings).await
+            };
+            let response = tokio::select! {
+                response = tokio::time::timeout(timeout, sync) => response,
+                _ = check_cancel.recv() => break,
+            };
+            let duration = sync_started.elapsed();
+            let response = match response {
+                Ok(Ok(response)) => response,
+                Ok(Err(e)) => {
+                    log::debug!("error while syncing user {} - {:?}", user_id, e);
+                    if let Http(e) = e {
+                        notifier
+                            .send(Event::Error((UserRequest::Sync, e)))
+                            .await
+                            .expect("channel open");
                     }
+                    // same pause as the SDK sync loop before trying again
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+                Err(_) => {
+                    log::debug!("sync of user {} timed out after {:?}", user_id, duration);
+                    let timed_out = HttpError::timed_out().expect("http requests to time out");
+                    notifier
+                        .send(Event::Error((UserRequest::Sync, timed_out)))
+                        .await
+                        .expect("channel open");
+                    continue;
+                }
+            };
+
+            for consistent in
+                check_notification_counts(&response, &user_id, &mut notification_counts)
+            {
+                notifier
+                    .send(Event::NotificationCountChecked(consistent))
+                    .await
+                    .expect("channel open");
+            }
+            let lags = sync_lags(&response);
+            if !lags.is_empty() {
+                notifier
+                    .send(Event::SyncLags(lags))
+                    .await
+                    .expect("channel open");
+            }
+            notifier
+                .send(Event::SyncCompleted(SyncStats::new(
+                    &response, false, duration,
+                )))
+                .await
+                .expect("channel open");
+            // in background the next sync waits for the app to come back to foreground and catches up
+            let in_background =
+                background_at.map_or(false, |background_at| background_at <= Instant::now());
+            if let (true, Some(lifecycle)) = (in_background, &lifecycle) {
+                let background = lifecycle.background_period();
+                log::debug!("user {} in background for {:?}", user_id, background);
+                tokio::select! {
+                    _ = tokio::time::sleep(background) => {}
+                    _ = check_cancel.recv() => break,
                 }
+                background_at = Some(Instant::now() + lifecycle.foreground_period());
+            }
+            if check_cancel.try_recv().is_ok() {
+                break;
+            }
+        }
+    }
+}
+
+impl SyncStats {
+    /// Sizes are the JSON bytes of the timeline and state events of the joined rooms, the rest of the response is left out
+    fn new(response: &SyncResponse, initial: bool, duration: Duration) -> Self {
+        let sizes = response
+            .rooms
+            .join
+            .values()
+            .flat_map(|room| {
+                let timeline = room
+                    .timeline
+                    .events
+                    .iter()
+                    .map(|event| event.event.json().get().len());
+                let state = room
+                    .state
+                    .events
+                    .iter()
+                    .map(|event| event.json().get().len());
+                timeline.chain(state)
             })
-            .await
-        {
-            Ok(_) => {}
-            Err(err) => log::debug!("error while syncing an user - {:?}", err),
+            .collect::<Vec<_>>();
+        Self {
+            initial,
+            duration,
+            events_size: sizes.iter().sum(),
+            events: sizes.len(),
+            returned_early: !initial && duration < SYNC_LONG_POLL,
+        }
+    }
+}
+
+fn sync_lags(response: &SyncResponse) -> Vec<u128> {
+    let now = time_now();
+    let timestamp = |timestamp: Option<u64>| timestamp.map(|ts| now.saturating_sub(ts as u128));
+    response
+        .rooms
+        .join
+        .values()
+        .flat_map(|room| {
+            let timeline = room.timeline.events.iter().filter_map(move |event| {
+                timestamp(event.event.get_field("origin_server_ts").ok().flatten())
+            });
+            let state = room.state.events.iter().filter_map(move |event| {
+                timestamp(event.get_field("origin_server_ts").ok().flatten())
+            });
+            timeline.chain(state)
+        })
+        .collect()
+}
+
+///
+/// Compare the notification count of every room in the sync response with the one of the previous
+/// response plus the notifying events sent by others, returns if each checked room was consistent.
+/// Messages never mention users, so the highlight count has to stay the same.
+/// Users never send read receipts, so counts only grow; rooms with limited timelines, own events
+/// (which may reset the count) or seen for the first time are not checked.
+///
+fn check_notification_counts(
+    response: &SyncResponse,
+    user_id: &UserId,
+    notification_counts: &mut HashMap<OwnedRoomId, (u64, u64)>,
+) -> Vec<bool> {
+    let mut checks = vec![];
+    for (room_id, room) in &response.rooms.join {
+        let count = room.unread_notifications.notification_count;
+        let highlight_count = room.unread_notifications.highlight_count;
+        let (previous, previous_highlight_count) =
+            match notification_counts.insert(room_id.clone(), (count, highlight_count)) {
+                Some(previous) => previous,
+                None => continue,
+            };
+        let senders = room
+            .timeline
+            .events
+            .iter()
+            .filter(|event| {
+                event
+                    .event
+                    .get_field::<String>("type")
+                    .ok()
+                    .flatten()
+                    .map_or(false, |event_type| {
+                        NOTIFYING_EVENT_TYPES.contains(&event_type.as_str())
+                    })
+            })
+            .filter_map(|event| {
+                event
+                    .event
+                    .get_field::<OwnedUserId>("sender")
+                    .ok()
+                    .flatten()
+            })
+            .collect::<Vec<_>>();
+        if room.timeline.limited || senders.iter().any(|sender| sender == user_id) {
+            continue;
+        }
+        let expected = previous + senders.len() as u64;
+        if count != expected {
+            log::debug!(
+                "user {} notification count in room {} is {}, expected {}",
+                user_id,
+                room_id,
+                count,
+                expected
+            );
         }
+        if highlight_count != previous_highlight_count {
+            log::debug!(
+                "user {} highlight count in room {} is {}, expected {}",
+                user_id,
+                room_id,
+                highlight_count,
+                previous_highlight_count
+            );
+        }
+        checks.push(count == expected && highlight_count == previous_highlight_count);
     }
+    checks
 }
 
 async fn add_room_message_event_handler(
@@ -498,21 +3494,208 @@ async fn add_room_message_event_handler(
             }
         }
     });
+    // stickers are only reported for their delivery time, users don't respond to them
+    client.add_event_handler({
+        let user_id = user_id.to_owned();
+        let notifier = notifier.clone();
+        move |event: OriginalSyncStickerEvent| {
+            let user_id = user_id.clone();
+            let notifier = notifier.clone();
+            async move {
+                if event.sender.localpart() != user_id.localpart() {
+                    notifier
+                        .send(Event::MessageReceived(event.event_id.to_string()))
+                        .await
+                        .expect("channel open");
+                }
+            }
+        }
+    });
+}
+
+/// Messages of ignored users must stop appearing in the sync, the ones received after the grace period are reported
+async fn add_ignored_users_handler(
+    client: &matrix_sdk::Client,
+    ignored_users: &Arc<Mutex<HashMap<OwnedUserId, u128>>>,
+    notifier: &SyncEventsSender,
+) {
+    client.add_event_handler({
+        let ignored_users = ignored_users.clone();
+        let notifier = notifier.clone();
+        move |event: OriginalSyncRoomMessageEvent| {
+            let ignored_users = ignored_users.clone();
+            let notifier = notifier.clone();
+            async move {
+                let ignored_at = ignored_users
+                    .lock()
+                    .expect("lock not poisoned")
+                    .get(&event.sender)
+                    .copied();
+                if let Some(ignored_at) = ignored_at {
+                    if time_now() > ignored_at + IGNORE_GRACE_PERIOD_MS {
+                        log::debug!(
+                            "message {} of ignored user {}",
+                            event.event_id,
+                            event.sender
+                        );
+                        notifier
+                            .send(Event::IgnoredUserMessageReceived)
+                            .await
+                            .expect("channel open");
+                    }
+                }
+            }
+        }
+    });
+}
+
+async fn add_seen_events_handler(
+    client: &matrix_sdk::Client,
+    seen_events: &Arc<Mutex<VecDeque<(OwnedRoomId, OwnedEventId)>>>,
+    user_id: &UserId,
+) {
+    client.add_event_handler({
+        let seen_events = seen_events.clone();
+        let user_id = user_id.to_owned();
+        move |event: OriginalSyncRoomMessageEvent, room: Room| {
+            let seen_events = seen_events.clone();
+            let user_id = user_id.clone();
+            async move {
+                if event.sender != user_id {
+                    let mut seen_events = seen_events.lock().expect("lock not poisoned");
+                    if seen_events.len() == SEEN_EVENTS {
+                        seen_events.pop_front();
+                    }
+                    seen_events.push_back((room.room_id().to_owned(), event.event_id));
+                }
+            }
+        }
+    });
 }
 
 async fn add_invite_event_handler(
     client: &matrix_sdk::Client,
     tx: &Sender<SyncEvent>,
     user_id: &UserId,
+    notifier: &SyncEventsSender,
 ) {
     client.add_event_handler({
         let tx = tx.clone();
         let user_id = user_id.to_owned();
+        let notifier = notifier.clone();
         move |event, room| {
             let tx = tx.clone();
             let user_id = user_id.clone();
+            let notifier = notifier.clone();
+            async move {
+                on_room_member_event(event, room, tx, user_id, &notifier).await;
+            }
+        }
+    });
+}
+
+/// Other users joining the rooms of the user, the first member seeing a join ends its join latency
+async fn add_join_event_handler(
+    client: &matrix_sdk::Client,
+    user_id: &UserId,
+    notifier: &SyncEventsSender,
+) {
+    client.add_event_handler({
+        let user_id = user_id.to_owned();
+        let notifier = notifier.clone();
+        move |event: OriginalSyncRoomMemberEvent, room: Room| {
+            let user_id = user_id.clone();
+            let notifier = notifier.clone();
+            async move {
+                let was_member = event
+                    .unsigned
+                    .prev_content
+                    .as_ref()
+                    .map_or(false, |content| content.membership == MembershipState::Join);
+                if event.content.membership == MembershipState::Join
+                    && event.state_key != user_id
+                    && !was_member
+                {
+                    notifier
+                        .send(Event::JoinObserved(membership_key(
+                            room.room_id(),
+                            &event.state_key,
+                        )))
+                        .await
+                        .expect("channel open");
+                }
+            }
+        }
+    });
+}
+
+/// Identifies the invite or join of a user to a room in the join latency events
+fn membership_key(room_id: &RoomId, user_id: &UserId) -> String {
+    format!("{room_id} {user_id}")
+}
+
+async fn add_knock_event_handler(client: &matrix_sdk::Client, tx: &Sender<SyncEvent>) {
+    client.add_event_handler({
+        let tx = tx.clone();
+        move |event: OriginalSyncRoomMemberEvent, room: Room| {
+            let tx = tx.clone();
+            async move {
+                if event.content.membership == MembershipState::Knock {
+                    log::debug!("user {} knocked on room {}", event.sender, room.room_id());
+                    tx.send(SyncEvent::Knock(room.room_id().to_owned(), event.sender))
+                        .await
+                        .expect("channel to be open");
+                }
+            }
+        }
+    });
+}
+
+async fn add_call_invite_event_handler(
+    client: &matrix_sdk::Client,
+    tx: &Sender<SyncEvent>,
+    user_id: &UserId,
+) {
+    client.add_event_handler({
+        let tx = tx.clone();
+        let user_id = user_id.to_owned();
+        move |event: OriginalSyncCallInviteEvent, room: Room| {
+            let tx = tx.clone();
+            let user_id = user_id.clone();
+            async move {
+                // as real clients do, invites older than their lifetime are ignored, ex: received in a catch-up sync
+                let sent_at = u128::from(u64::from(event.origin_server_ts.0));
+                let lifetime = u128::from(u64::from(event.content.lifetime));
+                if time_now().saturating_sub(sent_at) > lifetime {
+                    log::debug!("call {} to {} expired", event.content.call_id, user_id);
+                    return;
+                }
+                if event.sender != user_id {
+                    log::debug!("user {} is calling {}", event.sender, user_id);
+                    tx.send(SyncEvent::CallInvite(
+                        room.room_id().to_owned(),
+                        event.content.call_id.to_string(),
+                    ))
+                    .await
+                    .expect("channel to be open");
+                }
+            }
+        }
+    });
+}
+
+async fn add_to_device_event_handler(client: &matrix_sdk::Client, notifier: &SyncEventsSender) {
+    client.add_event_handler({
+        let notifier = notifier.clone();
+        move |event: ToDeviceRoomKeyRequestEvent| {
+            let notifier = notifier.clone();
             async move {
-                on_room_member_event(event, room, tx, user_id).await;
+                notifier
+                    .send(Event::ToDeviceReceived(
+                        event.content.request_id.to_string(),
+                    ))
+                    .await
+                    .expect("channel open");
             }
         }
     });
@@ -536,6 +3719,34 @@ async fn add_room_join_rules_event_handler(
     });
 }
 
+async fn add_tombstone_event_handler(
+    client: &matrix_sdk::Client,
+    tx: &Sender<SyncEvent>,
+    user_id: &UserId,
+) {
+    client.add_event_handler({
+        let tx = tx.clone();
+        let user_id = user_id.to_owned();
+        move |event: OriginalSyncRoomTombstoneEvent, room: Room| {
+            let tx = tx.clone();
+            let user_id = user_id.clone();
+            async move {
+                // the user who upgraded the room already joined the replacement
+                if event.sender != user_id {
+                    let tombstoned_at = u128::from(u64::from(event.origin_server_ts.0));
+                    tx.send(SyncEvent::RoomUpgraded(
+                        room.room_id().to_owned(),
+                        event.content.replacement_room,
+                        tombstoned_at,
+                    ))
+                    .await
+                    .expect("channel to be open");
+                }
+            }
+        }
+    });
+}
+
 async fn on_room_join_rules(
     room: Room,
     user_notifier: UserNotificationsSender,
@@ -560,6 +3771,7 @@ async fn on_room_member_event(
     room: Room,
     sender: Sender<SyncEvent>,
     user_id: OwnedUserId,
+    notifier: &SyncEventsSender,
 ) {
     // ignore event when it doesn't affect the current user
     if room_member.state_key != user_id {
@@ -567,8 +3779,16 @@ async fn on_room_member_event(
     }
     if let Room::Invited(room) = &room {
         log::debug!("user {} was invited to room {}!", user_id, room.room_id());
+        notifier
+            .send(Event::InviteReceived(membership_key(
+                room.room_id(),
+                &user_id,
+            )))
+            .await
+            .expect("channel open");
+        let inviter = (room_member.content.is_direct == Some(true)).then_some(room_member.sender);
         sender
-            .send(SyncEvent::Invite(room.room_id().to_owned()))
+            .send(SyncEvent::Invite(room.room_id().to_owned(), inviter))
             .await
             .expect("channel to be open");
     }
@@ -582,45 +3802,132 @@ async fn on_room_message(
     notifier: &SyncEventsSender,
 ) {
     if let Room::Joined(joined_room) = &room {
-        if let MatrixMessageType::Text(text) = event.content.msgtype {
-            if event.sender.localpart() == user_id.localpart() {
-                return;
+        if event.sender.localpart() == user_id.localpart() {
+            return;
+        }
+        match event.content.msgtype {
+            MatrixMessageType::Text(text) => {
+                let message_type = if is_channel(&room) {
+                    RoomType::Channel
+                } else {
+                    RoomType::DirectMessage
+                };
+
+                log::debug!(
+                    "Message {:?} received! next time user {} will have someone to respond :D",
+                    message_type,
+                    user_id
+                );
+
+                sender
+                    .send(SyncEvent::MessageReceived(
+                        joined_room.room_id().to_owned(),
+                        text.body,
+                        message_type,
+                    ))
+                    .await
+                    .expect("channel open");
             }
+            // locations are only reported for their delivery time, users don't respond to them
+            MatrixMessageType::Location(_) => {}
+            _ => return,
+        }
+        notifier
+            .send(Event::MessageReceived(event.event_id.to_string()))
+            .await
+            .expect("channel open");
+    }
+}
 
-            let message_type = if is_channel(&room) {
-                RoomType::Channel
-            } else {
-                RoomType::DirectMessage
-            };
+fn is_expired_account(error: &HttpError) -> bool {
+    matches!(
+        error,
+        Api(Server(Known(RumaApiError::ClientApi(Error { kind, .. }))))
+            if kind.to_string() == EXPIRED_ACCOUNT
+    )
+}
 
-            log::debug!(
-                "Message {:?} received! next time user {} will have someone to respond :D",
-                message_type,
-                user_id
-            );
+fn get_status_code(error: &HttpError) -> Option<u16> {
+    match error {
+        Api(Server(Known(RumaApiError::ClientApi(Error { status_code, .. }))))
+        | UiaaError(Server(Known(UiaaResponse::MatrixError(Error { status_code, .. }))))
+        | HttpError::Server(status_code) => Some(status_code.as_u16()),
+        _ => None,
+    }
+}
 
-            sender
-                .send(SyncEvent::MessageReceived(
-                    joined_room.room_id().to_owned(),
-                    text.body,
-                    message_type,
-                ))
-                .await
-                .expect("channel open");
-            notifier
-                .send(Event::MessageReceived(event.event_id.to_string()))
-                .await
-                .expect("channel open");
+/// Time to wait if the request was rate limited (M_LIMIT_EXCEEDED or 429).
+/// The Retry-After header is not exposed by the ruma errors, so `retry_after_ms` from the body is used instead.
+/// Any 429 counts whatever its errcode, server flavors don't agree on it and some omit `retry_after_ms`.
+fn get_retry_after(error: &HttpError) -> Option<Duration> {
+    match error {
+        Api(Server(Known(RumaApiError::ClientApi(Error {
+            kind: ErrorKind::LimitExceeded { retry_after_ms },
+            ..
+        })))) => Some(retry_after_ms.unwrap_or(DEFAULT_RETRY_AFTER)),
+        Api(Server(Known(RumaApiError::ClientApi(Error { status_code, .. }))))
+        | HttpError::Server(status_code)
+            if status_code.as_u16() == 429 =>
+        {
+            Some(DEFAULT_RETRY_AFTER)
         }
+        _ => None,
     }
 }
 
+fn is_soft_logout(error: &HttpError) -> bool {
+    matches!(
+        error,
+        Api(Server(Known(RumaApiError::ClientApi(Error {
+            kind: ErrorKind::UnknownToken { soft_logout: true },
+            ..
+        }))))
+    )
+}
+
+/// Unpadded base64 of random bytes, the format of keys and signatures
+fn random_key(bytes: usize) -> String {
+    let bytes = (0..bytes).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+    base64::encode_config(bytes, base64::STANDARD_NO_PAD)
+}
+
+/// Secret encrypted with a secret storage key (`m.secret_storage.v1.aes-hmac-sha2`)
+fn encrypted_secret() -> Value {
+    json!({ "iv": random_key(16), "ciphertext": random_key(32), "mac": random_key(32) })
+}
+
+fn random_blob(size: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(size)
+        .map(char::from)
+        .collect()
+}
+
+fn raw_json<T>(value: &Value) -> Raw<T> {
+    Raw::from_json(to_raw_value(value).expect("json value to be serializable"))
+}
+
+fn fast_forward_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(18)
+        .map(char::from)
+        .collect()
+}
+
+fn fast_forward_room_id() -> OwnedRoomId {
+    RoomId::parse(format!("!{}:{FAST_FORWARD_SERVER_NAME}", fast_forward_id()))
+        .expect("fast forward room id to be valid")
+}
+
 fn get_room_alias(first: &UserId, second: &UserId) -> String {
     let mut names = vec![first.localpart(), second.localpart()];
     names.sort();
     names.join("-")
 }
 
+/// Channels are the rooms anyone can ask to join: public, knock and restricted rooms, excluding spaces.
 fn is_channel(room: &Room) -> bool {
-    room.is_public()
+    !room.is_space() && !matches!(room.join_rule(), JoinRule::Invite | JoinRule::Private)
 }
@@ -1,10 +1,15 @@
+use crate::text::Corpus;
 use crate::time::time_now;
-use clap::Parser;
-use config::{ConfigError, File};
+use clap::{Parser, Subcommand};
+use config::{ConfigError, File, FileFormat};
+use rand::Rng;
 use regex::Regex;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use serde_with::serde_as;
-use serde_with::DurationSeconds;
+use serde_with::{DurationMilliSeconds, DurationSeconds};
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
 
 /// This function returns homeserver domain and url, ex:
@@ -19,12 +24,27 @@ pub fn get_homeserver_url(homeserver: &str, default_protocol: Option<&str>) -> S
     }
 }
 
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Merge reports generated by independent instances into a combined report
+    Aggregate {
+        /// Report files to merge
+        #[clap(required = true, value_parser)]
+        reports: Vec<String>,
+    },
+    /// Run the canary script every `canary.every_in_secs` until stopped, exporting the results to Prometheus
+    Canary,
+}
+
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Homeserver to use during the simulation
-    #[clap(short, long, value_parser)]
-    homeserver: String,
+    #[clap(short, long, value_parser, required = true)]
+    homeserver: Option<String>,
 
     /// Number of times to tick during the simulation
     #[clap(short, long, value_parser)]
@@ -56,12 +76,112 @@ pub struct Args {
     /// Probability of a user to have a short life. Should be a number between 0 and 100. Default is 50 (%).
     #[clap(long, value_parser)]
     probability_for_short_lifes: Option<i64>,
+
+    /// CSV or JSON file with pre-provisioned credentials to use instead of generating users
+    #[clap(long, value_parser)]
+    credentials_file: Option<String>,
+
+    /// Run without waiting between ticks and without sending requests to the server
+    #[clap(long, action)]
+    fast_forward: bool,
+
+    /// Show live dashboard instead of progress bars
+    #[clap(long, action)]
+    dashboard: bool,
+
+    /// Print the expected plan of the simulation without sending any request
+    #[clap(long, action)]
+    dry_run: bool,
+
+    /// Start without checking the homeserver is reachable and able to register and login users
+    #[clap(long, action)]
+    skip_preflight: bool,
+
+    /// Resume an interrupted run from its last checkpoint, using the same execution ID
+    #[clap(long, value_parser)]
+    resume: Option<String>,
+
+    /// Record the users picked on every tick and their actions to this file
+    #[clap(long, value_parser)]
+    record: Option<String>,
+
+    /// Re-execute the users and actions of a recording instead of picking them at random
+    #[clap(long, value_parser)]
+    replay: Option<String>,
+
+    /// Role in distributed mode: standalone, coordinator or worker. Default is standalone.
+    #[clap(long, value_parser)]
+    role: Option<String>,
+
+    /// Report of a previous run to compare the key metrics of this run against
+    #[clap(long, value_parser)]
+    baseline: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Server {
     pub homeserver: String,
     pub wk_login: bool,
+    /// Homeserver implementation, features relying on Synapse admin APIs are skipped when it doesn't have them
+    pub flavor: ServerFlavor,
+    /// URLs of the same homeserver (ex: reverse proxies in front of its workers) used instead of `homeserver`,
+    /// each user sticks to one of them
+    pub base_urls: Vec<String>,
+    pub base_url_policy: BaseUrlPolicy,
+}
+
+impl Server {
+    /// URL of the homeserver used by the user, always the same for a user unless the policy is random
+    pub fn base_url(&self, id_number: usize) -> String {
+        if self.base_urls.is_empty() {
+            return get_homeserver_url(&self.homeserver, None);
+        }
+        let index = match self.base_url_policy {
+            BaseUrlPolicy::RoundRobin => id_number % self.base_urls.len(),
+            BaseUrlPolicy::HashByUser => {
+                (stable_hash(id_number) % self.base_urls.len() as u64) as usize
+            }
+            BaseUrlPolicy::Random => rand::thread_rng().gen_range(0..self.base_urls.len()),
+        };
+        get_homeserver_url(&self.base_urls[index], None)
+    }
+}
+
+/// How users are assigned to the base URLs
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BaseUrlPolicy {
+    /// Users are spread evenly in order
+    RoundRobin,
+    /// A hash of the user picks it, like proxies routing by user
+    HashByUser,
+    /// Picked at random when the user is created
+    Random,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerFlavor {
+    Synapse,
+    Dendrite,
+    Conduit,
+}
+
+impl ServerFlavor {
+    /// Registration through `/_synapse/admin/v1/register` with the registration shared secret
+    pub fn has_shared_secret_registration(&self) -> bool {
+        matches!(self, ServerFlavor::Synapse | ServerFlavor::Dendrite)
+    }
+
+    /// Renewal of expired accounts through the Synapse account validity admin API
+    pub fn has_account_validity(&self) -> bool {
+        matches!(self, ServerFlavor::Synapse)
+    }
+
+    /// Prometheus metrics endpoint
+    pub fn has_metrics(&self) -> bool {
+        matches!(self, ServerFlavor::Synapse | ServerFlavor::Dendrite)
+    }
 }
 
 #[serde_as]
@@ -76,16 +196,975 @@ pub struct Simulation {
     #[serde_as(as = "DurationSeconds<u64>")]
     #[serde(rename = "grace_period_duration_in_secs")]
     pub grace_period_duration: Duration,
+    /// Requests sent this long after the simulation started are counted but excluded from the latencies
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "warm_up_in_secs")]
+    pub warm_up: Duration,
     pub output: String,
     pub execution_id: String,
     pub probability_to_act: usize,
     pub probability_for_short_lifes: usize,
+    /// Probability (0 to 100) of a user leaving one of its direct message rooms when it acts
+    pub probability_to_leave_room: usize,
+    /// Probability (0 to 100) of a user sending a to-device key request to a friend when it acts
+    pub probability_to_send_to_device: usize,
+    /// Probability (0 to 100) of a new user setting up cross-signing keys and secret storage after registering
+    pub probability_to_bootstrap_cross_signing: usize,
+    /// Probability (0 to 100) of a user running a full-text search across its rooms when it acts
+    pub probability_to_search_messages: usize,
+    /// Probability (0 to 100) of a user fetching one of the last messages it received when it acts
+    pub probability_to_fetch_event: usize,
+    /// Probability (0 to 100) of a user changing the name, topic or avatar of one of the rooms it administers when it acts
+    pub probability_to_update_room: usize,
+    /// Probability (0 to 100) of a user upgrading one of the channels it administers to `rooms.upgrade_version` when it acts
+    pub probability_to_upgrade_room: usize,
+    /// Probability (0 to 100) of a user adding one of its friends to its ignore list (or removing it when ignored) when it acts
+    pub probability_to_ignore_user: usize,
+    /// Probability (0 to 100) of a user tagging one of its rooms as favourite or low priority (or untagging it) when it acts
+    pub probability_to_tag_room: usize,
+    /// Events each user keeps to react to (invites, messages...), the oldest are dropped when there are more
+    pub max_sync_events_per_user: usize,
+    /// Probability (0 to 100) of a user only registering, logging in and syncing, never acting after its initial sync
+    pub probability_for_idle_users: f64,
     pub channels_per_user: usize,
+    /// Spaces created by all users when spaces are enabled
+    pub max_spaces: usize,
+    /// Users don't create, join or accept invites to more rooms (direct messages and channels) than this
+    pub max_rooms_per_user: Option<usize>,
+    /// Users with this amount of invites not responded yet are not picked as new friends
+    pub max_pending_invites_per_user: Option<usize>,
+    pub credentials_file: Option<String>,
+    /// Offset for the user ids, so runs from different instances don't share users
+    pub first_user_id: usize,
+    /// Run the scenario without waiting between ticks and with a mocked client (no requests are sent),
+    /// useful to debug users behavior before a real run
+    pub fast_forward: bool,
+    /// Show a live dashboard with request rates, latencies and users per state instead of progress bars
+    pub dashboard: bool,
+    /// Save a checkpoint every this amount of ticks (0 to disable)
+    pub checkpoint_every_ticks: usize,
+    /// Continue the run of `execution_id` from its last checkpoint
+    pub resume: bool,
+    /// Print the expected plan of the simulation and exit without sending any request
+    pub dry_run: bool,
+    /// Check the homeserver versions, login flows and a register / login round-trip before starting
+    pub preflight_check: bool,
+    /// File where the users picked on every tick and their actions are recorded
+    pub record: Option<String>,
+    /// Recording re-executed instead of picking users and actions at random, rooms and messages are still random
+    pub replay: Option<String>,
+    /// What the users do once the run finishes, so the server state doesn't depend on their last random actions
+    pub teardown: Teardown,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Teardown {
+    /// Users stay logged in and in their rooms
+    Keep,
+    /// Every session is logged out
+    Logout,
+    /// Users leave all their rooms, then log out
+    LeaveRooms,
+    /// Accounts are deactivated, pre-provisioned users are only logged out
+    Deactivate,
+}
+
+/// Proportion (0 to 100) of the message bodies generated with multi-byte contents,
+/// the rest of them are plain ASCII lorem ipsum.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Messages {
+    pub emoji_probability: usize,
+    pub rtl_probability: usize,
+    /// Proportion (0 to 100) of the messages ending with one of the `urls`, recipients request its preview
+    pub url_probability: usize,
+    pub urls: Vec<String>,
+    /// Proportion (0 to 100) of the messages sent as `m.sticker` events
+    pub sticker_probability: usize,
+    /// Proportion (0 to 100) of the messages sent as `m.location` messages
+    pub location_probability: usize,
+    pub length: MessageLength,
+    /// Text messages are picked from this file (one message per line) instead of being generated
+    pub corpus_file: Option<String>,
+    #[serde(skip)]
+    pub corpus: Option<Arc<Corpus>>, // loaded from `corpus_file` when the simulation starts
+}
+
+/// Length in characters of the text message bodies, log-normally distributed between `min` and `max`
+/// with a tail of `large_length` messages.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MessageLength {
+    pub min: usize,
+    pub max: usize,
+    /// Mean and standard deviation of the natural logarithm of the length
+    pub mu: f64,
+    pub sigma: f64,
+    /// Proportion (0 to 100) of the messages of `large_length` characters
+    pub large_probability: f64,
+    pub large_length: usize,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Requests {
-    pub retry_enabled: bool,
+    /// Request refreshable access tokens on login (MSC2918) and refresh them on soft logout
+    pub refresh_token_enabled: bool,
+    /// Users that get rate limited act less often (half of the ticks every time they are rate limited)
+    pub adaptive_pace: bool,
+    /// Max requests in flight across all users, requests wait for a free slot (unlimited if not set).
+    /// Sync loops are left out: their long-polling requests stay open waiting for events, so idle users would keep
+    /// the slots taken. Rate limit probes ignore it on purpose.
+    pub max_in_flight_requests: Option<usize>,
+}
+
+/// Timeout of each kind of request, the rest use the default one
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct Timeouts {
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "default_in_secs")]
+    pub default: Duration,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "sync_in_secs")]
+    pub sync: Duration,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "send_in_secs")]
+    pub send: Duration,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "register_in_secs")]
+    pub register: Duration,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "login_in_secs")]
+    pub login: Duration,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "create_room_in_secs")]
+    pub create_room: Duration,
+}
+
+impl Timeouts {
+    /// Longest timeout, used by the underlying client so it doesn't cancel requests before us
+    pub fn max(&self) -> Duration {
+        [
+            self.default,
+            self.sync,
+            self.send,
+            self.register,
+            self.login,
+            self.create_room,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(self.default)
+    }
+}
+
+/// How failed requests are retried, delay between attempts grows exponentially from `base_delay_ms` up to `max_delay_ms`
+#[derive(Debug, Deserialize, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one, 1 means no retries
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Wait a random time between 0 and the computed delay (full jitter)
+    pub jitter: bool,
+    /// Responses with these status codes are retried, connection errors are always retried
+    pub retryable_status_codes: Vec<u16>,
+}
+
+impl RetryPolicy {
+    /// Delay to wait before next attempt, `attempt` starts at 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay_ms
+            .saturating_mul(1u64 << attempt.saturating_sub(1).min(32));
+        let delay = exponential.min(self.max_delay_ms);
+        if self.jitter {
+            Duration::from_millis(rand::thread_rng().gen_range(0..=delay))
+        } else {
+            Duration::from_millis(delay)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FriendshipModel {
+    /// Friends are picked uniformly at random among syncing users (Erdős–Rényi)
+    Random,
+    /// Watts–Strogatz: friends are the closest users, a few of them rewired to random users
+    SmallWorld,
+    /// Barabási–Albert: users prefer to befriend users with more friends
+    ScaleFree,
+}
+
+/// Model of the friendship graph, users only add as friends their neighbours in the graph.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Friendships {
+    pub model: FriendshipModel,
+    /// Average amount of friends per user in the small world and scale free graphs
+    pub average_friends: usize,
+    /// Probability of rewiring each friendship to a random user in the small world graph
+    pub rewiring_probability: f64,
+    /// Graph to use instead of generating it, as edge list or adjacency JSON (`.json`) of user positions
+    pub import_file: Option<String>,
+    /// Write the graph used by the run to this file, as edge list or adjacency JSON (`.json`)
+    pub export_file: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    PrivateChat,
+    PublicChat,
+    TrustedPrivateChat,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryVisibility {
+    Invited,
+    Joined,
+    Shared,
+    WorldReadable,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinRule {
+    Public,
+    Invite,
+    /// Users knock and the room admins invite them
+    Knock,
+    /// Only members of a space can join, the space is picked among the existing ones when the room is created
+    Restricted,
+}
+
+/// State event sent when the room is created, the content is sent as is
+#[derive(Debug, Deserialize, Clone)]
+pub struct InitialStateEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default)]
+    pub state_key: String,
+    pub content: serde_json::Value,
+}
+
+/// Options used to create rooms, history visibility and join rule are sent as initial state events.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RoomOptions {
+    /// Server default version when missing
+    pub room_version: Option<String>,
+    pub preset: Preset,
+    pub history_visibility: Option<HistoryVisibility>,
+    pub join_rule: Option<JoinRule>,
+    #[serde(default)]
+    pub initial_state: Vec<InitialStateEvent>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Rooms {
+    /// Rooms created when users add a friend
+    pub direct: RoomOptions,
+    /// Public channels created by users
+    pub channel: RoomOptions,
+    /// Version of the replacement rooms when channels are upgraded
+    pub upgrade_version: String,
+    /// Channels are created across every combination of history visibility and join rule, instead of the
+    /// configured ones, and their requests are reported by combination
+    pub permutations: bool,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CustomEventKind {
+    Message,
+    State,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CustomEventTarget {
+    Direct,
+    Channel,
+    /// Any room of the user, direct messages or channels
+    All,
+}
+
+/// Product-specific event sent by users when they act, ex: Decentraland in-world position updates.
+/// String values of the content and the state key are templates, see [`crate::text::render_template`].
+#[derive(Debug, Deserialize, Clone)]
+pub struct CustomEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub kind: CustomEventKind,
+    #[serde(default)]
+    pub state_key: String,
+    pub content: serde_json::Value,
+    pub target: CustomEventTarget,
+    /// Probability (0 to 100) of sending the event when a user acts
+    pub probability: f64,
+}
+
+/// Messages replaced by malformed or edge-case payloads (oversized events, invalid JSON, wrong field types,
+/// weird unicode and bad transaction ids) to catch server errors under dirty traffic
+#[derive(Debug, Deserialize, Clone)]
+pub struct Fuzzing {
+    /// Probability (0 to 100) of a message being replaced
+    pub probability: f64,
+}
+
+/// Clients abruptly disappearing, to check the server and the tool recover from it
+#[derive(Debug, Deserialize, Clone)]
+pub struct Chaos {
+    /// Probability (0 to 100) of a syncing user killing its sync loop and starting a new one when it acts
+    #[serde(default)]
+    pub sync_restart_probability: f64,
+    /// Probability (0 to 100) of a request connection being dropped before the response arrives
+    #[serde(default)]
+    pub request_drop_probability: f64,
+}
+
+/// Retry policy per endpoint class, classes without policy use the default one
+#[derive(Debug, Deserialize, Clone)]
+pub struct Retry {
+    pub default: RetryPolicy,
+    /// Register, login and token refresh
+    pub auth: Option<RetryPolicy>,
+    /// Initial sync
+    pub sync: Option<RetryPolicy>,
+    /// Send and read messages
+    pub messages: Option<RetryPolicy>,
+    /// Create, join and leave rooms or get their members
+    pub rooms: Option<RetryPolicy>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginMethod {
+    Password,
+    /// `org.matrix.login.jwt`
+    Jwt,
+    /// `m.login.token`
+    Token,
+}
+
+/// How users log in, password login is disabled in servers only allowing SSO-style auth.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Login {
+    pub method: LoginMethod,
+    /// Key to sign HS256 JWTs, the localpart is used as subject
+    pub jwt_secret: Option<String>,
+    /// Token used by jwt or token login, `{localpart}` is replaced with the user localpart
+    pub token_template: Option<String>,
+}
+
+impl Login {
+    pub fn token(&self, localpart: &str) -> Option<String> {
+        self.token_template
+            .as_ref()
+            .map(|template| template.replace("{localpart}", localpart))
+    }
+}
+
+/// Localpart, password and display name of the users not loaded from a credentials file. Templates can use
+/// `{i}` (the user id number) and `{execution_id}`, the password and display name also `{localpart}`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Accounts {
+    pub localpart_template: String,
+    /// Every user has the same password when the template has no placeholders
+    pub password_template: String,
+    /// Display name set after registering, the server uses the localpart when missing
+    pub display_name_template: Option<String>,
+}
+
+impl Accounts {
+    pub fn localpart(&self, id_number: usize, execution_id: &str) -> String {
+        self.localpart_template
+            .replace("{i}", &id_number.to_string())
+            .replace("{execution_id}", execution_id)
+    }
+
+    pub fn password(&self, id_number: usize, execution_id: &str) -> String {
+        self.render(&self.password_template, id_number, execution_id)
+    }
+
+    pub fn display_name(&self, id_number: usize, execution_id: &str) -> Option<String> {
+        self.display_name_template
+            .as_ref()
+            .map(|template| self.render(template, id_number, execution_id))
+    }
+
+    fn render(&self, template: &str, id_number: usize, execution_id: &str) -> String {
+        template
+            .replace("{localpart}", &self.localpart(id_number, execution_id))
+            .replace("{i}", &id_number.to_string())
+            .replace("{execution_id}", execution_id)
+    }
+}
+
+/// Credentials used to complete User-Interactive Auth stages during registration,
+/// needed when the homeserver doesn't allow registering with dummy auth only.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Registration {
+    /// Token for the `m.login.registration_token` stage
+    pub registration_token: Option<String>,
+    /// Response for the `m.login.recaptcha` stage (ex: a test key accepted by the server)
+    pub recaptcha_response: Option<String>,
+    /// Synapse `registration_shared_secret`, when present users are registered through the admin API
+    pub shared_secret: Option<String>,
+}
+
+/// Act as an application service, users are virtual users in the AS namespace
+/// so they don't need to login (requests are sent with the AS token).
+#[derive(Debug, Deserialize, Clone)]
+pub struct Appservice {
+    pub as_token: String,
+    /// Server name used to build the virtual users ids, ex: "@user_1:server_name"
+    pub server_name: String,
+}
+
+/// Renew expired accounts on servers with account validity enabled
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccountValidity {
+    /// Access token of a server admin, used to call the renewal admin API
+    pub admin_token: String,
+}
+
+/// End the tick (or abort the simulation) when the server is not able to handle the load anymore
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct Watchdog {
+    /// Rolling window used to compute error rate and latency
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "window_in_secs")]
+    pub window: Duration,
+    /// Errors per request (0 to 1) in the window
+    pub max_error_rate: Option<f64>,
+    #[serde_as(as = "Option<DurationMilliSeconds<u64>>")]
+    #[serde(rename = "max_p99_latency_in_ms", default)]
+    pub max_p99_latency: Option<Duration>,
+    /// Thresholds are not checked until the window has this amount of requests
+    pub min_requests: usize,
+    /// Abort the whole run when a threshold is exceeded, by default only the tick being run is ended
+    #[serde(default)]
+    pub abort_run: bool,
+}
+
+/// Account data written by users as real clients do: settings blobs, recent rooms and per-room preferences
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccountData {
+    /// Probability (0 to 100) of writing global account data when a user acts
+    pub global_probability: f64,
+    /// Probability (0 to 100) of writing account data of one of the user rooms when a user acts
+    pub room_probability: f64,
+    /// Size in bytes of the settings blobs
+    pub blob_size: usize,
+}
+
+/// Users register an HTTP pusher after login, so the server evaluates push rules and sends notifications
+#[derive(Debug, Deserialize, Clone)]
+pub struct Push {
+    /// Push gateway notify endpoint, ex: "http://localhost:5000/_matrix/push/v1/notify"
+    pub gateway_url: String,
+    /// Start a push gateway on this address (ex: "0.0.0.0:5000") to measure push latency, `gateway_url` must reach it
+    pub gateway_address: Option<String>,
+}
+
+/// Friends call each other in their direct message rooms, only the signaling events are sent
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct Calls {
+    /// Probability (0 to 100) of calling a friend when a user acts
+    pub probability: f64,
+    /// Calls are hung up the first time the caller acts after this duration
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "duration_in_secs")]
+    pub duration: Duration,
+}
+
+/// Homeserver Prometheus metrics scraped when the simulation starts and ends, embedded in the report
+#[derive(Debug, Deserialize, Clone)]
+pub struct ServerMetrics {
+    /// Metrics endpoint, ex: "http://localhost:9000/_synapse/metrics"
+    pub url: String,
+    /// Metrics scraped, the defaults of the server flavor when empty
+    #[serde(default)]
+    pub metrics: Vec<String>,
+}
+
+impl ServerMetrics {
+    /// CPU and memory of the process, plus events persisted and database usage for Synapse.
+    /// Conduit has no metrics endpoint, the configuration is rejected by `plan::validate`.
+    pub fn metrics(&self, flavor: ServerFlavor) -> Vec<String> {
+        if !self.metrics.is_empty() {
+            return self.metrics.clone();
+        }
+        let process = ["process_cpu_seconds_total", "process_resident_memory_bytes"];
+        let synapse = [
+            "synapse_storage_events_persisted_events_total",
+            "synapse_background_process_db_txn_count_total",
+            "synapse_background_process_db_sched_duration_seconds_total",
+        ];
+        if flavor == ServerFlavor::Synapse {
+            process
+                .iter()
+                .chain(synapse.iter())
+                .map(|name| name.to_string())
+                .collect()
+        } else {
+            process.map(String::from).to_vec()
+        }
+    }
+}
+
+/// Reports are uploaded to a bucket when they are stored, so they survive the machine running the simulation.
+/// Credentials can be left out to read them from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Upload {
+    pub provider: UploadProvider,
+    pub bucket: String,
+    /// Objects are stored as `<prefix>/<execution_id>/<file>`
+    pub prefix: Option<String>,
+    /// Region of the S3 bucket, GCS ignores it
+    #[serde(default = "default_upload_region")]
+    pub region: String,
+    /// S3 compatible endpoint used instead of the provider one (path style), ex: "http://localhost:9000" for MinIO
+    pub endpoint: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// Token of temporary credentials, sent as `x-amz-security-token`
+    pub session_token: Option<String>,
+}
+
+fn default_upload_region() -> String {
+    "us-east-1".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadProvider {
+    S3,
+    /// Google Cloud Storage XML API, credentials are HMAC keys of a service account
+    Gcs,
+}
+
+/// A summary of the run (status, key metrics and whether the watchdog thresholds were met) is posted when it ends
+#[derive(Debug, Deserialize, Clone)]
+pub struct Notification {
+    pub webhook_url: String,
+    /// Payload expected by the webhook
+    #[serde(default = "default_notification_format")]
+    pub format: NotificationFormat,
+}
+
+fn default_notification_format() -> NotificationFormat {
+    NotificationFormat::Json
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationFormat {
+    /// The summary as a JSON object
+    Json,
+    /// Incoming webhook message (`text`)
+    Slack,
+    /// Webhook message (`content`)
+    Discord,
+}
+
+/// Key metrics of the run are compared against the ones of a previous report when it finishes,
+/// the metrics that got worse beyond the tolerance are listed in the `baseline_comparison` section of the report
+#[derive(Debug, Deserialize, Clone)]
+pub struct Baseline {
+    /// Report (yaml) of the run to compare against, ex: the last one before a homeserver upgrade
+    pub report: String,
+    /// Change (in %) of a metric allowed before it is considered a regression
+    #[serde(default = "default_baseline_tolerance")]
+    pub tolerance_percent: f64,
+    /// Exit with an error when any metric regressed
+    #[serde(default)]
+    pub fail_on_regression: bool,
+}
+
+fn default_baseline_tolerance() -> f64 {
+    10.
+}
+
+/// Every request attempt (timestamp, user, endpoint, duration and status) is written to a compressed file
+/// next to the report, for offline analysis of what the aggregates hide
+#[derive(Debug, Deserialize, Clone)]
+pub struct RequestLog {
+    #[serde(default = "default_request_log_format")]
+    pub format: RequestLogFormat,
+}
+
+fn default_request_log_format() -> RequestLogFormat {
+    RequestLogFormat::Ndjson
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestLogFormat {
+    Ndjson,
+    Csv,
+}
+
+///
+/// Headers, path prefix, proxies and network conditions of the requests sent by the users clients, ex: auth of an internal gateway.
+/// Header values can use `{user}` (user id number) and `{ip}` (a fake IP per user, ex: for `X-Forwarded-For`).
+///
+#[derive(Debug, Deserialize, Clone)]
+pub struct Http {
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Path between the homeserver URL and the Matrix API, ex: "/matrix" for `https://gateway/matrix/_matrix/client`
+    pub path_prefix: Option<String>,
+    /// HTTP or SOCKS5 proxies (ex: "socks5://10.0.0.2:1080"), users are spread across them
+    #[serde(default)]
+    pub proxies: Vec<String>,
+    pub network: Option<Network>,
+    #[serde(default)]
+    pub groups: Vec<HttpGroup>,
+}
+
+/// Users with id numbers from `from` (included) to `to` (excluded) send these headers on top of the global ones,
+/// and use this path prefix, proxies and network conditions instead of the global ones
+#[derive(Debug, Deserialize, Clone)]
+pub struct HttpGroup {
+    pub from: usize,
+    pub to: usize,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub path_prefix: Option<String>,
+    #[serde(default)]
+    pub proxies: Vec<String>,
+    pub network: Option<Network>,
+}
+
+///
+/// Poor network simulated on the client side, ex: mobile users. Every attempt of a request waits the latency
+/// (plus or minus a random jitter) before being sent, and dropped requests fail with a timeout.
+///
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct Network {
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "latency_in_ms", default)]
+    pub latency: Duration,
+    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    #[serde(rename = "jitter_in_ms", default)]
+    pub jitter: Duration,
+    /// Probability (0 to 100) of a request never reaching the server
+    #[serde(default)]
+    pub drop_probability: f64,
+}
+
+impl Network {
+    pub fn delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.latency;
+        }
+        let jitter = rand::thread_rng().gen_range(0..=self.jitter.as_millis() as u64 * 2);
+        (self.latency + Duration::from_millis(jitter)).saturating_sub(self.jitter)
+    }
+
+    pub fn drops_request(&self) -> bool {
+        self.drop_probability > 0.
+            && rand::thread_rng().gen_bool((self.drop_probability / 100.).min(1.))
+    }
+}
+
+impl Chaos {
+    pub fn restarts_sync(&self) -> bool {
+        self.sync_restart_probability > 0.
+            && rand::thread_rng().gen_bool((self.sync_restart_probability / 100.).min(1.))
+    }
+
+    /// Time after which the request connection is dropped, if it has to be dropped
+    pub fn drop_after(&self) -> Option<Duration> {
+        if self.request_drop_probability > 0.
+            && rand::thread_rng().gen_bool((self.request_drop_probability / 100.).min(1.))
+        {
+            // up to half a second, most requests are still in flight by then
+            Some(Duration::from_millis(rand::thread_rng().gen_range(0..500)))
+        } else {
+            None
+        }
+    }
+}
+
+impl Http {
+    pub fn headers(&self, id_number: usize) -> Vec<(String, String)> {
+        let group_headers = self.group(id_number).map(|group| &group.headers);
+        self.headers
+            .iter()
+            .filter(|(name, _)| group_headers.map_or(true, |headers| !headers.contains_key(*name)))
+            .chain(group_headers.into_iter().flatten())
+            .map(|(name, value)| {
+                let value = value
+                    .replace("{user}", &id_number.to_string())
+                    .replace("{ip}", &fake_ip(id_number));
+                (name.clone(), value)
+            })
+            .collect()
+    }
+
+    pub fn path_prefix(&self, id_number: usize) -> Option<&str> {
+        self.group(id_number)
+            .and_then(|group| group.path_prefix.as_deref())
+            .or(self.path_prefix.as_deref())
+    }
+
+    pub fn network(&self, id_number: usize) -> Option<&Network> {
+        self.group(id_number)
+            .and_then(|group| group.network.as_ref())
+            .or(self.network.as_ref())
+    }
+
+    /// Proxy of the user, users are assigned to the proxies in order
+    pub fn proxy(&self, id_number: usize) -> Option<&str> {
+        let proxies = match self.group(id_number) {
+            Some(group) if !group.proxies.is_empty() => &group.proxies,
+            _ => &self.proxies,
+        };
+        if proxies.is_empty() {
+            return None;
+        }
+        Some(proxies[id_number % proxies.len()].as_str())
+    }
+
+    /// Users send different headers or use different proxies, so they can't share an HTTP client
+    pub fn is_per_user(&self) -> bool {
+        !self.groups.is_empty()
+            || self.proxies.len() > 1
+            || self.headers.values().any(|value| value.contains('{'))
+    }
+
+    fn group(&self, id_number: usize) -> Option<&HttpGroup> {
+        self.groups
+            .iter()
+            .find(|group| (group.from..group.to).contains(&id_number))
+    }
+}
+
+/// IP in the 10.0.0.0/8 range, different for every user up to 2^24 users
+fn fake_ip(id_number: usize) -> String {
+    format!(
+        "10.{}.{}.{}",
+        (id_number >> 16) & 0xff,
+        (id_number >> 8) & 0xff,
+        id_number & 0xff
+    )
+}
+
+/// Snapshots of the report summary written while the simulation runs, to see degradation before it ends
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct InterimReports {
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "every_in_secs")]
+    pub every: Duration,
+    #[serde(default = "default_interim_target")]
+    pub target: InterimTarget,
+}
+
+fn default_interim_target() -> InterimTarget {
+    InterimTarget::Stdout
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum InterimTarget {
+    Stdout,
+    /// JSON lines file next to the report
+    File,
+    /// Prometheus text file next to the report, replaced on every snapshot
+    Prometheus,
+}
+
+/// TLS options of the users clients, ex: for staging servers with self-signed certificates
+#[derive(Debug, Deserialize, Clone)]
+pub struct Tls {
+    /// PEM file with the certificates of the CAs trusted on top of the system ones
+    pub ca_bundle: Option<String>,
+    /// Accept any certificate, even expired, self-signed or for another host
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+}
+
+/// All users clients share a single HTTP connection pool instead of opening their own connections
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConnectionPool {
+    /// Idle connections kept open to the homeserver
+    pub max_idle_connections: usize,
+    /// Send HTTP/2 requests without negotiating it, for homeservers serving h2c over plain HTTP.
+    /// HTTP/2 is negotiated on TLS connections regardless of this
+    pub http2_prior_knowledge: bool,
+}
+
+/// Syncing users send messages at a target rate instead of picking them as random actions.
+/// Each user rate is drawn from a log-normal distribution with the configured mean.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MessageRate {
+    /// Mean of the messages per minute sent by a syncing user, responses not included
+    pub per_minute: f64,
+    /// Standard deviation of the logarithm of the user rates, 0 to send at the same rate
+    pub sigma: f64,
+}
+
+///
+/// Mobile app lifecycle: users go background, with their sync loop paused, and come back to foreground
+/// with a catch-up sync. Every period lasts between half and one and a half times its configured duration.
+///
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct Lifecycle {
+    /// Probability (0 to 100) of a user following the lifecycle, the rest are always-on clients
+    pub probability: f64,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "foreground_in_secs")]
+    pub foreground: Duration,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "background_in_secs")]
+    pub background: Duration,
+}
+
+impl Lifecycle {
+    pub fn foreground_period(&self) -> Duration {
+        self.foreground
+            .mul_f64(rand::thread_rng().gen_range(0.5..1.5))
+    }
+
+    pub fn background_period(&self) -> Duration {
+        self.background
+            .mul_f64(rand::thread_rng().gen_range(0.5..1.5))
+    }
+}
+
+/// Users failing to register, log in or make their initial sync `max_failures` times in a row, ex: broken accounts
+#[derive(Debug, Deserialize, Clone)]
+pub struct StuckUsers {
+    pub max_failures: usize,
+    #[serde(default = "default_stuck_user_recovery")]
+    pub recovery: StuckUserRecovery,
+}
+
+fn default_stuck_user_recovery() -> StuckUserRecovery {
+    StuckUserRecovery::Quarantine
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum StuckUserRecovery {
+    /// The user is never picked to act again
+    Quarantine,
+    /// The user client is reset and the user logs in again
+    Restart,
+}
+
+/// Abusers mass-inviting other users to new rooms instead of socializing, to test the anti-abuse rate limits of
+/// the homeserver and how invite storms impact the sync of their victims
+#[derive(Debug, Deserialize, Clone)]
+pub struct InviteSpam {
+    /// Amount of abusers, the users with the lowest id numbers
+    pub abusers: usize,
+    /// Syncing users invited, one by one, to a new room every time an abuser acts
+    pub invites_per_act: usize,
+}
+
+impl InviteSpam {
+    pub fn is_abuser(&self, id_number: usize, first_user_id: usize) -> bool {
+        id_number.saturating_sub(first_user_id) < self.abusers
+    }
+}
+
+/// Users ignoring the client pacing (Retry-After, adaptive pace and request limits) to send bursts of requests as
+/// fast as possible, to check how quickly and consistently the homeserver rate limits them
+#[derive(Debug, Deserialize, Clone)]
+pub struct RateLimitProbe {
+    /// Probability (0 to 100) of a user probing instead of socializing
+    pub probability: f64,
+    #[serde(default = "default_probe_endpoint")]
+    pub endpoint: ProbeEndpoint,
+    /// Requests sent every time a prober acts
+    pub requests_per_burst: usize,
+}
+
+fn default_probe_endpoint() -> ProbeEndpoint {
+    ProbeEndpoint::SendMessage
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeEndpoint {
+    /// Messages to one of the prober rooms, presence updates when it has none
+    SendMessage,
+    UpdateStatus,
+}
+
+/// Users forking the state of a shared room (concurrent state events and membership flapping) to grow its
+/// forward extremities, while the latency of their messages to it shows how the homeserver degrades
+#[derive(Debug, Deserialize, Clone)]
+pub struct ForwardExtremities {
+    /// Probability (0 to 100) of a user forking the room instead of socializing
+    pub probability: f64,
+    /// State events sent concurrently every time a forker acts
+    #[serde(default = "default_state_events_per_act")]
+    pub state_events_per_act: usize,
+    /// Probability (0 to 100) of a forker leaving the room and joining it again when it acts
+    #[serde(default)]
+    pub flap_probability: f64,
+}
+
+impl ForwardExtremities {
+    pub fn flaps(&self) -> bool {
+        self.flap_probability > 0.
+            && rand::thread_rng().gen_bool((self.flap_probability / 100.).min(1.))
+    }
+
+    /// Alias of the room forked by all the users of the execution
+    pub fn room_alias_name(&self, execution_id: &str) -> String {
+        format!("forward_extremities_{execution_id}")
+    }
+}
+
+fn default_state_events_per_act() -> usize {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Standalone,
+    Coordinator,
+    Worker,
+}
+
+/// Handful of users running a fixed script (login, send, receive and read receipt) against the homeserver
+/// in canary mode, a synthetic monitor of the chat availability
+#[serde_as]
+#[derive(Debug, Deserialize, Clone)]
+pub struct Canary {
+    /// Users running the script, the first one sends the message and the others receive it (at least 2)
+    pub users: usize,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "every_in_secs")]
+    pub every: Duration,
+    /// Time for the receivers to get the message before the run fails
+    #[serde_as(as = "DurationSeconds<u64>")]
+    #[serde(rename = "timeout_in_secs")]
+    pub timeout: Duration,
+    /// Address where the results are served for Prometheus (`/metrics`), ex: "0.0.0.0:9464"
+    pub metrics_address: String,
+}
+
+/// Distributed mode: a coordinator splits the users between workers and merges their reports
+#[derive(Debug, Deserialize, Clone)]
+pub struct Distributed {
+    pub role: Role,
+    /// Address where the coordinator listens and workers connect to, ex: "127.0.0.1:7878"
+    pub coordinator_address: String,
+    /// Number of workers the coordinator waits for before starting the simulation
+    pub workers: usize,
 }
 
 #[derive(Debug, Deserialize)]
@@ -93,44 +1172,370 @@ pub struct Config {
     pub server: Server,
     pub simulation: Simulation,
     pub requests: Requests,
+    pub retry: Retry,
+    pub timeouts: Timeouts,
     pub feature_flags: FeatureFlags,
+    pub messages: Messages,
+    pub friendships: Friendships,
+    pub rooms: Rooms,
+    #[serde(default)]
+    pub custom_events: Vec<CustomEvent>,
+    pub login: Login,
+    pub accounts: Accounts,
+    #[serde(default)]
+    pub registration: Registration,
+    pub appservice: Option<Appservice>,
+    pub account_validity: Option<AccountValidity>,
+    pub watchdog: Option<Watchdog>,
+    pub calls: Option<Calls>,
+    pub push: Option<Push>,
+    pub account_data: Option<AccountData>,
+    pub message_rate: Option<MessageRate>,
+    pub lifecycle: Option<Lifecycle>,
+    pub stuck_users: Option<StuckUsers>,
+    pub invite_spam: Option<InviteSpam>,
+    pub rate_limit_probe: Option<RateLimitProbe>,
+    pub forward_extremities: Option<ForwardExtremities>,
+    pub fuzzing: Option<Fuzzing>,
+    pub chaos: Option<Chaos>,
+    pub connection_pool: Option<ConnectionPool>,
+    pub http: Option<Http>,
+    pub tls: Option<Tls>,
+    pub server_metrics: Option<ServerMetrics>,
+    pub upload: Option<Upload>,
+    pub notification: Option<Notification>,
+    pub baseline: Option<Baseline>,
+    pub request_log: Option<RequestLog>,
+    pub interim_reports: Option<InterimReports>,
+    pub distributed: Distributed,
+    pub canary: Canary,
+    #[serde(skip)]
+    pub command: Option<Command>,
+    /// Effective configuration (defaults, files and overrides) with the secrets redacted, embedded in the reports
+    #[serde(skip)]
+    pub snapshot: Value,
 }
 
+/// Values whose key contains any of these words are redacted from the snapshot, ex: `jwt.secret` or `appservice.as_token`
+/// Keys of the values redacted, the token of webhooks is part of their URL
+const SECRET_KEYS: [&str; 5] = ["secret", "password", "token", "authorization", "webhook"];
+const REDACTED: &str = "<redacted>";
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct FeatureFlags {
     pub channels_load: bool,
     pub allow_get_channel_members: bool,
     pub presence_enabled: bool,
+    /// Publish channels to the public rooms directory and let users browse it to join them by alias
+    pub directory_enabled: bool,
+    /// Users create spaces with their channels as children and browse their hierarchy to join them
+    pub spaces_enabled: bool,
+    /// Channel creators change power levels, kick or ban members of their channels
+    pub moderation_enabled: bool,
+    /// Users upload device and one-time keys on login, query their friends keys and claim one-time keys
+    pub keys_enabled: bool,
+    /// Users search the user directory with prefixes of other users localparts
+    pub user_directory_enabled: bool,
+}
+
+/// What a user does once it is syncing, every user has one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserRole {
+    /// Socializes with its friends and rooms
+    Social,
+    /// Lurker that only keeps syncing
+    Idle,
+    /// Mass-invites other users
+    Abuser,
+    /// Sends bursts of requests to probe the rate limits
+    Prober,
+    /// Forks the state of a shared room to grow its forward extremities
+    Forker,
+}
+
+/// SplitMix64 of the id number, unlike `DefaultHasher` it doesn't change across Rust releases
+fn stable_hash(id_number: usize) -> u64 {
+    let mut hash = (id_number as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
 }
 
 impl Config {
+    ///
+    /// Role of the user: abusers are the users with the lowest id numbers, the other roles take consecutive
+    /// slices of the users by a stable hash of their id number, so a user gets the same role when a run is resumed
+    /// or replayed, even by another build of the tool.
+    ///
+    pub fn role(&self, id_number: usize) -> UserRole {
+        let abuser = self.invite_spam.as_ref().map_or(false, |invite_spam| {
+            invite_spam.is_abuser(id_number, self.simulation.first_user_id)
+        });
+        if abuser {
+            return UserRole::Abuser;
+        }
+        // hundredths of percent
+        let bucket = (stable_hash(id_number) % 10_000) as f64;
+        let roles = [
+            (UserRole::Idle, self.simulation.probability_for_idle_users),
+            (
+                UserRole::Prober,
+                self.rate_limit_probe
+                    .as_ref()
+                    .map_or(0., |probe| probe.probability),
+            ),
+            (
+                UserRole::Forker,
+                self.forward_extremities
+                    .as_ref()
+                    .map_or(0., |forward_extremities| forward_extremities.probability),
+            ),
+        ];
+        let mut until = 0.;
+        for (role, probability) in roles {
+            until += probability * 100.;
+            if bucket < until {
+                return role;
+            }
+        }
+        UserRole::Social
+    }
+
+    /// Homeserver URL of the user with the path prefix of its group
+    pub fn base_url(&self, id_number: usize) -> String {
+        let base_url = self.server.base_url(id_number);
+        match self
+            .http
+            .as_ref()
+            .and_then(|http| http.path_prefix(id_number))
+        {
+            Some(prefix) => format!(
+                "{}/{}",
+                base_url.trim_end_matches('/'),
+                prefix.trim_matches('/')
+            ),
+            None => base_url,
+        }
+    }
+
+    /// Configuration of the binary: `configuration.toml` overridden by the command line arguments
     pub fn new() -> Result<Self, ConfigError> {
         let args = Args::parse();
         log::debug!("Args: {:#?}", args);
 
-        let config = config::Config::builder()
+        let config = with_defaults(config::Config::builder())?
             .add_source(File::with_name("configuration"))
-            .set_override("server.homeserver", args.homeserver)?
+            .set_override_option("server.homeserver", args.homeserver)?
             .set_override_option("simulation.ticks", args.ticks)?
             .set_override_option("simulation.duration", args.duration)?
             .set_override_option("simulation.max_users", args.max_users)?
             .set_override_option("simulation.users_per_tick", args.users_per_tick)?
             .set_override_option("simulation.output", args.output)?
-            .set_default("simulation.execution_id", time_now().to_string())?
             .set_override_option("simulation.execution_id", args.execution_id)?
-            .set_default("simulation.probability_to_act", 100.)?
-            .set_default("simulation.probability_for_short_lifes", 50.)?
+            .set_override_option("simulation.execution_id", args.resume.clone())?
+            .set_override_option("simulation.resume", args.resume.is_some().then_some(true))?
             .set_override_option("simulation.probability_to_act", args.probability_to_act)?
             .set_override_option(
                 "simulation.probability_for_short_lifes",
                 args.probability_for_short_lifes,
             )?
-            .set_default("feature_flags.channels_load", true)?
-            .set_default("feature_flags.allow_get_channel_members", false)?
-            .set_default("feature_flags.presence_enabled", true)?
+            .set_override_option("simulation.credentials_file", args.credentials_file)?
+            .set_override_option("simulation.record", args.record)?
+            .set_override_option("simulation.replay", args.replay)?
+            .set_override_option("simulation.dashboard", args.dashboard.then_some(true))?
+            .set_override_option("simulation.fast_forward", args.fast_forward.then_some(true))?
+            .set_override_option("simulation.dry_run", args.dry_run.then_some(true))?
+            .set_override_option(
+                "simulation.preflight_check",
+                args.skip_preflight.then_some(false),
+            )?
+            .set_override_option("distributed.role", args.role)?
+            .set_override_option("baseline.report", args.baseline)?
             .build()?;
 
         log::debug!("Config: {:#?}", config);
-        config.try_deserialize()
+        let mut config = Self::deserialize_with_snapshot(config)?;
+        config.command = args.command;
+        Ok(config)
     }
+
+    fn deserialize_with_snapshot(config: config::Config) -> Result<Self, ConfigError> {
+        let snapshot = config.clone().try_deserialize::<Value>()?;
+        let mut config: Self = config.try_deserialize()?;
+        config.snapshot = redact_secrets(snapshot);
+        let errors = crate::plan::validate(&config);
+        if !errors.is_empty() {
+            return Err(ConfigError::Message(format!(
+                "invalid configuration:\n - {}",
+                errors.join("\n - ")
+            )));
+        }
+        Ok(config)
+    }
+
+    /// Builder of a configuration (with the defaults) for simulations run as a library
+    pub fn builder() -> Result<ConfigBuilder, ConfigError> {
+        Ok(ConfigBuilder {
+            builder: with_defaults(config::Config::builder())?,
+        })
+    }
+}
+
+/// Builds a configuration in code, ex: to run simulations from integration tests or other crates.
+/// Values not set fall back to the defaults of the binary, the required ones (ex: `server.homeserver`) must be set.
+pub struct ConfigBuilder {
+    builder: config::ConfigBuilder<config::builder::DefaultState>,
+}
+
+impl ConfigBuilder {
+    /// Read a TOML configuration file, ex: "configuration.toml"
+    pub fn file(mut self, path: &str) -> Self {
+        self.builder = self.builder.add_source(File::with_name(path));
+        self
+    }
+
+    /// Read a TOML configuration, ex: a scenario embedded in a test
+    pub fn toml(mut self, toml: &str) -> Self {
+        self.builder = self
+            .builder
+            .add_source(File::from_str(toml, FileFormat::Toml));
+        self
+    }
+
+    /// Set a value by its path, it takes precedence over files, ex: `set("simulation.ticks", 10)`
+    pub fn set(mut self, key: &str, value: impl Into<config::Value>) -> Result<Self, ConfigError> {
+        self.builder = self.builder.set_override(key, value)?;
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<Config, ConfigError> {
+        Config::deserialize_with_snapshot(self.builder.build()?)
+    }
+}
+
+fn redact_secrets(value: Value) -> Value {
+    match value {
+        Value::Object(values) => Value::Object(
+            values
+                .into_iter()
+                .map(|(key, value)| {
+                    let key_lowercase = key.to_lowercase();
+                    let secret = value.is_string()
+                        && SECRET_KEYS
+                            .iter()
+                            .any(|secret| key_lowercase.contains(secret));
+                    let value = match value {
+                        _ if secret => Value::String(REDACTED.to_string()),
+                        // headers of the requests often carry API keys, whatever their name
+                        Value::Object(headers) if key == "headers" => Value::Object(
+                            headers
+                                .into_iter()
+                                .map(|(name, _)| (name, Value::String(REDACTED.to_string())))
+                                .collect(),
+                        ),
+                        value => redact_secrets(value),
+                    };
+                    (key, value)
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(redact_secrets).collect()),
+        Value::String(value) => Value::String(redact_userinfo(&value).unwrap_or(value)),
+        value => value,
+    }
+}
+
+/// URL without its user and password, ex: the credentials of a proxy, none when it has none
+fn redact_userinfo(value: &str) -> Option<String> {
+    let (scheme, rest) = value.split_once("://")?;
+    let authority_end = rest.find('/').unwrap_or(rest.len());
+    let (_, host) = rest[..authority_end].rsplit_once('@')?;
+    Some(format!(
+        "{scheme}://{REDACTED}@{host}{}",
+        &rest[authority_end..]
+    ))
+}
+
+/// Values that can be left out of the configuration
+fn with_defaults(
+    builder: config::ConfigBuilder<config::builder::DefaultState>,
+) -> Result<config::ConfigBuilder<config::builder::DefaultState>, ConfigError> {
+    builder
+        .set_default("server.flavor", "synapse")?
+        .set_default("server.base_urls", Vec::<String>::new())?
+        .set_default("server.base_url_policy", "round_robin")?
+        .set_default("simulation.execution_id", time_now().to_string())?
+        .set_default("simulation.checkpoint_every_ticks", 10)?
+        .set_default("simulation.warm_up_in_secs", 0)?
+        .set_default("simulation.max_spaces", 3)?
+        .set_default("simulation.resume", false)?
+        .set_default("simulation.probability_to_act", 100.)?
+        .set_default("simulation.probability_for_short_lifes", 50.)?
+        .set_default("simulation.probability_to_leave_room", 0)?
+        .set_default("simulation.probability_to_send_to_device", 0)?
+        .set_default("simulation.probability_to_search_messages", 0)?
+        .set_default("simulation.probability_to_fetch_event", 0)?
+        .set_default("simulation.probability_to_update_room", 0)?
+        .set_default("simulation.probability_to_upgrade_room", 0)?
+        .set_default("simulation.probability_to_ignore_user", 0)?
+        .set_default("simulation.probability_to_tag_room", 0)?
+        .set_default("simulation.max_sync_events_per_user", 1000)?
+        .set_default("simulation.probability_for_idle_users", 0.)?
+        .set_default("simulation.probability_to_bootstrap_cross_signing", 0)?
+        .set_default("simulation.first_user_id", 0)?
+        .set_default("simulation.fast_forward", false)?
+        .set_default("simulation.dashboard", false)?
+        .set_default("simulation.dry_run", false)?
+        .set_default("simulation.preflight_check", true)?
+        .set_default("simulation.teardown", "keep")?
+        .set_default("distributed.role", "standalone")?
+        .set_default("distributed.coordinator_address", "127.0.0.1:7878")?
+        .set_default("distributed.workers", 1)?
+        .set_default("canary.users", 2)?
+        .set_default("canary.every_in_secs", 60)?
+        .set_default("canary.timeout_in_secs", 30)?
+        .set_default("canary.metrics_address", "0.0.0.0:9464")?
+        .set_default("feature_flags.channels_load", true)?
+        .set_default("feature_flags.allow_get_channel_members", false)?
+        .set_default("feature_flags.presence_enabled", true)?
+        .set_default("feature_flags.directory_enabled", false)?
+        .set_default("feature_flags.spaces_enabled", false)?
+        .set_default("feature_flags.moderation_enabled", false)?
+        .set_default("feature_flags.keys_enabled", false)?
+        .set_default("feature_flags.user_directory_enabled", false)?
+        .set_default("requests.refresh_token_enabled", false)?
+        .set_default("requests.adaptive_pace", false)?
+        .set_default("timeouts.default_in_secs", 30)?
+        .set_default("timeouts.sync_in_secs", 30)?
+        .set_default("timeouts.send_in_secs", 30)?
+        .set_default("timeouts.register_in_secs", 30)?
+        .set_default("timeouts.login_in_secs", 30)?
+        .set_default("timeouts.create_room_in_secs", 30)?
+        .set_default("retry.default.max_attempts", 1)?
+        .set_default("retry.default.base_delay_ms", 200)?
+        .set_default("retry.default.max_delay_ms", 5000)?
+        .set_default("retry.default.jitter", true)?
+        .set_default("retry.default.retryable_status_codes", vec![502, 503, 504])?
+        .set_default("login.method", "password")?
+        .set_default("accounts.localpart_template", "user_{i}_{execution_id}")?
+        .set_default("accounts.password_template", "asdfasdf")?
+        .set_default("messages.emoji_probability", 0)?
+        .set_default("messages.rtl_probability", 0)?
+        .set_default("messages.url_probability", 0)?
+        .set_default("messages.sticker_probability", 0)?
+        .set_default("messages.location_probability", 0)?
+        .set_default("messages.length.min", 20)?
+        .set_default("messages.length.max", 200)?
+        .set_default("messages.length.mu", 4.)?
+        .set_default("messages.length.sigma", 0.5)?
+        .set_default("messages.length.large_probability", 0.)?
+        .set_default("messages.length.large_length", 16384)?
+        .set_default("messages.urls", vec!["https://matrix.org"])?
+        .set_default("rooms.direct.preset", "trusted_private_chat")?
+        .set_default("rooms.channel.preset", "public_chat")?
+        .set_default("rooms.upgrade_version", "10")?
+        .set_default("rooms.permutations", false)?
+        .set_default("friendships.model", "random")?
+        .set_default("friendships.average_friends", 10)?
+        .set_default("friendships.rewiring_probability", 0.1)
 }
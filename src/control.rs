@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+// how often a paused simulation checks if it was resumed
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Pause and resume the simulation with SIGUSR1, users don't act while paused but their sync loops are kept alive.
+#[derive(Clone, Default)]
+pub struct PauseControl {
+    paused: Arc<AtomicBool>,
+}
+
+impl PauseControl {
+    ///
+    /// Start listening SIGUSR1, every signal toggles the pause.
+    ///
+    /// # Panics
+    ///
+    /// If the signal handler cannot be registered.
+    ///
+    #[cfg(unix)]
+    pub fn listen() -> Self {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let control = Self::default();
+        let paused = control.paused.clone();
+        let mut signals =
+            signal(SignalKind::user_defined1()).expect("SIGUSR1 handler to be registered");
+        tokio::spawn(async move {
+            while signals.recv().await.is_some() {
+                let was_paused = paused.fetch_xor(true, Ordering::Relaxed);
+                if was_paused {
+                    println!("simulation resumed");
+                } else {
+                    println!("simulation paused, send SIGUSR1 again to resume");
+                }
+            }
+        });
+        control
+    }
+
+    /// Signals are not available, the simulation cannot be paused
+    #[cfg(not(unix))]
+    pub fn listen() -> Self {
+        Self::default()
+    }
+
+    /// Wait until the simulation is resumed, returns whether it was paused
+    pub async fn wait_while_paused(&self) -> bool {
+        let mut was_paused = false;
+        while self.paused.load(Ordering::Relaxed) {
+            was_paused = true;
+            sleep(PAUSE_CHECK_INTERVAL).await;
+        }
+        was_paused
+    }
+}
@@ -0,0 +1,56 @@
+use serde::Deserialize;
+use std::fs::File;
+use std::path::Path;
+
+/// Credentials of an account provisioned outside of the tool.
+/// `user_id` can be a localpart or a full Matrix ID (required to login with an access token).
+#[derive(Debug, Deserialize, Clone)]
+pub struct Credentials {
+    pub user_id: String,
+    pub password: Option<String>,
+    pub access_token: Option<String>,
+    pub device_id: Option<String>,
+}
+
+impl Credentials {
+    pub fn localpart(&self) -> &str {
+        // "@localpart:server" => "localpart"
+        self.user_id
+            .trim_start_matches('@')
+            .split(':')
+            .next()
+            .unwrap_or(&self.user_id)
+    }
+}
+
+///
+/// Load credentials from a JSON (array of objects) or CSV (with header `user_id,password,access_token,device_id`) file.
+///
+/// # Panics
+///
+/// If the file cannot be read, has an invalid format or has no credentials, instead of silently generating users.
+///
+pub fn load_credentials(path: &str) -> Vec<Credentials> {
+    let file =
+        File::open(path).unwrap_or_else(|_| panic!("could not open credentials file {path}"));
+
+    let is_json = Path::new(path)
+        .extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("json"));
+
+    let credentials: Vec<Credentials> = if is_json {
+        serde_json::from_reader(file)
+            .unwrap_or_else(|e| panic!("could not parse credentials file {path}: {e}"))
+    } else {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(file)
+            .deserialize()
+            .collect::<Result<Vec<Credentials>, _>>()
+            .unwrap_or_else(|e| panic!("could not parse credentials file {path}: {e}"))
+    };
+    if credentials.is_empty() {
+        panic!("credentials file {path} has no credentials");
+    }
+    credentials
+}
@@ -0,0 +1,182 @@
+use crate::events::{LiveMetrics, LiveWindow};
+use crate::progress::{Progress, UsersOverview};
+use crossterm::execute;
+use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Gauge, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct DashboardState {
+    tick: usize,
+    total_ticks: usize,
+    users: UsersOverview,
+    total_errors: usize,
+}
+
+/// Live terminal dashboard showing request rates, error counts, latency percentiles and users per state.
+/// It is refreshed every second from a separated thread.
+pub struct Dashboard {
+    state: Arc<Mutex<DashboardState>>,
+    live_metrics: Arc<LiveMetrics>,
+    running: Arc<AtomicBool>,
+    render_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Dashboard {
+    pub fn new(total_ticks: usize, live_metrics: Arc<LiveMetrics>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(DashboardState {
+                total_ticks,
+                ..Default::default()
+            })),
+            live_metrics,
+            running: Arc::new(AtomicBool::new(false)),
+            render_thread: Mutex::new(None),
+        }
+    }
+
+    fn render_loop(
+        state: Arc<Mutex<DashboardState>>,
+        live_metrics: Arc<LiveMetrics>,
+        running: Arc<AtomicBool>,
+    ) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+        let mut last_render = Instant::now();
+        while running.load(Ordering::Relaxed) {
+            thread::sleep(REFRESH_INTERVAL);
+            let window = live_metrics.take();
+            let elapsed = last_render.elapsed();
+            last_render = Instant::now();
+
+            let mut state = state.lock().expect("lock not poisoned");
+            state.total_errors += window.errors;
+            terminal.draw(|frame| Self::draw(frame, &state, &window, elapsed))?;
+        }
+
+        execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+        terminal.show_cursor()
+    }
+
+    fn draw<B: Backend>(
+        frame: &mut Frame<B>,
+        state: &DashboardState,
+        window: &LiveWindow,
+        elapsed: Duration,
+    ) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Length(4),
+                Constraint::Min(5),
+            ])
+            .split(frame.size());
+
+        let ratio = if state.total_ticks > 0 {
+            state.tick as f64 / state.total_ticks as f64
+        } else {
+            0.
+        };
+        let gauge = Gauge::default()
+            .block(Block::default().title("Simulation").borders(Borders::ALL))
+            .ratio(ratio.min(1.))
+            .label(format!("tick {}/{}", state.tick, state.total_ticks));
+        frame.render_widget(gauge, chunks[0]);
+
+        let users = state
+            .users
+            .per_state
+            .iter()
+            .map(|(state, count)| format!("{state}: {count}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let errors_per_sec = window.errors as f64 / elapsed.as_secs_f64();
+        let summary = Paragraph::new(format!(
+            "{users}\nsync backlog: {}  errors/s: {:.1}  total errors: {}",
+            state.users.sync_backlog, errors_per_sec, state.total_errors
+        ))
+        .block(Block::default().title("Users").borders(Borders::ALL));
+        frame.render_widget(summary, chunks[1]);
+
+        let mut requests = window.requests.iter().collect::<Vec<_>>();
+        requests.sort_by_key(|(request, _)| request.to_string());
+        let rows = requests.into_iter().map(|(request, durations)| {
+            let mut durations = durations.clone();
+            durations.sort_unstable();
+            Row::new(vec![
+                request.to_string(),
+                format!("{:.1}", durations.len() as f64 / elapsed.as_secs_f64()),
+                format!("{}", percentile(&durations, 50).as_millis()),
+                format!("{}", percentile(&durations, 95).as_millis()),
+                format!("{}", percentile(&durations, 99).as_millis()),
+            ])
+        });
+        let table = Table::new(rows)
+            .header(
+                Row::new(vec!["request", "req/s", "p50 (ms)", "p95 (ms)", "p99 (ms)"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::default().title("Requests").borders(Borders::ALL))
+            .widths(&[
+                Constraint::Percentage(40),
+                Constraint::Percentage(15),
+                Constraint::Percentage(15),
+                Constraint::Percentage(15),
+                Constraint::Percentage(15),
+            ]);
+        frame.render_widget(table, chunks[2]);
+    }
+}
+
+/// Get percentile from sorted durations
+fn percentile(sorted: &[Duration], percentile: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = (sorted.len() * percentile / 100).min(sorted.len() - 1);
+    sorted[index]
+}
+
+impl Progress for Dashboard {
+    fn start(&self) {
+        self.running.store(true, Ordering::Relaxed);
+        let state = self.state.clone();
+        let live_metrics = self.live_metrics.clone();
+        let running = self.running.clone();
+        let handle = thread::spawn(move || {
+            if let Err(e) = Self::render_loop(state, live_metrics, running) {
+                log::error!("dashboard stopped: {}", e);
+            }
+        });
+        *self.render_thread.lock().expect("lock not poisoned") = Some(handle);
+    }
+
+    fn tick(&mut self, _users_syncing: u64) {
+        self.state.lock().expect("lock not poisoned").tick += 1;
+    }
+
+    fn update_users(&mut self, users: &UsersOverview) {
+        self.state.lock().expect("lock not poisoned").users = users.clone();
+    }
+
+    fn finish(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.render_thread.lock().expect("lock not poisoned").take() {
+            handle.join().expect("dashboard thread to finish");
+        }
+        println!("Simulation finished!");
+    }
+}
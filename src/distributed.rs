@@ -0,0 +1,150 @@
+use crate::configuration::Config;
+use crate::http_server;
+use crate::report::Report;
+use crate::simulation::Simulation;
+use crate::time::execution_id;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Range of users and schedule sent by the coordinator to each worker
+#[derive(Serialize, Deserialize, Debug)]
+struct Assignment {
+    execution_id: String,
+    first_user_id: usize,
+    max_users: usize,
+    users_per_tick: usize,
+    ticks: usize,
+    tick_duration: Duration,
+}
+
+///
+/// Wait for all the workers to connect, distribute the users between them and merge their reports into a single one.
+/// Workers and coordinator exchange one JSON message per line: the coordinator sends the assignment and the worker replies with its final report.
+///
+/// # Panics
+///
+/// If the coordinator cannot listen on the configured address.
+///
+pub async fn run_coordinator(config: Config) {
+    let address = &config.distributed.coordinator_address;
+    let workers = config.distributed.workers;
+    let listener = http_server::bind(address, "coordinator").await;
+
+    println!("coordinator waiting for {workers} workers on {address}");
+    let mut connections = vec![];
+    while connections.len() < workers {
+        match listener.accept().await {
+            Ok((stream, worker_address)) => {
+                println!(
+                    "worker {} connected from {}",
+                    connections.len(),
+                    worker_address
+                );
+                connections.push(stream);
+            }
+            Err(e) => log::debug!("couldn't accept worker connection: {}", e),
+        }
+    }
+
+    let assignments = assign_users(&config);
+    let reports = join_all(
+        connections
+            .into_iter()
+            .zip(assignments)
+            .map(|(stream, assignment)| drive_worker(stream, assignment)),
+    )
+    .await
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+
+    println!("received {} of {} worker reports", reports.len(), workers);
+
+    let output_folder = config.simulation.output.as_str();
+    let homeserver = config.server.homeserver.as_str();
+    let output_dir = format!("{output_folder}/{homeserver}");
+    Report::merge(&reports).generate(&output_dir, &execution_id(), None);
+}
+
+///
+/// Connect to the coordinator, run the assigned part of the simulation and send back the report.
+///
+/// # Panics
+///
+/// If the coordinator is not reachable or sends an invalid assignment.
+///
+pub async fn run_worker(mut config: Config) {
+    let address = config.distributed.coordinator_address.clone();
+    let stream = TcpStream::connect(&address)
+        .await
+        .unwrap_or_else(|e| panic!("could not connect to coordinator {address}: {e}"));
+    let (reader, mut writer) = stream.into_split();
+
+    let line = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await
+        .ok()
+        .flatten()
+        .expect("coordinator to send the assignment");
+    let assignment: Assignment = serde_json::from_str(&line).expect("assignment to be valid");
+    println!("worker assignment: {:#?}", assignment);
+
+    config.simulation.execution_id = assignment.execution_id;
+    config.simulation.first_user_id = assignment.first_user_id;
+    config.simulation.max_users = assignment.max_users;
+    config.simulation.users_per_tick = assignment.users_per_tick;
+    config.simulation.ticks = assignment.ticks;
+    config.simulation.tick_duration = assignment.tick_duration;
+
+    let report = Simulation::with(config).run().await;
+
+    let message = serde_json::to_string(&report).expect("report to be serializable") + "\n";
+    if let Err(e) = writer.write_all(message.as_bytes()).await {
+        log::error!("couldn't send report to coordinator: {}", e);
+    }
+}
+
+async fn drive_worker(stream: TcpStream, assignment: Assignment) -> Option<Report> {
+    let (reader, mut writer) = stream.into_split();
+
+    let message = serde_json::to_string(&assignment).ok()? + "\n";
+    writer.write_all(message.as_bytes()).await.ok()?;
+
+    // the worker answers with its report when the simulation ends
+    let line = BufReader::new(reader).lines().next_line().await.ok()??;
+    match serde_json::from_str(&line) {
+        Ok(report) => Some(report),
+        Err(e) => {
+            log::error!("invalid report received from worker: {}", e);
+            None
+        }
+    }
+}
+
+/// Split users (and users acting per tick) between workers, remainders go to the first workers.
+fn assign_users(config: &Config) -> Vec<Assignment> {
+    let workers = config.distributed.workers;
+    let split =
+        |total: usize, worker: usize| total / workers + usize::from(worker < total % workers);
+
+    let mut first_user_id = config.simulation.first_user_id;
+    (0..workers)
+        .map(|worker| {
+            let max_users = split(config.simulation.max_users, worker);
+            let assignment = Assignment {
+                execution_id: config.simulation.execution_id.clone(),
+                first_user_id,
+                max_users,
+                users_per_tick: split(config.simulation.users_per_tick, worker),
+                ticks: config.simulation.ticks,
+                tick_duration: config.simulation.tick_duration,
+            };
+            first_user_id += max_users;
+            assignment
+        })
+        .collect()
+}
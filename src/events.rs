@@ -1,10 +1,18 @@
-use crate::report::Report;
+use crate::configuration::StuckUserRecovery;
+use crate::interim::InterimWriter;
+use crate::report::{
+    Distribution, Report, ReportInput, ReportSummary, TickDistribution, TickThroughput, TickUsers,
+};
+use crate::request_log::RequestRecord;
 use crate::room::RoomType;
+use crate::watchdog::Watchdog;
 use matrix_sdk::locks::RwLock;
 use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId};
 use matrix_sdk::HttpError;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc;
 use std::sync::Arc;
+use std::sync::Mutex as SyncMutex;
 use std::time::Duration;
 use std::{collections::HashMap, time::Instant};
 use strum::Display;
@@ -13,15 +21,22 @@ use tokio::sync::mpsc::Sender;
 use tokio::task::JoinHandle;
 
 pub type SyncEventsSender = Sender<Event>;
+
+/// Status of the requests abandoned by the client after their timeout (nginx's "client closed request"),
+/// so they are not confused with the server answering 408
+pub const CLIENT_TIMEOUT: u16 = 499;
 pub type UserNotificationsSender = Sender<UserNotifications>;
 
-#[derive(Serialize, Debug, Eq, Hash, PartialEq, Clone, Display)]
+#[derive(Serialize, Deserialize, Debug, Eq, Hash, PartialEq, Clone, Display)]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
 pub enum UserRequest {
     Register,
     Login,
+    Logout,
+    DeactivateAccount,
     InitialSync,
+    Sync,
     CreateRoom,
     JoinRoom,
     SendMessage,
@@ -30,12 +45,165 @@ pub enum UserRequest {
     CreateChannel,
     GetChannelMembers,
     LeaveChannel,
+    LeaveRoom,
+    RenewAccount,
+    RefreshToken,
+    UpdateDirectRooms,
+    PublishRoom,
+    PublicRooms,
+    JoinRoomByAlias,
+    CreateSpace,
+    AddSpaceChild,
+    SpaceHierarchy,
+    KickUser,
+    BanUser,
+    GetPowerLevels,
+    UpdatePowerLevels,
+    UpdateRoomDetails,
+    UpgradeRoom,
+    FollowTombstone,
+    GetRoomPredecessor,
+    Knock,
+    AcceptKnock,
+    SpamInvite,
+    SendCustomEvent,
+    SendCustomStateEvent,
+    ForkRoomState,
+    SendForkedRoomMessage,
+    SendSticker,
+    SendLocation,
+    CallInvite,
+    CallAnswer,
+    CallCandidates,
+    CallHangup,
+    SendToDevice,
+    UploadKeys,
+    QueryKeys,
+    ClaimKeys,
+    UploadCrossSigningKeys,
+    UpdateSecretStorage,
+    SetPusher,
+    SetDisplayName,
+    UpdateAccountData,
+    UpdateRoomAccountData,
+    UpdateIgnoredUsers,
+    TagRoom,
+    UntagRoom,
+    SearchUser,
+    SearchMessages,
+    GetEvent,
+    GetEventContext,
+    PreviewUrl,
+    SendReadReceipt,
+    CustomAction, // requests sent by registered actions
+}
+
+impl UserRequest {
+    /// Main endpoint called by the request, used to correlate logs with the server ones
+    pub fn endpoint(&self) -> &'static str {
+        match self {
+            UserRequest::Register => "/_matrix/client/v3/register",
+            UserRequest::Login => "/_matrix/client/v3/login",
+            UserRequest::Logout => "/_matrix/client/v3/logout",
+            UserRequest::DeactivateAccount => "/_matrix/client/v3/account/deactivate",
+            UserRequest::InitialSync | UserRequest::Sync => "/_matrix/client/v3/sync",
+            UserRequest::CreateRoom | UserRequest::CreateChannel => "/_matrix/client/v3/createRoom",
+            UserRequest::JoinRoom => "/_matrix/client/v3/rooms/{roomId}/join",
+            UserRequest::SendMessage => {
+                "/_matrix/client/v3/rooms/{roomId}/send/{eventType}/{txnId}"
+            }
+            UserRequest::UpdateStatus => "/_matrix/client/v3/presence/{userId}/status",
+            UserRequest::Messages => "/_matrix/client/v3/rooms/{roomId}/messages",
+            UserRequest::GetChannelMembers => "/_matrix/client/v3/rooms/{roomId}/members",
+            UserRequest::LeaveChannel | UserRequest::LeaveRoom => {
+                "/_matrix/client/v3/rooms/{roomId}/leave"
+            }
+            UserRequest::RenewAccount => "/_synapse/admin/v1/account_validity/validity",
+            UserRequest::RefreshToken => "/_matrix/client/v3/refresh",
+            UserRequest::UpdateDirectRooms => {
+                "/_matrix/client/v3/user/{userId}/account_data/{type}"
+            }
+            UserRequest::PublishRoom => "/_matrix/client/v3/directory/list/room/{roomId}",
+            UserRequest::PublicRooms => "/_matrix/client/v3/publicRooms",
+            UserRequest::JoinRoomByAlias => "/_matrix/client/v3/join/{roomIdOrAlias}",
+            UserRequest::CreateSpace => "/_matrix/client/v3/createRoom",
+            UserRequest::AddSpaceChild => {
+                "/_matrix/client/v3/rooms/{roomId}/state/{eventType}/{stateKey}"
+            }
+            UserRequest::SpaceHierarchy => "/_matrix/client/v1/rooms/{roomId}/hierarchy",
+            UserRequest::KickUser => "/_matrix/client/v3/rooms/{roomId}/kick",
+            UserRequest::BanUser => "/_matrix/client/v3/rooms/{roomId}/ban",
+            UserRequest::GetPowerLevels | UserRequest::UpdatePowerLevels => {
+                "/_matrix/client/v3/rooms/{roomId}/state/{eventType}/{stateKey}"
+            }
+            UserRequest::UpdateRoomDetails => {
+                "/_matrix/client/v3/rooms/{roomId}/state/{eventType}/{stateKey}"
+            }
+            UserRequest::UpgradeRoom => "/_matrix/client/v3/rooms/{roomId}/upgrade",
+            UserRequest::FollowTombstone => "/_matrix/client/v3/rooms/{roomId}/join",
+            UserRequest::GetRoomPredecessor => {
+                "/_matrix/client/v3/rooms/{roomId}/state/{eventType}/{stateKey}"
+            }
+            UserRequest::Knock => "/_matrix/client/v3/knock/{roomIdOrAlias}",
+            UserRequest::AcceptKnock | UserRequest::SpamInvite => {
+                "/_matrix/client/v3/rooms/{roomId}/invite"
+            }
+            UserRequest::SendCustomEvent => {
+                "/_matrix/client/v3/rooms/{roomId}/send/{eventType}/{txnId}"
+            }
+            UserRequest::SendCustomStateEvent | UserRequest::ForkRoomState => {
+                "/_matrix/client/v3/rooms/{roomId}/state/{eventType}/{stateKey}"
+            }
+            UserRequest::SendForkedRoomMessage => {
+                "/_matrix/client/v3/rooms/{roomId}/send/{eventType}/{txnId}"
+            }
+            UserRequest::SendSticker
+            | UserRequest::SendLocation
+            | UserRequest::CallInvite
+            | UserRequest::CallAnswer
+            | UserRequest::CallCandidates
+            | UserRequest::CallHangup => {
+                "/_matrix/client/v3/rooms/{roomId}/send/{eventType}/{txnId}"
+            }
+            UserRequest::SendToDevice => "/_matrix/client/v3/sendToDevice/{eventType}/{txnId}",
+            UserRequest::UploadKeys => "/_matrix/client/v3/keys/upload",
+            UserRequest::QueryKeys => "/_matrix/client/v3/keys/query",
+            UserRequest::ClaimKeys => "/_matrix/client/v3/keys/claim",
+            UserRequest::UploadCrossSigningKeys => "/_matrix/client/v3/keys/device_signing/upload",
+            UserRequest::UpdateSecretStorage => {
+                "/_matrix/client/v3/user/{userId}/account_data/{type}"
+            }
+            UserRequest::SetPusher => "/_matrix/client/v3/pushers/set",
+            UserRequest::SetDisplayName => "/_matrix/client/v3/profile/{userId}/displayname",
+            UserRequest::UpdateAccountData => {
+                "/_matrix/client/v3/user/{userId}/account_data/{type}"
+            }
+            UserRequest::UpdateRoomAccountData => {
+                "/_matrix/client/v3/user/{userId}/rooms/{roomId}/account_data/{type}"
+            }
+            UserRequest::UpdateIgnoredUsers => {
+                "/_matrix/client/v3/user/{userId}/account_data/{type}"
+            }
+            UserRequest::TagRoom | UserRequest::UntagRoom => {
+                "/_matrix/client/v3/user/{userId}/rooms/{roomId}/tags/{tag}"
+            }
+            UserRequest::SearchUser => "/_matrix/client/v3/user_directory/search",
+            UserRequest::SearchMessages => "/_matrix/client/v3/search",
+            UserRequest::GetEvent => "/_matrix/client/v3/rooms/{roomId}/event/{eventId}",
+            UserRequest::GetEventContext => "/_matrix/client/v3/rooms/{roomId}/context/{eventId}",
+            UserRequest::PreviewUrl => "/_matrix/media/v3/preview_url",
+            UserRequest::SendReadReceipt => {
+                "/_matrix/client/v3/rooms/{roomId}/receipt/{receiptType}/{eventId}"
+            }
+            UserRequest::CustomAction => "custom",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum UserNotifications {
     NewChannel(OwnedRoomId),
-    NewSyncedUser(OwnedUserId),
+    NewSyncedUser(OwnedUserId, usize), // and its id number
     UserLoggedOut(OwnedUserId),
 }
 
@@ -43,19 +211,69 @@ pub enum UserNotifications {
 pub enum Event {
     MessageSent(String),
     MessageReceived(String),
+    ToDeviceSent(String), // request id of the to-device message
+    ToDeviceReceived(String),
+    PushReceived(String), // event id of the notification received by the push gateway
+    InviteSent(String),   // room and invitee of an invite
+    InviteReceived(String),
+    JoinSent(String),     // room and user of a join
+    JoinObserved(String), // join seen by another member of the room
+    SyncLags(Vec<u128>), // milliseconds since the origin_server_ts of the events of a sync response
+    SyncCompleted(SyncStats),
+    NotificationCountChecked(bool), // room notification count was consistent with the messages received
     RequestDuration((UserRequest, Duration)),
+    BaseUrlRequestDuration((String, Duration)), // only sent when there are several base URLs
     Error((UserRequest, HttpError)),
+    RateLimited(UserRequest),
+    TickCompleted(TickThroughput),
+    UsersPerState(TickUsers), // sent before the tick is completed
+    ActCancelled(String),     // action the user was performing when its act was cancelled
+    SyncEventsDropped(usize), // events dropped by a user buffer full of events to react to
+    UserStuck(StuckUserRecovery),
+    RateLimitProbed(ProbeBurst),
+    FuzzResponse(String), // payload and status of the response to a malformed request, ex: "invalid_json: 400"
+    ChaosSyncRestarted,
+    ChaosRequestDropped,
+    RoomUpgraded,
+    TombstoneFollowed(Duration), // time from the tombstone being sent to the replacement room being joined
+    PredecessorChecked(bool),    // replacement room linked back to the tombstoned one
+    ChannelPermuted((OwnedRoomId, String)), // channel created with a permutation, by its name
+    RoomRequestDuration((OwnedRoomId, UserRequest, Duration)), // only sent in permutations mode
+    IgnoredUsersUpdated(bool), // true when a user was added to the ignore list, false when removed
+    IgnoredUserMessageReceived, // message of an ignored user still in the sync after the ignore
+    RequestRecord(RequestRecord), // only sent when the request log is enabled
     Finish,
 }
 
 #[derive(Clone, Debug)]
 pub enum SyncEvent {
-    Invite(OwnedRoomId),
+    Invite(OwnedRoomId, Option<OwnedUserId>), // user who invited to a direct message room
     RoomCreated(OwnedRoomId),
     UnreadRoom(OwnedRoomId),
     MessageReceived(OwnedRoomId, String, RoomType),
     ChannelCreated(OwnedRoomId),
     GetChannelMembers(OwnedRoomId),
+    Knock(OwnedRoomId, OwnedUserId), // user knocked on a room the current user is in
+    CallInvite(OwnedRoomId, String), // call id of a friend calling
+    RoomUpgraded(OwnedRoomId, OwnedRoomId, u128), // replacement room and when the tombstone was sent
+}
+
+/// Sync response received by a user, incremental syncs durations include the long-polling wait
+#[derive(Debug)]
+pub struct SyncStats {
+    pub initial: bool,
+    pub duration: Duration,
+    pub events_size: usize, // JSON bytes of the timeline and state events of the joined rooms
+    pub events: usize,
+    pub returned_early: bool, // incremental sync answered before the long-polling timeout
+}
+
+/// Requests of a rate limit prober sent one after the other without pacing
+#[derive(Debug, Default)]
+pub struct ProbeBurst {
+    pub requests: usize,
+    pub rate_limited: usize,
+    pub first_limited: Option<(usize, Duration)>, // requests sent and time elapsed when the first one was rate limited
 }
 
 #[derive(Default)]
@@ -66,34 +284,262 @@ pub struct MessageTimes {
 
 pub struct EventCollector {
     events: Arc<Events>,
+    live_metrics: Arc<LiveMetrics>,
+    watchdog: Option<Arc<Watchdog>>,
+    request_log: Option<mpsc::Sender<RequestRecord>>,
+    interim: Option<InterimWriter>,
+    warm_up: Duration,
+}
+
+/// Requests and errors since last read, used to display live stats while the simulation is running
+#[derive(Default)]
+pub struct LiveMetrics {
+    window: SyncMutex<LiveWindow>,
+}
+
+#[derive(Default)]
+pub struct LiveWindow {
+    pub requests: HashMap<UserRequest, Vec<Duration>>,
+    pub errors: usize,
+}
+
+impl LiveMetrics {
+    fn record_request(&self, request: &UserRequest, duration: Duration) {
+        let mut window = self.window.lock().expect("lock not poisoned");
+        window
+            .requests
+            .entry(request.clone())
+            .or_default()
+            .push(duration);
+    }
+
+    fn record_error(&self) {
+        self.window.lock().expect("lock not poisoned").errors += 1;
+    }
+
+    /// Take the requests and errors recorded since last call
+    pub fn take(&self) -> LiveWindow {
+        std::mem::take(&mut *self.window.lock().expect("lock not poisoned"))
+    }
+}
+
+/// Counters since the last interim snapshot, so snapshots show the run as it goes without rebuilding the report
+#[derive(Default)]
+struct IntervalCounters {
+    requests: u128,
+    measured_requests: u128, // sent after the warm-up, included in the average time
+    requests_time: Duration,
+    http_errors: usize,
+    rate_limited_requests: usize,
+    messages_sent: usize,
+    real_time_messages: usize,
+    delivery_time: Duration,
+    acts_cancelled: usize,
+    ticks_aborted: usize,
+}
+
+impl IntervalCounters {
+    /// Summary of the interval, the counters start from zero again
+    fn take(&mut self) -> ReportSummary {
+        let counters = std::mem::take(self);
+        ReportSummary {
+            requests: counters.requests,
+            http_errors: counters.http_errors,
+            rate_limited_requests: counters.rate_limited_requests,
+            requests_average_time: (counters.measured_requests > 0)
+                .then(|| counters.requests_time.as_millis() / counters.measured_requests),
+            message_delivery_average_time: (counters.real_time_messages > 0)
+                .then(|| counters.delivery_time.as_millis() / counters.real_time_messages as u128),
+            messages_sent: counters.messages_sent,
+            real_time_messages: counters.real_time_messages,
+            acts_cancelled: counters.acts_cancelled,
+            ticks_aborted: counters.ticks_aborted,
+        }
+    }
 }
 
 #[derive(Default)]
 struct Events {
     requests: RwLock<Vec<(UserRequest, Duration)>>,
+    warm_up_requests: RwLock<Vec<(UserRequest, Duration)>>,
     errors: RwLock<Vec<(UserRequest, HttpError)>>,
+    rate_limited: RwLock<Vec<UserRequest>>,
     messages: RwLock<HashMap<String, MessageTimes>>,
+    to_device: RwLock<HashMap<String, MessageTimes>>,
+    pushes: RwLock<Vec<(String, Instant)>>,
+    invites: RwLock<HashMap<String, MessageTimes>>,
+    joins: RwLock<HashMap<String, MessageTimes>>,
+    sync_lags: RwLock<Vec<u128>>,
+    tick_sync_lags: RwLock<Vec<u128>>, // lags since the last tick completed
+    sync_lag_per_tick: RwLock<Vec<TickDistribution>>,
+    tick_forked_room_messages: RwLock<Vec<u128>>, // latencies since the last tick completed
+    forked_room_message_latency_per_tick: RwLock<Vec<TickDistribution>>,
+    initial_syncs: RwLock<Vec<SyncStats>>,
+    incremental_syncs: RwLock<Vec<SyncStats>>,
+    probe_bursts: RwLock<Vec<ProbeBurst>>,
+    fuzz_responses: RwLock<Vec<String>>,
+    tick_syncs: RwLock<Vec<(usize, usize)>>, // size and events of the syncs since the last tick completed
+    sync_events_size_per_tick: RwLock<Vec<TickDistribution>>,
+    sync_events_per_tick: RwLock<Vec<TickDistribution>>,
+    notification_counts: RwLock<Vec<bool>>,
+    ticks: RwLock<Vec<TickThroughput>>,
+    users_per_tick: RwLock<Vec<TickUsers>>,
+    base_url_requests: RwLock<Vec<(String, Duration)>>,
+    cancelled_acts: RwLock<Vec<String>>,
+    dropped_sync_events: RwLock<usize>,
+    quarantined_users: RwLock<usize>,
+    restarted_stuck_users: RwLock<usize>,
+    chaos_sync_restarts: RwLock<usize>,
+    chaos_dropped_requests: RwLock<usize>,
+    rooms_upgraded: RwLock<usize>,
+    tombstone_follow_latencies: RwLock<Vec<u128>>,
+    predecessor_checks: RwLock<Vec<bool>>,
+    channel_permutations: RwLock<HashMap<OwnedRoomId, String>>,
+    room_requests: RwLock<Vec<(OwnedRoomId, UserRequest, Duration)>>,
+    users_ignored: RwLock<usize>,
+    users_unignored: RwLock<usize>,
+    ignored_user_messages: RwLock<usize>,
+    interval: SyncMutex<IntervalCounters>,
 }
 
 impl Events {
+    fn count(&self, update: impl FnOnce(&mut IntervalCounters)) {
+        update(&mut self.interval.lock().expect("lock not poisoned"));
+    }
+
     async fn report(&self) -> Report {
         let errors = self.errors.read().await;
+        let rate_limited = self.rate_limited.read().await;
         let requests = self.requests.read().await;
+        let warm_up_requests = self.warm_up_requests.read().await;
         let messages = self.messages.read().await;
+        let to_device = self.to_device.read().await;
+        let pushes = self.pushes.read().await;
+        let invites = self.invites.read().await;
+        let joins = self.joins.read().await;
+        let sync_lags = self.sync_lags.read().await;
+        let sync_lag_per_tick = self.sync_lag_per_tick.read().await;
+        let forked_room_message_latency_per_tick =
+            self.forked_room_message_latency_per_tick.read().await;
+        let initial_syncs = self.initial_syncs.read().await;
+        let incremental_syncs = self.incremental_syncs.read().await;
+        let probe_bursts = self.probe_bursts.read().await;
+        let fuzz_responses = self.fuzz_responses.read().await;
+        let sync_events_size_per_tick = self.sync_events_size_per_tick.read().await;
+        let sync_events_per_tick = self.sync_events_per_tick.read().await;
+        let notification_counts = self.notification_counts.read().await;
+        let ticks = self.ticks.read().await;
+        let users_per_tick = self.users_per_tick.read().await;
+        let cancelled_acts = self.cancelled_acts.read().await;
+        let dropped_sync_events = self.dropped_sync_events.read().await;
+        let quarantined_users = self.quarantined_users.read().await;
+        let restarted_stuck_users = self.restarted_stuck_users.read().await;
+        let chaos_sync_restarts = self.chaos_sync_restarts.read().await;
+        let chaos_dropped_requests = self.chaos_dropped_requests.read().await;
+        let rooms_upgraded = self.rooms_upgraded.read().await;
+        let tombstone_follow_latencies = self.tombstone_follow_latencies.read().await;
+        let predecessor_checks = self.predecessor_checks.read().await;
+        let users_ignored = self.users_ignored.read().await;
+        let users_unignored = self.users_unignored.read().await;
+        let ignored_user_messages = self.ignored_user_messages.read().await;
+        let channel_permutations = self.channel_permutations.read().await;
+        // direct messages and channels created before the mode was enabled have no permutation
+        let permutation_requests = self
+            .room_requests
+            .read()
+            .await
+            .iter()
+            .filter_map(|(room_id, request, duration)| {
+                channel_permutations
+                    .get(room_id)
+                    .map(|permutation| (permutation.clone(), request.clone(), *duration))
+            })
+            .collect::<Vec<_>>();
+        let base_url_requests = self.base_url_requests.read().await;
 
-        Report::from(&errors, &requests, &messages)
+        Report::from(ReportInput {
+            http_errors: &errors,
+            rate_limited: &rate_limited,
+            request_times: &requests,
+            warm_up_request_times: &warm_up_requests,
+            messages: &messages,
+            to_device: &to_device,
+            pushes: &pushes,
+            invites: &invites,
+            joins: &joins,
+            sync_lags: &sync_lags,
+            sync_lag_per_tick: &sync_lag_per_tick,
+            forked_room_message_latency_per_tick: &forked_room_message_latency_per_tick,
+            initial_syncs: &initial_syncs,
+            incremental_syncs: &incremental_syncs,
+            probe_bursts: &probe_bursts,
+            fuzz_responses: &fuzz_responses,
+            sync_events_size_per_tick: &sync_events_size_per_tick,
+            sync_events_per_tick: &sync_events_per_tick,
+            notification_counts: &notification_counts,
+            ticks: &ticks,
+            users_per_tick: &users_per_tick,
+            cancelled_acts: &cancelled_acts,
+            dropped_sync_events: *dropped_sync_events,
+            quarantined_users: *quarantined_users,
+            restarted_stuck_users: *restarted_stuck_users,
+            chaos_sync_restarts: *chaos_sync_restarts,
+            chaos_dropped_requests: *chaos_dropped_requests,
+            rooms_upgraded: *rooms_upgraded,
+            tombstone_follow_latencies: &tombstone_follow_latencies,
+            predecessor_checks: &predecessor_checks,
+            users_ignored: *users_ignored,
+            users_unignored: *users_unignored,
+            ignored_user_messages: *ignored_user_messages,
+            permutation_requests: &permutation_requests,
+            base_url_requests: &base_url_requests,
+        })
     }
 }
 
 impl EventCollector {
-    pub fn new() -> Self {
+    pub fn new(
+        live_metrics: Arc<LiveMetrics>,
+        watchdog: Option<Arc<Watchdog>>,
+        request_log: Option<mpsc::Sender<RequestRecord>>,
+        interim: Option<InterimWriter>,
+        warm_up: Duration,
+    ) -> Self {
         Self {
             events: Arc::new(Events::default()),
+            live_metrics,
+            watchdog,
+            request_log,
+            interim,
+            warm_up,
         }
     }
 
-    pub fn start(&self, receiver: Receiver<Event>) -> JoinHandle<Report> {
-        tokio::spawn(Self::collect_events(receiver, self.events.clone()))
+    pub fn start(&mut self, receiver: Receiver<Event>) -> JoinHandle<Report> {
+        let interim = self
+            .interim
+            .take()
+            .map(|interim| tokio::spawn(Self::write_interim_reports(interim, self.events.clone())));
+        tokio::spawn(Self::collect_events(
+            receiver,
+            self.events.clone(),
+            self.live_metrics.clone(),
+            self.watchdog.clone(),
+            self.request_log.clone(),
+            interim,
+            Instant::now() + self.warm_up,
+        ))
+    }
+
+    async fn write_interim_reports(mut interim: InterimWriter, events: Arc<Events>) {
+        let mut interval = tokio::time::interval(interim.every());
+        // the first tick completes immediately, there is nothing to report yet
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let counters = events.interval.lock().expect("lock not poisoned").take();
+            interim.write(counters);
+        }
     }
 
     ///
@@ -101,23 +547,216 @@ impl EventCollector {
     /// If message sent event is processed and the message_id is already present in the messages map
     /// If message received event is processed  and the message_id is not present in the messages map
     ///
-    async fn collect_events(mut receiver: Receiver<Event>, events: Arc<Events>) -> Report {
+    async fn collect_events(
+        mut receiver: Receiver<Event>,
+        events: Arc<Events>,
+        live_metrics: Arc<LiveMetrics>,
+        watchdog: Option<Arc<Watchdog>>,
+        request_log: Option<mpsc::Sender<RequestRecord>>,
+        interim: Option<JoinHandle<()>>,
+        warm_up_until: Instant,
+    ) -> Report {
         while let Some(event) = receiver.recv().await {
             log::debug!("Event received {:?}", event);
             match event {
                 Event::Error(e) => {
+                    live_metrics.record_error();
+                    events.count(|counters| counters.http_errors += 1);
+                    if let Some(watchdog) = &watchdog {
+                        watchdog.record_error();
+                    }
                     events.errors.write().await.push(e);
                 }
+                Event::RateLimited(request) => {
+                    events.count(|counters| counters.rate_limited_requests += 1);
+                    events.rate_limited.write().await.push(request);
+                }
                 Event::MessageSent(message_id) => {
+                    events.count(|counters| counters.messages_sent += 1);
                     let mut messages = events.messages.write().await;
                     messages.entry(message_id).or_default().sent = Some(Instant::now());
                 }
                 Event::MessageReceived(message_id) => {
                     let mut messages = events.messages.write().await;
-                    messages.entry(message_id).or_default().received = Some(Instant::now());
+                    let times = messages.entry(message_id).or_default();
+                    let received = Instant::now();
+                    // counted once, when it is first received
+                    if let (Some(sent), None) = (times.sent, times.received) {
+                        events.count(|counters| {
+                            counters.real_time_messages += 1;
+                            counters.delivery_time += received - sent;
+                        });
+                    }
+                    times.received = Some(received);
+                }
+                Event::ToDeviceSent(request_id) => {
+                    let mut to_device = events.to_device.write().await;
+                    to_device.entry(request_id).or_default().sent = Some(Instant::now());
+                }
+                Event::ToDeviceReceived(request_id) => {
+                    let mut to_device = events.to_device.write().await;
+                    to_device.entry(request_id).or_default().received = Some(Instant::now());
+                }
+                Event::PushReceived(event_id) => {
+                    events.pushes.write().await.push((event_id, Instant::now()));
+                }
+                Event::InviteSent(key) => {
+                    let mut invites = events.invites.write().await;
+                    invites.entry(key).or_default().sent = Some(Instant::now());
+                }
+                Event::InviteReceived(key) => {
+                    let mut invites = events.invites.write().await;
+                    invites.entry(key).or_default().received = Some(Instant::now());
+                }
+                Event::JoinSent(key) => {
+                    let mut joins = events.joins.write().await;
+                    joins.entry(key).or_default().sent = Some(Instant::now());
+                }
+                Event::JoinObserved(key) => {
+                    // only the first member seeing the join counts
+                    let mut joins = events.joins.write().await;
+                    joins
+                        .entry(key)
+                        .or_default()
+                        .received
+                        .get_or_insert_with(Instant::now);
+                }
+                Event::SyncLags(lags) => {
+                    events.tick_sync_lags.write().await.extend(&lags);
+                    events.sync_lags.write().await.extend(lags);
+                }
+                Event::SyncCompleted(stats) => {
+                    events
+                        .tick_syncs
+                        .write()
+                        .await
+                        .push((stats.events_size, stats.events));
+                    if stats.initial {
+                        events.initial_syncs.write().await.push(stats);
+                    } else {
+                        events.incremental_syncs.write().await.push(stats);
+                    }
+                }
+                Event::NotificationCountChecked(consistent) => {
+                    events.notification_counts.write().await.push(consistent);
                 }
                 Event::RequestDuration(request) => {
-                    events.requests.write().await.push(request);
+                    live_metrics.record_request(&request.0, request.1);
+                    if request.0 == UserRequest::SendForkedRoomMessage {
+                        events
+                            .tick_forked_room_messages
+                            .write()
+                            .await
+                            .push(request.1.as_millis());
+                    }
+                    let measured = Instant::now() >= warm_up_until;
+                    events.count(|counters| {
+                        counters.requests += 1;
+                        if measured {
+                            counters.measured_requests += 1;
+                            counters.requests_time += request.1;
+                        }
+                    });
+                    if !measured {
+                        events.warm_up_requests.write().await.push(request);
+                    } else {
+                        if let Some(watchdog) = &watchdog {
+                            watchdog.record_request(request.1);
+                        }
+                        events.requests.write().await.push(request);
+                    }
+                }
+                Event::BaseUrlRequestDuration(request) => {
+                    events.base_url_requests.write().await.push(request);
+                }
+                Event::TickCompleted(throughput) => {
+                    events.count(|counters| {
+                        counters.ticks_aborted += usize::from(throughput.aborted)
+                    });
+                    let tick = throughput.tick;
+                    let lags = std::mem::take(&mut *events.tick_sync_lags.write().await);
+                    push_tick_distribution(&events.sync_lag_per_tick, tick, lags).await;
+                    let latencies =
+                        std::mem::take(&mut *events.tick_forked_room_messages.write().await);
+                    push_tick_distribution(
+                        &events.forked_room_message_latency_per_tick,
+                        tick,
+                        latencies,
+                    )
+                    .await;
+                    let syncs = std::mem::take(&mut *events.tick_syncs.write().await);
+                    let sizes = syncs.iter().map(|(size, _)| *size as u128).collect();
+                    push_tick_distribution(&events.sync_events_size_per_tick, tick, sizes).await;
+                    let counts = syncs.iter().map(|(_, count)| *count as u128).collect();
+                    push_tick_distribution(&events.sync_events_per_tick, tick, counts).await;
+                    events.ticks.write().await.push(throughput);
+                }
+                Event::UsersPerState(users) => {
+                    events.users_per_tick.write().await.push(users);
+                }
+                Event::ActCancelled(action) => {
+                    events.count(|counters| counters.acts_cancelled += 1);
+                    events.cancelled_acts.write().await.push(action);
+                }
+                Event::SyncEventsDropped(dropped) => {
+                    *events.dropped_sync_events.write().await += dropped;
+                }
+                Event::RateLimitProbed(burst) => {
+                    events.probe_bursts.write().await.push(burst);
+                }
+                Event::FuzzResponse(response) => {
+                    events.fuzz_responses.write().await.push(response);
+                }
+                Event::UserStuck(StuckUserRecovery::Quarantine) => {
+                    *events.quarantined_users.write().await += 1;
+                }
+                Event::UserStuck(StuckUserRecovery::Restart) => {
+                    *events.restarted_stuck_users.write().await += 1;
+                }
+                Event::ChaosSyncRestarted => {
+                    *events.chaos_sync_restarts.write().await += 1;
+                }
+                Event::ChaosRequestDropped => {
+                    *events.chaos_dropped_requests.write().await += 1;
+                }
+                Event::RoomUpgraded => {
+                    *events.rooms_upgraded.write().await += 1;
+                }
+                Event::IgnoredUsersUpdated(true) => {
+                    *events.users_ignored.write().await += 1;
+                }
+                Event::IgnoredUsersUpdated(false) => {
+                    *events.users_unignored.write().await += 1;
+                }
+                Event::IgnoredUserMessageReceived => {
+                    *events.ignored_user_messages.write().await += 1;
+                }
+                Event::TombstoneFollowed(latency) => {
+                    events
+                        .tombstone_follow_latencies
+                        .write()
+                        .await
+                        .push(latency.as_millis());
+                }
+                Event::PredecessorChecked(continuous) => {
+                    events.predecessor_checks.write().await.push(continuous);
+                }
+                Event::ChannelPermuted((room_id, permutation)) => {
+                    events
+                        .channel_permutations
+                        .write()
+                        .await
+                        .insert(room_id, permutation);
+                }
+                Event::RoomRequestDuration(request) => {
+                    events.room_requests.write().await.push(request);
+                }
+                Event::RequestRecord(record) => {
+                    if let Some(request_log) = &request_log {
+                        request_log
+                            .send(record)
+                            .expect("request log writer to be running");
+                    }
                 }
                 Event::Finish => break,
             }
@@ -125,7 +764,25 @@ impl EventCollector {
 
         log::debug!("couldn't read event or simulation finished");
         receiver.close();
+        if let Some(interim) = interim {
+            interim.abort();
+            // the interim writer holds a queue of the report writer until it is dropped
+            let _ = interim.await;
+        }
 
         events.report().await
     }
 }
+
+async fn push_tick_distribution(
+    distributions: &RwLock<Vec<TickDistribution>>,
+    tick: usize,
+    values: Vec<u128>,
+) {
+    if let Some(distribution) = Distribution::from_values(values) {
+        distributions
+            .write()
+            .await
+            .push(TickDistribution { tick, distribution });
+    }
+}
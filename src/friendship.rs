@@ -0,0 +1,202 @@
+use crate::configuration::{FriendshipModel, Friendships};
+use rand::prelude::SliceRandom;
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{read_to_string, File};
+use std::io::{BufWriter, Write};
+
+/// Friendship graph built before the simulation starts, users can only be friends of their neighbours.
+#[derive(Debug, Default)]
+pub struct FriendshipGraph {
+    friends: HashMap<String, HashSet<String>>,
+    edges: Vec<(usize, usize)>, // by user position, so the graph can be exported and used by other runs
+}
+
+impl FriendshipGraph {
+    ///
+    /// Build the graph for the users localparts, imported from `import_file` when present or generated
+    /// using the configured model. Random model has no graph, friends are picked among all users.
+    /// When `export_file` is present the graph is written to it.
+    ///
+    /// # Panics
+    ///
+    /// If the graph cannot be imported or exported.
+    ///
+    pub fn new(config: &Friendships, localparts: &[String]) -> Option<Self> {
+        let edges = match (&config.import_file, &config.model) {
+            (Some(path), _) => import(path),
+            (None, FriendshipModel::Random) => {
+                if config.export_file.is_some() {
+                    println!("random friendship model has no graph to export");
+                }
+                return None;
+            }
+            (None, FriendshipModel::SmallWorld) => small_world(
+                localparts.len(),
+                config.average_friends,
+                config.rewiring_probability,
+            ),
+            (None, FriendshipModel::ScaleFree) => {
+                scale_free(localparts.len(), config.average_friends)
+            }
+        };
+
+        let mut graph = Self::default();
+        for (a, b) in edges {
+            match (localparts.get(a), localparts.get(b)) {
+                (Some(user_a), Some(user_b)) if a != b => {
+                    graph.add(user_a, user_b);
+                    graph.edges.push((a, b));
+                }
+                _ => log::debug!("friendship {a} - {b} ignored, users out of range"),
+            }
+        }
+        log::debug!(
+            "friendship graph built with {} friendships",
+            graph.friendships()
+        );
+
+        if let Some(path) = &config.export_file {
+            graph.export(path);
+        }
+        Some(graph)
+    }
+
+    fn add(&mut self, a: &str, b: &str) {
+        self.friends
+            .entry(a.to_string())
+            .or_default()
+            .insert(b.to_string());
+        self.friends
+            .entry(b.to_string())
+            .or_default()
+            .insert(a.to_string());
+    }
+
+    pub fn friends_of(&self, localpart: &str) -> Option<&HashSet<String>> {
+        self.friends.get(localpart)
+    }
+
+    pub fn friendships(&self) -> usize {
+        self.friends.values().map(HashSet::len).sum::<usize>() / 2
+    }
+
+    /// Write the graph as adjacency JSON (`.json` files) or as an edge list, one `a,b` friendship per line.
+    fn export(&self, path: &str) {
+        let file = File::create(path)
+            .unwrap_or_else(|_| panic!("could not create friendship graph {path}"));
+        if path.ends_with(".json") {
+            let mut adjacency = BTreeMap::<usize, Vec<usize>>::new();
+            for (a, b) in &self.edges {
+                adjacency.entry(*a).or_default().push(*b);
+                adjacency.entry(*b).or_default().push(*a);
+            }
+            serde_json::to_writer(file, &adjacency).expect("couldn't write friendship graph");
+        } else {
+            let mut writer = BufWriter::new(file);
+            for (a, b) in &self.edges {
+                writeln!(writer, "{a},{b}").expect("couldn't write friendship graph");
+            }
+        }
+        println!("friendship graph exported to {path}");
+    }
+}
+
+///
+/// Read a graph written by `export`, users are identified by their position (0 to max users) in the run.
+/// Edge lists accept comma or whitespace separated pairs and `#` comments.
+///
+fn import(path: &str) -> Vec<(usize, usize)> {
+    let content =
+        read_to_string(path).unwrap_or_else(|_| panic!("could not read friendship graph {path}"));
+    if path.ends_with(".json") {
+        let adjacency: BTreeMap<usize, Vec<usize>> = serde_json::from_str(&content)
+            .unwrap_or_else(|e| panic!("could not parse friendship graph {path}: {e}"));
+        // every friendship is listed in both users
+        adjacency
+            .into_iter()
+            .flat_map(|(a, friends)| friends.into_iter().map(move |b| (a, b)))
+            .filter(|(a, b)| a < b)
+            .collect()
+    } else {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let users = line
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|user| !user.is_empty())
+                    .map(|user| user.parse::<usize>())
+                    .collect::<Result<Vec<_>, _>>();
+                match users.as_deref() {
+                    Ok([a, b]) => (*a, *b),
+                    _ => panic!("invalid friendship '{line}' in {path}"),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Watts–Strogatz: users in a ring are connected to their `average_friends / 2` closest users on each side,
+/// then every friendship is rewired to a random user with `rewiring_probability`.
+fn small_world(
+    users: usize,
+    average_friends: usize,
+    rewiring_probability: f64,
+) -> Vec<(usize, usize)> {
+    let mut rng = rand::thread_rng();
+    let mut edges = HashSet::new();
+    let neighbours = (average_friends / 2).min(users.saturating_sub(1) / 2);
+
+    for user in 0..users {
+        for distance in 1..=neighbours {
+            let mut friend = (user + distance) % users;
+            if rng.gen_bool(rewiring_probability) {
+                let candidate = rng.gen_range(0..users);
+                if candidate != user && !edges.contains(&edge(user, candidate)) {
+                    friend = candidate;
+                }
+            }
+            edges.insert(edge(user, friend));
+        }
+    }
+    edges.into_iter().collect()
+}
+
+/// Barabási–Albert: starting from a complete graph, every new user befriends `average_friends / 2` users
+/// picked with a probability proportional to the friends they already have.
+fn scale_free(users: usize, average_friends: usize) -> Vec<(usize, usize)> {
+    let mut rng = rand::thread_rng();
+    let friends_per_user = (average_friends / 2).max(1);
+    let initial_users = (friends_per_user + 1).min(users);
+
+    let mut edges = vec![];
+    // every user appears once per friend, picking from it is proportional to the friends count
+    let mut endpoints = vec![];
+    for a in 0..initial_users {
+        for b in (a + 1)..initial_users {
+            edges.push((a, b));
+            endpoints.extend([a, b]);
+        }
+    }
+
+    for user in initial_users..users {
+        let mut friends = HashSet::new();
+        while friends.len() < friends_per_user.min(user) {
+            let friend = *endpoints
+                .choose(&mut rng)
+                .expect("previous users to have friends");
+            friends.insert(friend);
+        }
+        for friend in friends {
+            edges.push((friend, user));
+            endpoints.extend([friend, user]);
+        }
+    }
+    edges
+}
+
+fn edge(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
@@ -0,0 +1,125 @@
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use reqwest::Url;
+use serde_json::json;
+use strum::Display;
+
+// events bigger than 64 KiB must be rejected by the homeserver
+const OVERSIZED_BODY_LENGTH: usize = 70_000;
+const BAD_TXN_ID_LENGTH: usize = 1_000;
+// control characters, bidi overrides, zero width joiners, combining marks, a lone BOM and non BMP characters
+const WEIRD_UNICODE: &str =
+    "\u{0000}\u{0007}\u{202E}attack\u{202C}\u{200D}\u{200D}\u{FEFF}e\u{0301}\u{0301}\u{0301}\u{1F468}\u{200D}\u{1F469}\u{10FFFF}";
+
+/// Malformed or edge-case payload sent instead of a message
+#[derive(Debug, Clone, Copy, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Payload {
+    OversizedEvent,
+    InvalidJson,
+    WrongFieldTypes,
+    WeirdUnicode,
+    BadTxnId,
+}
+
+impl Payload {
+    pub fn random() -> Self {
+        match rand::thread_rng().gen_range(0..5) {
+            0 => Payload::OversizedEvent,
+            1 => Payload::InvalidJson,
+            2 => Payload::WrongFieldTypes,
+            3 => Payload::WeirdUnicode,
+            _ => Payload::BadTxnId,
+        }
+    }
+
+    fn body(&self) -> String {
+        match self {
+            Payload::OversizedEvent => json!({
+                "msgtype": "m.text",
+                "body": "a".repeat(OVERSIZED_BODY_LENGTH),
+            })
+            .to_string(),
+            // truncated in the middle of the body
+            Payload::InvalidJson => r#"{"msgtype": "m.text", "body": "unterminated"#.to_string(),
+            Payload::WrongFieldTypes => json!({
+                "msgtype": 42,
+                "body": { "nested": [null, -1.5e308, true] },
+                "format": [],
+            })
+            .to_string(),
+            Payload::WeirdUnicode => json!({
+                "msgtype": "m.text",
+                "body": WEIRD_UNICODE,
+                "formatted_body": format!("<b>{WEIRD_UNICODE}</b>"),
+                "format": "org.matrix.custom.html",
+            })
+            .to_string(),
+            Payload::BadTxnId => json!({ "msgtype": "m.text", "body": "bad txn id" }).to_string(),
+        }
+    }
+
+    fn txn_id(&self) -> String {
+        match self {
+            Payload::BadTxnId => {
+                let random = rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(BAD_TXN_ID_LENGTH)
+                    .map(char::from)
+                    .collect::<String>();
+                format!("../{WEIRD_UNICODE}/{random}")
+            }
+            _ => rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(16)
+                .map(char::from)
+                .collect(),
+        }
+    }
+}
+
+///
+/// Send the payload as a message of the room, returns the status of the response or "error" when the server
+/// didn't respond.
+///
+/// # Panics
+///
+/// If the homeserver URL is not a valid base URL.
+///
+pub async fn send(
+    http_client: &reqwest::Client,
+    homeserver_url: &str,
+    access_token: &str,
+    room_id: &str,
+    payload: Payload,
+) -> String {
+    let mut url = Url::parse(homeserver_url).expect("homeserver url to be valid");
+    url.path_segments_mut()
+        .expect("homeserver url to be a base")
+        .pop_if_empty()
+        .extend([
+            "_matrix",
+            "client",
+            "v3",
+            "rooms",
+            room_id,
+            "send",
+            "m.room.message",
+            &payload.txn_id(),
+        ]);
+
+    let response = http_client
+        .put(url)
+        .bearer_auth(access_token)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(payload.body())
+        .send()
+        .await;
+    match response {
+        Ok(response) => response.status().as_u16().to_string(),
+        Err(e) => {
+            log::debug!("fuzzed {} request failed: {}", payload, e);
+            "error".to_string()
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use futures::Future;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// Response with status 200 to every request
+pub struct Response {
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    pub fn new(content_type: &'static str, body: String) -> Self {
+        Self { content_type, body }
+    }
+
+    fn to_http(&self) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+///
+/// Listen on the address, `name` tells in the panic message what couldn't listen.
+///
+/// # Panics
+///
+/// If it cannot listen on the address.
+///
+pub async fn bind(address: &str, name: &str) -> TcpListener {
+    TcpListener::bind(address)
+        .await
+        .unwrap_or_else(|e| panic!("{name} could not listen on {address}: {e}"))
+}
+
+///
+/// Serve HTTP on the address, every request is answered with the response of the handler to its body,
+/// no response closes the connection. Connections are kept alive and served concurrently.
+///
+/// # Panics
+///
+/// If it cannot listen on the address.
+///
+pub async fn serve<H, F>(address: &str, name: &'static str, handler: H) -> JoinHandle<()>
+where
+    H: Fn(Vec<u8>) -> F + Clone + Send + 'static,
+    F: Future<Output = Option<Response>> + Send,
+{
+    let listener = bind(address, name).await;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(stream, name, handler.clone()));
+                }
+                Err(e) => log::debug!("{} couldn't accept connection: {}", name, e),
+            }
+        }
+    })
+}
+
+/// Requests are read until the connection is closed.
+async fn handle_connection<H, F>(stream: TcpStream, name: &str, handler: H)
+where
+    H: Fn(Vec<u8>) -> F,
+    F: Future<Output = Option<Response>>,
+{
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    loop {
+        let body = match read_request(&mut reader).await {
+            Ok(Some(body)) => body,
+            Ok(None) => return,
+            Err(e) => {
+                log::debug!("{} couldn't read request: {}", name, e);
+                return;
+            }
+        };
+        let response = match handler(body).await {
+            Some(response) => response,
+            None => return,
+        };
+        if let Err(e) = writer.write_all(response.to_http().as_bytes()).await {
+            log::debug!("{} couldn't respond: {}", name, e);
+            return;
+        }
+    }
+}
+
+/// Read an HTTP request and return its body, None when the connection was closed.
+async fn read_request<R>(reader: &mut BufReader<R>) -> std::io::Result<Option<Vec<u8>>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut content_length = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or_default();
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
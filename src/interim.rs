@@ -0,0 +1,154 @@
+use crate::configuration::{InterimReports, InterimTarget};
+use crate::report::{ReportSummary, WriteQueue};
+use serde::Serialize;
+use std::fs::{rename, File, OpenOptions};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+const METRIC_PREFIX: &str = "matrix_reloaded";
+
+/// Snapshot of the metrics since the previous one
+#[derive(Serialize)]
+struct Snapshot {
+    elapsed_secs: u64,
+    #[serde(flatten)]
+    metrics: ReportSummary,
+    /// counts since the simulation started, for the Prometheus counters
+    #[serde(skip)]
+    totals: ReportSummary,
+}
+
+/// Writes snapshots of the summary of every interval while the simulation runs, to stdout, a JSON lines file
+/// or a Prometheus text file (for the node exporter textfile collector)
+pub struct InterimWriter {
+    config: InterimReports,
+    path: String,
+    started_at: Instant,
+    totals: ReportSummary,
+    queue: WriteQueue, // files are written by the report writer
+}
+
+impl InterimWriter {
+    pub fn new(
+        config: InterimReports,
+        reports_dir: &str,
+        execution_id: &str,
+        queue: WriteQueue,
+    ) -> Self {
+        let path = match config.target {
+            InterimTarget::Stdout => String::new(),
+            InterimTarget::File => format!("{reports_dir}/interim_{execution_id}.ndjson"),
+            InterimTarget::Prometheus => format!("{reports_dir}/interim_{execution_id}.prom"),
+        };
+        Self {
+            config,
+            path,
+            started_at: Instant::now(),
+            totals: ReportSummary::default(),
+            queue,
+        }
+    }
+
+    pub fn every(&self) -> Duration {
+        self.config.every
+    }
+
+    /// Queue the snapshot of the interval to the report writer
+    pub fn write(&mut self, interval: ReportSummary) {
+        let totals = &mut self.totals;
+        totals.requests += interval.requests;
+        totals.http_errors += interval.http_errors;
+        totals.rate_limited_requests += interval.rate_limited_requests;
+        totals.messages_sent += interval.messages_sent;
+        totals.real_time_messages += interval.real_time_messages;
+        totals.acts_cancelled += interval.acts_cancelled;
+        totals.ticks_aborted += interval.ticks_aborted;
+        let snapshot = Snapshot {
+            elapsed_secs: self.started_at.elapsed().as_secs(),
+            metrics: interval,
+            totals: totals.clone(),
+        };
+        let target = self.config.target;
+        let path = self.path.clone();
+        self.queue
+            .write(move || write_snapshot(target, &path, &snapshot));
+    }
+}
+
+///
+/// # Panics
+///
+/// If the snapshot file cannot be written.
+///
+fn write_snapshot(target: InterimTarget, path: &str, snapshot: &Snapshot) {
+    match target {
+        InterimTarget::Stdout => println!("{}", text(snapshot)),
+        InterimTarget::File => {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap_or_else(|_| panic!("could not open interim reports {path}"));
+            serde_json::to_writer(&mut file, snapshot).expect("couldn't write interim report");
+            file.write_all(b"\n")
+                .expect("couldn't write interim report");
+        }
+        InterimTarget::Prometheus => {
+            // written aside and renamed, so the collector never reads a partial file
+            let partial = format!("{path}.partial");
+            let mut file = File::create(&partial)
+                .unwrap_or_else(|_| panic!("could not create interim reports {partial}"));
+            file.write_all(prometheus(snapshot).as_bytes())
+                .expect("couldn't write interim report");
+            rename(&partial, path).expect("couldn't write interim report");
+        }
+    }
+}
+
+fn text(snapshot: &Snapshot) -> String {
+    let metrics = &snapshot.metrics;
+    let milliseconds =
+        |time: Option<u128>| time.map_or_else(|| "-".to_string(), |time| format!("{time}ms"));
+    format!(
+        "[{}s] requests: {}, http errors: {}, rate limited: {}, average time: {}, messages sent: {}, received in real time: {}, average delivery time: {}, acts cancelled: {}",
+        snapshot.elapsed_secs,
+        metrics.requests,
+        metrics.http_errors,
+        metrics.rate_limited_requests,
+        milliseconds(metrics.requests_average_time),
+        metrics.messages_sent,
+        metrics.real_time_messages,
+        milliseconds(metrics.message_delivery_average_time),
+        metrics.acts_cancelled
+    )
+}
+
+/// Counters are the totals since the start, average times are of the last interval
+fn prometheus(snapshot: &Snapshot) -> String {
+    let (metrics, totals) = (&snapshot.metrics, &snapshot.totals);
+    let mut values = vec![
+        ("elapsed_seconds", snapshot.elapsed_secs as u128),
+        ("requests_total", totals.requests),
+        ("http_errors_total", totals.http_errors as u128),
+        (
+            "rate_limited_requests_total",
+            totals.rate_limited_requests as u128,
+        ),
+        ("messages_sent_total", totals.messages_sent as u128),
+        (
+            "real_time_messages_total",
+            totals.real_time_messages as u128,
+        ),
+        ("acts_cancelled_total", totals.acts_cancelled as u128),
+    ];
+    if let Some(time) = metrics.requests_average_time {
+        values.push(("requests_average_time_ms", time));
+    }
+    if let Some(time) = metrics.message_delivery_average_time {
+        values.push(("message_delivery_average_time_ms", time));
+    }
+    values
+        .into_iter()
+        .map(|(name, value)| format!("{METRIC_PREFIX}_{name} {value}\n"))
+        .collect()
+}
@@ -0,0 +1,76 @@
+use hmac::{Hmac, Mac};
+use matrix_sdk::ruma::{OwnedDeviceId, OwnedUserId};
+use matrix_sdk::{HttpError, Session};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+const LOGIN_PATH: &str = "_matrix/client/v3/login";
+const JWT_LOGIN_TYPE: &str = "org.matrix.login.jwt";
+
+#[derive(Serialize)]
+struct JwtLoginRequest<'a> {
+    #[serde(rename = "type")]
+    login_type: &'a str,
+    token: &'a str,
+    refresh_token: bool,
+}
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    user_id: OwnedUserId,
+    access_token: String,
+    refresh_token: Option<String>,
+    device_id: OwnedDeviceId,
+}
+
+/// Login with a JWT (`org.matrix.login.jwt`), returns the session to be restored in the client.
+pub async fn login(
+    http_client: &reqwest::Client,
+    homeserver_url: &str,
+    token: &str,
+    refresh_token: bool,
+) -> Result<Session, HttpError> {
+    let url = format!("{}/{LOGIN_PATH}", homeserver_url.trim_end_matches('/'));
+    let request = JwtLoginRequest {
+        login_type: JWT_LOGIN_TYPE,
+        token,
+        refresh_token,
+    };
+
+    let response = http_client.post(url).json(&request).send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(HttpError::Server(status));
+    }
+
+    let LoginResponse {
+        user_id,
+        access_token,
+        refresh_token,
+        device_id,
+    } = response.json().await?;
+
+    Ok(Session {
+        access_token,
+        refresh_token,
+        user_id,
+        device_id,
+    })
+}
+
+/// Encode a HS256 JWT with the localpart as subject, ex: {"sub": "user_1_ci"}
+pub fn encode(secret: &str, subject: &str) -> String {
+    let header = base64::encode_config(r#"{"alg":"HS256","typ":"JWT"}"#, base64::URL_SAFE_NO_PAD);
+    let claims = base64::encode_config(
+        serde_json::json!({ "sub": subject }).to_string(),
+        base64::URL_SAFE_NO_PAD,
+    );
+    let message = format!("{header}.{claims}");
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(message.as_bytes());
+    let signature = base64::encode_config(mac.finalize().into_bytes(), base64::URL_SAFE_NO_PAD);
+
+    format!("{message}.{signature}")
+}
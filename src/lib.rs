@@ -1,10 +1,45 @@
+mod account_validity;
+pub mod action;
+pub mod aggregate;
+pub mod canary;
+mod checkpoint;
 mod client;
 pub mod configuration;
+mod control;
+mod credentials;
+mod dashboard;
+pub mod distributed;
 mod events;
+mod friendship;
+mod fuzzing;
+mod http_server;
+mod interim;
+mod jwt;
+pub mod logging;
+mod manifest;
+mod notification;
+pub mod plan;
+mod preflight;
 pub mod progress;
+mod push_gateway;
+mod pusher;
+mod recording;
+pub mod regression;
 mod report;
+mod request_log;
+mod resources;
 mod room;
+mod server_metrics;
+mod shared_secret;
 pub mod simulation;
+mod telemetry;
 mod text;
 mod time;
+mod upload;
 mod user;
+mod versions;
+mod watchdog;
+
+pub use client::Client;
+pub use report::{Report, ReportSummary};
+pub use user::User;
@@ -0,0 +1,36 @@
+use crate::telemetry;
+use std::env;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter};
+
+// env var used to choose the logs format, `text` (default) or `json`
+const LOG_FORMAT: &str = "LOG_FORMAT";
+
+///
+/// Initialize logs, the level is configured with `RUST_LOG` (error by default) and the format with `LOG_FORMAT`.
+/// JSON logs include the fields of the current span (e.g. user localpart) so they can be ingested and correlated with server logs.
+/// Records from the `log` crate are also forwarded.
+/// When `OTEL_EXPORTER_OTLP_ENDPOINT` is set, spans are also exported with OpenTelemetry.
+///
+/// # Panics
+///
+/// If a global logger was already initialized.
+///
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error"));
+    let fmt_layer = match env::var(LOG_FORMAT) {
+        Ok(format) if format.eq_ignore_ascii_case("json") => fmt::layer().json().boxed(),
+        _ => fmt::layer().boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(fmt_layer.with_filter(filter))
+        .with(telemetry::layer().with_filter(LevelFilter::INFO))
+        .init();
+}
+
+/// Flush pending logs and traces
+pub fn finish() {
+    telemetry::shutdown();
+}
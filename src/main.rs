@@ -1,28 +1,69 @@
-use config::ConfigError;
-use matrix_reloaded::{configuration::Config, simulation::Simulation};
+use matrix_reloaded::{
+    aggregate::aggregate,
+    canary,
+    configuration::{Command, Config, Role},
+    distributed, logging, plan,
+    regression::RegressionError,
+    simulation::Simulation,
+};
 use miette::Result;
+use std::error::Error;
 use std::time::Duration;
 use tokio_graceful_shutdown::SubsystemHandle;
 use tokio_graceful_shutdown::Toplevel;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
+    logging::init();
 
     // graceful shutdown
-    Toplevel::new()
+    let result = Toplevel::new()
         .start("Simulation", simulation)
         .catch_signals()
-        .handle_shutdown_requests(Duration::from_secs(1))
-        .await
-        .map_err(Into::into)
+        // time to store the partial report after a shutdown is requested
+        .handle_shutdown_requests(Duration::from_secs(10))
+        .await;
+
+    logging::finish();
+    result.map_err(Into::into)
 }
 
-async fn simulation(_: SubsystemHandle) -> Result<(), ConfigError> {
+async fn simulation(subsys: SubsystemHandle) -> Result<(), Box<dyn Error + Send + Sync>> {
     log::debug!("Simulation started.");
 
-    let mut simulation = Simulation::with(Config::new()?);
-    simulation.run().await;
+    let config = Config::new()?;
+    if let Some(Command::Aggregate { reports }) = &config.command {
+        aggregate(&config, reports);
+        return Ok(());
+    }
+    if let Some(Command::Canary) = &config.command {
+        canary::run(&config, subsys.on_shutdown_requested()).await;
+        return Ok(());
+    }
+    if config.simulation.dry_run {
+        plan::dry_run(&config);
+        return Ok(());
+    }
+
+    let fail_on_regression = config
+        .baseline
+        .as_ref()
+        .map_or(false, |baseline| baseline.fail_on_regression);
+    match config.distributed.role {
+        Role::Standalone => {
+            let report = Simulation::with(config)
+                .run_until(subsys.on_shutdown_requested())
+                .await;
+            if fail_on_regression && report.regressions() > 0 {
+                return Err(RegressionError {
+                    regressions: report.regressions(),
+                }
+                .into());
+            }
+        }
+        Role::Coordinator => distributed::run_coordinator(config).await,
+        Role::Worker => distributed::run_worker(config).await,
+    }
 
     log::debug!("Simulation stopped.");
 
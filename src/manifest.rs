@@ -0,0 +1,69 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::fs::File;
+use std::path::Path;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Completed,
+    Aborted,
+    Interrupted,
+}
+
+/// Outcome of a run, written as `manifest.json` next to the reports so orchestration scripts don't need to parse logs.
+#[derive(Serialize, Debug)]
+pub struct Manifest {
+    pub status: RunStatus,
+    pub started_at: String,
+    pub finished_at: String,
+    pub ticks_completed: usize,
+    pub total_ticks: usize,
+    pub abort_reason: Option<String>,
+    pub files: Vec<String>,
+}
+
+impl Manifest {
+    pub fn new(started_at: DateTime<Local>, ticks_completed: usize, total_ticks: usize) -> Self {
+        Self {
+            status: RunStatus::Completed,
+            started_at: started_at.to_rfc3339(),
+            finished_at: Local::now().to_rfc3339(),
+            ticks_completed,
+            total_ticks,
+            abort_reason: None,
+            files: vec![],
+        }
+    }
+
+    pub fn abort(&mut self, reason: String) {
+        self.status = RunStatus::Aborted;
+        self.abort_reason = Some(reason);
+    }
+
+    /// Shutdown was requested (SIGINT / SIGTERM) before the run finished
+    pub fn interrupt(&mut self) {
+        self.status = RunStatus::Interrupted;
+    }
+
+    ///
+    /// Write manifest into the reports directory and print it to stdout as a single JSON line.
+    ///
+    /// # Panics
+    ///
+    /// If the manifest file cannot be created.
+    ///
+    pub fn write(&mut self, reports_dir: &str) {
+        let path = format!("{reports_dir}/manifest.json");
+        self.files.push(path.clone());
+
+        let buffer = File::create(Path::new(&path))
+            .unwrap_or_else(|_| panic!("could not create manifest file {path}"));
+        serde_json::to_writer_pretty(buffer, self).expect("couldn't write manifest to file");
+
+        println!(
+            "{}",
+            serde_json::to_string(self).expect("manifest to be serializable")
+        );
+    }
+}
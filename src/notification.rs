@@ -0,0 +1,95 @@
+use crate::configuration::{Notification, NotificationFormat};
+use crate::manifest::{Manifest, RunStatus};
+use crate::report::ReportSummary;
+use serde::Serialize;
+use serde_json::json;
+
+/// Payload of the notification, `sla_passed` is only present when watchdog thresholds are configured
+#[derive(Serialize)]
+pub struct RunSummary<'a> {
+    pub execution_id: &'a str,
+    pub homeserver: &'a str,
+    pub status: RunStatus,
+    pub abort_reason: Option<&'a str>,
+    pub ticks_completed: usize,
+    pub total_ticks: usize,
+    pub sla_passed: Option<bool>,
+    pub metrics: ReportSummary,
+}
+
+impl<'a> RunSummary<'a> {
+    pub fn new(
+        execution_id: &'a str,
+        homeserver: &'a str,
+        manifest: &'a Manifest,
+        watchdog_enabled: bool,
+        metrics: ReportSummary,
+    ) -> Self {
+        Self {
+            execution_id,
+            homeserver,
+            status: manifest.status,
+            abort_reason: manifest.abort_reason.as_deref(),
+            ticks_completed: manifest.ticks_completed,
+            total_ticks: manifest.total_ticks,
+            sla_passed: watchdog_enabled
+                .then_some(manifest.status != RunStatus::Aborted && metrics.ticks_aborted == 0),
+            metrics,
+        }
+    }
+
+    fn text(&self) -> String {
+        let metrics = &self.metrics;
+        let mut text = format!(
+            "Load test {} against {} {:?} after {}/{} ticks",
+            self.execution_id, self.homeserver, self.status, self.ticks_completed, self.total_ticks
+        );
+        if let Some(reason) = self.abort_reason {
+            text.push_str(&format!(" ({reason})"));
+        }
+        if let Some(sla_passed) = self.sla_passed {
+            let sla = if sla_passed { "passed" } else { "failed" };
+            text.push_str(&format!("\nSLA {sla}"));
+        }
+        text.push_str(&format!(
+            "\nrequests: {}, http errors: {}, rate limited: {}, average time: {}",
+            metrics.requests,
+            metrics.http_errors,
+            metrics.rate_limited_requests,
+            milliseconds(metrics.requests_average_time)
+        ));
+        text.push_str(&format!(
+            "\nmessages sent: {}, received in real time: {}, average delivery time: {}",
+            metrics.messages_sent,
+            metrics.real_time_messages,
+            milliseconds(metrics.message_delivery_average_time)
+        ));
+        text.push_str(&format!(
+            "\nacts cancelled: {}, ticks ended by the watchdog: {}",
+            metrics.acts_cancelled, metrics.ticks_aborted
+        ));
+        text
+    }
+}
+
+fn milliseconds(time: Option<u128>) -> String {
+    time.map_or_else(|| "-".to_string(), |time| format!("{time}ms"))
+}
+
+/// Post the summary to the webhook, failures are printed and don't affect the run.
+pub async fn notify(notification: &Notification, summary: &RunSummary<'_>) {
+    let payload = match notification.format {
+        NotificationFormat::Json => json!(summary),
+        NotificationFormat::Slack => json!({ "text": summary.text() }),
+        NotificationFormat::Discord => json!({ "content": summary.text() }),
+    };
+    let response = reqwest::Client::new()
+        .post(&notification.webhook_url)
+        .json(&payload)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = response {
+        println!("couldn't send run notification: {e}");
+    }
+}
@@ -0,0 +1,405 @@
+use crate::configuration::Config;
+use crate::user::social_action_probabilities;
+use matrix_sdk::ruma::RoomVersionId;
+use std::time::Duration;
+
+// rows printed for the plan, evenly distributed across the ticks
+const PLAN_ROWS: usize = 10;
+
+/// Expected state of the simulation after a tick, users are picked uniformly at random on every tick.
+struct PlannedTick {
+    tick: usize,
+    users: f64,
+    friendships: f64,
+    requests: f64,
+}
+
+/// Print the expected plan of the simulation without sending any request: users created, friendships and requests
+/// per tick and the estimated duration of the run. The configuration was validated when it was loaded.
+pub fn dry_run(config: &Config) {
+    println!("simulation config: {:#?}", config.simulation);
+    println!("feature flags config: {:#?}", config.feature_flags);
+
+    let probabilities = social_action_probabilities(config);
+    println!("actions per user act:");
+    for (action, probability) in &probabilities {
+        println!("  {action:<24} {:>6.2}%", probability * 100.);
+    }
+
+    if let Some(message_rate) = &config.message_rate {
+        println!(
+            "target message rate: {} messages per minute per syncing user, {} with max users syncing",
+            message_rate.per_minute,
+            message_rate.per_minute * config.simulation.max_users as f64
+        );
+    }
+
+    let plan = plan(config, &probabilities);
+    println!(
+        "{:>8} {:>12} {:>14} {:>14}",
+        "tick", "users", "friendships", "requests"
+    );
+    // values are cumulative
+    let step = (plan.len() / PLAN_ROWS).max(1);
+    for planned in plan
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| (i + 1) % step == 0 || i + 1 == plan.len())
+        .map(|(_, planned)| planned)
+    {
+        println!(
+            "{:>8} {:>12.0} {:>14.0} {:>14.0}",
+            planned.tick, planned.users, planned.friendships, planned.requests
+        );
+    }
+
+    let duration = if config.simulation.fast_forward {
+        Duration::ZERO
+    } else {
+        config.simulation.tick_duration * config.simulation.ticks as u32
+            + config.simulation.grace_period_duration
+    };
+    println!(
+        "estimated duration: {:?} ({} ticks of {:?} and {:?} of grace period)",
+        duration,
+        config.simulation.ticks,
+        config.simulation.tick_duration,
+        config.simulation.grace_period_duration
+    );
+}
+
+/// Errors of a configuration not valid for a simulation, checked when the configuration is built
+pub(crate) fn validate(config: &Config) -> Vec<String> {
+    let simulation = &config.simulation;
+    let mut errors = vec![];
+    if simulation.ticks == 0 {
+        errors.push("ticks must be greater than 0".to_string());
+    }
+    if simulation.max_users == 0 {
+        errors.push("max_users must be greater than 0".to_string());
+    }
+    if simulation.users_per_tick == 0 || simulation.users_per_tick > simulation.max_users {
+        errors.push(format!(
+            "users_per_tick must be between 1 and max_users ({}), got {}",
+            simulation.max_users, simulation.users_per_tick
+        ));
+    }
+    if simulation.max_sync_events_per_user == 0 {
+        errors.push("max_sync_events_per_user must be greater than 0".to_string());
+    }
+    // without the id number every user would register the same account
+    if !config.accounts.localpart_template.contains("{i}") {
+        errors.push(format!(
+            "accounts localpart_template must contain {{i}}, got {}",
+            config.accounts.localpart_template
+        ));
+    }
+    if simulation.probability_to_act > 100 {
+        errors.push(format!(
+            "probability_to_act must be between 0 and 100, got {}",
+            simulation.probability_to_act
+        ));
+    }
+    if simulation.probability_for_short_lifes > 100 {
+        errors.push(format!(
+            "probability_for_short_lifes must be between 0 and 100, got {}",
+            simulation.probability_for_short_lifes
+        ));
+    }
+    if simulation.probability_to_leave_room > 100 {
+        errors.push(format!(
+            "probability_to_leave_room must be between 0 and 100, got {}",
+            simulation.probability_to_leave_room
+        ));
+    }
+    if let Some(calls) = &config.calls {
+        if !(0. ..=100.).contains(&calls.probability) {
+            errors.push(format!(
+                "calls probability must be between 0 and 100, got {}",
+                calls.probability
+            ));
+        }
+    }
+    if let Some(message_rate) = &config.message_rate {
+        if message_rate.per_minute <= 0. || message_rate.sigma < 0. {
+            errors.push(format!(
+                "message_rate per_minute must be positive and sigma not negative, got {} and {}",
+                message_rate.per_minute, message_rate.sigma
+            ));
+        }
+    }
+    if let Some(account_data) = &config.account_data {
+        for (name, probability) in [
+            ("global_probability", account_data.global_probability),
+            ("room_probability", account_data.room_probability),
+        ] {
+            if !(0. ..=100.).contains(&probability) {
+                errors.push(format!(
+                    "account data {name} must be between 0 and 100, got {probability}"
+                ));
+            }
+        }
+    }
+    for event in &config.custom_events {
+        if !(0. ..=100.).contains(&event.probability) {
+            errors.push(format!(
+                "custom event {} probability must be between 0 and 100, got {}",
+                event.event_type, event.probability
+            ));
+        }
+    }
+    if simulation.probability_to_send_to_device > 100 {
+        errors.push(format!(
+            "probability_to_send_to_device must be between 0 and 100, got {}",
+            simulation.probability_to_send_to_device
+        ));
+    }
+    if simulation.probability_to_bootstrap_cross_signing > 100 {
+        errors.push(format!(
+            "probability_to_bootstrap_cross_signing must be between 0 and 100, got {}",
+            simulation.probability_to_bootstrap_cross_signing
+        ));
+    }
+    if simulation.probability_to_search_messages > 100 {
+        errors.push(format!(
+            "probability_to_search_messages must be between 0 and 100, got {}",
+            simulation.probability_to_search_messages
+        ));
+    }
+    if simulation.probability_to_fetch_event > 100 {
+        errors.push(format!(
+            "probability_to_fetch_event must be between 0 and 100, got {}",
+            simulation.probability_to_fetch_event
+        ));
+    }
+    if config.messages.url_probability > 100 {
+        errors.push(format!(
+            "messages url_probability must be between 0 and 100, got {}",
+            config.messages.url_probability
+        ));
+    }
+    if config.messages.sticker_probability + config.messages.location_probability > 100 {
+        errors.push(format!(
+            "messages sticker_probability and location_probability must add up to 100 at most, got {}",
+            config.messages.sticker_probability + config.messages.location_probability
+        ));
+    }
+    let length = &config.messages.length;
+    if length.min == 0 || length.min > length.max {
+        errors.push(format!(
+            "messages length min must be between 1 and max ({}), got {}",
+            length.max, length.min
+        ));
+    }
+    if length.sigma < 0. {
+        errors.push(format!(
+            "messages length sigma must not be negative, got {}",
+            length.sigma
+        ));
+    }
+    if !(0. ..=100.).contains(&length.large_probability) {
+        errors.push(format!(
+            "messages length large_probability must be between 0 and 100, got {}",
+            length.large_probability
+        ));
+    }
+    let flavor = config.server.flavor;
+    if config.registration.shared_secret.is_some() && !flavor.has_shared_secret_registration() {
+        errors.push(format!(
+            "{flavor:?} has no shared-secret registration, remove registration shared_secret"
+        ));
+    }
+    if config.account_validity.is_some() && !flavor.has_account_validity() {
+        errors.push(format!(
+            "{flavor:?} has no account validity admin API, remove the account_validity section"
+        ));
+    }
+    if config.server_metrics.is_some() && !flavor.has_metrics() {
+        errors.push(format!(
+            "{flavor:?} has no Prometheus metrics, remove the server_metrics section"
+        ));
+    }
+    if !(0. ..=1.).contains(&config.friendships.rewiring_probability) {
+        errors.push(format!(
+            "friendships rewiring_probability must be between 0 and 1, got {}",
+            config.friendships.rewiring_probability
+        ));
+    }
+    for (name, probability) in [
+        (
+            "simulation probability_to_update_room",
+            simulation.probability_to_update_room,
+        ),
+        (
+            "simulation probability_to_upgrade_room",
+            simulation.probability_to_upgrade_room,
+        ),
+        (
+            "simulation probability_to_ignore_user",
+            simulation.probability_to_ignore_user,
+        ),
+        (
+            "simulation probability_to_tag_room",
+            simulation.probability_to_tag_room,
+        ),
+        (
+            "messages emoji_probability",
+            config.messages.emoji_probability,
+        ),
+        ("messages rtl_probability", config.messages.rtl_probability),
+    ] {
+        if probability > 100 {
+            errors.push(format!(
+                "{name} must be between 0 and 100, got {probability}"
+            ));
+        }
+    }
+    let mut percentages = vec![];
+    if let Some(fuzzing) = &config.fuzzing {
+        percentages.push(("fuzzing probability", fuzzing.probability));
+    }
+    if let Some(chaos) = &config.chaos {
+        percentages.push((
+            "chaos sync_restart_probability",
+            chaos.sync_restart_probability,
+        ));
+        percentages.push((
+            "chaos request_drop_probability",
+            chaos.request_drop_probability,
+        ));
+    }
+    if let Some(forward_extremities) = &config.forward_extremities {
+        percentages.push((
+            "forward_extremities flap_probability",
+            forward_extremities.flap_probability,
+        ));
+        percentages.push((
+            "forward_extremities probability",
+            forward_extremities.probability,
+        ));
+    }
+    percentages.push((
+        "simulation probability_for_idle_users",
+        simulation.probability_for_idle_users,
+    ));
+    if let Some(lifecycle) = &config.lifecycle {
+        percentages.push(("lifecycle probability", lifecycle.probability));
+    }
+    if let Some(rate_limit_probe) = &config.rate_limit_probe {
+        percentages.push(("rate_limit_probe probability", rate_limit_probe.probability));
+    }
+    // every user has one role, the roles share the users
+    let roles = simulation.probability_for_idle_users
+        + config
+            .rate_limit_probe
+            .as_ref()
+            .map_or(0., |probe| probe.probability)
+        + config
+            .forward_extremities
+            .as_ref()
+            .map_or(0., |forward_extremities| forward_extremities.probability);
+    if roles > 100. {
+        errors.push(format!(
+            "probability_for_idle_users, rate_limit_probe and forward_extremities probabilities must add up to 100 at most, got {roles}"
+        ));
+    }
+    if let Some(http) = &config.http {
+        let networks = http.network.iter().chain(
+            http.groups
+                .iter()
+                .filter_map(|group| group.network.as_ref()),
+        );
+        percentages.extend(
+            networks.map(|network| ("http network drop_probability", network.drop_probability)),
+        );
+    }
+    for (name, probability) in percentages {
+        if !(0. ..=100.).contains(&probability) {
+            errors.push(format!(
+                "{name} must be between 0 and 100, got {probability}"
+            ));
+        }
+    }
+    // an error rate, like the ones of the report
+    if let Some(max_error_rate) = config
+        .watchdog
+        .as_ref()
+        .and_then(|watchdog| watchdog.max_error_rate)
+    {
+        if !(0. ..=1.).contains(&max_error_rate) {
+            errors.push(format!(
+                "watchdog max_error_rate must be between 0 and 1, got {max_error_rate}"
+            ));
+        }
+    }
+    let room_versions = [
+        ("rooms upgrade_version", Some(&config.rooms.upgrade_version)),
+        (
+            "rooms direct room_version",
+            config.rooms.direct.room_version.as_ref(),
+        ),
+        (
+            "rooms channel room_version",
+            config.rooms.channel.room_version.as_ref(),
+        ),
+    ];
+    for (name, version) in room_versions {
+        if let Some(version) = version {
+            if RoomVersionId::try_from(version.as_str()).is_err() {
+                errors.push(format!("{name} is not a valid room version, got {version}"));
+            }
+        }
+    }
+    errors
+}
+
+/// Expected values per tick, each picked user acts depending on the times it was picked before: first it wakes up,
+/// then it registers, logs in and starts syncing (one request each) and from then on it socializes.
+fn plan(config: &Config, probabilities: &[(String, f64)]) -> Vec<PlannedTick> {
+    let max_users = config.simulation.max_users as f64;
+    let picked_per_tick = config.simulation.users_per_tick as f64;
+    let pick_probability = picked_per_tick / max_users;
+    let probability_of = |name: &str| {
+        probabilities
+            .iter()
+            .find(|(action, _)| action == name)
+            .map_or(0., |(_, probability)| *probability)
+    };
+    let add_friend = probability_of("AddFriend");
+    let no_action = probability_of("None");
+
+    let mut friendships = 0.;
+    let mut requests = 0.;
+    (1..=config.simulation.ticks)
+        .map(|tick| {
+            let awake = picked_at_least(1, tick - 1, pick_probability);
+            let socializing = picked_at_least(4, tick - 1, pick_probability);
+
+            friendships += picked_per_tick * socializing * add_friend;
+            requests += picked_per_tick * (awake - socializing * no_action);
+            PlannedTick {
+                tick,
+                users: max_users * picked_at_least(1, tick, pick_probability),
+                friendships,
+                requests,
+            }
+        })
+        .collect()
+}
+
+/// Probability of a user being picked at least `times` in `ticks` ticks (binomial distribution).
+fn picked_at_least(times: usize, ticks: usize, probability: f64) -> f64 {
+    if ticks < times {
+        return 0.;
+    }
+    let mut coefficient = 1.;
+    let mut fewer = 0.;
+    for k in 0..times {
+        if k > 0 {
+            coefficient *= (ticks - k + 1) as f64 / k as f64;
+        }
+        fewer +=
+            coefficient * probability.powi(k as i32) * (1. - probability).powi((ticks - k) as i32);
+    }
+    (1. - fewer).max(0.)
+}
@@ -0,0 +1,184 @@
+use crate::client::{Client, LoginResult, RegisterResult};
+use crate::configuration::{Config, LoginMethod};
+use crate::credentials::Credentials;
+use crate::events::Event;
+use matrix_sdk::HttpError;
+use serde::Deserialize;
+use std::fmt::Display;
+use tokio::sync::mpsc::{self, Receiver};
+
+const VERSIONS_PATH: &str = "_matrix/client/versions";
+const LOGIN_PATH: &str = "_matrix/client/v3/login";
+// endpoints used by the client are available since spec v1.1
+const SUPPORTED_VERSIONS: [&str; 5] = ["v1.1", "v1.2", "v1.3", "v1.4", "v1.5"];
+
+#[derive(Deserialize)]
+struct VersionsResponse {
+    versions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct LoginFlow {
+    #[serde(rename = "type")]
+    login_type: String,
+}
+
+#[derive(Deserialize)]
+struct LoginFlowsResponse {
+    flows: Vec<LoginFlow>,
+}
+
+#[derive(Debug)]
+pub enum PreflightError {
+    Unreachable(String, reqwest::Error),
+    UnsupportedVersions(Vec<String>),
+    UnsupportedLogin(&'static str, Vec<String>),
+    Register(String),
+    Login(String),
+    Capabilities(HttpError),
+}
+
+impl Display for PreflightError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unreachable(url, e) => write!(f, "homeserver unreachable at {url}: {e}"),
+            Self::UnsupportedVersions(versions) => write!(
+                f,
+                "homeserver advertises spec versions {versions:?}, one of {SUPPORTED_VERSIONS:?} is required"
+            ),
+            Self::UnsupportedLogin(login_type, flows) => write!(
+                f,
+                "homeserver doesn't support {login_type} login, available flows are {flows:?}"
+            ),
+            Self::Register(reason) => write!(f, "couldn't register probe user: {reason}"),
+            Self::Login(reason) => write!(f, "couldn't login probe user: {reason}"),
+            Self::Capabilities(e) => write!(f, "couldn't get homeserver capabilities: {e}"),
+        }
+    }
+}
+
+///
+/// Check the homeserver is ready for the simulation before any user is created: it must be reachable,
+/// advertise a compatible spec version, support the configured login method and complete a
+/// register / login round-trip with a probe user (first pre-provisioned credentials when present).
+///
+pub async fn check(
+    config: &Config,
+    credentials: Option<&Credentials>,
+) -> Result<(), PreflightError> {
+    let homeserver_url = config.base_url(0);
+    let homeserver_url = homeserver_url.trim_end_matches('/');
+
+    // requests errors are collected to explain why the round-trip failed
+    let (tx, mut rx) = mpsc::channel::<Event>(100);
+    let client = Client::new(tx, None, None, 0, config).await;
+
+    let http_client = client.direct_http_client();
+    check_versions(http_client, homeserver_url).await?;
+    if config.appservice.is_none() && credentials.is_none() {
+        check_login_flow(http_client, homeserver_url, &config.login.method).await?;
+    }
+
+    let login = match credentials {
+        Some(credentials) => client.login_with_credentials(credentials).await,
+        None => {
+            let localpart = format!("user_preflight_{}", config.simulation.execution_id);
+            let can_register =
+                config.appservice.is_some() || config.login.method == LoginMethod::Password;
+            if can_register {
+                if let RegisterResult::Failed = client.register(&localpart).await {
+                    return Err(PreflightError::Register(last_error(&mut rx)));
+                }
+            }
+            client.login(&localpart).await
+        }
+    };
+    match login {
+        LoginResult::Ok => {}
+        LoginResult::NotRegistered => {
+            return Err(PreflightError::Login("user is not registered".to_string()))
+        }
+        LoginResult::Failed => return Err(PreflightError::Login(last_error(&mut rx))),
+    }
+
+    let capabilities = client
+        .capabilities()
+        .await
+        .map_err(PreflightError::Capabilities)?;
+    log::debug!("homeserver capabilities: {:?}", capabilities);
+
+    Ok(())
+}
+
+async fn check_versions(
+    http_client: &reqwest::Client,
+    homeserver_url: &str,
+) -> Result<(), PreflightError> {
+    let url = format!("{homeserver_url}/{VERSIONS_PATH}");
+    let response = get::<VersionsResponse>(http_client, &url).await?;
+    log::debug!("homeserver spec versions: {:?}", response.versions);
+
+    if response
+        .versions
+        .iter()
+        .any(|version| SUPPORTED_VERSIONS.contains(&version.as_str()))
+    {
+        Ok(())
+    } else {
+        Err(PreflightError::UnsupportedVersions(response.versions))
+    }
+}
+
+async fn check_login_flow(
+    http_client: &reqwest::Client,
+    homeserver_url: &str,
+    login_method: &LoginMethod,
+) -> Result<(), PreflightError> {
+    let login_type = match login_method {
+        LoginMethod::Password => "m.login.password",
+        LoginMethod::Jwt => "org.matrix.login.jwt",
+        LoginMethod::Token => "m.login.token",
+    };
+
+    let url = format!("{homeserver_url}/{LOGIN_PATH}");
+    let response = get::<LoginFlowsResponse>(http_client, &url).await?;
+    let flows = response
+        .flows
+        .into_iter()
+        .map(|flow| flow.login_type)
+        .collect::<Vec<_>>();
+
+    if flows.iter().any(|flow| flow == login_type) {
+        Ok(())
+    } else {
+        Err(PreflightError::UnsupportedLogin(login_type, flows))
+    }
+}
+
+async fn get<T: for<'de> Deserialize<'de>>(
+    http_client: &reqwest::Client,
+    url: &str,
+) -> Result<T, PreflightError> {
+    let unreachable = |e| PreflightError::Unreachable(url.to_string(), e);
+    http_client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(unreachable)?
+        .json::<T>()
+        .await
+        .map_err(unreachable)
+}
+
+fn last_error(rx: &mut Receiver<Event>) -> String {
+    let mut reason = "no error was returned by the homeserver".to_string();
+    while let Ok(event) = rx.try_recv() {
+        match event {
+            Event::Error((request, e)) => reason = format!("{request} failed: {e}"),
+            Event::RateLimited(request) => reason = format!("{request} was rate limited"),
+            _ => {}
+        }
+    }
+    reason
+}
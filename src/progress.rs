@@ -1,7 +1,17 @@
-use std::{env, sync::Arc, thread};
+use std::{collections::BTreeMap, env, sync::Arc, thread};
 
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 
+use crate::dashboard::Dashboard;
+use crate::events::LiveMetrics;
+
+/// Amount of users per state and pending sync events at the end of a tick
+#[derive(Default, Debug, Clone)]
+pub struct UsersOverview {
+    pub per_state: BTreeMap<&'static str, usize>,
+    pub sync_backlog: usize,
+}
+
 pub trait Progress
 where
     Self: Sync + Send,
@@ -9,12 +19,16 @@ where
     fn start(&self);
     fn tick(&mut self, users_syncing: u64);
     fn finish(&self);
+    /// Receive users overview on every tick, only needed by detailed progresses
+    fn update_users(&mut self, _users: &UsersOverview) {}
 }
 
 pub struct SimulationProgress {
     multi_progress: Arc<MultiProgress>,
     progress_bar: ProgressBar,
     users_bar: ProgressBar,
+    live_metrics: Arc<LiveMetrics>,
+    total_errors: usize,
 }
 
 impl SimulationProgress {
@@ -22,7 +36,7 @@ impl SimulationProgress {
         let progress_bar = ProgressBar::new(total_ticks.try_into().unwrap());
         let style = ProgressStyle::default_bar()
         .template(
-            "{prefix:>12.cyan.bold}: {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} ({eta})",
+            "{prefix:>12.cyan.bold}: {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} ({eta}) {msg}",
         )
         .progress_chars("=>-");
         progress_bar.set_style(style);
@@ -52,7 +66,7 @@ impl SimulationProgress {
 }
 
 impl SimulationProgress {
-    pub fn new(total_ticks: usize, max_users: usize) -> Self {
+    pub fn new(total_ticks: usize, max_users: usize, live_metrics: Arc<LiveMetrics>) -> Self {
         let multi_progress = Arc::new(MultiProgress::new());
         let progress_bar = multi_progress.add(Self::create_simulation_bar(total_ticks));
         let users_bar = multi_progress.add(Self::create_users_bar(max_users));
@@ -60,6 +74,8 @@ impl SimulationProgress {
             multi_progress,
             progress_bar,
             users_bar,
+            live_metrics,
+            total_errors: 0,
         }
     }
 }
@@ -74,11 +90,14 @@ impl Progress for SimulationProgress {
     }
 
     fn tick(&mut self, users_syncing: u64) {
+        self.total_errors += self.live_metrics.take().errors;
         let is_ci = env::var("CI").is_ok();
         if is_ci {
             println!("users syncing: {users_syncing}");
         } else {
             self.progress_bar.inc(1);
+            self.progress_bar
+                .set_message(format!("errors: {}", self.total_errors));
             self.users_bar.set_position(users_syncing);
         }
     }
@@ -98,10 +117,25 @@ impl Progress for SimulationProgress {
     }
 }
 
-#[derive(Default)]
+/// Plain text progress, one line per tick, used when stdout is not a terminal (e.g. CI logs)
 pub struct QuietProgress {
     tick: usize,
+    total_ticks: usize,
     max_users_connected: u64,
+    total_errors: usize,
+    live_metrics: Arc<LiveMetrics>,
+}
+
+impl QuietProgress {
+    pub fn new(total_ticks: usize, live_metrics: Arc<LiveMetrics>) -> Self {
+        Self {
+            tick: 0,
+            total_ticks,
+            max_users_connected: 0,
+            total_errors: 0,
+            live_metrics,
+        }
+    }
 }
 
 impl Progress for QuietProgress {
@@ -112,7 +146,17 @@ impl Progress for QuietProgress {
             self.max_users_connected = users_syncing;
         }
         self.tick += 1;
-        println!("users syncing: {users_syncing}");
+        let errors = self.live_metrics.take().errors;
+        self.total_errors += errors;
+        let percentage = if self.total_ticks > 0 {
+            self.tick * 100 / self.total_ticks
+        } else {
+            100
+        };
+        println!(
+            "tick {}/{} ({}%) - users syncing: {} - errors: {} (total {})",
+            self.tick, self.total_ticks, percentage, users_syncing, errors, self.total_errors
+        );
     }
 
     fn finish(&self) {
@@ -123,10 +167,21 @@ impl Progress for QuietProgress {
     }
 }
 
-pub fn create_progress(ticks: usize, max_users: usize) -> Box<dyn Progress> {
+/// Create the progress for the simulation:
+///  - plain text lines when running in CI or stdout is not a terminal
+///  - live dashboard if requested
+///  - progress bars otherwise
+pub fn create_progress(
+    ticks: usize,
+    max_users: usize,
+    dashboard: bool,
+    live_metrics: Arc<LiveMetrics>,
+) -> Box<dyn Progress> {
     let is_ci = env::var("CI").is_ok();
-    match is_ci {
-        true => Box::new(QuietProgress::default()),
-        false => Box::new(SimulationProgress::new(ticks, max_users)),
+    let is_interactive = !is_ci && atty::is(atty::Stream::Stdout);
+    match (is_interactive, dashboard) {
+        (false, _) => Box::new(QuietProgress::new(ticks, live_metrics)),
+        (true, true) => Box::new(Dashboard::new(ticks, live_metrics)),
+        (true, false) => Box::new(SimulationProgress::new(ticks, max_users, live_metrics)),
     }
 }
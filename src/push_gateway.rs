@@ -0,0 +1,61 @@
+use crate::events::Event;
+use crate::http_server::{self, Response};
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+
+// every notification is accepted, pushkeys are never rejected
+const RESPONSE: &str = "{\"rejected\":[]}";
+
+#[derive(Deserialize)]
+struct Notification {
+    event_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NotifyRequest {
+    notification: Notification,
+}
+
+///
+/// Start a push gateway (`/_matrix/push/v1/notify`) receiving the notifications of the users pushers,
+/// every notification is reported with its event id to correlate it with the message sent.
+///
+/// # Panics
+///
+/// If the gateway cannot listen on the address.
+///
+pub async fn start(address: &str, notifier: Sender<Event>) -> JoinHandle<()> {
+    let server = http_server::serve(address, "push gateway", move |body| {
+        let notifier = notifier.clone();
+        async move {
+            if let Some(event_id) = event_id(&body) {
+                if notifier.send(Event::PushReceived(event_id)).await.is_err() {
+                    // simulation finished, events are not collected anymore
+                    return None;
+                }
+            }
+            Some(Response::new("application/json", RESPONSE.to_string()))
+        }
+    })
+    .await;
+    println!("push gateway listening on {address}");
+    server
+}
+
+fn event_id(body: &[u8]) -> Option<String> {
+    match serde_json::from_slice::<NotifyRequest>(body) {
+        Ok(NotifyRequest {
+            notification: Notification { event_id },
+        }) => {
+            if event_id.is_none() {
+                log::debug!("push notification without event id");
+            }
+            event_id
+        }
+        Err(e) => {
+            log::debug!("invalid push notification: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use matrix_sdk::HttpError;
+use serde::Serialize;
+
+const PUSHERS_PATH: &str = "_matrix/client/v3/pushers/set";
+const APP_ID: &str = "org.decentraland.matrix-reloaded";
+
+#[derive(Serialize)]
+struct PusherData<'a> {
+    url: &'a str,
+}
+
+#[derive(Serialize)]
+struct SetPusherRequest<'a> {
+    kind: &'static str,
+    app_id: &'static str,
+    pushkey: &'a str,
+    app_display_name: &'static str,
+    device_display_name: &'a str,
+    lang: &'static str,
+    data: PusherData<'a>,
+    append: bool,
+}
+
+/// Register an HTTP pusher for the user device, the homeserver sends a notification to the gateway
+/// for every event matching the user push rules.
+pub async fn set(
+    http_client: &reqwest::Client,
+    homeserver_url: &str,
+    access_token: &str,
+    device_id: &str,
+    gateway_url: &str,
+) -> Result<(), HttpError> {
+    let url = format!("{}/{PUSHERS_PATH}", homeserver_url.trim_end_matches('/'));
+    let request = SetPusherRequest {
+        kind: "http",
+        app_id: APP_ID,
+        pushkey: device_id,
+        app_display_name: "Matrix Reloaded",
+        device_display_name: device_id,
+        lang: "en",
+        data: PusherData { url: gateway_url },
+        append: false,
+    };
+
+    http_client
+        .post(url)
+        .bearer_auth(access_token)
+        .json(&request)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use matrix_sdk::ruma::OwnedRoomId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What a recorded action was performed on, replayed when it still applies: the room, the friend (by id number)
+/// and the text of the message sent
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Target {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<OwnedRoomId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub friend: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+/// Action of a user on a tick as it is replayed
+pub struct RecordedAction {
+    pub action: String,
+    pub delay: Duration, // since the tick started, as the act started in the recorded run
+    pub target: Target,
+}
+
+/// Line of a recording, users are identified by their id number (the one in their localpart)
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Record {
+    /// Users picked to act on the tick
+    Tick {
+        tick: usize,
+        offset_ms: u128,
+        users: Vec<usize>,
+    },
+    /// Action performed by a user on the tick, ex: SendMessage(Channel), the offset is the one of the act start
+    Action {
+        tick: usize,
+        offset_ms: u128,
+        user: usize,
+        action: String,
+        #[serde(flatten)]
+        target: Target,
+    },
+}
+
+/// Writes the users picked on every tick and the action each of them performed, with its target, as JSON lines
+pub struct Recorder {
+    writer: Mutex<BufWriter<File>>,
+    started_at: Instant,
+}
+
+impl Recorder {
+    ///
+    /// # Panics
+    ///
+    /// If the recording file cannot be created.
+    ///
+    pub fn create(path: &str) -> Self {
+        let file =
+            File::create(path).unwrap_or_else(|_| panic!("could not create recording file {path}"));
+        Self {
+            writer: Mutex::new(BufWriter::new(file)),
+            started_at: Instant::now(),
+        }
+    }
+
+    pub fn record_tick(&self, tick: usize, users: Vec<usize>) {
+        self.write(&Record::Tick {
+            tick,
+            offset_ms: self.started_at.elapsed().as_millis(),
+            users,
+        });
+    }
+
+    /// Milliseconds since the recording started, taken when an act starts to record it with its action
+    pub fn offset_ms(&self) -> u128 {
+        self.started_at.elapsed().as_millis()
+    }
+
+    pub fn record_action(
+        &self,
+        tick: usize,
+        offset_ms: u128,
+        user: usize,
+        action: String,
+        target: Target,
+    ) {
+        self.write(&Record::Action {
+            tick,
+            offset_ms,
+            user,
+            action,
+            target,
+        });
+    }
+
+    ///
+    /// # Panics
+    ///
+    /// If the recording file cannot be written.
+    ///
+    pub fn flush(&self) {
+        self.writer
+            .lock()
+            .expect("lock not poisoned")
+            .flush()
+            .expect("couldn't write recording");
+    }
+
+    fn write(&self, record: &Record) {
+        let mut writer = self.writer.lock().expect("lock not poisoned");
+        serde_json::to_writer(&mut *writer, record).expect("couldn't write recording");
+        writer.write_all(b"\n").expect("couldn't write recording");
+    }
+}
+
+/// Recorded run re-executed instead of picking users and actions at random
+#[derive(Default)]
+pub struct Replay {
+    ticks: HashMap<usize, Vec<usize>>,
+    actions: HashMap<(usize, usize), RecordedAction>,
+}
+
+impl Replay {
+    ///
+    /// # Panics
+    ///
+    /// If the recording cannot be read or one of its lines is not a record.
+    ///
+    pub fn load(path: &str) -> Self {
+        let file = File::open(path).unwrap_or_else(|_| panic!("could not open recording {path}"));
+        let mut replay = Self::default();
+        let mut tick_offsets = HashMap::new();
+        let mut actions = vec![];
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.unwrap_or_else(|_| panic!("could not read recording {path}"));
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str::<Record>(&line)
+                .unwrap_or_else(|e| panic!("invalid record in line {} of {path}: {e}", number + 1));
+            match record {
+                Record::Tick {
+                    tick,
+                    offset_ms,
+                    users,
+                } => {
+                    replay.ticks.insert(tick, users);
+                    tick_offsets.insert(tick, offset_ms);
+                }
+                Record::Action {
+                    tick,
+                    offset_ms,
+                    user,
+                    action,
+                    target,
+                } => actions.push((tick, offset_ms, user, action, target)),
+            }
+        }
+        for (tick, offset_ms, user, action, target) in actions {
+            let tick_offset_ms = tick_offsets.get(&tick).copied().unwrap_or(offset_ms);
+            let delay = Duration::from_millis(offset_ms.saturating_sub(tick_offset_ms) as u64);
+            let action = RecordedAction {
+                action,
+                delay,
+                target,
+            };
+            replay.actions.insert((tick, user), action);
+        }
+        println!(
+            "replaying {} ticks and {} actions from {path}",
+            replay.ticks.len(),
+            replay.actions.len()
+        );
+        replay
+    }
+
+    /// Users picked on the tick, none if the recording didn't reach it
+    pub fn users(&self, tick: usize) -> Vec<usize> {
+        self.ticks.get(&tick).cloned().unwrap_or_default()
+    }
+
+    pub fn action(&self, tick: usize, user: usize) -> Option<&RecordedAction> {
+        self.actions.get(&(tick, user))
+    }
+}
@@ -0,0 +1,136 @@
+use crate::report::{Report, ReportSummary};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Key metric of the run that got worse than in the baseline beyond the tolerance
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Regression {
+    pub metric: String,
+    pub baseline: f64,
+    pub current: f64,
+    /// change relative to the baseline, missing when the baseline value is zero
+    pub change_percent: Option<f64>,
+}
+
+/// Key metrics of the run compared against the ones of a baseline report
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Comparison {
+    pub baseline: String,
+    pub tolerance_percent: f64,
+    pub regressions: Vec<Regression>,
+}
+
+/// Returned when the run regressed and `baseline.fail_on_regression` is set, so the process exits with an error
+#[derive(Debug)]
+pub struct RegressionError {
+    pub regressions: usize,
+}
+
+impl fmt::Display for RegressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} metrics regressed against the baseline",
+            self.regressions
+        )
+    }
+}
+
+impl std::error::Error for RegressionError {}
+
+enum Better {
+    Lower,
+    Higher,
+}
+
+/// Metrics compared, rates instead of totals so runs of different sizes can be compared
+fn metrics(summary: &ReportSummary) -> Vec<(&'static str, Better, Option<f64>)> {
+    let percent = |part: usize, total: u128| (total > 0).then(|| part as f64 * 100. / total as f64);
+    vec![
+        (
+            "requests_average_time_ms",
+            Better::Lower,
+            summary.requests_average_time.map(|time| time as f64),
+        ),
+        (
+            "message_delivery_average_time_ms",
+            Better::Lower,
+            summary
+                .message_delivery_average_time
+                .map(|time| time as f64),
+        ),
+        (
+            "http_errors_percent",
+            Better::Lower,
+            percent(summary.http_errors, summary.requests),
+        ),
+        (
+            "rate_limited_requests_percent",
+            Better::Lower,
+            percent(summary.rate_limited_requests, summary.requests),
+        ),
+        (
+            "real_time_messages_percent",
+            Better::Higher,
+            percent(summary.real_time_messages, summary.messages_sent as u128),
+        ),
+    ]
+}
+
+///
+/// Compare the key metrics of the run against the baseline, metrics missing in any of the reports are skipped.
+///
+pub fn compare(
+    baseline_path: &str,
+    baseline: &Report,
+    current: &Report,
+    tolerance_percent: f64,
+) -> Comparison {
+    let tolerance = tolerance_percent / 100.;
+    let regressions = metrics(&baseline.summary())
+        .into_iter()
+        .zip(metrics(&current.summary()))
+        .filter_map(|((metric, better, baseline), (_, _, current))| {
+            let (baseline, current) = (baseline?, current?);
+            let regressed = match better {
+                Better::Lower => current > baseline * (1. + tolerance),
+                Better::Higher => current < baseline * (1. - tolerance),
+            };
+            regressed.then(|| Regression {
+                metric: metric.to_string(),
+                baseline,
+                current,
+                change_percent: (baseline > 0.).then(|| (current - baseline) * 100. / baseline),
+            })
+        })
+        .collect();
+    Comparison {
+        baseline: baseline_path.to_string(),
+        tolerance_percent,
+        regressions,
+    }
+}
+
+/// Regression section printed when the run finishes
+pub fn print(comparison: &Comparison) {
+    if comparison.regressions.is_empty() {
+        println!(
+            "no regressions against the baseline {} (tolerance {}%)",
+            comparison.baseline, comparison.tolerance_percent
+        );
+        return;
+    }
+    println!(
+        "regressions against the baseline {} (tolerance {}%):",
+        comparison.baseline, comparison.tolerance_percent
+    );
+    for regression in &comparison.regressions {
+        let change = regression
+            .change_percent
+            .map_or_else(|| "new".to_string(), |change| format!("{change:+.1}%"));
+        println!(
+            "  {}: {:.2} -> {:.2} ({change})",
+            regression.metric, regression.baseline, regression.current
+        );
+    }
+}
@@ -1,26 +1,430 @@
-use crate::events::MessageTimes;
-use crate::events::UserRequest;
+use crate::events::{MessageTimes, ProbeBurst, SyncStats};
+use crate::events::{UserRequest, CLIENT_TIMEOUT};
+use crate::regression::Comparison;
+use crate::resources::ResourceUsage;
+use crate::server_metrics::{ServerMetric, TickServerMetrics};
 use crate::simulation::ChannelsInfo;
+use crate::versions::Versions;
 use matrix_sdk::ruma::api::client::uiaa::UiaaResponse;
 use matrix_sdk::ruma::api::error::*;
 use matrix_sdk::HttpError;
 use matrix_sdk::RumaApiError;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use serde_with::serde_as;
 use serde_with::DisplayFromStr;
 use std::fs::create_dir_all;
 use std::fs::File;
-use std::{cmp::Reverse, collections::HashMap, time::Duration};
+use std::hash::Hash;
+use std::sync::mpsc;
+use std::time::Instant;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+// error code of requests cancelled by the client after their timeout, a 408 of the server keeps its status
+const TIMEOUT: &str = "timeout";
+// category of rate limited requests
+const LIMIT_EXCEEDED: &str = "M_LIMIT_EXCEEDED";
+// amount of different error messages kept in the report
+const TOP_ERROR_MESSAGES: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ErrorMessage {
+    message: String,
+    count: usize,
+}
+
+/// Acts of the users picked on a tick: planned, completed before the tick ended and cancelled by the tick timeout
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct TickThroughput {
+    pub tick: usize,
+    pub planned: usize,
+    pub completed: usize,
+    pub cancelled: usize,
+    /// ended early by the watchdog, its remaining acts were cancelled
+    #[serde(default)]
+    pub aborted: bool,
+}
+
+/// Users in each state when a tick ended, `Waiting` users were not picked for the first time yet
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct TickUsers {
+    pub tick: usize,
+    #[serde(flatten)]
+    pub per_state: BTreeMap<String, usize>,
+}
+
+/// Key metrics of a report, sent when the run finishes
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ReportSummary {
+    pub requests: u128,
+    pub http_errors: usize,
+    pub rate_limited_requests: usize,
+    pub requests_average_time: Option<u128>,
+    pub message_delivery_average_time: Option<u128>,
+    pub messages_sent: usize,
+    pub real_time_messages: usize,
+    pub acts_cancelled: usize,
+    pub ticks_aborted: usize,
+}
+
+/// Distribution of latencies in milliseconds (or sizes), percentiles of merged reports are computed from the merged buckets
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Distribution {
+    pub count: usize,
+    pub average: u128,
+    pub p50: u128,
+    pub p90: u128,
+    pub p99: u128,
+    /// count of the values rounded down to two significant digits, by rounded value
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub buckets: BTreeMap<u64, usize>,
+}
+
+/// Bucket of the value, rounded down to two significant digits so the bucket is less than 10% wider than its values
+fn bucket(value: u128) -> u64 {
+    let value = u64::try_from(value).unwrap_or(u64::MAX);
+    let mut scale = 1;
+    while value / scale >= 100 {
+        scale *= 10;
+    }
+    value / scale * scale
+}
+
+impl Distribution {
+    /// Distribution of the times received after being sent, none when there are no such times
+    fn from_times(times: &HashMap<String, MessageTimes>) -> Option<Self> {
+        let latencies = times
+            .values()
+            .filter_map(|times| match (times.sent, times.received) {
+                (Some(sent), Some(received)) => {
+                    Some(received.saturating_duration_since(sent).as_millis())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        Self::from_values(latencies)
+    }
+
+    pub fn from_values(mut values: Vec<u128>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_unstable();
+        let percentile =
+            |percentile: usize| values[(values.len() * percentile / 100).min(values.len() - 1)];
+        let mut buckets = BTreeMap::new();
+        for value in &values {
+            *buckets.entry(bucket(*value)).or_default() += 1;
+        }
+        Some(Self {
+            count: values.len(),
+            average: values.iter().sum::<u128>() / values.len() as u128,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            buckets,
+        })
+    }
+
+    /// Percentile of the bucketed values, the rounded value of the bucket it falls in
+    fn bucket_percentile(buckets: &BTreeMap<u64, usize>, count: usize, percentile: usize) -> u128 {
+        let index = (count * percentile / 100).min(count - 1);
+        let mut seen = 0;
+        for (value, bucket_count) in buckets {
+            seen += bucket_count;
+            if seen > index {
+                return *value as u128;
+            }
+        }
+        buckets.keys().next_back().copied().unwrap_or_default() as u128
+    }
+
+    ///
+    /// Merge the distributions of different reports. Percentiles are computed from the merged buckets, reports
+    /// stored before the buckets were recorded only have their percentiles averaged weighted by their count,
+    /// an approximation.
+    ///
+    fn merge(distributions: &[&Self]) -> Option<Self> {
+        let count = distributions
+            .iter()
+            .map(|distribution| distribution.count)
+            .sum::<usize>();
+        if count == 0 {
+            return None;
+        }
+        let weighted = |value: fn(&Self) -> u128| {
+            distributions
+                .iter()
+                .map(|distribution| value(distribution) * distribution.count as u128)
+                .sum::<u128>()
+                / count as u128
+        };
+        let bucketed = distributions
+            .iter()
+            .all(|distribution| distribution.count == 0 || !distribution.buckets.is_empty());
+        if !bucketed {
+            return Some(Self {
+                count,
+                average: weighted(|distribution| distribution.average),
+                p50: weighted(|distribution| distribution.p50),
+                p90: weighted(|distribution| distribution.p90),
+                p99: weighted(|distribution| distribution.p99),
+                buckets: BTreeMap::new(),
+            });
+        }
+        let mut buckets = BTreeMap::new();
+        for distribution in distributions {
+            for (value, bucket_count) in &distribution.buckets {
+                *buckets.entry(*value).or_default() += bucket_count;
+            }
+        }
+        Some(Self {
+            count,
+            average: weighted(|distribution| distribution.average),
+            p50: Self::bucket_percentile(&buckets, count, 50),
+            p90: Self::bucket_percentile(&buckets, count, 90),
+            p99: Self::bucket_percentile(&buckets, count, 99),
+            buckets,
+        })
+    }
+}
+
+/// Distribution of the values recorded during a tick
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TickDistribution {
+    pub tick: usize,
+    #[serde(flatten)]
+    pub distribution: Distribution,
+}
+
+impl TickDistribution {
+    /// Merge the distributions of the same tick in different reports
+    fn merge(reports: &[&[TickDistribution]]) -> Vec<TickDistribution> {
+        let mut ticks = BTreeMap::<usize, Vec<&Distribution>>::new();
+        for report in reports {
+            for tick in report.iter() {
+                ticks.entry(tick.tick).or_default().push(&tick.distribution);
+            }
+        }
+        ticks
+            .into_iter()
+            .filter_map(|(tick, distributions)| {
+                Some(TickDistribution {
+                    tick,
+                    distribution: Distribution::merge(&distributions)?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Sync responses of a kind (initial or incremental)
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct SyncMetrics {
+    pub count: usize,
+    /// time until the response of initial syncs
+    pub latency: Option<Distribution>,
+    /// time incremental syncs were held by the server until there were new events or the long-polling timeout
+    #[serde(default)]
+    pub wait: Option<Distribution>,
+    /// incremental syncs answered before the long-polling timeout
+    #[serde(default)]
+    pub returned_early: usize,
+    /// JSON bytes of the timeline and state events of the joined rooms, not of the whole response
+    #[serde(alias = "size")]
+    pub events_size: Option<Distribution>,
+    #[serde(default)]
+    pub events: Option<Distribution>,
+}
+
+impl SyncMetrics {
+    fn from(syncs: &[SyncStats]) -> Option<Self> {
+        if syncs.is_empty() {
+            return None;
+        }
+        let durations =
+            Distribution::from_values(syncs.iter().map(|sync| sync.duration.as_millis()).collect());
+        // incremental syncs are long-polling requests, their duration is mostly waiting for new events
+        let (latency, wait) = match syncs[0].initial {
+            true => (durations, None),
+            false => (None, durations),
+        };
+        Some(Self {
+            count: syncs.len(),
+            latency,
+            wait,
+            returned_early: syncs.iter().filter(|sync| sync.returned_early).count(),
+            events_size: Distribution::from_values(
+                syncs.iter().map(|sync| sync.events_size as u128).collect(),
+            ),
+            events: Distribution::from_values(
+                syncs.iter().map(|sync| sync.events as u128).collect(),
+            ),
+        })
+    }
+
+    fn merge(metrics: &[&Self]) -> Option<Self> {
+        if metrics.is_empty() {
+            return None;
+        }
+        Some(Self {
+            count: metrics.iter().map(|metrics| metrics.count).sum(),
+            latency: Distribution::merge(
+                &metrics
+                    .iter()
+                    .filter_map(|metrics| metrics.latency.as_ref())
+                    .collect::<Vec<_>>(),
+            ),
+            wait: Distribution::merge(
+                &metrics
+                    .iter()
+                    .filter_map(|metrics| metrics.wait.as_ref())
+                    .collect::<Vec<_>>(),
+            ),
+            returned_early: metrics.iter().map(|metrics| metrics.returned_early).sum(),
+            events_size: Distribution::merge(
+                &metrics
+                    .iter()
+                    .filter_map(|metrics| metrics.events_size.as_ref())
+                    .collect::<Vec<_>>(),
+            ),
+            events: Distribution::merge(
+                &metrics
+                    .iter()
+                    .filter_map(|metrics| metrics.events.as_ref())
+                    .collect::<Vec<_>>(),
+            ),
+        })
+    }
+}
+
+/// Bursts of the rate limit probers, `consistency` is the share of the requests rate limited once the first one was
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct RateLimitProbes {
+    pub bursts: usize,
+    /// bursts that were never rate limited
+    pub bursts_not_limited: usize,
+    pub requests: usize,
+    pub rate_limited: usize,
+    /// requests sent until the first one was rate limited, including it
+    pub requests_to_limit: Option<Distribution>,
+    pub time_to_limit: Option<Distribution>,
+    /// requests sent since the first one was rate limited, including it
+    pub requests_after_limit: usize,
+    pub consistency: Option<f64>,
+}
+
+impl RateLimitProbes {
+    fn from(bursts: &[ProbeBurst]) -> Option<Self> {
+        if bursts.is_empty() {
+            return None;
+        }
+        let limited = bursts
+            .iter()
+            .filter_map(|burst| burst.first_limited.map(|first| (burst, first)))
+            .collect::<Vec<_>>();
+        let requests_after_limit = limited
+            .iter()
+            .map(|(burst, (requests, _))| burst.requests - requests + 1)
+            .sum();
+        let rate_limited = bursts.iter().map(|burst| burst.rate_limited).sum();
+        Some(Self {
+            bursts: bursts.len(),
+            bursts_not_limited: bursts.len() - limited.len(),
+            requests: bursts.iter().map(|burst| burst.requests).sum(),
+            rate_limited,
+            requests_to_limit: Distribution::from_values(
+                limited
+                    .iter()
+                    .map(|(_, (requests, _))| *requests as u128)
+                    .collect(),
+            ),
+            time_to_limit: Distribution::from_values(
+                limited
+                    .iter()
+                    .map(|(_, (_, elapsed))| elapsed.as_millis())
+                    .collect(),
+            ),
+            requests_after_limit,
+            consistency: Self::consistency(rate_limited, requests_after_limit),
+        })
+    }
+
+    fn merge(probes: &[&Self]) -> Option<Self> {
+        if probes.is_empty() {
+            return None;
+        }
+        let rate_limited = probes.iter().map(|probes| probes.rate_limited).sum();
+        let requests_after_limit = probes
+            .iter()
+            .map(|probes| probes.requests_after_limit)
+            .sum();
+        Some(Self {
+            bursts: probes.iter().map(|probes| probes.bursts).sum(),
+            bursts_not_limited: probes.iter().map(|probes| probes.bursts_not_limited).sum(),
+            requests: probes.iter().map(|probes| probes.requests).sum(),
+            rate_limited,
+            requests_to_limit: Distribution::merge(
+                &probes
+                    .iter()
+                    .filter_map(|probes| probes.requests_to_limit.as_ref())
+                    .collect::<Vec<_>>(),
+            ),
+            time_to_limit: Distribution::merge(
+                &probes
+                    .iter()
+                    .filter_map(|probes| probes.time_to_limit.as_ref())
+                    .collect::<Vec<_>>(),
+            ),
+            requests_after_limit,
+            consistency: Self::consistency(rate_limited, requests_after_limit),
+        })
+    }
+
+    fn consistency(rate_limited: usize, requests_after_limit: usize) -> Option<f64> {
+        (requests_after_limit > 0).then(|| rate_limited as f64 / requests_after_limit as f64)
+    }
+}
+
+/// Requests sent through one of the base URLs of the homeserver
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct BaseUrlRequests {
+    pub requests: usize,
+    pub average_time: u128,
+}
 
 #[serde_as]
-#[derive(Serialize, Default, Debug)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct Report {
+    /// the run was aborted or interrupted before all the ticks were completed
+    #[serde(default)]
+    incomplete: bool,
     #[serde_as(as = "HashMap<_, _>")]
     requests_average_time: Vec<(UserRequest, u128)>,
     #[serde_as(as = "HashMap<_, _>")]
     total_requests: Vec<(UserRequest, u128)>,
+    /// requests sent during the warm-up, included in the totals but not in the average times
+    #[serde_as(as = "HashMap<_, _>")]
+    #[serde(default)]
+    warm_up_requests: Vec<(UserRequest, u128)>,
     #[serde_as(as = "HashMap<DisplayFromStr, _>")]
     http_errors_per_request: Vec<(String, usize)>,
+    /// requests rejected with 429 / M_LIMIT_EXCEEDED, not counted as http errors
+    #[serde_as(as = "HashMap<_, _>")]
+    #[serde(default)]
+    rate_limited_requests: Vec<(UserRequest, usize)>,
+    /// errors by Matrix errcode, http status (`http_{status}`), timeout or connection error
+    #[serde_as(as = "HashMap<_, _>")]
+    #[serde(default)]
+    errors_per_category: Vec<(String, usize)>,
+    /// most frequent error messages
+    #[serde(default)]
+    top_error_messages: Vec<ErrorMessage>,
     message_delivery_average_time: Option<u128>,
     /// number of messages sent correctly but not received (receipent is offline)
     messages_sent: usize,
@@ -28,22 +432,239 @@ pub struct Report {
     messages_not_sent: usize,
     /// number of messages sent and received during simulation
     real_time_messages: usize,
+    #[serde(default)]
+    to_device_delivery_average_time: Option<u128>,
+    /// number of to-device messages sent and received by the recipient sync loop during simulation
+    #[serde(default)]
+    to_device_delivered: usize,
+    /// number of to-device messages sent but not received
+    #[serde(default)]
+    to_device_not_delivered: usize,
+    /// average time from a message being sent to its push notification reaching the push gateway
+    #[serde(default)]
+    push_delivery_average_time: Option<u128>,
+    /// number of push notifications received for messages sent during simulation
+    #[serde(default)]
+    pushes_received: usize,
+    /// time from an invite being sent to the invitee receiving it in sync
+    #[serde(default)]
+    invite_latency: Option<Distribution>,
+    /// time from a `/join` response to the first other member of the room seeing the join in sync
+    #[serde(default)]
+    join_latency: Option<Distribution>,
+    /// time from the `origin_server_ts` of the events received by the sync loops to their arrival, it includes
+    /// the clock difference between the homeserver and the load generator
+    #[serde(default)]
+    sync_lag: Option<Distribution>,
+    #[serde(default)]
+    sync_lag_per_tick: Vec<TickDistribution>,
+    /// latency of the messages sent to the room forked by the forward extremities scenario, it grows as the
+    /// extremities pile up
+    #[serde(default)]
+    forked_room_message_latency_per_tick: Vec<TickDistribution>,
+    /// first sync of every session, without a `since` token
+    #[serde(default)]
+    initial_sync: Option<SyncMetrics>,
+    /// syncs of the sync loops, their latency includes the long-polling wait until there are new events
+    #[serde(default)]
+    incremental_sync: Option<SyncMetrics>,
+    /// bursts of the users probing the homeserver rate limits, not included in the other requests metrics
+    #[serde(default)]
+    rate_limit_probes: Option<RateLimitProbes>,
+    /// responses to the malformed payloads sent instead of messages, by payload and status
+    #[serde_as(as = "HashMap<_, _>")]
+    #[serde(default)]
+    fuzz_responses: Vec<(String, usize)>,
+    /// malformed payloads the homeserver failed with a 5xx status instead of rejecting them
+    #[serde(default)]
+    fuzz_server_errors: usize,
+    /// events size and events count of the sync responses (initial and incremental) received on each tick, to spot payloads growing
+    #[serde(default, alias = "sync_size_per_tick")]
+    sync_events_size_per_tick: Vec<TickDistribution>,
+    #[serde(default)]
+    sync_events_per_tick: Vec<TickDistribution>,
+    /// rooms notification counts checked against the messages received in the same sync
+    #[serde(default)]
+    notification_counts_checked: usize,
+    #[serde(default)]
+    notification_count_mismatches: usize,
+    /// acts of the users picked along the simulation, cancelled acts mean the load generator couldn't keep up
+    #[serde(default)]
+    acts_planned: usize,
+    #[serde(default)]
+    acts_completed: usize,
+    #[serde(default)]
+    acts_cancelled: usize,
+    /// cancelled acts by the action the user was performing
+    #[serde_as(as = "HashMap<_, _>")]
+    #[serde(default)]
+    cancelled_acts_per_action: Vec<(String, usize)>,
+    /// sync events users never reacted to because their buffer was full
+    #[serde(default)]
+    dropped_sync_events: usize,
+    /// users that failed to register, log in or sync too many times in a row
+    #[serde(default)]
+    quarantined_users: usize,
+    #[serde(default)]
+    restarted_stuck_users: usize,
+    /// sync loops killed and restarted by the chaos option
+    #[serde(default)]
+    chaos_sync_restarts: usize,
+    /// requests abandoned by the chaos option, they are also counted as timeout errors
+    #[serde(default)]
+    chaos_dropped_requests: usize,
+    #[serde(default)]
+    rooms_upgraded: usize,
+    /// time from a tombstone being sent to a member of the old room joining the replacement, it includes the
+    /// time until the member acts
+    #[serde(default)]
+    tombstone_follow_latency: Option<Distribution>,
+    /// replacement rooms whose `m.room.create` doesn't link back to the tombstoned room, breaking the history
+    #[serde(default)]
+    broken_predecessors: usize,
+    /// users added to and removed from the ignore lists
+    #[serde(default)]
+    users_ignored: usize,
+    #[serde(default)]
+    users_unignored: usize,
+    /// messages of ignored users still received in the sync of the user ignoring them, they must be filtered out
+    #[serde(default)]
+    ignored_user_messages: usize,
+    /// latency of the requests to the channels by history visibility and join rule, in permutations mode
+    #[serde(default)]
+    requests_per_permutation: BTreeMap<String, BTreeMap<String, Distribution>>,
+    /// homeserver metrics when the simulation started and ended
+    #[serde_as(as = "HashMap<_, _>")]
+    #[serde(default)]
+    server_metrics: Vec<(String, ServerMetric)>,
+    /// change of the homeserver metrics along every tick, scraped when each tick ended
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    server_metrics_per_tick: Vec<TickServerMetrics>,
+    /// resources used by the load generator, high usage means latencies may be caused by the generator
+    #[serde(default)]
+    generator_resources: Option<ResourceUsage>,
+    /// versions of the tool and the homeserver, so old reports remain interpretable
+    #[serde(default)]
+    versions: Option<Versions>,
+    /// effective configuration of the run with the secrets redacted
+    #[serde(default)]
+    configuration: Option<Value>,
+    /// key metrics that regressed against the baseline report, when one is configured
+    #[serde(default)]
+    baseline_comparison: Option<Comparison>,
+    #[serde(default)]
+    throughput_per_tick: Vec<TickThroughput>,
+    /// users stuck in a state other than `Sync` don't add load, even if they were picked to act
+    #[serde(default)]
+    users_per_tick: Vec<TickUsers>,
+    /// requests and their average time by base URL, when there are several
+    #[serde_as(as = "HashMap<_, _>")]
+    #[serde(default)]
+    requests_per_base_url: Vec<(String, BaseUrlRequests)>,
+}
+
+/// Everything collected during the run that the report is built from
+pub struct ReportInput<'a> {
+    pub http_errors: &'a [(UserRequest, HttpError)],
+    pub rate_limited: &'a [UserRequest],
+    pub request_times: &'a [(UserRequest, Duration)],
+    pub warm_up_request_times: &'a [(UserRequest, Duration)],
+    pub messages: &'a HashMap<String, MessageTimes>,
+    pub to_device: &'a HashMap<String, MessageTimes>,
+    pub pushes: &'a [(String, Instant)],
+    pub invites: &'a HashMap<String, MessageTimes>,
+    pub joins: &'a HashMap<String, MessageTimes>,
+    pub sync_lags: &'a [u128],
+    pub sync_lag_per_tick: &'a [TickDistribution],
+    pub forked_room_message_latency_per_tick: &'a [TickDistribution],
+    pub initial_syncs: &'a [SyncStats],
+    pub incremental_syncs: &'a [SyncStats],
+    pub probe_bursts: &'a [ProbeBurst],
+    pub fuzz_responses: &'a [String],
+    pub sync_events_size_per_tick: &'a [TickDistribution],
+    pub sync_events_per_tick: &'a [TickDistribution],
+    pub notification_counts: &'a [bool],
+    pub ticks: &'a [TickThroughput],
+    pub users_per_tick: &'a [TickUsers],
+    pub cancelled_acts: &'a [String],
+    pub dropped_sync_events: usize,
+    pub quarantined_users: usize,
+    pub restarted_stuck_users: usize,
+    pub chaos_sync_restarts: usize,
+    pub chaos_dropped_requests: usize,
+    pub rooms_upgraded: usize,
+    pub tombstone_follow_latencies: &'a [u128],
+    pub predecessor_checks: &'a [bool],
+    pub users_ignored: usize,
+    pub users_unignored: usize,
+    pub ignored_user_messages: usize,
+    pub permutation_requests: &'a [(String, UserRequest, Duration)],
+    pub base_url_requests: &'a [(String, Duration)],
 }
 
 impl Report {
-    pub fn from(
-        http_errors: &[(UserRequest, HttpError)],
-        request_times: &[(UserRequest, Duration)],
-        messages: &HashMap<String, MessageTimes>,
-    ) -> Self {
+    pub fn from(input: ReportInput) -> Self {
+        let ReportInput {
+            http_errors,
+            rate_limited,
+            request_times,
+            warm_up_request_times,
+            messages,
+            to_device,
+            pushes,
+            invites,
+            joins,
+            sync_lags,
+            sync_lag_per_tick,
+            forked_room_message_latency_per_tick,
+            initial_syncs,
+            incremental_syncs,
+            probe_bursts,
+            fuzz_responses,
+            sync_events_size_per_tick,
+            sync_events_per_tick,
+            notification_counts,
+            ticks,
+            users_per_tick,
+            cancelled_acts,
+            dropped_sync_events,
+            quarantined_users,
+            restarted_stuck_users,
+            chaos_sync_restarts,
+            chaos_dropped_requests,
+            rooms_upgraded,
+            tombstone_follow_latencies,
+            predecessor_checks,
+            users_ignored,
+            users_unignored,
+            ignored_user_messages,
+            permutation_requests,
+            base_url_requests,
+        } = input;
         let mut http_errors_per_request = Self::calculate_http_errors_per_request(http_errors);
+        let mut rate_limited_requests = Self::count_occurrences(rate_limited);
+        let mut errors_per_category = Self::calculate_errors_per_category(http_errors);
+        if !rate_limited.is_empty() {
+            errors_per_category.push((LIMIT_EXCEEDED.to_string(), rate_limited.len()));
+        }
+        let top_error_messages = Self::top_error_messages(
+            http_errors
+                .iter()
+                .map(|(request, e)| (format!("{request}: {e}"), 1)),
+        );
         let mut requests_average_time = Self::calculate_requests_average_time(request_times);
-        let total_requests_by_request = Self::total_requests_by_request(request_times);
+        let total_requests_by_request =
+            Self::total_requests_by_request(&[request_times, warm_up_request_times].concat());
+        let warm_up_requests = Self::total_requests_by_request(warm_up_request_times);
 
         let message_delivery_average_time = Self::calculate_message_delivery_average_time(messages);
 
         requests_average_time.sort_unstable_by_key(|(_, time)| Reverse(*time));
         http_errors_per_request.sort_unstable_by_key(|(_, count)| Reverse(*count));
+        rate_limited_requests.sort_unstable_by_key(|(_, count)| Reverse(*count));
+        errors_per_category.sort_unstable_by_key(|(_, count)| Reverse(*count));
+        let mut cancelled_acts_per_action = Self::count_occurrences(cancelled_acts);
+        cancelled_acts_per_action.sort_unstable_by_key(|(_, count)| Reverse(*count));
 
         let (real_time_messages, messages_sent, messages_not_sent, unknown_messages) =
             Self::classify_messages(messages);
@@ -53,14 +674,514 @@ impl Report {
             unknown_messages
         );
 
+        let (to_device_delivered, to_device_not_delivered, _, _) =
+            Self::classify_messages(to_device);
+        let delivery_times = to_device
+            .values()
+            .filter_map(|times| match (times.sent, times.received) {
+                (Some(sent), Some(received)) => Some(received.duration_since(sent).as_millis()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let to_device_delivery_average_time = (!delivery_times.is_empty())
+            .then(|| delivery_times.iter().sum::<u128>() / delivery_times.len() as u128);
+
+        // a message is pushed once per recipient
+        let push_times = pushes
+            .iter()
+            .filter_map(|(event_id, pushed)| {
+                messages
+                    .get(event_id)
+                    .and_then(|times| times.sent)
+                    .map(|sent| pushed.saturating_duration_since(sent).as_millis())
+            })
+            .collect::<Vec<_>>();
+        let push_delivery_average_time = (!push_times.is_empty())
+            .then(|| push_times.iter().sum::<u128>() / push_times.len() as u128);
+
         Self {
+            incomplete: false,
             requests_average_time,
             total_requests: total_requests_by_request,
+            warm_up_requests,
             http_errors_per_request,
+            rate_limited_requests,
+            errors_per_category,
+            top_error_messages,
             message_delivery_average_time,
             messages_not_sent,
             messages_sent,
             real_time_messages,
+            to_device_delivery_average_time,
+            to_device_delivered,
+            to_device_not_delivered,
+            push_delivery_average_time,
+            pushes_received: push_times.len(),
+            invite_latency: Distribution::from_times(invites),
+            join_latency: Distribution::from_times(joins),
+            sync_lag: Distribution::from_values(sync_lags.to_vec()),
+            sync_lag_per_tick: sync_lag_per_tick.to_vec(),
+            forked_room_message_latency_per_tick: forked_room_message_latency_per_tick.to_vec(),
+            initial_sync: SyncMetrics::from(initial_syncs),
+            incremental_sync: SyncMetrics::from(incremental_syncs),
+            rate_limit_probes: RateLimitProbes::from(probe_bursts),
+            fuzz_responses: Self::count_occurrences(fuzz_responses),
+            fuzz_server_errors: fuzz_responses
+                .iter()
+                .filter(|response| {
+                    response
+                        .split_once(": ")
+                        .map_or(false, |(_, status)| status.starts_with('5'))
+                })
+                .count(),
+            sync_events_size_per_tick: sync_events_size_per_tick.to_vec(),
+            sync_events_per_tick: sync_events_per_tick.to_vec(),
+            notification_counts_checked: notification_counts.len(),
+            notification_count_mismatches: notification_counts
+                .iter()
+                .filter(|consistent| !**consistent)
+                .count(),
+            acts_planned: ticks.iter().map(|tick| tick.planned).sum(),
+            acts_completed: ticks.iter().map(|tick| tick.completed).sum(),
+            acts_cancelled: ticks.iter().map(|tick| tick.cancelled).sum(),
+            cancelled_acts_per_action,
+            dropped_sync_events,
+            quarantined_users,
+            restarted_stuck_users,
+            chaos_sync_restarts,
+            chaos_dropped_requests,
+            rooms_upgraded,
+            tombstone_follow_latency: Distribution::from_values(
+                tombstone_follow_latencies.to_vec(),
+            ),
+            broken_predecessors: predecessor_checks
+                .iter()
+                .filter(|continuous| !**continuous)
+                .count(),
+            users_ignored,
+            users_unignored,
+            ignored_user_messages,
+            requests_per_permutation: Self::calculate_requests_per_permutation(
+                permutation_requests,
+            ),
+            server_metrics: vec![],
+            server_metrics_per_tick: vec![],
+            generator_resources: None,
+            versions: None,
+            configuration: None,
+            baseline_comparison: None,
+            throughput_per_tick: ticks.to_vec(),
+            users_per_tick: users_per_tick.to_vec(),
+            requests_per_base_url: Self::calculate_requests_per_base_url(base_url_requests),
+        }
+    }
+
+    ///
+    /// Load report from a yaml file generated by a previous run.
+    ///
+    /// # Panics
+    ///
+    /// If the file cannot be read or is not a valid report.
+    ///
+    pub fn load(path: &str) -> Self {
+        let file = File::open(path).unwrap_or_else(|_| panic!("could not open report {path}"));
+        serde_yaml::from_reader(file)
+            .unwrap_or_else(|e| panic!("could not parse report {path}: {e}"))
+    }
+
+    /// Merge reports of independent runs, averages are weighted by the amount of requests (or messages) of each report.
+    pub fn merge(reports: &[Report]) -> Self {
+        let mut total_requests = HashMap::<UserRequest, u128>::new();
+        let mut total_time = HashMap::<UserRequest, u128>::new();
+        let mut warm_up_requests = HashMap::<UserRequest, u128>::new();
+        let mut http_errors_per_request = HashMap::<String, usize>::new();
+        let mut rate_limited_requests = HashMap::<UserRequest, usize>::new();
+        let mut errors_per_category = HashMap::<String, usize>::new();
+        let mut error_messages = vec![];
+        let mut total_delivery_time = 0;
+        let mut total_to_device_delivery_time = 0;
+        let mut total_push_delivery_time = 0;
+        let mut ticks = BTreeMap::<usize, TickThroughput>::new();
+        let mut users_per_tick = BTreeMap::<usize, TickUsers>::new();
+        let mut cancelled_acts_per_action = HashMap::<String, usize>::new();
+        let mut fuzz_responses = HashMap::<String, usize>::new();
+        let mut base_url_time = BTreeMap::<String, (usize, u128)>::new();
+        let mut merged = Self::default();
+
+        for report in reports {
+            let requests = report
+                .total_requests
+                .iter()
+                .cloned()
+                .collect::<HashMap<_, _>>();
+            for (request, average) in &report.requests_average_time {
+                *total_time.entry(request.clone()).or_default() +=
+                    average * report.measured_requests(request);
+            }
+            for (request, count) in requests {
+                *total_requests.entry(request).or_default() += count;
+            }
+            for (request, count) in &report.warm_up_requests {
+                *warm_up_requests.entry(request.clone()).or_default() += count;
+            }
+            for (error, count) in &report.http_errors_per_request {
+                *http_errors_per_request.entry(error.clone()).or_default() += count;
+            }
+            for (request, count) in &report.rate_limited_requests {
+                *rate_limited_requests.entry(request.clone()).or_default() += count;
+            }
+            for (category, count) in &report.errors_per_category {
+                *errors_per_category.entry(category.clone()).or_default() += count;
+            }
+            error_messages.extend(
+                report
+                    .top_error_messages
+                    .iter()
+                    .map(|error| (error.message.clone(), error.count)),
+            );
+            if let Some(average) = report.message_delivery_average_time {
+                total_delivery_time += average * report.real_time_messages as u128;
+            }
+            merged.incomplete |= report.incomplete;
+            merged.messages_sent += report.messages_sent;
+            merged.messages_not_sent += report.messages_not_sent;
+            merged.real_time_messages += report.real_time_messages;
+            if let Some(average) = report.to_device_delivery_average_time {
+                total_to_device_delivery_time += average * report.to_device_delivered as u128;
+            }
+            merged.to_device_delivered += report.to_device_delivered;
+            merged.to_device_not_delivered += report.to_device_not_delivered;
+            if let Some(average) = report.push_delivery_average_time {
+                total_push_delivery_time += average * report.pushes_received as u128;
+            }
+            merged.pushes_received += report.pushes_received;
+            merged.notification_counts_checked += report.notification_counts_checked;
+            merged.notification_count_mismatches += report.notification_count_mismatches;
+            merged.acts_planned += report.acts_planned;
+            merged.acts_completed += report.acts_completed;
+            merged.acts_cancelled += report.acts_cancelled;
+            merged.dropped_sync_events += report.dropped_sync_events;
+            merged.quarantined_users += report.quarantined_users;
+            merged.restarted_stuck_users += report.restarted_stuck_users;
+            merged.chaos_sync_restarts += report.chaos_sync_restarts;
+            merged.chaos_dropped_requests += report.chaos_dropped_requests;
+            merged.rooms_upgraded += report.rooms_upgraded;
+            merged.broken_predecessors += report.broken_predecessors;
+            merged.users_ignored += report.users_ignored;
+            merged.users_unignored += report.users_unignored;
+            merged.ignored_user_messages += report.ignored_user_messages;
+            // every worker scrapes the same homeserver
+            // instances of a distributed run share the configuration and the homeserver
+            if merged.versions.is_none() {
+                merged.versions = report.versions.clone();
+                merged.configuration = report.configuration.clone();
+            }
+            if merged.server_metrics.is_empty() {
+                merged.server_metrics = report.server_metrics.clone();
+                merged.server_metrics_per_tick = report.server_metrics_per_tick.clone();
+            }
+            for (base_url, requests) in &report.requests_per_base_url {
+                let (count, time) = base_url_time.entry(base_url.clone()).or_default();
+                *count += requests.requests;
+                *time += requests.average_time * requests.requests as u128;
+            }
+            for (action, count) in &report.cancelled_acts_per_action {
+                *cancelled_acts_per_action.entry(action.clone()).or_default() += count;
+            }
+            for (response, count) in &report.fuzz_responses {
+                *fuzz_responses.entry(response.clone()).or_default() += count;
+            }
+            merged.fuzz_server_errors += report.fuzz_server_errors;
+            for throughput in &report.throughput_per_tick {
+                let tick = ticks.entry(throughput.tick).or_insert(TickThroughput {
+                    tick: throughput.tick,
+                    ..Default::default()
+                });
+                tick.planned += throughput.planned;
+                tick.completed += throughput.completed;
+                tick.cancelled += throughput.cancelled;
+                tick.aborted |= throughput.aborted;
+            }
+            for users in &report.users_per_tick {
+                let tick = users_per_tick.entry(users.tick).or_insert(TickUsers {
+                    tick: users.tick,
+                    ..Default::default()
+                });
+                for (state, count) in &users.per_state {
+                    *tick.per_state.entry(state.clone()).or_default() += count;
+                }
+            }
+        }
+
+        merged.requests_average_time = total_time
+            .into_iter()
+            .filter_map(|(request, time)| {
+                let count = total_requests
+                    .get(&request)
+                    .copied()
+                    .unwrap_or_default()
+                    .saturating_sub(warm_up_requests.get(&request).copied().unwrap_or_default());
+                (count > 0).then(|| (request, time / count))
+            })
+            .collect();
+        merged.total_requests = total_requests.into_iter().collect();
+        merged.warm_up_requests = warm_up_requests.into_iter().collect();
+        merged.http_errors_per_request = http_errors_per_request.into_iter().collect();
+        merged.rate_limited_requests = rate_limited_requests.into_iter().collect();
+        merged.errors_per_category = errors_per_category.into_iter().collect();
+        merged.top_error_messages = Self::top_error_messages(error_messages.into_iter());
+        merged.throughput_per_tick = ticks.into_values().collect();
+        merged.users_per_tick = users_per_tick.into_values().collect();
+        merged.generator_resources = ResourceUsage::merge(
+            &reports
+                .iter()
+                .filter_map(|report| report.generator_resources.as_ref())
+                .collect::<Vec<_>>(),
+        );
+        merged.cancelled_acts_per_action = cancelled_acts_per_action.into_iter().collect();
+        merged.fuzz_responses = fuzz_responses.into_iter().collect();
+        merged.invite_latency = Distribution::merge(
+            &reports
+                .iter()
+                .filter_map(|report| report.invite_latency.as_ref())
+                .collect::<Vec<_>>(),
+        );
+        merged.join_latency = Distribution::merge(
+            &reports
+                .iter()
+                .filter_map(|report| report.join_latency.as_ref())
+                .collect::<Vec<_>>(),
+        );
+        let mut permutation_distributions =
+            BTreeMap::<(&String, &String), Vec<&Distribution>>::new();
+        for report in reports {
+            for (permutation, requests) in &report.requests_per_permutation {
+                for (request, distribution) in requests {
+                    permutation_distributions
+                        .entry((permutation, request))
+                        .or_default()
+                        .push(distribution);
+                }
+            }
+        }
+        for ((permutation, request), distributions) in permutation_distributions {
+            if let Some(distribution) = Distribution::merge(&distributions) {
+                merged
+                    .requests_per_permutation
+                    .entry(permutation.clone())
+                    .or_default()
+                    .insert(request.clone(), distribution);
+            }
+        }
+        merged.tombstone_follow_latency = Distribution::merge(
+            &reports
+                .iter()
+                .filter_map(|report| report.tombstone_follow_latency.as_ref())
+                .collect::<Vec<_>>(),
+        );
+        merged.sync_lag = Distribution::merge(
+            &reports
+                .iter()
+                .filter_map(|report| report.sync_lag.as_ref())
+                .collect::<Vec<_>>(),
+        );
+        merged.initial_sync = SyncMetrics::merge(
+            &reports
+                .iter()
+                .filter_map(|report| report.initial_sync.as_ref())
+                .collect::<Vec<_>>(),
+        );
+        merged.incremental_sync = SyncMetrics::merge(
+            &reports
+                .iter()
+                .filter_map(|report| report.incremental_sync.as_ref())
+                .collect::<Vec<_>>(),
+        );
+        merged.rate_limit_probes = RateLimitProbes::merge(
+            &reports
+                .iter()
+                .filter_map(|report| report.rate_limit_probes.as_ref())
+                .collect::<Vec<_>>(),
+        );
+        merged.sync_events_size_per_tick = TickDistribution::merge(
+            &reports
+                .iter()
+                .map(|report| report.sync_events_size_per_tick.as_slice())
+                .collect::<Vec<_>>(),
+        );
+        merged.sync_events_per_tick = TickDistribution::merge(
+            &reports
+                .iter()
+                .map(|report| report.sync_events_per_tick.as_slice())
+                .collect::<Vec<_>>(),
+        );
+        merged.sync_lag_per_tick = TickDistribution::merge(
+            &reports
+                .iter()
+                .map(|report| report.sync_lag_per_tick.as_slice())
+                .collect::<Vec<_>>(),
+        );
+        merged.forked_room_message_latency_per_tick = TickDistribution::merge(
+            &reports
+                .iter()
+                .map(|report| report.forked_room_message_latency_per_tick.as_slice())
+                .collect::<Vec<_>>(),
+        );
+        merged.requests_per_base_url = base_url_time
+            .into_iter()
+            .map(|(base_url, (requests, time))| {
+                let average_time = time / requests.max(1) as u128;
+                (
+                    base_url,
+                    BaseUrlRequests {
+                        requests,
+                        average_time,
+                    },
+                )
+            })
+            .collect();
+        if merged.real_time_messages > 0 {
+            merged.message_delivery_average_time =
+                Some(total_delivery_time / merged.real_time_messages as u128);
+        }
+        if merged.to_device_delivered > 0 {
+            merged.to_device_delivery_average_time =
+                Some(total_to_device_delivery_time / merged.to_device_delivered as u128);
+        }
+        if merged.pushes_received > 0 {
+            merged.push_delivery_average_time =
+                Some(total_push_delivery_time / merged.pushes_received as u128);
+        }
+
+        merged
+            .requests_average_time
+            .sort_unstable_by_key(|(_, time)| Reverse(*time));
+        merged
+            .http_errors_per_request
+            .sort_unstable_by_key(|(_, count)| Reverse(*count));
+        merged
+            .rate_limited_requests
+            .sort_unstable_by_key(|(_, count)| Reverse(*count));
+        merged
+            .errors_per_category
+            .sort_unstable_by_key(|(_, count)| Reverse(*count));
+        merged
+            .cancelled_acts_per_action
+            .sort_unstable_by_key(|(_, count)| Reverse(*count));
+
+        merged
+    }
+
+    pub fn set_incomplete(&mut self) {
+        self.incomplete = true;
+    }
+
+    pub fn set_server_metrics(
+        &mut self,
+        server_metrics: Vec<(String, ServerMetric)>,
+        server_metrics_per_tick: Vec<TickServerMetrics>,
+    ) {
+        self.server_metrics = server_metrics;
+        self.server_metrics_per_tick = server_metrics_per_tick;
+    }
+
+    pub fn set_generator_resources(&mut self, usage: Option<ResourceUsage>) {
+        self.generator_resources = usage;
+    }
+
+    pub fn set_run_info(&mut self, versions: Versions, configuration: Value) {
+        self.versions = Some(versions);
+        self.configuration = Some(configuration);
+    }
+
+    pub fn set_baseline_comparison(&mut self, comparison: Comparison) {
+        self.baseline_comparison = Some(comparison);
+    }
+
+    /// Metrics that regressed against the baseline, zero when there is no baseline
+    pub fn regressions(&self) -> usize {
+        self.baseline_comparison
+            .as_ref()
+            .map_or(0, |comparison| comparison.regressions.len())
+    }
+
+    /// Requests sent of the kind, by its name in the report, ex: "initial_sync"
+    pub fn requests_of(&self, request: &str) -> u128 {
+        self.total_requests
+            .iter()
+            .filter(|(counted, _)| counted.to_string() == request)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Failed requests of the kind by HTTP error, by its name in the report, ex: "initial_sync"
+    pub fn http_errors_of(&self, request: &str) -> usize {
+        let error_of = |error: &str, request: &str| error.starts_with(&format!("{request}_"));
+        self.http_errors_per_request
+            .iter()
+            .filter(|(error, _)| error_of(error, request))
+            // errors of the kinds named after this one, ex: join_room_by_alias of join_room
+            .filter(|(error, _)| {
+                !self.total_requests.iter().any(|(other, _)| {
+                    let other = other.to_string();
+                    other.len() > request.len() && error_of(error, &other)
+                })
+            })
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Requests of the kind included in its average time, the ones sent after the warm-up
+    fn measured_requests(&self, request: &UserRequest) -> u128 {
+        let count = |requests: &[(UserRequest, u128)]| {
+            requests
+                .iter()
+                .find(|(counted, _)| counted == request)
+                .map_or(0, |(_, count)| *count)
+        };
+        count(&self.total_requests).saturating_sub(count(&self.warm_up_requests))
+    }
+
+    pub fn summary(&self) -> ReportSummary {
+        let requests = self
+            .total_requests
+            .iter()
+            .map(|(_, total)| total)
+            .sum::<u128>();
+        // average of all the requests, weighted by the requests of each kind
+        let measured_requests = requests.saturating_sub(
+            self.warm_up_requests
+                .iter()
+                .map(|(_, total)| total)
+                .sum::<u128>(),
+        );
+        let total_time = self
+            .requests_average_time
+            .iter()
+            .map(|(request, average)| average * self.measured_requests(request))
+            .sum::<u128>();
+        ReportSummary {
+            requests,
+            http_errors: self
+                .http_errors_per_request
+                .iter()
+                .map(|(_, errors)| errors)
+                .sum(),
+            rate_limited_requests: self
+                .rate_limited_requests
+                .iter()
+                .map(|(_, requests)| requests)
+                .sum(),
+            requests_average_time: (measured_requests > 0).then(|| total_time / measured_requests),
+            message_delivery_average_time: self.message_delivery_average_time,
+            messages_sent: self.messages_sent,
+            real_time_messages: self.real_time_messages,
+            acts_cancelled: self.acts_cancelled,
+            ticks_aborted: self
+                .throughput_per_tick
+                .iter()
+                .filter(|tick| tick.aborted)
+                .count(),
         }
     }
 
@@ -69,6 +1190,9 @@ impl Report {
             HttpError::Api(FromHttpResponseError::Server(ServerError::Known(
                 RumaApiError::ClientApi(e),
             ))) => e.status_code.as_u16().to_string(),
+            HttpError::Server(status_code) if status_code.as_u16() == CLIENT_TIMEOUT => {
+                TIMEOUT.to_string()
+            }
             HttpError::Server(status_code) => status_code.as_u16().to_string(),
             HttpError::UiaaError(FromHttpResponseError::Server(ServerError::Known(e))) => match e {
                 UiaaResponse::AuthResponse(e) => e.auth_error.as_ref().unwrap().message.clone(),
@@ -76,7 +1200,9 @@ impl Report {
                 _ => e.to_string(),
             },
             HttpError::Reqwest(e) => {
-                if e.is_request() {
+                if e.is_timeout() {
+                    TIMEOUT.to_string()
+                } else if e.is_request() {
                     log::error!("{}", e);
                     "failed_to_send_request".to_string()
                 } else {
@@ -87,6 +1213,73 @@ impl Report {
         }
     }
 
+    fn get_error_category(e: &HttpError) -> String {
+        match e {
+            HttpError::Api(FromHttpResponseError::Server(ServerError::Known(
+                RumaApiError::ClientApi(e),
+            )))
+            | HttpError::UiaaError(FromHttpResponseError::Server(ServerError::Known(
+                UiaaResponse::MatrixError(e),
+            ))) => e.kind.to_string(),
+            HttpError::UiaaError(FromHttpResponseError::Server(ServerError::Known(
+                UiaaResponse::AuthResponse(_),
+            ))) => "uiaa_auth_required".to_string(),
+            HttpError::Server(status_code) if status_code.as_u16() == CLIENT_TIMEOUT => {
+                TIMEOUT.to_string()
+            }
+            HttpError::Server(status_code) => format!("http_{}", status_code.as_u16()),
+            HttpError::Reqwest(e) if e.is_timeout() => TIMEOUT.to_string(),
+            HttpError::Reqwest(e) if e.is_connect() => "connection_error".to_string(),
+            HttpError::Reqwest(e) => match e.status() {
+                Some(status_code) => format!("http_{}", status_code.as_u16()),
+                None => "request_error".to_string(),
+            },
+            _ => "other".to_string(),
+        }
+    }
+
+    fn calculate_errors_per_category(
+        http_errors: &[(UserRequest, HttpError)],
+    ) -> Vec<(String, usize)> {
+        http_errors
+            .iter()
+            .fold(HashMap::<String, usize>::new(), |mut map, (_, e)| {
+                *map.entry(Self::get_error_category(e)).or_default() += 1;
+                map
+            })
+            .into_iter()
+            .collect()
+    }
+
+    /// Group messages and keep the most frequent ones
+    fn top_error_messages(messages: impl Iterator<Item = (String, usize)>) -> Vec<ErrorMessage> {
+        let mut messages = messages
+            .fold(
+                HashMap::<String, usize>::new(),
+                |mut map, (message, count)| {
+                    *map.entry(message).or_default() += count;
+                    map
+                },
+            )
+            .into_iter()
+            .map(|(message, count)| ErrorMessage { message, count })
+            .collect::<Vec<_>>();
+        messages.sort_unstable_by_key(|error| Reverse(error.count));
+        messages.truncate(TOP_ERROR_MESSAGES);
+        messages
+    }
+
+    fn count_occurrences<T: Clone + Eq + Hash>(items: &[T]) -> Vec<(T, usize)> {
+        items
+            .iter()
+            .fold(HashMap::<T, usize>::new(), |mut map, item| {
+                *map.entry(item.clone()).or_default() += 1;
+                map
+            })
+            .into_iter()
+            .collect()
+    }
+
     fn total_requests_by_request(
         request_times: &[(UserRequest, Duration)],
     ) -> Vec<(UserRequest, u128)> {
@@ -105,6 +1298,60 @@ impl Report {
             .collect()
     }
 
+    fn calculate_requests_per_permutation(
+        permutation_requests: &[(String, UserRequest, Duration)],
+    ) -> BTreeMap<String, BTreeMap<String, Distribution>> {
+        let mut latencies = BTreeMap::<String, BTreeMap<String, Vec<u128>>>::new();
+        for (permutation, request, duration) in permutation_requests {
+            latencies
+                .entry(permutation.clone())
+                .or_default()
+                .entry(request.to_string())
+                .or_default()
+                .push(duration.as_millis());
+        }
+        latencies
+            .into_iter()
+            .map(|(permutation, requests)| {
+                let requests = requests
+                    .into_iter()
+                    .filter_map(|(request, latencies)| {
+                        Some((request, Distribution::from_values(latencies)?))
+                    })
+                    .collect();
+                (permutation, requests)
+            })
+            .collect()
+    }
+
+    fn calculate_requests_per_base_url(
+        base_url_requests: &[(String, Duration)],
+    ) -> Vec<(String, BaseUrlRequests)> {
+        base_url_requests
+            .iter()
+            .fold(
+                BTreeMap::<String, (usize, u128)>::new(),
+                |mut map, (base_url, duration)| {
+                    let (requests, time) = map.entry(base_url.clone()).or_default();
+                    *requests += 1;
+                    *time += duration.as_millis();
+                    map
+                },
+            )
+            .into_iter()
+            .map(|(base_url, (requests, time))| {
+                let average_time = time / requests as u128;
+                (
+                    base_url,
+                    BaseUrlRequests {
+                        requests,
+                        average_time,
+                    },
+                )
+            })
+            .collect()
+    }
+
     fn calculate_requests_average_time(
         request_times: &[(UserRequest, Duration)],
     ) -> Vec<(UserRequest, u128)> {
@@ -197,15 +1444,20 @@ impl Report {
         )
     }
 
+    /// Write report to file and return its path
     pub fn generate(
         &self,
         output_dir: &str,
         execution_id: &str,
-        channels_info: Option<ChannelsInfo>,
-    ) {
+        channels_info: Option<&ChannelsInfo>,
+    ) -> String {
         let reports_dir = Self::ensure_execution_directory(output_dir, execution_id);
 
-        let path = format!("{reports_dir}/report_{execution_id}.yaml");
+        let path = if self.incomplete {
+            format!("{reports_dir}/report_partial_{execution_id}.yaml")
+        } else {
+            format!("{reports_dir}/report_{execution_id}.yaml")
+        };
         let buffer = File::create(&path).unwrap();
 
         serde_yaml::to_writer(buffer, self).expect("couldn't write report to file");
@@ -215,9 +1467,10 @@ impl Report {
         if let Some(channels_info) = channels_info {
             println!("{:#?}\n", channels_info);
         }
+        path
     }
 
-    fn compute_reports_dir(output_dir: &str, execution_id: &str) -> String {
+    pub fn compute_reports_dir(output_dir: &str, execution_id: &str) -> String {
         format!("{}/{}", output_dir, execution_id)
     }
 
@@ -229,7 +1482,7 @@ impl Report {
     ///
     /// If we are not able to create the directory for the current execution.
     ///
-    fn ensure_execution_directory(output_dir: &str, execution_id: &str) -> String {
+    pub fn ensure_execution_directory(output_dir: &str, execution_id: &str) -> String {
         let directory = Self::compute_reports_dir(output_dir, execution_id);
 
         create_dir_all(directory.clone())
@@ -237,3 +1490,65 @@ impl Report {
         directory
     }
 }
+
+/// Write run by the report writer, returns the path of the report written
+type WriteJob = Box<dyn FnOnce() -> Option<String> + Send>;
+
+/// Serializes and writes reports, interim snapshots and checkpoints in a background thread with its own queue,
+/// in the order they are queued, so heavy writing never delays the simulation.
+pub struct ReportWriter {
+    jobs: mpsc::Sender<WriteJob>,
+    handle: JoinHandle<Vec<String>>,
+}
+
+/// Queue of the report writer used by other tasks, the writer finishes once every queue is dropped
+#[derive(Clone)]
+pub struct WriteQueue(mpsc::Sender<WriteJob>);
+
+impl WriteQueue {
+    /// Queue write, returns immediately.
+    pub fn write(&self, write: impl FnOnce() + Send + 'static) {
+        let job = move || {
+            write();
+            None
+        };
+        self.0
+            .send(Box::new(job))
+            .expect("report writer to be running");
+    }
+}
+
+impl ReportWriter {
+    pub fn start() -> Self {
+        let (jobs, receiver) = mpsc::channel::<WriteJob>();
+        let handle =
+            tokio::task::spawn_blocking(move || receiver.iter().filter_map(|job| job()).collect());
+        Self { jobs, handle }
+    }
+
+    pub fn queue(&self) -> WriteQueue {
+        WriteQueue(self.jobs.clone())
+    }
+
+    /// Queue report to be written, returns immediately.
+    pub fn write(
+        &self,
+        report: Report,
+        output_dir: &str,
+        execution_id: &str,
+        channels_info: Option<ChannelsInfo>,
+    ) {
+        let output_dir = output_dir.to_string();
+        let execution_id = execution_id.to_string();
+        let job = move || Some(report.generate(&output_dir, &execution_id, channels_info.as_ref()));
+        self.jobs
+            .send(Box::new(job))
+            .expect("report writer to be running");
+    }
+
+    /// Wait for the queued writes to be done, once the other queues are dropped, and return the paths of the reports.
+    pub async fn finish(self) -> Vec<String> {
+        drop(self.jobs);
+        self.handle.await.expect("report writer to finish")
+    }
+}
@@ -0,0 +1,94 @@
+use crate::configuration::RequestLogFormat;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Single attempt of a request, status is "ok", the HTTP status code or "error" when the server didn't respond
+#[derive(Serialize, Debug, Clone)]
+pub struct RequestRecord {
+    /// milliseconds since the Unix epoch when the request finished
+    pub timestamp: u128,
+    pub user: Option<String>,
+    pub request: String,
+    pub endpoint: &'static str,
+    pub duration_ms: u128,
+    pub status: String,
+    pub attempt: u32,
+}
+
+/// Streams every request record to a gzip compressed file in the reports directory while the simulation runs
+pub struct RequestLog {
+    records: mpsc::Sender<RequestRecord>,
+    handle: JoinHandle<String>,
+}
+
+impl RequestLog {
+    ///
+    /// Create the log file and start writing the records sent to it. A resumed run appends them to the log of the
+    /// run as a new gzip member, that gzip readers concatenate (CSV logs repeat the header).
+    ///
+    /// # Panics
+    ///
+    /// If the file cannot be created or written.
+    ///
+    pub fn start(reports_dir: &str, execution_id: &str, format: RequestLogFormat) -> Self {
+        let extension = match format {
+            RequestLogFormat::Ndjson => "ndjson",
+            RequestLogFormat::Csv => "csv",
+        };
+        let path = format!("{reports_dir}/requests_{execution_id}.{extension}.gz");
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .unwrap_or_else(|_| panic!("could not create request log file {path}"));
+        let encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+
+        let (records, receiver) = mpsc::channel::<RequestRecord>();
+        let handle = tokio::task::spawn_blocking(move || {
+            match format {
+                RequestLogFormat::Ndjson => {
+                    let mut writer = encoder;
+                    for record in receiver {
+                        serde_json::to_writer(&mut writer, &record)
+                            .expect("couldn't write request record");
+                        writer
+                            .write_all(b"\n")
+                            .expect("couldn't write request record");
+                    }
+                    writer.finish().expect("couldn't finish request log");
+                }
+                RequestLogFormat::Csv => {
+                    let mut writer = csv::Writer::from_writer(encoder);
+                    for record in receiver {
+                        writer
+                            .serialize(&record)
+                            .expect("couldn't write request record");
+                    }
+                    writer
+                        .into_inner()
+                        .map_err(|e| e.to_string())
+                        .and_then(|encoder| encoder.finish().map_err(|e| e.to_string()))
+                        .expect("couldn't finish request log");
+                }
+            }
+            path
+        });
+        Self { records, handle }
+    }
+
+    /// Sender of the records to the log, it must be dropped for the log to finish
+    pub fn recorder(&self) -> mpsc::Sender<RequestRecord> {
+        self.records.clone()
+    }
+
+    /// Wait for all the recorders to be dropped and the records written, returns the path of the log.
+    pub async fn finish(self) -> String {
+        drop(self.records);
+        self.handle.await.expect("request log writer to finish")
+    }
+}
@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{read_dir, read_link, read_to_string};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+// resources of the load generator are sampled once per interval
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+// clock ticks per second of the cpu times in `/proc/self/stat`, 100 on every mainstream Linux
+const CLOCK_TICKS_PER_SECOND: f64 = 100.;
+
+// tasks spawned with `spawn` that are still running
+static TASKS: AtomicUsize = AtomicUsize::new(0);
+
+struct TaskGuard;
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        TASKS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Spawn a task counted in the load generator resources, aborted tasks are not counted anymore.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    TASKS.fetch_add(1, Ordering::Relaxed);
+    let guard = TaskGuard;
+    tokio::spawn(async move {
+        let _guard = guard;
+        future.await
+    })
+}
+
+/// Resources used by the load generator itself along the run, to tell apart a saturated generator
+/// from a slow homeserver. CPU is the percentage of one core, it's over 100 when using several cores.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct ResourceUsage {
+    pub samples: usize,
+    pub cpu_average_percent: f64,
+    pub cpu_max_percent: f64,
+    pub rss_average_bytes: u64,
+    pub rss_max_bytes: u64,
+    pub open_sockets_max: usize,
+    /// users acts and sync loops running at the same time
+    pub tasks_max: usize,
+}
+
+impl ResourceUsage {
+    fn add(&mut self, sample: &Sample) {
+        let samples = self.samples as f64;
+        self.cpu_average_percent =
+            (self.cpu_average_percent * samples + sample.cpu_percent) / (samples + 1.);
+        self.rss_average_bytes = ((self.rss_average_bytes as f64 * samples
+            + sample.rss_bytes as f64)
+            / (samples + 1.)) as u64;
+        self.cpu_max_percent = self.cpu_max_percent.max(sample.cpu_percent);
+        self.rss_max_bytes = self.rss_max_bytes.max(sample.rss_bytes);
+        self.open_sockets_max = self.open_sockets_max.max(sample.open_sockets);
+        self.tasks_max = self.tasks_max.max(sample.tasks);
+        self.samples += 1;
+    }
+
+    /// Merge usages of independent runs (workers), averages are weighted by the samples of each usage.
+    pub fn merge(usages: &[&ResourceUsage]) -> Option<Self> {
+        let samples = usages.iter().map(|usage| usage.samples).sum::<usize>();
+        if samples == 0 {
+            return None;
+        }
+        let weighted = |value: fn(&ResourceUsage) -> f64| {
+            usages
+                .iter()
+                .map(|usage| value(usage) * usage.samples as f64)
+                .sum::<f64>()
+                / samples as f64
+        };
+        Some(Self {
+            samples,
+            cpu_average_percent: weighted(|usage| usage.cpu_average_percent),
+            cpu_max_percent: usages
+                .iter()
+                .map(|usage| usage.cpu_max_percent)
+                .fold(0., f64::max),
+            rss_average_bytes: weighted(|usage| usage.rss_average_bytes as f64) as u64,
+            rss_max_bytes: usages.iter().map(|usage| usage.rss_max_bytes).max()?,
+            open_sockets_max: usages.iter().map(|usage| usage.open_sockets_max).max()?,
+            tasks_max: usages.iter().map(|usage| usage.tasks_max).max()?,
+        })
+    }
+}
+
+struct Sample {
+    cpu_percent: f64,
+    rss_bytes: u64,
+    open_sockets: usize,
+    tasks: usize,
+}
+
+/// Samples the process resources until the sender is used or dropped, then returns their usage.
+/// Usage is `None` where `/proc` is not available (non Linux systems).
+pub fn monitor() -> (oneshot::Sender<()>, JoinHandle<Option<ResourceUsage>>) {
+    let (stop, mut stopped) = oneshot::channel::<()>();
+    let handle = tokio::spawn(async move {
+        let mut usage = ResourceUsage::default();
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        let mut last_cpu = cpu_seconds().map(|seconds| (Instant::now(), seconds));
+        // first tick completes immediately
+        interval.tick().await;
+        loop {
+            tokio::select! {
+                _ = &mut stopped => break,
+                _ = interval.tick() => {
+                    let now = Instant::now();
+                    let cpu = cpu_seconds();
+                    if let (Some((last_time, last_seconds)), Some(seconds)) = (last_cpu, cpu) {
+                        let elapsed = now.duration_since(last_time).as_secs_f64();
+                        let sample = Sample {
+                            cpu_percent: (seconds - last_seconds) / elapsed * 100.,
+                            rss_bytes: rss_bytes().unwrap_or_default(),
+                            open_sockets: open_sockets().unwrap_or_default(),
+                            tasks: TASKS.load(Ordering::Relaxed),
+                        };
+                        log::debug!(
+                            "generator resources: cpu {:.1}%, rss {} bytes, {} sockets, {} tasks",
+                            sample.cpu_percent,
+                            sample.rss_bytes,
+                            sample.open_sockets,
+                            sample.tasks
+                        );
+                        usage.add(&sample);
+                    }
+                    last_cpu = cpu.map(|seconds| (now, seconds));
+                }
+            }
+        }
+        (usage.samples > 0).then_some(usage)
+    });
+    (stop, handle)
+}
+
+/// User and system CPU time of the process in seconds
+fn cpu_seconds() -> Option<f64> {
+    let stat = read_to_string("/proc/self/stat").ok()?;
+    // fields after the process name, which can contain spaces, utime and stime are 14th and 15th
+    let fields = stat
+        .rsplit_once(')')?
+        .1
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    let utime = fields.get(11)?.parse::<f64>().ok()?;
+    let stime = fields.get(12)?.parse::<f64>().ok()?;
+    Some((utime + stime) / CLOCK_TICKS_PER_SECOND)
+}
+
+fn rss_bytes() -> Option<u64> {
+    let status = read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+fn open_sockets() -> Option<usize> {
+    let sockets = read_dir("/proc/self/fd")
+        .ok()?
+        .filter_map(Result::ok)
+        .filter_map(|fd| read_link(fd.path()).ok())
+        .filter(|target| target.to_string_lossy().starts_with("socket:"))
+        .count();
+    Some(sockets)
+}
@@ -1,5 +1,162 @@
+use crate::configuration::{HistoryVisibility, JoinRule, Preset, RoomOptions};
+use matrix_sdk::ruma::{
+    api::client::room::create_room::v3::RoomPreset, events::AnyInitialStateEvent, serde::Raw,
+    RoomId, RoomVersionId,
+};
+use serde_json::{json, value::to_raw_value, Value};
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum RoomType {
     Channel,
     DirectMessage,
 }
+
+/// History visibility and join rule of a channel created in permutations mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Permutation {
+    pub history_visibility: HistoryVisibility,
+    pub join_rule: JoinRule,
+}
+
+impl Permutation {
+    /// Every combination channels can have, invite only rooms are not channels and restricted ones need a space
+    pub fn all(with_space: bool) -> Vec<Self> {
+        let mut join_rules = vec![JoinRule::Public, JoinRule::Knock];
+        if with_space {
+            join_rules.push(JoinRule::Restricted);
+        }
+        [
+            HistoryVisibility::Invited,
+            HistoryVisibility::Joined,
+            HistoryVisibility::Shared,
+            HistoryVisibility::WorldReadable,
+        ]
+        .into_iter()
+        .flat_map(|history_visibility| {
+            join_rules.iter().map(move |join_rule| Self {
+                history_visibility: history_visibility.clone(),
+                join_rule: join_rule.clone(),
+            })
+        })
+        .collect()
+    }
+
+    /// Name of the combination in the report, ex: "world_readable/knock"
+    pub fn name(&self) -> String {
+        let name = |value: Value| value.as_str().unwrap_or_default().to_string();
+        format!(
+            "{}/{}",
+            name(json!(self.history_visibility)),
+            name(json!(self.join_rule))
+        )
+    }
+}
+
+/// Room creation options from the config, ready to be used in the create room requests.
+#[derive(Debug, Clone)]
+pub struct CreationOptions {
+    pub room_version: Option<RoomVersionId>,
+    pub preset: RoomPreset,
+    history_visibility: Option<HistoryVisibility>,
+    join_rule: Option<JoinRule>,
+    custom_state: Vec<Raw<AnyInitialStateEvent>>, // initial state events from the config, sent as is
+}
+
+impl From<&RoomOptions> for CreationOptions {
+    ///
+    /// # Panics
+    ///
+    /// If the room version is not valid.
+    ///
+    fn from(options: &RoomOptions) -> Self {
+        let room_version = options.room_version.as_ref().map(|version| {
+            RoomVersionId::try_from(version.as_str())
+                .unwrap_or_else(|_| panic!("invalid room version {version}"))
+        });
+        let preset = match options.preset {
+            Preset::PrivateChat => RoomPreset::PrivateChat,
+            Preset::PublicChat => RoomPreset::PublicChat,
+            Preset::TrustedPrivateChat => RoomPreset::TrustedPrivateChat,
+        };
+
+        let custom_state = options
+            .initial_state
+            .iter()
+            .map(|event| state_event(&event.event_type, &event.state_key, event.content.clone()))
+            .collect();
+
+        Self {
+            room_version,
+            preset,
+            history_visibility: options.history_visibility.clone(),
+            join_rule: options.join_rule.clone(),
+            custom_state,
+        }
+    }
+}
+
+impl CreationOptions {
+    /// Initial state of a room, restricted rooms allow the members of the space to join.
+    /// Without space the room uses the preset join rule.
+    pub fn initial_state(&self, space_id: Option<&RoomId>) -> Vec<Raw<AnyInitialStateEvent>> {
+        self.initial_state_with(
+            self.history_visibility.as_ref(),
+            self.join_rule.as_ref(),
+            space_id,
+        )
+    }
+
+    /// Initial state of a channel with the permutation instead of the configured visibility and join rule
+    pub fn permuted_initial_state(
+        &self,
+        permutation: &Permutation,
+        space_id: Option<&RoomId>,
+    ) -> Vec<Raw<AnyInitialStateEvent>> {
+        self.initial_state_with(
+            Some(&permutation.history_visibility),
+            Some(&permutation.join_rule),
+            space_id,
+        )
+    }
+
+    fn initial_state_with(
+        &self,
+        history_visibility: Option<&HistoryVisibility>,
+        join_rule: Option<&JoinRule>,
+        space_id: Option<&RoomId>,
+    ) -> Vec<Raw<AnyInitialStateEvent>> {
+        let mut initial_state = vec![];
+        if let Some(history_visibility) = history_visibility {
+            initial_state.push(state_event(
+                "m.room.history_visibility",
+                "",
+                json!({ "history_visibility": history_visibility }),
+            ));
+        }
+        let restricted = join_rule == Some(&JoinRule::Restricted);
+        if let (Some(join_rule), false) = (join_rule, restricted) {
+            initial_state.push(state_event(
+                "m.room.join_rules",
+                "",
+                json!({ "join_rule": join_rule }),
+            ));
+        }
+        initial_state.extend(self.custom_state.iter().cloned());
+        if let (true, Some(space_id)) = (restricted, space_id) {
+            initial_state.push(state_event(
+                "m.room.join_rules",
+                "",
+                json!({
+                    "join_rule": JoinRule::Restricted,
+                    "allow": [{ "type": "m.room_membership", "room_id": space_id }]
+                }),
+            ));
+        }
+        initial_state
+    }
+}
+
+fn state_event(event_type: &str, state_key: &str, content: Value) -> Raw<AnyInitialStateEvent> {
+    let event = json!({ "type": event_type, "state_key": state_key, "content": content });
+    Raw::from_json(to_raw_value(&event).expect("initial state event to be serializable"))
+}
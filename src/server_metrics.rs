@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Value of a homeserver metric when the simulation started and ended, series with different labels are added up.
+/// The rate is the change per second along the run, ex: CPU cores used or events persisted per second.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerMetric {
+    pub start: f64,
+    pub end: f64,
+    pub rate_per_second: f64,
+}
+
+/// Change of the homeserver metrics along a tick, from the previous scrape to the one when the tick ended
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TickServerMetrics {
+    pub tick: usize,
+    pub deltas: BTreeMap<String, f64>,
+}
+
+/// Get the metrics from a Prometheus endpoint (text exposition format), `None` if it cannot be scraped.
+pub async fn scrape(url: &str, names: &[String]) -> Option<BTreeMap<String, f64>> {
+    let response = reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status());
+    let body = match response {
+        Ok(response) => response.text().await,
+        Err(e) => Err(e),
+    };
+    match body {
+        Ok(body) => Some(parse(&body, names)),
+        Err(e) => {
+            println!("couldn't scrape homeserver metrics from {url}: {e}");
+            None
+        }
+    }
+}
+
+/// Metrics scraped when the simulation started and ended, missing metrics are ignored.
+pub fn compare(
+    start: &BTreeMap<String, f64>,
+    end: &BTreeMap<String, f64>,
+    elapsed: Duration,
+) -> Vec<(String, ServerMetric)> {
+    start
+        .iter()
+        .filter_map(|(name, start)| {
+            end.get(name).map(|end| {
+                let metric = ServerMetric {
+                    start: *start,
+                    end: *end,
+                    rate_per_second: (end - start) / elapsed.as_secs_f64().max(1.),
+                };
+                (name.clone(), metric)
+            })
+        })
+        .collect()
+}
+
+/// Changes between consecutive scrapes by tick, ticks whose scrape failed are covered by the next one.
+pub fn deltas(
+    start: &BTreeMap<String, f64>,
+    mut scrapes: Vec<(usize, BTreeMap<String, f64>)>,
+) -> Vec<TickServerMetrics> {
+    scrapes.sort_by_key(|(tick, _)| *tick);
+    let mut previous = start;
+    let mut deltas = vec![];
+    for (tick, metrics) in &scrapes {
+        deltas.push(TickServerMetrics {
+            tick: *tick,
+            deltas: metrics
+                .iter()
+                .filter_map(|(name, value)| {
+                    previous
+                        .get(name)
+                        .map(|previous| (name.clone(), value - previous))
+                })
+                .collect(),
+        });
+        previous = metrics;
+    }
+    deltas
+}
+
+/// Add up the samples of each metric, lines look like `name{label="value"} 1.5 [timestamp]`.
+fn parse(body: &str, names: &[String]) -> BTreeMap<String, f64> {
+    let mut metrics = BTreeMap::new();
+    for line in body.lines().filter(|line| !line.starts_with('#')) {
+        let name_end = line.find(|c| c == '{' || c == ' ').unwrap_or(line.len());
+        let name = &line[..name_end];
+        if !names.iter().any(|wanted| wanted == name) {
+            continue;
+        }
+        // labels values can contain spaces, the value comes after the closing brace
+        let rest = match line[name_end..].rfind('}') {
+            Some(labels_end) => &line[name_end + labels_end + 1..],
+            None => &line[name_end..],
+        };
+        match rest.split_whitespace().next().map(str::parse::<f64>) {
+            Some(Ok(value)) => *metrics.entry(name.to_string()).or_default() += value,
+            _ => log::debug!("invalid metric sample '{line}'"),
+        }
+    }
+    metrics
+}
@@ -0,0 +1,91 @@
+use hmac::{Hmac, Mac};
+use matrix_sdk::HttpError;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+const REGISTER_PATH: &str = "_synapse/admin/v1/register";
+const USER_IN_USE: &str = "M_USER_IN_USE";
+
+pub enum SharedSecretRegistration {
+    Registered,
+    UserInUse,
+}
+
+#[derive(Deserialize)]
+struct NonceResponse {
+    nonce: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    errcode: String,
+}
+
+#[derive(Serialize)]
+struct RegisterRequest<'a> {
+    nonce: &'a str,
+    username: &'a str,
+    password: &'a str,
+    admin: bool,
+    mac: String,
+}
+
+///
+/// Register user using Synapse shared-secret registration (admin API), this works even when open registration is disabled:
+///  1. Get a nonce from the server
+///  2. Send the user with an HMAC-SHA1 (using the shared secret as key) of the nonce, username, password and admin flag
+///
+pub async fn register(
+    http_client: &reqwest::Client,
+    homeserver_url: &str,
+    shared_secret: &str,
+    username: &str,
+    password: &str,
+) -> Result<SharedSecretRegistration, HttpError> {
+    let url = format!("{}/{REGISTER_PATH}", homeserver_url.trim_end_matches('/'));
+
+    let NonceResponse { nonce } = http_client
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let request = RegisterRequest {
+        nonce: &nonce,
+        username,
+        password,
+        admin: false,
+        mac: get_mac(shared_secret, &nonce, username, password),
+    };
+
+    let response = http_client.post(&url).json(&request).send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(SharedSecretRegistration::Registered);
+    }
+
+    match response.json::<ErrorResponse>().await {
+        Ok(ErrorResponse { errcode }) if errcode == USER_IN_USE => {
+            Ok(SharedSecretRegistration::UserInUse)
+        }
+        _ => Err(HttpError::Server(status)),
+    }
+}
+
+fn get_mac(shared_secret: &str, nonce: &str, username: &str, password: &str) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(shared_secret.as_bytes())
+        .expect("HMAC can take key of any size");
+    for (i, part) in [nonce, username, password, "notadmin"].iter().enumerate() {
+        if i > 0 {
+            mac.update(b"\x00");
+        }
+        mac.update(part.as_bytes());
+    }
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
@@ -1,46 +1,102 @@
-use crate::configuration::Config;
+use crate::action::{Action, ActionRegistry};
+use crate::checkpoint::Checkpoint;
+use crate::client::Client;
+use crate::configuration::{Config, Teardown};
+use crate::control::PauseControl;
+use crate::credentials::load_credentials;
+use crate::credentials::Credentials;
 use crate::events::Event;
 use crate::events::EventCollector;
+use crate::events::LiveMetrics;
 use crate::events::UserNotifications;
+use crate::friendship::FriendshipGraph;
+use crate::interim::InterimWriter;
+use crate::manifest::Manifest;
+use crate::notification;
+use crate::notification::RunSummary;
+use crate::preflight;
 use crate::progress::create_progress;
 use crate::progress::Progress;
+use crate::progress::UsersOverview;
+use crate::push_gateway;
+use crate::recording::{Recorder, Replay};
+use crate::regression;
 use crate::report::Report;
+use crate::report::ReportWriter;
+use crate::report::{TickThroughput, TickUsers};
+use crate::request_log::RequestLog;
+use crate::resources;
+use crate::server_metrics;
 use crate::text::default_spinner;
 use crate::text::spin_for;
+use crate::text::Corpus;
 use crate::time::execution_id;
+use crate::upload;
 use crate::user::State;
 use crate::user::User;
-use futures::future::join_all;
+use crate::versions;
+use crate::watchdog::Watchdog;
+use chrono::Local;
+use futures::future::{self, join_all};
+use futures::Future;
 use matrix_sdk::locks::RwLock;
 use matrix_sdk::ruma::OwnedRoomId;
 use matrix_sdk::ruma::OwnedUserId;
 use rand::prelude::IteratorRandom;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
-use std::{collections::BTreeMap, ops::Sub, sync::Arc, time::Instant};
+use std::{collections::BTreeMap, sync::Arc};
 use tokio::time::timeout;
 use tokio::{
-    sync::mpsc::{self, Receiver, Sender},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Semaphore,
+    },
     task::JoinHandle,
-    time::sleep,
+    time::{sleep, sleep_until, Instant},
 };
 
 enum Entity {
-    Waiting { id: usize },
-    Ready { user: Arc<RwLock<User>> },
+    Waiting {
+        id: usize,
+        credentials: Option<Credentials>,
+        registered: bool, // registered in the run being resumed
+    },
+    Ready {
+        user: Arc<RwLock<User>>,
+    },
 }
 
 enum EntityAction {
     WakeUp(User),
-    Act(JoinHandle<()>),
+    Act(JoinHandle<bool>), // false when the act was cancelled by the tick timeout
 }
 
 pub struct Context {
     pub syncing_users: RwLock<HashSet<OwnedUserId>>,
+    pub id_numbers: RwLock<HashMap<OwnedUserId, usize>>, // of every user that synced, to record and replay friends
     pub config: Arc<Config>,
     notifier: Sender<Event>,
     pub user_notifier: Sender<UserNotifications>,
     pub channels: RwLock<HashSet<OwnedRoomId>>, // public channels created by all users
+    pub knock_channels: RwLock<HashSet<OwnedRoomId>>, // channels created with the knock join rule in permutations mode
+    pub spaces: RwLock<HashSet<OwnedRoomId>>, // spaces created by all users, their children are channels
+    request_limiter: Option<Arc<Semaphore>>,  // shared by all users clients
+    http_client: Option<reqwest::Client>,     // connection pool shared by all users clients
+    pub friendships: Option<Arc<FriendshipGraph>>, // users can only befriend their neighbours when present
+    pub pending_invites: RwLock<HashMap<OwnedUserId, usize>>, // invites sent to each user not responded yet
+    tick: AtomicUsize, // tick being run, acts of users finish before the next one starts
+    recorder: Option<Recorder>,
+    pub replay: Option<Arc<Replay>>,
+    pub actions: ActionRegistry, // custom behaviors users perform when they socialize
+    watchdog: Option<Arc<Watchdog>>, // ends the tick being run when the thresholds are exceeded
+}
+
+impl Context {
+    pub fn current_tick(&self) -> usize {
+        self.tick.load(Ordering::Relaxed)
+    }
 }
 
 #[derive(Debug)]
@@ -55,8 +111,12 @@ pub struct ChannelsInfo {
 }
 
 impl Entity {
-    fn waiting(id: usize) -> Self {
-        Self::Waiting { id }
+    fn waiting(id: usize, credentials: Option<Credentials>, registered: bool) -> Self {
+        Self::Waiting {
+            id,
+            credentials,
+            registered,
+        }
     }
 
     fn from_user(user: User) -> Self {
@@ -67,9 +127,24 @@ impl Entity {
 
     async fn act(&self, context: Arc<Context>, time_to_act: Duration) -> EntityAction {
         match &self {
-            Entity::Waiting { id } => {
+            Entity::Waiting {
+                id,
+                credentials,
+                registered,
+            } => {
                 log::debug!(" --- waking up entity {}", id);
-                let user = User::new(*id, context.notifier.clone(), &context.config).await;
+                let mut user = User::new(
+                    *id,
+                    credentials.clone(),
+                    context.notifier.clone(),
+                    context.request_limiter.clone(),
+                    context.http_client.clone(),
+                    &context.config,
+                )
+                .await;
+                if *registered {
+                    user.mark_registered();
+                }
                 EntityAction::WakeUp(user)
             }
             Entity::Ready { user } => {
@@ -79,13 +154,42 @@ impl Entity {
                     async move {
                         let mut user = user.write().await;
                         log::debug!("user locked {}", user.localpart);
-                        if (timeout(time_to_act, user.act(&context)).await).is_err() {
+                        // replayed acts start as far into the tick as they did in the recorded run
+                        let delay = context.replay.as_ref().and_then(|replay| {
+                            replay
+                                .action(context.current_tick(), user.id_number)
+                                .map(|recorded| recorded.delay)
+                        });
+                        let offset_ms = context.recorder.as_ref().map(Recorder::offset_ms);
+                        let act = async {
+                            if let Some(delay) = delay {
+                                sleep(delay).await;
+                            }
+                            user.act(&context).await
+                        };
+                        let completed = timeout(time_to_act, act).await.is_ok();
+                        if let (Some(recorder), Some(offset_ms)) = (&context.recorder, offset_ms) {
+                            recorder.record_action(
+                                context.current_tick(),
+                                offset_ms,
+                                user.id_number,
+                                user.current_action(),
+                                user.take_action_target(),
+                            );
+                        }
+                        if !completed {
                             log::debug!("user action took more than {:?}", time_to_act);
+                            context
+                                .notifier
+                                .send(Event::ActCancelled(user.current_action()))
+                                .await
+                                .expect("channel open");
                         }
                         log::debug!("user unlocked {}", user.localpart);
+                        completed
                     }
                 };
-                let handle = tokio::spawn(action);
+                let handle = resources::spawn(action);
                 EntityAction::Act(handle)
             }
         }
@@ -95,34 +199,201 @@ pub struct Simulation {
     config: Arc<Config>,
     entities: BTreeMap<usize, Entity>,
     progress: Box<dyn Progress>,
+    live_metrics: Arc<LiveMetrics>,
+    checkpoint: Checkpoint,
+    friendships: Option<Arc<FriendshipGraph>>,
+    replay: Option<Arc<Replay>>,
+    actions: ActionRegistry,
 }
 
 impl Simulation {
-    pub fn with(config: Config) -> Self {
+    pub fn with(mut config: Config) -> Self {
+        let credentials = config
+            .simulation
+            .credentials_file
+            .as_deref()
+            .map(load_credentials)
+            .unwrap_or_default();
+
+        if !credentials.is_empty() && credentials.len() < config.simulation.max_users {
+            println!(
+                "max users limited to the {} pre-provisioned credentials",
+                credentials.len()
+            );
+            config.simulation.max_users = credentials.len();
+        }
+
+        config.messages.corpus = config
+            .messages
+            .corpus_file
+            .as_deref()
+            .map(|path| Arc::new(Corpus::load(path)));
+
+        let mut credentials = credentials.into_iter();
+        let live_metrics = Arc::new(LiveMetrics::default());
+
+        let checkpoint = if config.simulation.resume {
+            let checkpoint =
+                Checkpoint::load(&config.simulation.output, &config.simulation.execution_id);
+            println!(
+                "resuming run {} from tick {}",
+                checkpoint.execution_id, checkpoint.ticks_completed
+            );
+            checkpoint
+        } else {
+            Checkpoint {
+                execution_id: config.simulation.execution_id.clone(),
+                ..Default::default()
+            }
+        };
+
+        let first_user_id = config.simulation.first_user_id;
         let entities = (0..config.simulation.max_users).fold(BTreeMap::new(), |mut map, i| {
-            map.insert(i, Entity::waiting(i));
+            let id = first_user_id + i;
+            let registered = checkpoint.registered_users.contains(&id);
+            map.insert(i, Entity::waiting(id, credentials.next(), registered));
             map
         });
 
+        let localparts = entities
+            .values()
+            .map(|entity| match entity {
+                Entity::Waiting {
+                    credentials: Some(credentials),
+                    ..
+                } => credentials.localpart().to_string(),
+                Entity::Waiting { id, .. } => config
+                    .accounts
+                    .localpart(*id, &config.simulation.execution_id),
+                Entity::Ready { .. } => unreachable!("users are created on their first tick"),
+            })
+            .collect::<Vec<_>>();
+        let friendships = FriendshipGraph::new(&config.friendships, &localparts).map(Arc::new);
+
+        let replay = config
+            .simulation
+            .replay
+            .as_deref()
+            .map(|path| Arc::new(Replay::load(path)));
+
         Self {
             entities,
-            progress: create_progress(config.simulation.ticks, config.simulation.max_users),
+            friendships,
+            replay,
+            actions: ActionRegistry::default(),
+            progress: create_progress(
+                config
+                    .simulation
+                    .ticks
+                    .saturating_sub(checkpoint.ticks_completed),
+                config.simulation.max_users,
+                config.simulation.dashboard,
+                live_metrics.clone(),
+            ),
+            live_metrics,
+            checkpoint,
             config: Arc::new(config),
         }
     }
 
-    pub async fn run(&mut self) {
+    /// Add a behavior to the ones users perform when they socialize, it must be registered before running.
+    pub fn register_action(&mut self, action: impl Action + 'static) {
+        self.actions.register(action);
+    }
+
+    /// Run the simulation, store the report and return it.
+    pub async fn run(&mut self) -> Report {
+        self.run_until(future::pending()).await
+    }
+
+    ///
+    /// Run the simulation until it finishes or `shutdown` completes, in that case a partial report is stored.
+    ///
+    /// # Panics
+    ///
+    /// If the pre-flight check fails, the homeserver is not ready for the simulation,
+    /// or the push gateway cannot be started.
+    ///
+    pub async fn run_until(&mut self, shutdown: impl Future<Output = ()>) -> Report {
         println!("server: {:#?}", self.config.server);
         println!("simulation config: {:#?}", self.config.simulation);
         println!("feature flags config: {:#?}", self.config.feature_flags);
 
+        if self.config.simulation.preflight_check && !self.config.simulation.fast_forward {
+            let credentials = self.entities.values().find_map(|entity| match entity {
+                Entity::Waiting { credentials, .. } => credentials.clone(),
+                Entity::Ready { .. } => None,
+            });
+            if let Err(e) = preflight::check(&self.config, credentials.as_ref()).await {
+                panic!("pre-flight check failed: {e}");
+            }
+            println!("pre-flight check passed");
+        }
+
+        // loaded before the run, so a wrong baseline doesn't waste it
+        let baseline = self.config.baseline.clone().map(|baseline| {
+            let report = Report::load(&baseline.report);
+            (baseline, report)
+        });
+
+        let started_at = Local::now();
+        let report_writer = ReportWriter::start();
         self.progress.start();
         // channel used to share events from users to the Event Collector
         let (tx, rx) = mpsc::channel::<Event>(100);
+        let (stop_monitor, resources_monitor) = resources::monitor();
 
         // start collecting events in separated thread
-        let event_collector = EventCollector::new();
+        let watchdog = self
+            .config
+            .watchdog
+            .clone()
+            .map(|thresholds| Arc::new(Watchdog::new(thresholds)));
+        let request_log = self.config.request_log.as_ref().map(|request_log| {
+            let output_dir = format!(
+                "{}/{}",
+                self.config.simulation.output, self.config.server.homeserver
+            );
+            let reports_dir = Report::ensure_execution_directory(&output_dir, &execution_id());
+            // named after the run, the reports folder is shared by the runs of the same day
+            RequestLog::start(
+                &reports_dir,
+                &self.config.simulation.execution_id,
+                request_log.format,
+            )
+        });
+        let interim = self.config.interim_reports.clone().map(|interim| {
+            let output_dir = format!(
+                "{}/{}",
+                self.config.simulation.output, self.config.server.homeserver
+            );
+            let reports_dir = Report::ensure_execution_directory(&output_dir, &execution_id());
+            InterimWriter::new(
+                interim,
+                &reports_dir,
+                &self.config.simulation.execution_id,
+                report_writer.queue(),
+            )
+        });
+        let mut event_collector = EventCollector::new(
+            self.live_metrics.clone(),
+            watchdog.clone(),
+            request_log.as_ref().map(RequestLog::recorder),
+            interim,
+            self.config.simulation.warm_up,
+        );
         let events_report = event_collector.start(rx);
+        let push_gateway = match self
+            .config
+            .push
+            .as_ref()
+            .and_then(|push| push.gateway_address.as_ref())
+        {
+            Some(address) if !self.config.simulation.fast_forward => {
+                Some(push_gateway::start(address, tx.clone()).await)
+            }
+            _ => None,
+        };
 
         // channel used to allow each user to notify the simulation process
         let (user_notification_sender, user_notification_receiver) =
@@ -130,29 +401,165 @@ impl Simulation {
 
         let context = Arc::new(Context {
             syncing_users: RwLock::new(HashSet::new()),
+            id_numbers: RwLock::new(HashMap::new()),
             config: self.config.clone(),
             notifier: tx.clone(),
             user_notifier: user_notification_sender.clone(),
             channels: RwLock::new(HashSet::new()),
+            knock_channels: RwLock::new(HashSet::new()),
+            spaces: RwLock::new(HashSet::new()),
+            request_limiter: self
+                .config
+                .requests
+                .max_in_flight_requests
+                .map(|max| Arc::new(Semaphore::new(max))),
+            http_client: Client::shared_http_client(&self.config),
+            friendships: self.friendships.clone(),
+            pending_invites: RwLock::new(HashMap::new()),
+            tick: AtomicUsize::new(0),
+            recorder: self
+                .config
+                .simulation
+                .record
+                .as_deref()
+                .map(Recorder::create),
+            replay: self.replay.clone(),
+            actions: self.actions.clone(),
+            watchdog: watchdog.clone(),
         });
+        let watchdog_checks = watchdog.as_ref().map(Watchdog::start);
 
         tokio::spawn(Simulation::collect_user_notifications(
             user_notification_receiver,
             context.clone(),
         ));
 
+        let flavor = self.config.server.flavor;
+        let server_metrics_at_start = match &self.config.server_metrics {
+            Some(server_metrics) => {
+                server_metrics::scrape(&server_metrics.url, &server_metrics.metrics(flavor)).await
+            }
+            None => None,
+        };
+        let versions = versions::capture(
+            &Client::first_user_http_client(&self.config),
+            &self.config.base_url(0),
+        )
+        .await;
+        let simulation_start = Instant::now();
+
         // start simulation
-        for _ in 0..self.config.simulation.ticks {
-            self.tick(context.clone()).await;
+        let mut ticks_completed = self.checkpoint.ticks_completed;
+        let mut abort_reason = None;
+        let mut interrupted = false;
+        // scraped in the background when every tick ends, so slow scrapes don't delay the next tick
+        let mut server_metrics_scrapes = vec![];
+        let pause_control = PauseControl::listen();
+        tokio::pin!(shutdown);
+        for tick in (ticks_completed + 1)..=self.config.simulation.ticks {
+            tokio::select! {
+                _ = async {
+                    // nothing is requested while paused, that is not a stalled server
+                    if pause_control.wait_while_paused().await {
+                        if let Some(watchdog) = &watchdog {
+                            watchdog.reset();
+                        }
+                    }
+                    self.tick(tick, context.clone()).await
+                } => {}
+                _ = &mut shutdown => {
+                    println!("shutdown requested, storing partial report");
+                    interrupted = true;
+                    break;
+                }
+            }
             self.track_users().await;
+            ticks_completed += 1;
+            if let (Some(server_metrics), Some(_)) =
+                (&self.config.server_metrics, &server_metrics_at_start)
+            {
+                let url = server_metrics.url.clone();
+                let names = server_metrics.metrics(flavor);
+                server_metrics_scrapes.push(tokio::spawn(async move {
+                    (tick, server_metrics::scrape(&url, &names).await)
+                }));
+            }
+
+            let checkpoint_every_ticks = self.config.simulation.checkpoint_every_ticks;
+            if checkpoint_every_ticks > 0 && ticks_completed % checkpoint_every_ticks == 0 {
+                self.save_checkpoint(ticks_completed, &report_writer);
+            }
+
+            if let Some(watchdog) = &watchdog {
+                if let Some(reason) = watchdog.abort_reason() {
+                    if watchdog.aborts_run() {
+                        println!("aborting simulation: {reason}");
+                        abort_reason = Some(reason);
+                        break;
+                    }
+                    println!("tick {tick} ended by the watchdog: {reason}");
+                    watchdog.reset();
+                }
+            }
+        }
+        if let Some(watchdog_checks) = watchdog_checks {
+            watchdog_checks.abort();
+        }
+
+        if interrupted || abort_reason.is_some() {
+            self.save_checkpoint(ticks_completed, &report_writer);
         }
 
-        // notify simulation ended after a time period
-        self.cool_down(&tx).await;
+        // notify simulation ended after a time period (no need to wait for messages if it was aborted)
+        self.cool_down(&tx, abort_reason.is_none() && !interrupted, !interrupted)
+            .await;
         self.progress.finish();
 
         // wait for report response
-        let final_report = events_report.await.expect("events collection to end");
+        let mut final_report = events_report.await.expect("events collection to end");
+        // the monitor stops once its sender is dropped
+        drop(stop_monitor);
+        if let (Some(server_metrics), Some(start)) =
+            (&self.config.server_metrics, &server_metrics_at_start)
+        {
+            let mut scrapes = vec![];
+            for (tick, metrics) in join_all(server_metrics_scrapes).await.into_iter().flatten() {
+                if let Some(metrics) = metrics {
+                    scrapes.push((tick, metrics));
+                }
+            }
+            if let Some(end) =
+                server_metrics::scrape(&server_metrics.url, &server_metrics.metrics(flavor)).await
+            {
+                final_report.set_server_metrics(
+                    server_metrics::compare(start, &end, simulation_start.elapsed()),
+                    server_metrics::deltas(start, scrapes),
+                );
+            }
+        }
+        final_report
+            .set_generator_resources(resources_monitor.await.expect("resources monitor to end"));
+        final_report.set_run_info(versions, self.config.snapshot.clone());
+        if let Some((baseline, baseline_report)) = &baseline {
+            let comparison = regression::compare(
+                &baseline.report,
+                baseline_report,
+                &final_report,
+                baseline.tolerance_percent,
+            );
+            regression::print(&comparison);
+            final_report.set_baseline_comparison(comparison);
+        }
+        if let Some(push_gateway) = push_gateway {
+            push_gateway.abort();
+        }
+        if interrupted || abort_reason.is_some() {
+            final_report.set_incomplete();
+        }
+
+        if let Some(recorder) = &context.recorder {
+            recorder.flush();
+        }
 
         // collect channels info
         let mut channels_info: Option<ChannelsInfo> = None;
@@ -161,7 +568,34 @@ impl Simulation {
             channels_info = Some(collect);
         }
 
-        self.store_report(&final_report, channels_info).await;
+        let mut manifest = Manifest::new(started_at, ticks_completed, self.config.simulation.ticks);
+        if let Some(request_log) = request_log {
+            manifest.files.push(request_log.finish().await);
+        }
+        if let Some(reason) = abort_reason {
+            manifest.abort(reason);
+        }
+        if interrupted {
+            manifest.interrupt();
+        }
+        self.store_report(report_writer, &final_report, channels_info, manifest)
+            .await;
+
+        final_report
+    }
+
+    /// Users tear down concurrently before the events collection finishes, so their requests are in the report
+    async fn teardown(&self) {
+        let teardown = self.config.simulation.teardown;
+        if teardown == Teardown::Keep {
+            return;
+        }
+        println!("tearing down users: {teardown:?}");
+        join_all(
+            self.get_ready_entities()
+                .map(|user| async move { user.write().await.teardown(teardown).await }),
+        )
+        .await;
     }
 
     fn get_ready_entities(&self) -> impl Iterator<Item = &Arc<RwLock<User>>> {
@@ -227,38 +661,114 @@ impl Simulation {
         }
     }
 
-    async fn cool_down(&self, tx: &Sender<Event>) {
-        let spinner = default_spinner();
-        spinner.set_message("cool down: ");
-        // sleep main thread while missing messages are recevied
-        spin_for(self.config.simulation.grace_period_duration, &spinner).await;
+    async fn cool_down(&self, tx: &Sender<Event>, wait: bool, teardown: bool) {
+        if wait && !self.config.simulation.fast_forward {
+            let spinner = default_spinner();
+            spinner.set_message("cool down: ");
+            // sleep main thread while missing messages are recevied
+            spin_for(self.config.simulation.grace_period_duration, &spinner).await;
+        }
+        // skipped when interrupted, the partial report has to be stored before the shutdown timeout
+        if teardown {
+            self.teardown().await;
+        }
 
         // send finish event
         tx.send(Event::Finish).await.expect("channel open");
     }
 
-    async fn tick(&mut self, context: Arc<Context>) {
+    async fn tick(&mut self, tick: usize, context: Arc<Context>) {
         let tick_start = Instant::now();
         let tick_duration = self.config.simulation.tick_duration;
 
         let mut join_handles = vec![];
 
-        let user_ids = self.pick_users(self.config.simulation.users_per_tick);
+        context.tick.store(tick, Ordering::Relaxed);
+        let first_user_id = self.config.simulation.first_user_id;
+        let user_ids = match &self.replay {
+            Some(replay) => replay
+                .users(tick)
+                .into_iter()
+                .filter_map(|id| id.checked_sub(first_user_id))
+                .filter(|user_id| self.entities.contains_key(user_id))
+                .collect(),
+            None => self
+                .pick_users(self.config.simulation.users_per_tick)
+                .into_iter()
+                .filter(|user_id| !self.is_quarantined(*user_id))
+                .collect(),
+        };
+        if let Some(recorder) = &context.recorder {
+            let users = user_ids.iter().map(|i| first_user_id + i).collect();
+            recorder.record_tick(tick, users);
+        }
+        let mut throughput = TickThroughput {
+            tick,
+            planned: user_ids.len(),
+            ..Default::default()
+        };
         for user_id in user_ids {
             let entity = self.entities.get(&user_id).expect("user to exist");
             match entity.act(context.clone(), tick_duration).await {
                 EntityAction::WakeUp(user) => {
                     self.entities.insert(user_id, Entity::from_user(user));
+                    throughput.completed += 1;
                 }
                 EntityAction::Act(user_action) => {
                     join_handles.push(user_action);
                 }
             }
         }
-        join_all(join_handles).await;
-
-        if tick_start.elapsed().le(&tick_duration) {
-            sleep(tick_duration.sub(tick_start.elapsed())).await;
+        // the remaining acts are cancelled when the watchdog ends the tick
+        let acts = join_all(join_handles.iter_mut());
+        let completed = match &context.watchdog {
+            Some(watchdog) => tokio::select! {
+                completed = acts => Some(completed),
+                _ = watchdog.tripped() => None,
+            },
+            None => Some(acts.await),
+        };
+        let completed = match completed {
+            Some(completed) => completed,
+            None => {
+                throughput.aborted = true;
+                for join_handle in &join_handles {
+                    join_handle.abort();
+                }
+                join_all(join_handles.iter_mut()).await
+            }
+        };
+        let aborted = throughput.aborted;
+        // acts that panicked are counted as cancelled
+        for completed in completed {
+            match completed {
+                Ok(true) => throughput.completed += 1,
+                _ => throughput.cancelled += 1,
+            }
+        }
+        let users = TickUsers {
+            tick,
+            per_state: self
+                .get_users_overview()
+                .per_state
+                .into_iter()
+                .map(|(state, count)| (state.to_string(), count))
+                .collect(),
+        };
+        context
+            .notifier
+            .send(Event::UsersPerState(users))
+            .await
+            .expect("channel open");
+        context
+            .notifier
+            .send(Event::TickCompleted(throughput))
+            .await
+            .expect("channel open");
+
+        // the deadline is fixed when the tick starts, so there is no gap between checking and sleeping
+        if !self.config.simulation.fast_forward && !aborted {
+            sleep_until(tick_start + tick_duration).await;
         }
     }
 
@@ -268,18 +778,101 @@ impl Simulation {
         (0..self.config.simulation.max_users).choose_multiple(&mut rng, amount)
     }
 
+    fn is_quarantined(&self, user_id: usize) -> bool {
+        match self.entities.get(&user_id) {
+            Some(Entity::Ready { user }) => user.try_read().map_or(false, |user| user.quarantined),
+            _ => false,
+        }
+    }
+
     async fn track_users(&mut self) {
         let syncing = self.get_syncing_users().await.len();
+        let users_overview = self.get_users_overview();
+        self.progress.update_users(&users_overview);
         self.progress.tick(syncing as u64);
     }
 
-    async fn store_report(&self, report: &Report, channels_info: Option<ChannelsInfo>) {
+    fn get_users_overview(&self) -> UsersOverview {
+        self.entities
+            .values()
+            .fold(UsersOverview::default(), |mut overview, entity| {
+                match entity {
+                    Entity::Waiting { .. } => {
+                        *overview.per_state.entry("Waiting").or_default() += 1;
+                    }
+                    Entity::Ready { user } => {
+                        if let Ok(user) = user.try_read() {
+                            let state = if user.quarantined {
+                                "Quarantined"
+                            } else {
+                                user.state.name()
+                            };
+                            *overview.per_state.entry(state).or_default() += 1;
+                            overview.sync_backlog += user.state.sync_backlog();
+                        }
+                    }
+                }
+                overview
+            })
+    }
+
+    /// Checkpoint queued to the report writer, it is written before the report
+    fn save_checkpoint(&mut self, ticks_completed: usize, report_writer: &ReportWriter) {
+        let first_user_id = self.config.simulation.first_user_id;
+        for (i, entity) in &self.entities {
+            let registered = match entity {
+                Entity::Waiting { registered, .. } => *registered,
+                Entity::Ready { user } => user.try_read().map_or(false, |u| u.is_registered()),
+            };
+            if registered {
+                self.checkpoint.registered_users.insert(first_user_id + i);
+            }
+        }
+        self.checkpoint.ticks_completed = ticks_completed;
+        let checkpoint = self.checkpoint.clone();
+        let output = self.config.simulation.output.clone();
+        report_writer
+            .queue()
+            .write(move || checkpoint.save(&output));
+    }
+
+    async fn store_report(
+        &self,
+        report_writer: ReportWriter,
+        report: &Report,
+        channels_info: Option<ChannelsInfo>,
+        mut manifest: Manifest,
+    ) {
         let output_folder = self.config.simulation.output.as_str();
         let homeserver = self.config.server.homeserver.as_str();
 
         let output_dir = format!("{output_folder}/{homeserver}");
+        let execution_id = execution_id();
 
-        report.generate(output_dir.as_str(), &execution_id(), channels_info);
+        report_writer.write(report.clone(), &output_dir, &execution_id, channels_info);
+
+        manifest.files.extend(report_writer.finish().await);
+        manifest.write(&Report::compute_reports_dir(&output_dir, &execution_id));
+
+        if let Some(upload) = &self.config.upload {
+            upload::upload(
+                upload,
+                &self.config.simulation.execution_id,
+                &manifest.files,
+            )
+            .await;
+        }
+
+        if let Some(notification) = &self.config.notification {
+            let summary = RunSummary::new(
+                &self.config.simulation.execution_id,
+                homeserver,
+                &manifest,
+                self.config.watchdog.is_some(),
+                report.summary(),
+            );
+            notification::notify(notification, &summary).await;
+        }
     }
 
     async fn get_syncing_users(&self) -> Vec<OwnedUserId> {
@@ -312,12 +905,17 @@ impl Simulation {
                     );
                     context.channels.write().await.insert(room_id);
                 }
-                UserNotifications::NewSyncedUser(user_id) => {
+                UserNotifications::NewSyncedUser(user_id, id_number) => {
                     log::debug!(
                         "collect_user_notifications event => {} data => {}",
                         "NEW SYNCED USER",
                         user_id
                     );
+                    context
+                        .id_numbers
+                        .write()
+                        .await
+                        .insert(user_id.clone(), id_number);
                     context.syncing_users.write().await.insert(user_id);
                 }
                 UserNotifications::UserLoggedOut(user_id) => {
@@ -0,0 +1,110 @@
+use bytes::BufMut;
+use matrix_sdk::ruma::api::{
+    error::IntoHttpError, MatrixVersion, Metadata, OutgoingRequest, SendAccessToken,
+};
+use opentelemetry::global;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::HashMap;
+use std::env;
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+// standard OpenTelemetry env var, traces are only exported when it's set
+const OTLP_ENDPOINT: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+const SERVICE_NAME: &str = "matrix-reloaded";
+
+pub fn is_enabled() -> bool {
+    env::var(OTLP_ENDPOINT).is_ok()
+}
+
+///
+/// Layer exporting spans to the OTLP endpoint, None if no endpoint is configured.
+///
+/// # Panics
+///
+/// If the OTLP exporter cannot be created.
+///
+pub fn layer<S>() -> Option<impl Layer<S>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let endpoint = env::var(OTLP_ENDPOINT).ok()?;
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                SERVICE_NAME,
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)
+        .expect("OTLP exporter to be created");
+
+    Some(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Trace context headers (`traceparent`) of the span, sent to the homeserver so its traces are linked to ours
+pub fn trace_headers(span: &Span) -> HeaderMap {
+    let mut context = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&span.context(), &mut context)
+    });
+
+    context
+        .into_iter()
+        .filter_map(|(name, value): (String, String)| {
+            Some((
+                HeaderName::from_bytes(name.as_bytes()).ok()?,
+                HeaderValue::from_str(&value).ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// Request sent with the trace context of the current span, the request span when it's sent by `instrument`
+#[derive(Debug, Clone)]
+pub struct Traced<R>(pub R);
+
+impl<R: OutgoingRequest> OutgoingRequest for Traced<R> {
+    type EndpointError = R::EndpointError;
+    type IncomingResponse = R::IncomingResponse;
+
+    const METADATA: Metadata = R::METADATA;
+
+    fn try_into_http_request<T: Default + BufMut>(
+        self,
+        base_url: &str,
+        access_token: SendAccessToken<'_>,
+        considering_versions: &'_ [MatrixVersion],
+    ) -> Result<http::Request<T>, IntoHttpError> {
+        let mut request =
+            self.0
+                .try_into_http_request(base_url, access_token, considering_versions)?;
+        if is_enabled() {
+            request
+                .headers_mut()
+                .extend(trace_headers(&Span::current()));
+        }
+        Ok(request)
+    }
+}
+
+/// Export pending spans before exiting
+pub fn shutdown() {
+    if is_enabled() {
+        global::shutdown_tracer_provider();
+    }
+}
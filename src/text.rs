@@ -1,14 +1,218 @@
+use crate::configuration::{MessageLength, Messages};
+use crate::time::time_now;
 use indicatif::{ProgressBar, ProgressStyle};
 use lipsum::lipsum;
+use rand::distributions::WeightedIndex;
+use rand::prelude::Distribution;
+use rand::seq::SliceRandom;
 use rand::Rng;
-use std::time::{Duration, Instant};
-use tokio::time::sleep;
+use serde_json::Value;
+use std::fs::read_to_string;
+use std::time::Duration;
+use tokio::time::{interval, sleep_until, Instant};
 
 pub fn get_random_string() -> String {
     let random_number: usize = rand::thread_rng().gen_range(5..15);
     lipsum(random_number)
 }
 
+const EMOJIS: &[&str] = &[
+    "😀",
+    "😂",
+    "🥲",
+    "😍",
+    "🤔",
+    "👍",
+    "🔥",
+    "🎉",
+    "❤️",
+    "🚀",
+    "🌍",
+    "🍕",
+    "👀",
+    "✨",
+    "🙏",
+    "👨‍👩‍👧",
+];
+
+const RTL_WORDS: &[&str] = &[
+    "שלום",
+    "עולם",
+    "תודה",
+    "בוקר",
+    "טוב",
+    "חבר",
+    "مرحبا",
+    "عالم",
+    "كيف",
+    "حالك",
+    "صديق",
+    "شكرا",
+];
+
+/// Get a random message body, the proportion of emoji-heavy, RTL and link ending bodies is taken from config.
+pub fn get_random_message(messages: &Messages) -> String {
+    let mut rng = rand::thread_rng();
+    let roll = rng.gen_range(0..100);
+    if let Some(corpus) = &messages.corpus {
+        return with_url(corpus.pick().to_string(), messages);
+    }
+    let length = get_random_length(&messages.length);
+    // multi-byte words and emojis take around 6 characters
+    let body = if roll < messages.emoji_probability {
+        get_random_emoji_string((length / 6).max(1))
+    } else if roll < messages.emoji_probability + messages.rtl_probability {
+        get_random_rtl_string((length / 6).max(1))
+    } else {
+        get_random_string_of_length(length)
+    };
+    with_url(body, messages)
+}
+
+fn with_url(body: String, messages: &Messages) -> String {
+    let mut rng = rand::thread_rng();
+    match messages.urls.choose(&mut rng) {
+        Some(url) if rng.gen_range(0..100) < messages.url_probability => format!("{body} {url}"),
+        _ => body,
+    }
+}
+
+/// Messages taken from production traffic, picked with a probability proportional to their weight.
+#[derive(Debug)]
+pub struct Corpus {
+    messages: Vec<String>,
+    weights: WeightedIndex<f64>,
+}
+
+impl Corpus {
+    ///
+    /// Load a corpus file with one message per line, lines can be prefixed by a weight and a tab
+    /// (ex: `3.5\thello world`), the weight of the rest of them is 1. Empty lines are ignored.
+    ///
+    /// # Panics
+    ///
+    /// If the file cannot be read, has no messages or a weight is negative.
+    ///
+    pub fn load(path: &str) -> Self {
+        let content =
+            read_to_string(path).unwrap_or_else(|_| panic!("could not read corpus file {path}"));
+        let (weights, messages): (Vec<f64>, Vec<String>) = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| match line.split_once('\t') {
+                Some((weight, message)) => match weight.trim().parse::<f64>() {
+                    Ok(weight) => (weight, message.to_string()),
+                    Err(_) => (1., line.to_string()),
+                },
+                None => (1., line.to_string()),
+            })
+            .unzip();
+        let weights = WeightedIndex::new(&weights)
+            .unwrap_or_else(|e| panic!("invalid corpus file {path}: {e}"));
+        println!("{} messages loaded from corpus {path}", messages.len());
+        Self { messages, weights }
+    }
+
+    fn pick(&self) -> &str {
+        &self.messages[self.weights.sample(&mut rand::thread_rng())]
+    }
+}
+
+/// First link of a message body.
+pub fn find_url(body: &str) -> Option<&str> {
+    body.split_whitespace()
+        .find(|word| word.starts_with("https://") || word.starts_with("http://"))
+}
+
+/// Length of a message picked from the log-normal distribution or from the large tail.
+fn get_random_length(length: &MessageLength) -> usize {
+    let mut rng = rand::thread_rng();
+    if rng.gen_range(0. ..100.) < length.large_probability {
+        return length.large_length;
+    }
+    let sample = log_normal(length.mu, length.sigma).round() as usize;
+    sample.clamp(length.min, length.max.max(length.min))
+}
+
+/// Sample of a log-normal distribution, `mu` and `sigma` of the logarithm (Box-Muller transform).
+pub fn log_normal(mu: f64, sigma: f64) -> f64 {
+    let mut rng = rand::thread_rng();
+    let (u1, u2): (f64, f64) = (1. - rng.gen::<f64>(), rng.gen());
+    let normal = (-2. * u1.ln()).sqrt() * (2. * std::f64::consts::PI * u2).cos();
+    (mu + sigma * normal).exp()
+}
+
+/// Lorem ipsum text cut to the length, words take around 6 characters with the separator.
+fn get_random_string_of_length(length: usize) -> String {
+    let mut text = lipsum(length / 5 + 1);
+    text.truncate(length);
+    text
+}
+
+/// Lorem ipsum words with emojis in between, ex: "lorem 🔥 ipsum 🎉🚀 dolor"
+fn get_random_emoji_string(words_count: usize) -> String {
+    let mut rng = rand::thread_rng();
+    lipsum(words_count)
+        .split_whitespace()
+        .map(|word| {
+            let emojis = (0..rng.gen_range(0..3))
+                .filter_map(|_| EMOJIS.choose(&mut rng))
+                .copied()
+                .collect::<String>();
+            format!("{word} {emojis}")
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn get_random_rtl_string(words_count: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..words_count)
+        .filter_map(|_| RTL_WORDS.choose(&mut rng))
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+///
+/// Replace the placeholders of every string in the template: `{localpart}` of the user sending it,
+/// `{timestamp}` in milliseconds and `{random}`, a random number between 0 and 100.
+/// Strings made of a single `{timestamp}` or `{random}` placeholder are replaced by a number.
+///
+pub fn render_template(template: &Value, localpart: &str) -> Value {
+    match template {
+        Value::String(text) => match text.as_str() {
+            "{timestamp}" => Value::from(time_now() as u64),
+            "{random}" => Value::from(rand::thread_rng().gen_range(0. ..100.)),
+            _ => Value::String(render_text(text, localpart)),
+        },
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| render_template(value, localpart))
+                .collect(),
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), render_template(value, localpart)))
+                .collect(),
+        ),
+        _ => template.clone(),
+    }
+}
+
+pub fn render_text(text: &str, localpart: &str) -> String {
+    let mut rendered = text
+        .replace("{localpart}", localpart)
+        .replace("{timestamp}", &time_now().to_string());
+    while rendered.contains("{random}") {
+        let random = rand::thread_rng().gen_range(0. ..100.);
+        rendered = rendered.replacen("{random}", &format!("{random:.2}"), 1);
+    }
+    rendered
+}
+
 pub fn default_spinner() -> ProgressBar {
     ProgressBar::new_spinner().with_style(
         ProgressStyle::default_spinner()
@@ -18,12 +222,43 @@ pub fn default_spinner() -> ProgressBar {
 }
 
 pub async fn spin_for(time: Duration, spinner: &ProgressBar) {
-    let wait_time = Instant::now();
+    let deadline = Instant::now() + time;
+    let mut spin = interval(Duration::from_millis(100));
     loop {
-        if wait_time.elapsed().ge(&time) {
-            break;
+        tokio::select! {
+            _ = sleep_until(deadline) => break,
+            _ = spin.tick() => spinner.inc(1),
         }
-        sleep(Duration::from_millis(100)).await;
-        spinner.inc(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::time::sleep;
+
+    /// Waiting must yield to the runtime: with the clock paused, a task sleeping meanwhile is woken up on every
+    /// deadline before the wait ends, a blocking wait would not let the clock advance at all
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn spin_for_keeps_the_runtime_responsive() {
+        let wake_ups = Arc::new(AtomicUsize::new(0));
+        let probe = tokio::spawn({
+            let wake_ups = wake_ups.clone();
+            async move {
+                loop {
+                    sleep(Duration::from_millis(10)).await;
+                    wake_ups.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+
+        let start = Instant::now();
+        spin_for(Duration::from_millis(305), &ProgressBar::hidden()).await;
+        probe.abort();
+
+        assert_eq!(start.elapsed(), Duration::from_millis(305));
+        assert_eq!(wake_ups.load(Ordering::Relaxed), 30);
     }
 }
@@ -1,14 +1,20 @@
 use std::cmp::max;
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::Instant;
 
 use crate::client::{Client, RegisterResult};
 use crate::client::{LoginResult, SyncResult};
-use crate::configuration::Config;
+use crate::configuration::{
+    Config, CustomEvent, CustomEventTarget, JoinRule, LoginMethod, StuckUserRecovery, Teardown,
+    UserRole,
+};
+use crate::credentials::Credentials;
 use crate::events::{SyncEvent, SyncEventsSender, UserNotifications, UserNotificationsSender};
-use crate::room::RoomType;
+use crate::recording::Target;
+use crate::room::{Permutation, RoomType};
 use crate::simulation::Context;
-use crate::text::get_random_string;
+use crate::text::{find_url, get_random_message, get_random_string, log_normal};
 use async_channel::Sender;
 use futures::lock::Mutex;
 use matrix_sdk::locks::RwLock;
@@ -18,12 +24,52 @@ use rand::prelude::SliceRandom;
 use rand::rngs::StdRng;
 use rand::seq::IteratorRandom;
 use rand::Rng;
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+
+// slowest pace of a rate limited user, it acts once every MAX_PACE ticks
+const MAX_PACE: usize = 16;
+// rooms kept in the recent rooms account data
+const RECENT_ROOMS: usize = 10;
+// messages sent in a single act by users with a target message rate, the rest are sent in later acts
+const MAX_MESSAGES_PER_ACT: f64 = 10.;
+// proportion of the messages sent to channels by users with a target message rate
+const CHANNEL_MESSAGES_RATIO: f64 = 0.2;
 
 #[derive(Clone, Debug)]
 pub struct User {
     pub localpart: String,
+    pub id_number: usize, // position of the user in the simulation, used to record and replay its actions
     client: Client,
     pub state: State,
+    credentials: Option<Credentials>, // pre-provisioned account, these users are never registered
+    can_register: bool, // false when users are provisioned outside of the tool (SSO-style login)
+    pace: usize,        // user acts once every `pace` times, increased when rate limited
+    skipped_acts: usize,
+    calls: Vec<Call>, // calls started by the user, hung up once they last the configured duration
+    bootstrap_cross_signing: bool, // new user that sets up cross-signing on its first login
+    set_display_name: bool, // new user that sets its display name on its first login
+    message_pacer: Option<MessagePacer>, // target messages per minute of the user while syncing
+    current_action: Arc<SyncMutex<String>>, // last action started, reported when the act is cancelled
+    action_target: Arc<SyncMutex<Target>>, // room, friend and message of the current action, to record it
+    role: UserRole,                        // what the user does once it's in sync state
+    failures: usize, // register, login and initial sync attempts failed in a row
+    forked_room: Option<OwnedRoomId>, // shared room joined by the forker
+    pub quarantined: bool, // stuck user that is never picked to act again
+}
+
+#[derive(Clone, Debug)]
+struct MessagePacer {
+    per_minute: f64,
+    budget: f64, // messages due since the last act
+    updated_at: Option<Instant>,
+}
+
+#[derive(Clone, Debug)]
+struct Call {
+    room_id: OwnedRoomId,
+    call_id: String,
+    ends_at: Instant,
 }
 
 #[derive(Debug)]
@@ -34,8 +80,27 @@ enum SocialAction {
     UpdateStatus,
     CreateChannel,
     JoinChannel,
+    BrowseDirectory,
+    CreateSpace,
+    BrowseSpace,
+    Moderate,
     GetChannelMembers,
     LeaveChannel,
+    LeaveRoom,
+    Call,
+    SendToDevice,
+    QueryKeys,
+    UpdateAccountData,
+    UpdateRoomAccountData,
+    SearchUser,
+    SearchMessages,
+    FetchEvent,
+    UpdateRoomDetails,
+    UpgradeRoom,
+    IgnoreUser,
+    TagRoom,
+    SendCustomEvent(usize), // position in the configured custom events
+    Registered(usize),      // position in the actions registered in the simulation
     None,
 }
 
@@ -46,32 +111,202 @@ pub enum State {
     LoggedIn,
     Sync {
         rooms: Arc<RwLock<HashSet<(OwnedRoomId, RoomType)>>>, // rooms can be channels or direct messages
-        events: Arc<Mutex<Vec<SyncEvent>>>, // recent events to be processed and react, for instance to respond to friends or join rooms
-        cancel_sync: Sender<bool>,          // cancel sync task
-        ticks_to_live: usize,               // ticks to live
+        events: Arc<Mutex<VecDeque<SyncEvent>>>, // recent events to be processed and react, for instance to respond to friends or join rooms
+        cancel_sync: Sender<bool>,               // cancel sync task
+        ticks_to_live: usize,                    // ticks to live
     },
     LoggedOut,
 }
 
-impl User {
-    pub async fn new(id_number: usize, notifier: SyncEventsSender, config: &Config) -> Self {
-        let localpart = get_user_id_localpart(id_number, &config.simulation.execution_id);
+impl State {
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Unauthenticated => "Unauthenticated",
+            State::Unregistered => "Unregistered",
+            State::LoggedIn => "LoggedIn",
+            State::Sync { .. } => "Sync",
+            State::LoggedOut => "LoggedOut",
+        }
+    }
+
+    /// Amount of sync events waiting to be processed by the user
+    pub fn sync_backlog(&self) -> usize {
+        match self {
+            State::Sync { events, .. } => events.try_lock().map_or(0, |events| events.len()),
+            _ => 0,
+        }
+    }
+}
 
-        let client = Client::new(notifier, config).await;
-        Self {
-            localpart,
-            client,
-            state: State::Unregistered,
+impl User {
+    pub async fn new(
+        id_number: usize,
+        credentials: Option<Credentials>,
+        notifier: SyncEventsSender,
+        request_limiter: Option<Arc<Semaphore>>,
+        http_client: Option<reqwest::Client>,
+        config: &Config,
+    ) -> Self {
+        let client = Client::new(notifier, request_limiter, http_client, id_number, config).await;
+        match credentials {
+            Some(credentials) => Self {
+                localpart: credentials.localpart().to_string(),
+                id_number,
+                client,
+                state: State::Unauthenticated,
+                credentials: Some(credentials),
+                can_register: false,
+                pace: 1,
+                skipped_acts: 0,
+                calls: vec![],
+                bootstrap_cross_signing: false,
+                set_display_name: false,
+                message_pacer: MessagePacer::new(config),
+                current_action: Arc::default(),
+                action_target: Arc::default(),
+                role: config.role(id_number),
+                forked_room: None,
+                failures: 0,
+                quarantined: false,
+            },
+            None => {
+                // users login with jwt or token are created by the server on first login
+                let can_register = config.login.method == LoginMethod::Password;
+                Self {
+                    localpart: config
+                        .accounts
+                        .localpart(id_number, &config.simulation.execution_id),
+                    id_number,
+                    client,
+                    state: if can_register {
+                        State::Unregistered
+                    } else {
+                        State::Unauthenticated
+                    },
+                    credentials: None,
+                    can_register,
+                    pace: 1,
+                    skipped_acts: 0,
+                    calls: vec![],
+                    bootstrap_cross_signing: false,
+                    set_display_name: false,
+                    message_pacer: MessagePacer::new(config),
+                    current_action: Arc::default(),
+                    action_target: Arc::default(),
+                    role: config.role(id_number),
+                    forked_room: None,
+                    failures: 0,
+                    quarantined: false,
+                }
+            }
         }
     }
 
     pub async fn act(&mut self, context: &Context) {
-        match &self.state {
-            State::Unregistered => self.register().await,
-            State::Unauthenticated => self.log_in().await,
-            State::LoggedIn => self.sync(&context.config, &context.user_notifier).await,
-            State::Sync { .. } => self.socialize(context).await,
-            State::LoggedOut => self.restart(&context.config).await,
+        if context.config.requests.adaptive_pace && self.role != UserRole::Prober && self.skip_act()
+        {
+            log::debug!("user '{}' skips act to reduce pace", self.localpart);
+            self.set_current_action("Skip");
+            return;
+        }
+        self.chaos_restart_sync(&context.config).await;
+        let span = tracing::info_span!("act", user = %self.localpart, state = self.state.name());
+        async {
+            match &self.state {
+                State::Unregistered => {
+                    self.set_current_action("Register");
+                    self.register(&context.config).await
+                }
+                State::Unauthenticated => {
+                    self.set_current_action("LogIn");
+                    self.log_in(&context.config).await
+                }
+                State::LoggedIn => {
+                    self.set_current_action("Sync");
+                    self.sync(&context.config, &context.user_notifier).await
+                }
+                State::Sync { events, rooms, .. } => {
+                    // users with other roles still read their events so the sync loop buffer doesn't grow
+                    let capacity = context.config.simulation.max_sync_events_per_user;
+                    match self.role {
+                        UserRole::Social => {
+                            self.set_current_action("Socialize");
+                            self.socialize(context).await
+                        }
+                        UserRole::Idle => {
+                            self.set_current_action("Idle");
+                            self.read_sync_events(events, capacity).await;
+                        }
+                        UserRole::Abuser => {
+                            self.set_current_action("SpamInvites");
+                            self.read_sync_events(events, capacity).await;
+                            self.spam_invites(context).await;
+                        }
+                        UserRole::Prober => {
+                            self.set_current_action("ProbeRateLimit");
+                            self.read_sync_events(events, capacity).await;
+                            self.probe_rate_limit(rooms, &context.config).await;
+                        }
+                        UserRole::Forker => {
+                            self.set_current_action("ForkRoom");
+                            self.read_sync_events(events, capacity).await;
+                            self.fork_room(&context.config).await;
+                        }
+                    }
+                }
+                State::LoggedOut => {
+                    self.set_current_action("Restart");
+                    self.restart(&context.config).await
+                }
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Last action started by the user, the one it was performing if its act was cancelled
+    pub fn current_action(&self) -> String {
+        self.current_action
+            .lock()
+            .expect("lock not poisoned")
+            .clone()
+    }
+
+    /// Target of the current action, the next action starts without one
+    pub fn take_action_target(&self) -> Target {
+        std::mem::take(&mut *self.action_target.lock().expect("lock not poisoned"))
+    }
+
+    fn set_current_action(&self, action: impl ToString) {
+        *self.current_action.lock().expect("lock not poisoned") = action.to_string();
+        *self.action_target.lock().expect("lock not poisoned") = Target::default();
+    }
+
+    fn update_action_target(&self, update: impl FnOnce(&mut Target)) {
+        update(&mut self.action_target.lock().expect("lock not poisoned"));
+    }
+
+    /// Halve the pace every time the user is rate limited and skip acts accordingly
+    fn skip_act(&mut self) -> bool {
+        if self.client.take_rate_limited() {
+            self.pace = (self.pace * 2).min(MAX_PACE);
+        }
+        self.skipped_acts += 1;
+        if self.skipped_acts < self.pace {
+            return true;
+        }
+        self.skipped_acts = 0;
+        false
+    }
+
+    pub fn is_registered(&self) -> bool {
+        !matches!(self.state, State::Unregistered)
+    }
+
+    /// User was registered in a previous run, it only needs to log in
+    pub fn mark_registered(&mut self) {
+        if let State::Unregistered = self.state {
+            self.state = State::Unauthenticated;
         }
     }
 
@@ -81,19 +316,107 @@ impl User {
         }
     }
 
+    async fn remove_room(&self, room: &(OwnedRoomId, RoomType)) {
+        if let State::Sync { rooms, .. } = &self.state {
+            rooms.write().await.remove(room);
+        }
+    }
+
     async fn restart(&mut self, config: &Config) {
         log::debug!("user '{}' act => {}", self.localpart, "RESTART");
+        if let Some(message_pacer) = &mut self.message_pacer {
+            message_pacer.updated_at = None;
+        }
         self.client.reset(config).await;
         self.state = State::Unauthenticated;
+        self.forked_room = None;
     }
 
-    async fn log_in(&mut self) {
+    /// Kill the sync loop of syncing users at random and start a new one, as a client crashing and coming back would
+    async fn chaos_restart_sync(&mut self, config: &Config) {
+        let restarts = match &config.chaos {
+            Some(chaos) => chaos.restarts_sync(),
+            None => false,
+        };
+        if !restarts {
+            return;
+        }
+        if let State::Sync { cancel_sync, .. } = &mut self.state {
+            if let Some(restarted) = self.client.restart_sync_loop().await {
+                log::debug!("user '{}' act => {}", self.localpart, "CHAOS RESTART SYNC");
+                *cancel_sync = restarted;
+            }
+        }
+    }
+
+    /// Count a failed register, login or initial sync, users failing too many times in a row are quarantined or restarted
+    async fn record_failure(&mut self, config: &Config) {
+        self.failures += 1;
+        match &config.stuck_users {
+            Some(stuck_users) if self.failures >= stuck_users.max_failures => {
+                log::debug!(
+                    "user '{}' is stuck after {} failures => {:?}",
+                    self.localpart,
+                    self.failures,
+                    stuck_users.recovery
+                );
+                self.failures = 0;
+                self.client.notify_stuck_user(stuck_users.recovery).await;
+                match stuck_users.recovery {
+                    StuckUserRecovery::Quarantine => self.quarantined = true,
+                    StuckUserRecovery::Restart => self.restart(config).await,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn log_in(&mut self, config: &Config) {
         log::debug!("user '{}' act => {}", self.localpart, "LOG IN");
 
-        match self.client.login(&self.localpart).await {
+        let result = match &self.credentials {
+            Some(credentials) => self.client.login_with_credentials(credentials).await,
+            None => self.client.login(&self.localpart).await,
+        };
+
+        match result {
             LoginResult::Ok => {
+                if config.feature_flags.keys_enabled {
+                    self.client.upload_keys().await;
+                }
+                if let Some(push) = &config.push {
+                    log::debug!("user '{}' act => {}", self.localpart, "SET PUSHER");
+                    self.client.set_pusher(&push.gateway_url).await;
+                }
+                if self.bootstrap_cross_signing {
+                    log::debug!(
+                        "user '{}' act => {}",
+                        self.localpart,
+                        "BOOTSTRAP CROSS-SIGNING"
+                    );
+                    self.client.bootstrap_cross_signing().await;
+                    self.bootstrap_cross_signing = false;
+                }
+                if self.set_display_name {
+                    let execution_id = &config.simulation.execution_id;
+                    if let Some(display_name) =
+                        config.accounts.display_name(self.id_number, execution_id)
+                    {
+                        log::debug!("user '{}' act => {}", self.localpart, "SET DISPLAY NAME");
+                        self.client.set_display_name(&display_name).await;
+                    }
+                    self.set_display_name = false;
+                }
+                self.failures = 0;
                 self.state = State::LoggedIn;
             }
+            LoginResult::NotRegistered if !self.can_register => {
+                log::debug!(
+                    "user {} cannot login and cannot be registered, maybe retry next time...",
+                    self.localpart
+                );
+                self.record_failure(config).await;
+            }
             LoginResult::NotRegistered => {
                 log::debug!("user {} not registered", self.localpart);
                 self.state = State::Unregistered;
@@ -103,18 +426,30 @@ impl User {
                     "user {} failed to login, maybe retry next time...",
                     self.localpart
                 );
+                self.record_failure(config).await;
             }
         }
     }
 
-    async fn register(&mut self) {
+    async fn register(&mut self, config: &Config) {
         log::debug!("user '{}' act => {}", self.localpart, "REGISTER");
         match self.client.register(&self.localpart).await {
-            RegisterResult::Ok => self.state = State::Unauthenticated,
-            RegisterResult::Failed => log::debug!(
-                "could not register user {}, will retry next time...",
-                self.localpart
-            ),
+            RegisterResult::Ok => {
+                self.bootstrap_cross_signing = rand::thread_rng().gen_ratio(
+                    config.simulation.probability_to_bootstrap_cross_signing as u32,
+                    100,
+                );
+                self.set_display_name = config.accounts.display_name_template.is_some();
+                self.failures = 0;
+                self.state = State::Unauthenticated
+            }
+            RegisterResult::Failed => {
+                log::debug!(
+                    "could not register user {}, will retry next time...",
+                    self.localpart
+                );
+                self.record_failure(config).await;
+            }
         }
     }
 
@@ -197,14 +532,23 @@ impl User {
                     &invited_rooms.len()
                 );
 
-                let mut events = vec![];
-                for invited_room in invited_rooms {
-                    events.push(SyncEvent::Invite(invited_room));
-                }
-
-                for (room_id, _) in &rooms {
-                    events.push(SyncEvent::UnreadRoom(room_id.clone()));
+                let capacity = config.simulation.max_sync_events_per_user;
+                let mut events = VecDeque::new();
+                let initial_events = invited_rooms
+                    .into_iter()
+                    .map(|invited_room| SyncEvent::Invite(invited_room, None))
+                    .chain(
+                        rooms
+                            .iter()
+                            .map(|(room_id, _)| SyncEvent::UnreadRoom(room_id.clone())),
+                    );
+                let mut dropped = 0;
+                for event in initial_events {
+                    if push_bounded(&mut events, event, capacity) {
+                        dropped += 1;
+                    }
                 }
+                self.client.notify_dropped_sync_events(dropped).await;
 
                 let rooms = rooms
                     .iter()
@@ -214,6 +558,7 @@ impl User {
                     });
 
                 let ticks_to_live = get_ticks_to_live(config);
+                self.failures = 0;
                 self.state = State::Sync {
                     rooms: Arc::new(RwLock::new(rooms)),
                     events: Arc::new(Mutex::new(events)),
@@ -223,7 +568,10 @@ impl User {
                 let user_id = self.id();
                 if let Some(user_id) = user_id {
                     user_notifier
-                        .send(UserNotifications::NewSyncedUser(user_id.to_owned()))
+                        .send(UserNotifications::NewSyncedUser(
+                            user_id.to_owned(),
+                            self.id_number,
+                        ))
                         .await
                         .expect("channel to be open");
 
@@ -237,17 +585,23 @@ impl User {
                 }
                 log::debug!("user '{}' now is syncing", self.localpart);
             }
-            SyncResult::Failed => log::debug!(
-                "user {} couldn't make initial sync, will retry next time...",
-                self.localpart
-            ),
+            SyncResult::Failed => {
+                log::debug!(
+                    "user {} couldn't make initial sync, will retry next time...",
+                    self.localpart
+                );
+                self.record_failure(config).await;
+            }
         }
     }
 
-    async fn read_sync_events(&self, events: &Mutex<Vec<SyncEvent>>) {
+    /// Move the events received by the sync loop to the user buffer, the oldest ones are dropped
+    /// when it holds more than `capacity` events.
+    async fn read_sync_events(&self, events: &Mutex<VecDeque<SyncEvent>>, capacity: usize) {
         log::debug!("user '{}' reading sync events", self.localpart);
         let new_events = self.client.read_sync_events().await;
         let mut events = events.lock().await;
+        let mut dropped = 0;
         for event in new_events {
             match event {
                 SyncEvent::RoomCreated(room_id) => {
@@ -257,9 +611,14 @@ impl User {
                 SyncEvent::ChannelCreated(room_id) => {
                     self.add_room((room_id.to_owned(), RoomType::Channel)).await
                 }
-                _ => events.push(event),
+                _ => {
+                    if push_bounded(&mut events, event, capacity) {
+                        dropped += 1;
+                    }
+                }
             }
         }
+        self.client.notify_dropped_sync_events(dropped).await;
     }
 
     // user social skills are:
@@ -272,6 +631,11 @@ impl User {
         log::debug!("user '{}' act => {}", self.localpart, "SOCIALIZE");
 
         self.decrease_ticks_to_live();
+        self.hang_up_calls(false).await;
+        let due_messages = self
+            .message_pacer
+            .as_mut()
+            .map_or(0, MessagePacer::take_due_messages);
         if let State::Sync {
             rooms,
             events,
@@ -279,10 +643,14 @@ impl User {
             ticks_to_live,
         } = &self.state
         {
-            self.read_sync_events(events).await;
+            self.send_due_messages(due_messages, rooms, &context.config)
+                .await;
+            self.read_sync_events(events, context.config.simulation.max_sync_events_per_user)
+                .await;
             let mut events = events.lock().await;
-            if let Some(event) = events.pop() {
+            if let Some(event) = events.pop_back() {
                 log::debug!("--- user '{}' going to react", self.localpart);
+                self.set_current_action("React");
                 self.react(event, context).await
             } else {
                 drop(events);
@@ -290,50 +658,75 @@ impl User {
                 log::debug!("--- user '{}' going to start interaction", self.localpart);
                 if ticks_to_live <= &0 {
                     // it's time to log out
+                    self.set_current_action("LogOut");
                     self.log_out(cancel_sync.clone(), &context.user_notifier)
                         .await;
                 } else {
-                    match pick_random_action(
-                        context.config.simulation.probability_to_act,
-                        context.config.feature_flags.channels_load,
-                        context.config.feature_flags.allow_get_channel_members,
-                    ) {
-                        SocialAction::SendMessage(message_type) => match message_type {
-                            RoomType::DirectMessage => {
-                                self.send_message(
-                                    pick_room(rooms, RoomType::DirectMessage).await,
-                                    message_type,
-                                )
-                                .await
-                            }
-                            RoomType::Channel => {
-                                self.send_message(
-                                    pick_room(rooms, RoomType::Channel).await,
-                                    message_type,
-                                )
+                    let recorded = context
+                        .replay
+                        .as_ref()
+                        .and_then(|replay| replay.action(context.current_tick(), self.id_number));
+                    let action = match &context.replay {
+                        Some(_) => replayed_action(
+                            recorded.map(|recorded| recorded.action.as_str()),
+                            context,
+                        ),
+                        None => pick_random_action(context),
+                    };
+                    let target = recorded.map(|recorded| &recorded.target);
+                    match action {
+                        SocialAction::Registered(index) => {
+                            self.set_current_action(context.actions.get(index).name())
+                        }
+                        _ => self.set_current_action(format!("{action:?}")),
+                    }
+                    match action {
+                        SocialAction::SendMessage(message_type) => {
+                            let room = self
+                                .pick_target_room(rooms, message_type.clone(), target)
+                                .await;
+                            let content = target.and_then(|target| target.content.clone());
+                            self.send_message(room, message_type, content, &context.config)
                                 .await
-                            }
-                        },
-                        SocialAction::AddFriend => self.add_friend(context).await,
+                        }
+                        SocialAction::AddFriend => self.add_friend(context, target).await,
                         SocialAction::LogOut => {
                             self.log_out(cancel_sync.clone(), &context.user_notifier)
                                 .await
                         }
                         SocialAction::UpdateStatus => self.update_status().await,
                         SocialAction::CreateChannel => {
+                            if !self.can_join_more_rooms(&context.config).await {
+                                return;
+                            }
                             let rooms = rooms.read().await;
-                            self.create_channel(
-                                get_room_count(&*rooms, RoomType::Channel),
-                                context.config.simulation.channels_per_user,
-                            )
-                            .await
+                            self.create_channel(get_room_count(&*rooms, RoomType::Channel), context)
+                                .await
                         }
                         SocialAction::JoinChannel => {
-                            self.join_channel(self.pick_channel(context).await, context)
+                            let channel = self.pick_target_channel(context, target).await;
+                            self.join_channel(channel, context).await
+                        }
+                        SocialAction::BrowseDirectory => {
+                            self.browse_directory(rooms, context).await
+                        }
+                        SocialAction::CreateSpace => self.create_space(rooms, context).await,
+                        SocialAction::BrowseSpace => self.browse_space(rooms, context).await,
+                        SocialAction::Moderate => {
+                            log::debug!("user '{}' act => {}", self.localpart, "MODERATE");
+                            let channels = rooms
+                                .read()
                                 .await
+                                .iter()
+                                .filter(|(_, room_type)| *room_type == RoomType::Channel)
+                                .map(|(room_id, _)| room_id.clone())
+                                .collect::<Vec<_>>();
+                            self.client.moderate(&channels).await
                         }
                         SocialAction::GetChannelMembers => {
-                            let channel_id = pick_room(rooms, RoomType::Channel).await;
+                            let channel_id = self
+                                .pick_target_room(rooms, RoomType::Channel, target)
+                                .await;
                             if let Some(channel_id) = channel_id {
                                 self.get_channel_members(
                                     channel_id,
@@ -343,9 +736,56 @@ impl User {
                             }
                         }
                         SocialAction::LeaveChannel => {
-                            self.leave_channel(pick_room(rooms, RoomType::Channel).await)
+                            let channel_id = self
+                                .pick_target_room(rooms, RoomType::Channel, target)
+                                .await;
+                            self.leave_channel(channel_id).await
+                        }
+                        SocialAction::LeaveRoom => {
+                            let room_id = self
+                                .pick_target_room(rooms, RoomType::DirectMessage, target)
+                                .await;
+                            self.leave_room(room_id).await
+                        }
+                        SocialAction::Call => {
+                            let room_id = self
+                                .pick_target_room(rooms, RoomType::DirectMessage, target)
+                                .await;
+                            self.call(room_id, &context.config).await
+                        }
+                        SocialAction::SendToDevice => {
+                            log::debug!("user '{}' act => {}", self.localpart, "SEND TO DEVICE");
+                            self.client.send_to_device().await
+                        }
+                        SocialAction::QueryKeys => {
+                            log::debug!("user '{}' act => {}", self.localpart, "QUERY KEYS");
+                            self.client.query_keys().await
+                        }
+                        SocialAction::UpdateAccountData => {
+                            self.update_account_data(rooms, &context.config).await
+                        }
+                        SocialAction::UpdateRoomAccountData => {
+                            self.update_room_account_data(rooms, &context.config).await
+                        }
+                        SocialAction::SearchUser => self.search_user(context).await,
+                        SocialAction::SearchMessages => self.search_messages(rooms).await,
+                        SocialAction::FetchEvent => {
+                            log::debug!("user '{}' act => {}", self.localpart, "FETCH EVENT");
+                            self.client.fetch_event().await
+                        }
+                        SocialAction::UpdateRoomDetails => self.update_room_details(rooms).await,
+                        SocialAction::UpgradeRoom => self.upgrade_room(rooms).await,
+                        SocialAction::IgnoreUser => self.ignore_user().await,
+                        SocialAction::TagRoom => self.tag_room(rooms).await,
+                        SocialAction::SendCustomEvent(index) => {
+                            self.send_custom_event(&context.config.custom_events[index], rooms)
                                 .await
                         }
+                        SocialAction::Registered(index) => {
+                            let action = context.actions.get(index);
+                            log::debug!("user '{}' act => {}", self.localpart, action.name());
+                            action.execute(&self.client, context).await
+                        }
                         SocialAction::None => log::debug!("user {} did nothing", self.localpart),
                     };
                 }
@@ -363,8 +803,10 @@ impl User {
     async fn react(&self, event: SyncEvent, ctx: &Context) {
         log::debug!("user '{}' act => {}", self.localpart, "REACT");
         match event {
-            SyncEvent::Invite(room_id) => self.join(&room_id, RoomType::DirectMessage, false).await,
-            SyncEvent::MessageReceived(room_id, _, message_type) => {
+            SyncEvent::Invite(room_id, inviter) => {
+                self.respond_invite(&room_id, inviter, ctx).await
+            }
+            SyncEvent::MessageReceived(room_id, body, message_type) => {
                 if RoomType::Channel == message_type && !ctx.config.feature_flags.channels_load {
                     log::debug!(
                         "user '{}' not responding because channels are disabled",
@@ -372,9 +814,31 @@ impl User {
                     );
                     return;
                 }
-                self.respond(room_id, message_type).await
+                if let Some(url) = find_url(&body) {
+                    log::debug!("user '{}' act => {}", self.localpart, "PREVIEW URL");
+                    self.client.preview_url(url).await;
+                }
+                self.respond(room_id, message_type, &ctx.config).await
             }
             SyncEvent::UnreadRoom(room_id) => self.read_messages(room_id).await,
+            SyncEvent::Knock(room_id, knocker_id) => {
+                self.client.accept_knock(&room_id, &knocker_id).await
+            }
+            SyncEvent::CallInvite(room_id, call_id) => {
+                log::debug!("user '{}' act => ANSWER CALL", self.localpart);
+                self.client.answer_call(&room_id, &call_id).await
+            }
+            SyncEvent::RoomUpgraded(room_id, replacement_room, tombstoned_at) => {
+                log::debug!("user '{}' act => FOLLOW TOMBSTONE", self.localpart);
+                if self
+                    .client
+                    .follow_tombstone(&room_id, &replacement_room, tombstoned_at)
+                    .await
+                {
+                    self.remove_room(&(room_id, RoomType::Channel)).await;
+                    self.add_room((replacement_room, RoomType::Channel)).await;
+                }
+            }
             SyncEvent::GetChannelMembers(room_id) => {
                 self.get_channel_members(room_id, SocialAction::JoinChannel)
                     .await
@@ -383,6 +847,44 @@ impl User {
         }
     }
 
+    /// Join the friend room, invites are rejected once the user reached the rooms limit.
+    /// Direct message rooms are added to the `m.direct` account data.
+    async fn respond_invite(
+        &self,
+        room_id: &RoomId,
+        inviter: Option<OwnedUserId>,
+        context: &Context,
+    ) {
+        if self.can_join_more_rooms(&context.config).await {
+            let joined = self.join(room_id, RoomType::DirectMessage, false).await;
+            if let (true, Some(inviter)) = (joined, inviter) {
+                self.client.add_direct_room(&inviter, room_id).await;
+            }
+        } else {
+            log::debug!("user '{}' act => REJECT INVITE", self.localpart);
+            self.client.leave_room(room_id.to_owned()).await;
+        }
+        if let Some(user_id) = self.id() {
+            if let Some(pending) = context.pending_invites.write().await.get_mut(user_id) {
+                *pending = pending.saturating_sub(1);
+            }
+        }
+    }
+
+    async fn can_join_more_rooms(&self, config: &Config) -> bool {
+        let rooms = match &self.state {
+            State::Sync { rooms, .. } => rooms.read().await.len(),
+            _ => return true,
+        };
+        match config.simulation.max_rooms_per_user {
+            Some(max_rooms) if rooms >= max_rooms => {
+                log::debug!("user {} reached the rooms per user limit", self.localpart);
+                false
+            }
+            _ => true,
+        }
+    }
+
     async fn read_messages(&self, room_id: OwnedRoomId) {
         log::debug!("user '{}' act => {}", self.localpart, "READ MESSAGES");
         self.client.read_messages(room_id).await;
@@ -397,7 +899,27 @@ impl User {
         self.client.get_channel_members(&room_id).await
     }
 
-    async fn respond(&self, room: OwnedRoomId, message_type: RoomType) {
+    /// Messages of users with a target rate, sent to direct message rooms or channels.
+    async fn send_due_messages(
+        &self,
+        due_messages: usize,
+        rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>,
+        config: &Config,
+    ) {
+        for _ in 0..due_messages {
+            let message_type = if config.feature_flags.channels_load
+                && rand::thread_rng().gen_bool(CHANNEL_MESSAGES_RATIO)
+            {
+                RoomType::Channel
+            } else {
+                RoomType::DirectMessage
+            };
+            let room = pick_room(rooms, message_type.clone()).await;
+            self.send_message(room, message_type, None, config).await;
+        }
+    }
+
+    async fn respond(&self, room: OwnedRoomId, message_type: RoomType, config: &Config) {
         match message_type {
             RoomType::DirectMessage => log::debug!(
                 "user '{}' act => {}",
@@ -408,20 +930,41 @@ impl User {
                 log::debug!("user '{}' act => {}", self.localpart, "RESPOND CHANNEL")
             }
         }
-        self.send_message(Some(room), message_type).await;
+        self.send_message(Some(room), message_type, None, config)
+            .await;
     }
 
-    async fn add_friend(&self, context: &Context) {
+    async fn add_friend(&self, context: &Context, target: Option<&Target>) {
         log::debug!("user '{}' act => {}", self.localpart, "ADD FRIEND");
-        let friend_id = self.pick_friend(context).await;
+        if !self.can_join_more_rooms(&context.config).await {
+            return;
+        }
+        let replayed = match target.and_then(|target| target.friend) {
+            Some(friend) => self.replayed_friend(friend, context).await,
+            None => None,
+        };
+        let friend_id = match replayed {
+            Some(friend_id) => Some(friend_id),
+            None => self.pick_friend(context).await,
+        };
         if let Some(friend_id) = friend_id {
-            self.client.add_friend(&friend_id).await;
+            let friend = context.id_numbers.read().await.get(&friend_id).copied();
+            self.update_action_target(|target| target.friend = friend);
+            if self.client.add_friend(&friend_id).await {
+                *context
+                    .pending_invites
+                    .write()
+                    .await
+                    .entry(friend_id)
+                    .or_default() += 1;
+            }
         } else {
             log::debug!("there are no users to add as friend :(");
         }
     }
 
-    async fn create_channel(&self, current_user_channels: usize, channels_per_user: usize) {
+    async fn create_channel(&self, current_user_channels: usize, context: &Context) {
+        let channels_per_user = context.config.simulation.channels_per_user;
         if current_user_channels < channels_per_user {
             let channel_name: String = rand::thread_rng()
                 .sample_iter(&Alphanumeric)
@@ -434,7 +977,31 @@ impl User {
                 "CREATE CHANNEL",
                 channel_name
             );
-            self.client.create_channel(channel_name).await
+            // restricted channels can be joined by the members of a space
+            let space_id = context
+                .spaces
+                .read()
+                .await
+                .iter()
+                .choose(&mut rand::thread_rng())
+                .cloned();
+            let permutation = if context.config.rooms.permutations {
+                Permutation::all(space_id.is_some())
+                    .into_iter()
+                    .choose(&mut rand::thread_rng())
+            } else {
+                None
+            };
+            let room_id = self
+                .client
+                .create_channel(channel_name, space_id.as_deref(), permutation.as_ref())
+                .await;
+            if let (Some(room_id), Some(JoinRule::Knock)) = (
+                room_id,
+                permutation.map(|permutation| permutation.join_rule),
+            ) {
+                context.knock_channels.write().await.insert(room_id);
+            }
         } else {
             log::debug!(
                 "user '{}' act => {} per user: {}, current user: {}",
@@ -446,16 +1013,122 @@ impl User {
         }
     }
 
+    /// List the public rooms directory and join by alias one of the channels the user is not in.
+    async fn browse_directory(
+        &self,
+        rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>,
+        context: &Context,
+    ) {
+        log::debug!("user '{}' act => {}", self.localpart, "BROWSE DIRECTORY");
+        let public_rooms = self.client.public_rooms().await;
+        if !self.can_join_more_rooms(&context.config).await {
+            return;
+        }
+        let alias = {
+            let rooms = rooms.read().await;
+            public_rooms
+                .into_iter()
+                .filter(|(room_id, _)| !rooms.contains(&(room_id.clone(), RoomType::Channel)))
+                .filter_map(|(_, alias)| alias)
+                .choose(&mut rand::thread_rng())
+        };
+        match alias {
+            Some(alias) => self.client.join_room_by_alias(&alias).await,
+            None => log::debug!(
+                "user {} found no room to join in the directory",
+                self.localpart
+            ),
+        }
+    }
+
+    /// Create a space with the user channels as children, unless the maximum amount of spaces was reached.
+    async fn create_space(
+        &self,
+        rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>,
+        context: &Context,
+    ) {
+        if context.spaces.read().await.len() >= context.config.simulation.max_spaces {
+            log::debug!("user {} cannot create more spaces", self.localpart);
+            return;
+        }
+        log::debug!("user '{}' act => {}", self.localpart, "CREATE SPACE");
+        let channels = rooms
+            .read()
+            .await
+            .iter()
+            .filter(|(_, room_type)| *room_type == RoomType::Channel)
+            .map(|(room_id, _)| room_id.clone())
+            .collect::<Vec<_>>();
+        if let Some(space_id) = self.client.create_space(&channels).await {
+            context.spaces.write().await.insert(space_id);
+        }
+    }
+
+    /// Browse the hierarchy of a random space and join one of its rooms the user is not in.
+    async fn browse_space(
+        &self,
+        rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>,
+        context: &Context,
+    ) {
+        let space_id = context
+            .spaces
+            .read()
+            .await
+            .iter()
+            .choose(&mut rand::thread_rng())
+            .cloned();
+        let space_id = match space_id {
+            Some(space_id) => space_id,
+            None => {
+                log::debug!("there are no spaces to browse");
+                return;
+            }
+        };
+        log::debug!("user '{}' act => {}", self.localpart, "BROWSE SPACE");
+        // restricted children can only be joined by the space members
+        if !self.client.is_joined(&space_id) {
+            self.join(&space_id, RoomType::Channel, false).await;
+        }
+        let children = self.client.space_hierarchy(&space_id).await;
+        let room_id = {
+            let rooms = rooms.read().await;
+            children
+                .into_iter()
+                .filter(|room_id| !rooms.contains(&(room_id.clone(), RoomType::Channel)))
+                .choose(&mut rand::thread_rng())
+        };
+        match room_id {
+            Some(room_id) => self.join_channel(Some(room_id), context).await,
+            None => log::debug!("user {} found no room to join in the space", self.localpart),
+        }
+    }
+
     async fn join_channel(&self, room_id: Option<OwnedRoomId>, context: &Context) {
-        if let Some(room_id) = room_id {
-            self.join(
-                &room_id,
-                RoomType::Channel,
-                context.config.feature_flags.allow_get_channel_members,
-            )
-            .await;
-        } else {
-            log::debug!("user {} has no room to join", self.localpart);
+        if !self.can_join_more_rooms(&context.config).await {
+            return;
+        }
+        let knock = match &room_id {
+            Some(room_id) => {
+                context.config.rooms.channel.join_rule == Some(JoinRule::Knock)
+                    || context.knock_channels.read().await.contains(room_id)
+            }
+            None => false,
+        };
+        match room_id {
+            // knock channels are joined once an admin accepts the knock
+            Some(room_id) if knock => {
+                log::debug!("user '{}' act => KNOCK", self.localpart);
+                self.client.knock(&room_id).await
+            }
+            Some(room_id) => {
+                self.join(
+                    &room_id,
+                    RoomType::Channel,
+                    context.config.feature_flags.allow_get_channel_members,
+                )
+                .await;
+            }
+            None => log::debug!("user {} has no room to join", self.localpart),
         }
     }
 
@@ -500,28 +1173,67 @@ impl User {
         match channel_id {
             Some(room_id) => {
                 log::debug!("channel about to leave: {room_id}");
-                self.client.leave_room(room_id).await
+                if self.client.leave_room(room_id.clone()).await {
+                    self.remove_room(&(room_id, RoomType::Channel)).await;
+                }
             }
             None => log::debug!("there is no room to leave"),
         }
     }
 
-    async fn join(&self, room: &RoomId, room_type: RoomType, allow_get_channel_members: bool) {
+    /// Leave a direct message room, so the user rooms don't grow forever in long runs.
+    async fn leave_room(&self, room_id: Option<OwnedRoomId>) {
+        log::debug!("user '{}' act => {}", self.localpart, "LEAVE ROOM");
+        match room_id {
+            Some(room_id) => {
+                if self.client.leave_direct_room(&room_id).await {
+                    self.remove_room(&(room_id, RoomType::DirectMessage)).await;
+                }
+            }
+            None => log::debug!("there is no direct message room to leave"),
+        }
+    }
+
+    async fn join(
+        &self,
+        room: &RoomId,
+        room_type: RoomType,
+        allow_get_channel_members: bool,
+    ) -> bool {
         log::debug!("user '{}' act => JOIN {:?}", self.localpart, room_type);
 
         self.client
             .join_room(room, room_type, allow_get_channel_members)
-            .await;
+            .await
     }
 
-    async fn send_message(&self, room: Option<OwnedRoomId>, message_type: RoomType) {
+    /// Send the replayed content as a text message when present, a random message otherwise
+    async fn send_message(
+        &self,
+        room: Option<OwnedRoomId>,
+        message_type: RoomType,
+        content: Option<String>,
+        config: &Config,
+    ) {
         log::debug!(
             "user '{}' act => SEND {:?} MESSAGE",
             self.localpart,
             message_type
         );
         if let Some(room) = room {
-            self.client.send_message(&room, get_random_string()).await;
+            let messages = &config.messages;
+            let roll = rand::thread_rng().gen_range(0..100);
+            if content.is_none() && roll < messages.sticker_probability {
+                self.client.send_sticker(&room).await
+            } else if content.is_none()
+                && roll < messages.sticker_probability + messages.location_probability
+            {
+                self.client.send_location(&room).await
+            } else {
+                let message = content.unwrap_or_else(|| get_random_message(messages));
+                self.update_action_target(|target| target.content = Some(message.clone()));
+                self.client.send_message(&room, message).await
+            }
         } else {
             log::debug!(
                 "trying to send message to {:?} but don't have one :(",
@@ -530,16 +1242,308 @@ impl User {
         }
     }
 
+    /// Send a custom event to a random room of the event target.
+    async fn send_custom_event(
+        &self,
+        event: &CustomEvent,
+        rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>,
+    ) {
+        log::debug!(
+            "user '{}' act => SEND CUSTOM EVENT {}",
+            self.localpart,
+            event.event_type
+        );
+        let room_id = rooms
+            .read()
+            .await
+            .iter()
+            .filter(|(_, room_type)| match event.target {
+                CustomEventTarget::Direct => *room_type == RoomType::DirectMessage,
+                CustomEventTarget::Channel => *room_type == RoomType::Channel,
+                CustomEventTarget::All => true,
+            })
+            .choose(&mut rand::thread_rng())
+            .map(|(room_id, _)| room_id.clone());
+        match room_id {
+            Some(room_id) => {
+                self.client
+                    .send_custom_event(&room_id, event, &self.localpart)
+                    .await
+            }
+            None => log::debug!(
+                "user {} has no room to send {}",
+                self.localpart,
+                event.event_type
+            ),
+        }
+    }
+
+    /// Call a friend, the call is hung up when the user acts after the configured duration.
+    async fn call(&mut self, room_id: Option<OwnedRoomId>, config: &Config) {
+        log::debug!("user '{}' act => {}", self.localpart, "CALL");
+        let (room_id, calls) = match (room_id, &config.calls) {
+            (Some(room_id), Some(calls)) => (room_id, calls),
+            _ => {
+                log::debug!("user {} has no friend to call", self.localpart);
+                return;
+            }
+        };
+        if let Some(call_id) = self.client.start_call(&room_id).await {
+            self.calls.push(Call {
+                room_id,
+                call_id,
+                ends_at: Instant::now() + calls.duration,
+            });
+        }
+    }
+
+    /// Hang up the calls that lasted the configured duration, or all of them.
+    async fn hang_up_calls(&mut self, all: bool) {
+        let now = Instant::now();
+        let (ended, ongoing) = self
+            .calls
+            .drain(..)
+            .partition::<Vec<_>, _>(|call| all || call.ends_at <= now);
+        self.calls = ongoing;
+        for call in ended {
+            log::debug!("user '{}' act => {}", self.localpart, "HANG UP");
+            self.client.hang_up(&call.room_id, &call.call_id).await;
+        }
+    }
+
+    async fn update_account_data(
+        &self,
+        rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>,
+        config: &Config,
+    ) {
+        log::debug!("user '{}' act => {}", self.localpart, "UPDATE ACCOUNT DATA");
+        if let Some(account_data) = &config.account_data {
+            let recent_rooms = rooms
+                .read()
+                .await
+                .iter()
+                .map(|(room_id, _)| room_id.clone())
+                .choose_multiple(&mut rand::thread_rng(), RECENT_ROOMS);
+            self.client
+                .update_account_data(&recent_rooms, account_data.blob_size)
+                .await
+        }
+    }
+
+    async fn update_room_account_data(
+        &self,
+        rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>,
+        config: &Config,
+    ) {
+        log::debug!(
+            "user '{}' act => {}",
+            self.localpart,
+            "UPDATE ROOM ACCOUNT DATA"
+        );
+        let room_id = rooms
+            .read()
+            .await
+            .iter()
+            .choose(&mut rand::thread_rng())
+            .map(|(room_id, _)| room_id.clone());
+        match (room_id, &config.account_data) {
+            (Some(room_id), Some(account_data)) => {
+                self.client
+                    .update_room_account_data(&room_id, account_data.blob_size)
+                    .await
+            }
+            _ => log::debug!("user {} has no room to update", self.localpart),
+        }
+    }
+
+    async fn update_room_details(&self, rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>) {
+        log::debug!("user '{}' act => {}", self.localpart, "UPDATE ROOM DETAILS");
+        let room_id = rooms
+            .read()
+            .await
+            .iter()
+            .choose(&mut rand::thread_rng())
+            .map(|(room_id, _)| room_id.clone());
+        match room_id {
+            Some(room_id) => self.client.update_room_details(&room_id).await,
+            None => log::debug!("user {} has no room to update", self.localpart),
+        }
+    }
+
+    async fn tag_room(&self, rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>) {
+        log::debug!("user '{}' act => {}", self.localpart, "TAG ROOM");
+        let room_id = rooms
+            .read()
+            .await
+            .iter()
+            .choose(&mut rand::thread_rng())
+            .map(|(room_id, _)| room_id.clone());
+        match room_id {
+            Some(room_id) => self.client.toggle_room_tag(&room_id).await,
+            None => log::debug!("user {} has no room to tag", self.localpart),
+        }
+    }
+
+    /// Upgrade one of the user channels, the replacement takes its place in the user rooms
+    async fn upgrade_room(&self, rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>) {
+        log::debug!("user '{}' act => {}", self.localpart, "UPGRADE ROOM");
+        let room_id = rooms
+            .read()
+            .await
+            .iter()
+            .filter(|(_, room_type)| *room_type == RoomType::Channel)
+            .choose(&mut rand::thread_rng())
+            .map(|(room_id, _)| room_id.clone());
+        let room_id = match room_id {
+            Some(room_id) => room_id,
+            None => {
+                log::debug!("user {} has no channel to upgrade", self.localpart);
+                return;
+            }
+        };
+        if let Some(replacement_room) = self.client.upgrade_room(&room_id).await {
+            self.remove_room(&(room_id, RoomType::Channel)).await;
+            self.add_room((replacement_room, RoomType::Channel)).await;
+        }
+    }
+
+    /// Toggle a friend in the ignore list, its messages must stop (or resume) appearing in the sync
+    async fn ignore_user(&self) {
+        log::debug!("user '{}' act => {}", self.localpart, "IGNORE USER");
+        match self.client.pick_direct_friend() {
+            Some(friend_id) => self.client.toggle_ignored_user(&friend_id).await,
+            None => log::debug!("user {} has no friend to ignore", self.localpart),
+        }
+    }
+
+    async fn probe_rate_limit(
+        &self,
+        rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>,
+        config: &Config,
+    ) {
+        log::debug!("user '{}' act => {}", self.localpart, "PROBE RATE LIMIT");
+        let room_id = rooms
+            .read()
+            .await
+            .iter()
+            .choose(&mut rand::thread_rng())
+            .map(|(room_id, _)| room_id.clone());
+        if let Some(probe) = &config.rate_limit_probe {
+            self.client
+                .probe_rate_limit(probe, room_id.as_deref())
+                .await
+        }
+    }
+
+    /// Join the room forked by all the forkers, then flap the membership, fork the state and time a message to it
+    async fn fork_room(&mut self, config: &Config) {
+        log::debug!("user '{}' act => {}", self.localpart, "FORK ROOM");
+        let forward_extremities = match &config.forward_extremities {
+            Some(forward_extremities) => forward_extremities,
+            None => return,
+        };
+        let room_id = match &self.forked_room {
+            Some(room_id) => room_id.clone(),
+            None => {
+                let alias_name =
+                    forward_extremities.room_alias_name(&config.simulation.execution_id);
+                match self.client.join_forked_room(&alias_name).await {
+                    Some(room_id) => {
+                        self.forked_room = Some(room_id.clone());
+                        room_id
+                    }
+                    None => return,
+                }
+            }
+        };
+        if forward_extremities.flaps() {
+            self.client.flap_membership(&room_id).await;
+        }
+        self.client
+            .fork_room_state(&room_id, forward_extremities.state_events_per_act)
+            .await;
+        self.client.send_forked_room_message(&room_id).await;
+    }
+
+    async fn spam_invites(&self, context: &Context) {
+        log::debug!("user '{}' act => {}", self.localpart, "SPAM INVITES");
+        let invites_per_act = context
+            .config
+            .invite_spam
+            .as_ref()
+            .map_or(0, |invite_spam| invite_spam.invites_per_act);
+        let own_id = self.id().map(ToOwned::to_owned);
+        let victims = context
+            .syncing_users
+            .read()
+            .await
+            .iter()
+            .filter(|user_id| own_id.as_ref() != Some(*user_id))
+            .cloned()
+            .choose_multiple(&mut rand::thread_rng(), invites_per_act);
+        if victims.is_empty() {
+            log::debug!("there are no users to spam :(");
+            return;
+        }
+        self.client.spam_invites(&victims).await
+    }
+
+    /// Search the user directory with a random prefix of the localpart of a syncing user.
+    async fn search_user(&self, context: &Context) {
+        log::debug!("user '{}' act => {}", self.localpart, "SEARCH USER");
+        let localpart = context
+            .syncing_users
+            .read()
+            .await
+            .iter()
+            .choose(&mut rand::thread_rng())
+            .map(|user_id| user_id.localpart().to_string());
+        match localpart {
+            Some(localpart) => {
+                // localparts rendered from templates may have multi-byte characters
+                let length = rand::thread_rng().gen_range(1..=localpart.chars().count());
+                let prefix = localpart.chars().take(length).collect::<String>();
+                self.client.search_user(&prefix).await
+            }
+            None => log::debug!("there are no users to search"),
+        }
+    }
+
+    /// Search a random word across all the rooms of the user.
+    async fn search_messages(&self, rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>) {
+        log::debug!("user '{}' act => {}", self.localpart, "SEARCH MESSAGES");
+        let rooms = rooms
+            .read()
+            .await
+            .iter()
+            .map(|(room_id, _)| room_id.clone())
+            .collect::<Vec<_>>();
+        if rooms.is_empty() {
+            log::debug!("user {} has no rooms to search", self.localpart);
+            return;
+        }
+        let search_term = get_random_string()
+            .split_whitespace()
+            .choose(&mut rand::thread_rng())
+            .unwrap_or("lorem")
+            .to_string();
+        self.client.search_messages(&rooms, &search_term).await;
+    }
+
     /// Log out user and append new char to the localpart string so next iteration is a new user.
+    /// Pre-provisioned users keep their localpart and log in again with the same account.
     async fn log_out(
         &mut self,
         cancel_sync: Sender<bool>,
         user_notifier: &UserNotificationsSender,
     ) {
         log::debug!("user '{}' act => {}", self.localpart, "LOG OUT");
+        self.hang_up_calls(true).await;
         cancel_sync.send(true).await.expect("channel open");
         self.state = State::LoggedOut;
-        self.localpart += "_";
+        if self.credentials.is_none() {
+            self.localpart += "_";
+        }
         let user_id = self.id();
         if let Some(user_id) = user_id {
             user_notifier
@@ -554,6 +1558,49 @@ impl User {
         }
     }
 
+    /// End-of-run behavior of `simulation.teardown`, users without a session are left as they are
+    pub async fn teardown(&mut self, teardown: Teardown) {
+        if teardown == Teardown::Keep {
+            return;
+        }
+        let rooms: Vec<(OwnedRoomId, RoomType)> = match &self.state {
+            State::Sync {
+                rooms, cancel_sync, ..
+            } => {
+                // the sync loop would fail once the session is gone
+                cancel_sync.send(true).await.expect("channel open");
+                rooms.read().await.iter().cloned().collect()
+            }
+            State::LoggedIn => vec![],
+            _ => return,
+        };
+        log::debug!("user '{}' act => TEARDOWN {:?}", self.localpart, teardown);
+        self.hang_up_calls(true).await;
+        match teardown {
+            Teardown::Keep => {}
+            Teardown::Logout => {
+                self.client.logout().await;
+            }
+            Teardown::LeaveRooms => {
+                for (room_id, room_type) in rooms {
+                    match room_type {
+                        RoomType::DirectMessage => self.client.leave_direct_room(&room_id).await,
+                        RoomType::Channel => self.client.leave_room(room_id).await,
+                    };
+                }
+                self.client.logout().await;
+            }
+            // pre-provisioned accounts are reused by the next runs
+            Teardown::Deactivate if self.credentials.is_some() => {
+                self.client.logout().await;
+            }
+            Teardown::Deactivate => {
+                self.client.deactivate().await;
+            }
+        }
+        self.state = State::LoggedOut;
+    }
+
     async fn update_status(&self) {
         log::debug!("user '{}' act => {}", self.localpart, "UPDATE STATUS");
         self.client.update_status().await;
@@ -562,8 +1609,28 @@ impl User {
     async fn pick_friend(&self, context: &Context) -> Option<OwnedUserId> {
         let mut rng: StdRng = rand::SeedableRng::from_entropy(); // allow use it with threads
         let synced_users = context.syncing_users.read().await;
+        let pending_invites = context.pending_invites.read().await;
+        let max_pending_invites = context.config.simulation.max_pending_invites_per_user;
 
-        let mut synced_users = synced_users.iter().collect::<Vec<_>>();
+        // with a friendship graph only the user neighbours can be added as friends
+        let friends = context
+            .friendships
+            .as_ref()
+            .map(|graph| graph.friends_of(&self.localpart));
+        let mut synced_users = synced_users
+            .iter()
+            .filter(|user_id| match friends {
+                Some(Some(friends)) => friends.contains(user_id.localpart()),
+                Some(None) => false,
+                None => true,
+            })
+            .filter(|user_id| match max_pending_invites {
+                Some(max) => pending_invites
+                    .get(*user_id)
+                    .map_or(true, |pending| *pending < max),
+                None => true,
+            })
+            .collect::<Vec<_>>();
         synced_users.shuffle(&mut rng);
 
         while let Some(friend_id) = synced_users.pop() {
@@ -573,6 +1640,67 @@ impl User {
         }
         None
     }
+
+    /// Friend of the replayed action by its id number, when it is syncing
+    async fn replayed_friend(&self, friend: usize, context: &Context) -> Option<OwnedUserId> {
+        let syncing_users = context.syncing_users.read().await;
+        context
+            .id_numbers
+            .read()
+            .await
+            .iter()
+            .find(|(user_id, id_number)| {
+                **id_number == friend
+                    && user_id.localpart() != self.localpart
+                    && syncing_users.contains(*user_id)
+            })
+            .map(|(user_id, _)| user_id.clone())
+    }
+
+    /// Room of the replayed action when the user is still in it, a random one of the type otherwise
+    async fn pick_target_room(
+        &self,
+        rooms: &RwLock<HashSet<(OwnedRoomId, RoomType)>>,
+        room_type: RoomType,
+        target: Option<&Target>,
+    ) -> Option<OwnedRoomId> {
+        let replayed = match target.and_then(|target| target.room.clone()) {
+            Some(room_id) => {
+                let room = (room_id, room_type.clone());
+                rooms.read().await.contains(&room).then_some(room.0)
+            }
+            None => None,
+        };
+        let room_id = match replayed {
+            Some(room_id) => Some(room_id),
+            None => pick_room(rooms, room_type).await,
+        };
+        self.update_action_target(|target| target.room = room_id.clone());
+        room_id
+    }
+
+    /// Channel of the replayed action when it still exists, a random one to join otherwise
+    async fn pick_target_channel(
+        &self,
+        context: &Context,
+        target: Option<&Target>,
+    ) -> Option<OwnedRoomId> {
+        let replayed = match target.and_then(|target| target.room.clone()) {
+            Some(room_id) => context
+                .channels
+                .read()
+                .await
+                .contains(&room_id)
+                .then_some(room_id),
+            None => None,
+        };
+        let room_id = match replayed {
+            Some(room_id) => Some(room_id),
+            None => self.pick_channel(context).await,
+        };
+        self.update_action_target(|target| target.room = room_id.clone());
+        room_id
+    }
 }
 
 fn get_room_count<'r, I>(rooms: I, room_type: RoomType) -> usize
@@ -582,39 +1710,242 @@ where
     rooms.into_iter().filter(|(_, r)| room_type == *r).count()
 }
 
-fn get_user_id_localpart(id_number: usize, execution_id: &str) -> String {
-    format!("user_{id_number}_{execution_id}")
+// we probably want to distribute these actions and don't make them random (more send messages than logouts)
+/// Actions of a user acting on a tick with their chance of being picked when the previous ones were not,
+/// a message to a friend is sent when none of them is picked.
+fn social_actions(config: &Config) -> Vec<(SocialAction, f64)> {
+    let feature_flags = &config.feature_flags;
+    let channels_enabled = feature_flags.channels_load;
+    // custom events go first so they are sent with the configured probability
+    let custom_events = config
+        .custom_events
+        .iter()
+        .enumerate()
+        .map(|(index, event)| {
+            (
+                SocialAction::SendCustomEvent(index),
+                event.probability / 100.,
+                event.target != CustomEventTarget::Channel || channels_enabled,
+            )
+        });
+    custom_events
+        .chain([
+            (SocialAction::LogOut, 1. / 75., true),
+            (SocialAction::LeaveChannel, 1. / 70., channels_enabled),
+            (
+                SocialAction::LeaveRoom,
+                config.simulation.probability_to_leave_room as f64 / 100.,
+                true,
+            ),
+            (
+                SocialAction::GetChannelMembers,
+                1. / 60.,
+                channels_enabled && feature_flags.allow_get_channel_members,
+            ),
+            (SocialAction::CreateChannel, 1. / 50., channels_enabled),
+            (SocialAction::JoinChannel, 1. / 35., channels_enabled),
+            (
+                SocialAction::BrowseDirectory,
+                1. / 40.,
+                channels_enabled && feature_flags.directory_enabled,
+            ),
+            (
+                SocialAction::CreateSpace,
+                1. / 80.,
+                channels_enabled && feature_flags.spaces_enabled,
+            ),
+            (
+                SocialAction::BrowseSpace,
+                1. / 40.,
+                channels_enabled && feature_flags.spaces_enabled,
+            ),
+            (
+                SocialAction::Moderate,
+                1. / 90.,
+                channels_enabled && feature_flags.moderation_enabled,
+            ),
+            (
+                SocialAction::Call,
+                config
+                    .calls
+                    .as_ref()
+                    .map_or(0., |calls| calls.probability / 100.),
+                true,
+            ),
+            (
+                SocialAction::SendToDevice,
+                config.simulation.probability_to_send_to_device as f64 / 100.,
+                true,
+            ),
+            (
+                SocialAction::QueryKeys,
+                1. / 20.,
+                feature_flags.keys_enabled,
+            ),
+            (
+                SocialAction::UpdateAccountData,
+                config
+                    .account_data
+                    .as_ref()
+                    .map_or(0., |account_data| account_data.global_probability / 100.),
+                true,
+            ),
+            (
+                SocialAction::UpdateRoomAccountData,
+                config
+                    .account_data
+                    .as_ref()
+                    .map_or(0., |account_data| account_data.room_probability / 100.),
+                true,
+            ),
+            (
+                SocialAction::SearchUser,
+                1. / 30.,
+                feature_flags.user_directory_enabled,
+            ),
+            (
+                SocialAction::SearchMessages,
+                config.simulation.probability_to_search_messages as f64 / 100.,
+                true,
+            ),
+            (
+                SocialAction::FetchEvent,
+                config.simulation.probability_to_fetch_event as f64 / 100.,
+                true,
+            ),
+            (
+                SocialAction::UpdateRoomDetails,
+                config.simulation.probability_to_update_room as f64 / 100.,
+                true,
+            ),
+            (
+                SocialAction::UpgradeRoom,
+                config.simulation.probability_to_upgrade_room as f64 / 100.,
+                channels_enabled,
+            ),
+            (
+                SocialAction::IgnoreUser,
+                config.simulation.probability_to_ignore_user as f64 / 100.,
+                true,
+            ),
+            (
+                SocialAction::TagRoom,
+                config.simulation.probability_to_tag_room as f64 / 100.,
+                true,
+            ),
+            (SocialAction::UpdateStatus, 1. / 25., true),
+            (SocialAction::AddFriend, 1. / 3., true),
+            (
+                SocialAction::SendMessage(RoomType::Channel),
+                1. / 5.,
+                channels_enabled && config.message_rate.is_none(),
+            ),
+        ])
+        .filter(|(_, chance, enabled)| *enabled && *chance > 0.)
+        .map(|(action, chance, _)| (action, chance))
+        .collect()
+}
+
+impl MessagePacer {
+    fn new(config: &Config) -> Option<Self> {
+        config.message_rate.as_ref().map(|message_rate| {
+            // mean of the log-normal distribution is exp(mu + sigma^2 / 2)
+            let mu = message_rate.per_minute.ln() - message_rate.sigma.powi(2) / 2.;
+            Self {
+                per_minute: log_normal(mu, message_rate.sigma),
+                budget: 0.,
+                updated_at: None,
+            }
+        })
+    }
+
+    /// Messages due since the previous act, up to the max per act, the rest of the budget is kept for the next acts.
+    fn take_due_messages(&mut self) -> usize {
+        let now = Instant::now();
+        if let Some(updated_at) = self.updated_at {
+            let minutes = now.duration_since(updated_at).as_secs_f64() / 60.;
+            self.budget += minutes * self.per_minute;
+        }
+        self.updated_at = Some(now);
+        let due_messages = self.budget.floor().min(MAX_MESSAGES_PER_ACT);
+        self.budget -= due_messages;
+        due_messages as usize
+    }
 }
 
-// we probably want to distribute these actions and don't make them random (more send messages than logouts)
-fn pick_random_action(
-    probability_to_act: usize,
-    channels_enabled: bool,
-    allow_get_channel_members: bool,
-) -> SocialAction {
+/// Users with a target message rate never send messages as random actions.
+/// Registered actions go first, like custom events.
+fn pick_random_action(context: &Context) -> SocialAction {
+    let config = &context.config;
     let mut rng = rand::thread_rng();
-    if rng.gen_ratio(probability_to_act as u32, 100) {
-        if rng.gen_ratio(1, 75) {
-            SocialAction::LogOut
-        } else if channels_enabled && rng.gen_ratio(1, 70) {
-            SocialAction::LeaveChannel
-        } else if channels_enabled && allow_get_channel_members && rng.gen_ratio(1, 60) {
-            SocialAction::GetChannelMembers
-        } else if channels_enabled && rng.gen_ratio(1, 50) {
-            SocialAction::CreateChannel
-        } else if channels_enabled && rng.gen_ratio(1, 35) {
-            SocialAction::JoinChannel
-        } else if rng.gen_ratio(1, 25) {
-            SocialAction::UpdateStatus
-        } else if rng.gen_ratio(1, 3) {
-            SocialAction::AddFriend
-        } else if channels_enabled && rng.gen_ratio(1, 5) {
-            SocialAction::SendMessage(RoomType::Channel)
-        } else {
-            SocialAction::SendMessage(RoomType::DirectMessage)
-        }
+    if !rng.gen_ratio(config.simulation.probability_to_act as u32, 100) {
+        return SocialAction::None;
+    }
+    context
+        .actions
+        .chances(config)
+        .map(|(index, chance)| (SocialAction::Registered(index), (chance / 100.).min(1.)))
+        .chain(social_actions(config))
+        .find(|(_, chance)| rng.gen_bool(*chance))
+        .map_or_else(|| default_action(config), |(action, _)| action)
+}
+
+/// Action recorded by name, nothing is done if it's not a social action with the current configuration
+fn replayed_action(recorded: Option<&str>, context: &Context) -> SocialAction {
+    let config = &context.config;
+    let recorded = match recorded {
+        Some(recorded) => recorded,
+        None => return SocialAction::None,
+    };
+    if let Some(index) = context.actions.position(recorded) {
+        return SocialAction::Registered(index);
+    }
+    social_actions(config)
+        .into_iter()
+        .map(|(action, _)| action)
+        .chain([default_action(config)])
+        .find(|action| format!("{action:?}") == recorded)
+        .unwrap_or_else(|| {
+            log::debug!("recorded action {} is not replayed", recorded);
+            SocialAction::None
+        })
+}
+
+fn default_action(config: &Config) -> SocialAction {
+    match config.message_rate {
+        Some(_) => SocialAction::None,
+        None => SocialAction::SendMessage(RoomType::DirectMessage),
+    }
+}
+
+/// Probability of each action (by name) for a user acting on a tick, used to plan a run.
+pub fn social_action_probabilities(config: &Config) -> Vec<(String, f64)> {
+    let mut not_picked = config.simulation.probability_to_act as f64 / 100.;
+    let mut probabilities = vec![("None".to_string(), 1. - not_picked)];
+    for (action, chance) in social_actions(config) {
+        let probability = not_picked * chance;
+        not_picked -= probability;
+        probabilities.push((format!("{action:?}"), probability));
+    }
+    let default_action = format!("{:?}", default_action(config));
+    match probabilities
+        .iter_mut()
+        .find(|(action, _)| *action == default_action)
+    {
+        Some((_, probability)) => *probability += not_picked,
+        None => probabilities.push((default_action, not_picked)),
+    }
+    probabilities
+}
+
+/// Push the event dropping the oldest one when the buffer is full, returns true when an event was dropped.
+fn push_bounded(events: &mut VecDeque<SyncEvent>, event: SyncEvent, capacity: usize) -> bool {
+    events.push_back(event);
+    if events.len() > capacity {
+        events.pop_front();
+        true
     } else {
-        SocialAction::None
+        false
     }
 }
 
@@ -0,0 +1,75 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+const CLIENT_VERSIONS_PATH: &str = "_matrix/client/versions";
+const SERVER_VERSION_PATH: &str = "_matrix/federation/v1/version";
+
+/// Versions of the tool and of the homeserver a report was generated with
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Versions {
+    pub tool: String,
+    /// commit the tool was built from, missing when it wasn't built from a git checkout
+    pub tool_commit: Option<String>,
+    /// spec versions advertised by the homeserver in `/_matrix/client/versions`
+    pub spec_versions: Vec<String>,
+    /// implementation and version of the homeserver in `/_matrix/federation/v1/version`, ex: "Synapse 1.98.0"
+    pub server: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ClientVersionsResponse {
+    versions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ServerVersionResponse {
+    server: ServerVersion,
+}
+
+#[derive(Deserialize)]
+struct ServerVersion {
+    name: String,
+    version: String,
+}
+
+/// Versions of the tool and of the homeserver, the homeserver ones are left out when it doesn't answer
+pub async fn capture(http_client: &reqwest::Client, homeserver_url: &str) -> Versions {
+    let homeserver_url = homeserver_url.trim_end_matches('/');
+    let spec_versions = get::<ClientVersionsResponse>(
+        http_client,
+        &format!("{homeserver_url}/{CLIENT_VERSIONS_PATH}"),
+    )
+    .await
+    .map(|response| response.versions)
+    .unwrap_or_default();
+    let server = get::<ServerVersionResponse>(
+        http_client,
+        &format!("{homeserver_url}/{SERVER_VERSION_PATH}"),
+    )
+    .await
+    .map(|response| format!("{} {}", response.server.name, response.server.version));
+    Versions {
+        tool: env!("CARGO_PKG_VERSION").to_string(),
+        tool_commit: option_env!("MATRIX_RELOADED_COMMIT").map(ToString::to_string),
+        spec_versions,
+        server,
+    }
+}
+
+async fn get<T: DeserializeOwned>(http_client: &reqwest::Client, url: &str) -> Option<T> {
+    let response = http_client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    let body = match response {
+        Ok(response) => response.json::<T>().await,
+        Err(e) => Err(e),
+    };
+    match body {
+        Ok(body) => Some(body),
+        Err(e) => {
+            log::debug!("couldn't get version from {url}: {e}");
+            None
+        }
+    }
+}
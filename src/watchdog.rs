@@ -0,0 +1,151 @@
+use crate::configuration::Watchdog as Thresholds;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+// thresholds are checked on an interval, not on every request, to avoid sorting latencies on every request
+// and to notice a server that stopped responding at all
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Window {
+    requests: VecDeque<(Instant, Duration)>,
+    errors: VecDeque<Instant>,
+    last_request: Option<Instant>,
+}
+
+/// Watches the error rate and p99 latency of the last requests, the tick being run is ended when the thresholds are exceeded
+pub struct Watchdog {
+    thresholds: Thresholds,
+    window: Mutex<Window>,
+    abort_reason: Mutex<Option<String>>,
+    tripped: Notify,
+}
+
+impl Watchdog {
+    pub fn new(thresholds: Thresholds) -> Self {
+        Self {
+            thresholds,
+            window: Mutex::new(Window::default()),
+            abort_reason: Mutex::new(None),
+            tripped: Notify::new(),
+        }
+    }
+
+    /// Start checking the thresholds every interval, until the returned task is aborted
+    pub fn start(self: &Arc<Self>) -> JoinHandle<()> {
+        let watchdog = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                watchdog.check();
+            }
+        })
+    }
+
+    pub fn record_request(&self, duration: Duration) {
+        let mut window = self.window.lock().expect("lock not poisoned");
+        let now = Instant::now();
+        window.requests.push_back((now, duration));
+        window.last_request = Some(now);
+    }
+
+    pub fn record_error(&self) {
+        let mut window = self.window.lock().expect("lock not poisoned");
+        window.errors.push_back(Instant::now());
+    }
+
+    /// Whether the run has to be aborted when a threshold is exceeded, instead of ending only the tick being run
+    pub fn aborts_run(&self) -> bool {
+        self.thresholds.abort_run
+    }
+
+    /// Wait until any of the thresholds is exceeded, returns the reason
+    pub async fn tripped(&self) -> String {
+        loop {
+            // registered before checking the reason to not miss a notification sent in between
+            let notified = self.tripped.notified();
+            if let Some(reason) = self.abort_reason() {
+                return reason;
+            }
+            notified.await;
+        }
+    }
+
+    /// Reason to end the tick (or abort the simulation) if any of the thresholds was exceeded
+    pub fn abort_reason(&self) -> Option<String> {
+        self.abort_reason.lock().expect("lock not poisoned").clone()
+    }
+
+    /// Clear the reason and the window once the tick was ended, so the next tick is judged by its own requests
+    pub fn reset(&self) {
+        *self.window.lock().expect("lock not poisoned") = Window::default();
+        *self.abort_reason.lock().expect("lock not poisoned") = None;
+    }
+
+    fn check(&self) {
+        let reason = {
+            let mut window = self.window.lock().expect("lock not poisoned");
+            self.exceeded(&mut window)
+        };
+        if let Some(reason) = reason {
+            log::debug!("watchdog: {}", reason);
+            self.abort_reason
+                .lock()
+                .expect("lock not poisoned")
+                .get_or_insert(reason);
+            self.tripped.notify_waiters();
+        }
+    }
+
+    fn exceeded(&self, window: &mut Window) -> Option<String> {
+        let now = Instant::now();
+        if let Some(window_start) = now.checked_sub(self.thresholds.window) {
+            while matches!(window.requests.front(), Some((time, _)) if *time < window_start) {
+                window.requests.pop_front();
+            }
+            while matches!(window.errors.front(), Some(time) if *time < window_start) {
+                window.errors.pop_front();
+            }
+        }
+
+        // requests were completed before, but none in the whole window: the server stopped responding
+        if window.requests.is_empty() && window.last_request.is_some() {
+            return Some(format!(
+                "no requests completed in the last {:?}",
+                self.thresholds.window
+            ));
+        }
+
+        let requests = window.requests.len();
+        if requests < self.thresholds.min_requests {
+            return None;
+        }
+
+        self.error_rate_exceeded(window.errors.len(), requests)
+            .or_else(|| {
+                self.p99_latency_exceeded(window.requests.iter().map(|(_, duration)| *duration))
+            })
+    }
+
+    fn error_rate_exceeded(&self, errors: usize, requests: usize) -> Option<String> {
+        let max_error_rate = self.thresholds.max_error_rate?;
+        let error_rate = errors as f64 / requests as f64;
+        (error_rate > max_error_rate).then(|| {
+            format!("error rate {error_rate:.2} exceeded the threshold of {max_error_rate:.2}")
+        })
+    }
+
+    fn p99_latency_exceeded(&self, durations: impl Iterator<Item = Duration>) -> Option<String> {
+        let max_p99_latency = self.thresholds.max_p99_latency?;
+        let mut durations = durations.collect::<Vec<_>>();
+        durations.sort_unstable();
+        let index = (durations.len() * 99 / 100).min(durations.len().saturating_sub(1));
+        let p99 = *durations.get(index)?;
+        (p99 > max_p99_latency)
+            .then(|| format!("p99 latency {p99:?} exceeded the threshold of {max_p99_latency:?}"))
+    }
+}
@@ -0,0 +1,95 @@
+//! End-to-end tests running small simulations against a Conduit homeserver started with docker.
+//! They are only built with the `e2e` feature: `cargo test --features e2e`.
+#![cfg(feature = "e2e")]
+
+use config::ConfigError;
+use matrix_reloaded::configuration::Config;
+use matrix_reloaded::simulation::Simulation;
+use std::time::Duration;
+use testcontainers::clients::Cli;
+use testcontainers::images::generic::GenericImage;
+
+const CONDUIT_PORT: u16 = 6167;
+
+fn conduit() -> GenericImage {
+    GenericImage::new("matrixconduit/matrix-conduit", "v0.6.0")
+        .with_env_var("CONDUIT_CONFIG", "")
+        .with_env_var("CONDUIT_SERVER_NAME", "localhost")
+        .with_env_var("CONDUIT_DATABASE_BACKEND", "rocksdb")
+        .with_env_var("CONDUIT_DATABASE_PATH", "/var/lib/matrix-conduit/")
+        .with_env_var("CONDUIT_ADDRESS", "0.0.0.0")
+        .with_env_var("CONDUIT_PORT", CONDUIT_PORT.to_string())
+        .with_env_var("CONDUIT_ALLOW_REGISTRATION", "true")
+        .with_exposed_port(CONDUIT_PORT)
+}
+
+/// Wait until the homeserver answers the versions endpoint
+async fn wait_ready(homeserver: &str) {
+    let url = format!("{homeserver}/_matrix/client/versions");
+    for _ in 0..60 {
+        if let Ok(response) = reqwest::get(&url).await {
+            if response.status().is_success() {
+                return;
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    panic!("homeserver {homeserver} not ready");
+}
+
+/// Few users acting on every tick, long enough for them to register, sync and socialize
+fn tiny_simulation(homeserver: &str) -> Result<Config, ConfigError> {
+    let output = std::env::temp_dir().join("matrix-reloaded-e2e");
+    Config::builder()?
+        .file("configuration")
+        .set("server.homeserver", homeserver)?
+        .set("server.wk_login", false)?
+        .set("server.flavor", "conduit")?
+        .set("simulation.ticks", 12)?
+        .set("simulation.tick_duration_in_secs", 1)?
+        .set("simulation.max_users", 6)?
+        .set("simulation.users_per_tick", 6)?
+        .set("simulation.grace_period_duration_in_secs", 2)?
+        .set("simulation.output", output.to_string_lossy().to_string())?
+        .set("simulation.probability_for_short_lifes", 0)?
+        .build()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn users_register_sync_and_socialize() {
+    let docker = Cli::default();
+    let conduit = docker.run(conduit());
+    let homeserver = format!(
+        "http://127.0.0.1:{}",
+        conduit.get_host_port_ipv4(CONDUIT_PORT)
+    );
+    wait_ready(&homeserver).await;
+
+    let config = tiny_simulation(&homeserver).expect("configuration to be valid");
+    let report = Simulation::with(config).run().await;
+    let summary = report.summary();
+
+    assert!(summary.requests > 0, "no requests were sent: {summary:?}");
+    // other kinds may fail on Conduit, ex: the Synapse admin APIs
+    for request in [
+        "register",
+        "login",
+        "initial_sync",
+        "create_room",
+        "send_message",
+    ] {
+        assert!(
+            report.requests_of(request) > 0,
+            "no {request} requests were sent"
+        );
+        assert_eq!(
+            report.http_errors_of(request),
+            0,
+            "{request} requests failed: {summary:?}"
+        );
+    }
+    assert_eq!(
+        summary.acts_cancelled, 0,
+        "acts were cancelled: {summary:?}"
+    );
+}